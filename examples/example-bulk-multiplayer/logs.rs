@@ -1,16 +1,52 @@
-use core::fmt;
+use core::cell::RefCell;
+use core::fmt::{self, Write};
 
-use agb::external::portable_atomic::{AtomicU16, Ordering};
+use agb::external::critical_section::{self, Mutex};
+use agb::external::portable_atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
 use agb::mgba::{self, DebugLevel, Mgba};
+use serial_experiments_gba::uart::Uart;
 
 pub struct Logger {
     framecounter: AtomicU16,
     id: AtomicU16,
+    mgba_enabled: AtomicBool,
+    uart_level: AtomicU8,
+    uart: Mutex<RefCell<Option<UartSink>>>,
+}
+
+/// Wrapper to let the logger hold onto the UART handle.
+struct UartSink(Uart<'static>);
+
+/// #SAFETY
+///
+/// The GBA only has a single core, and the sink is only ever touched inside a
+/// critical section.
+unsafe impl Send for UartSink {}
+
+/// A single formatted log message.
+struct LogLine<'a> {
+    frame: u16,
+    id: u16,
+    level: &'static str,
+    msg: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for LogLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:010}] [{:03}] [{}] {}",
+            self.frame, self.id, self.level, self.msg
+        )
+    }
 }
 
 static LOGGER: Logger = Logger {
     framecounter: AtomicU16::new(0),
     id: AtomicU16::new(0),
+    mgba_enabled: AtomicBool::new(true),
+    uart_level: AtomicU8::new(DebugLevel::Info as u8),
+    uart: Mutex::new(RefCell::new(None)),
 };
 
 impl Logger {
@@ -18,10 +54,25 @@ impl Logger {
         &LOGGER
     }
     pub fn set_level(&self, level: DebugLevel) {
+        self.uart_level.store(level as u8, Ordering::Relaxed);
         if let Some(mut mgba) = mgba::Mgba::new() {
             mgba.set_level(level);
         }
     }
+    /// Sets whether or not messages should be sent to mgba's log console.
+    pub fn set_mgba_enabled(&self, enabled: bool) {
+        self.mgba_enabled.store(enabled, Ordering::Relaxed);
+    }
+    /// Sets the UART that messages should also be sent over, eg to a PC via a
+    /// USB-to-serial adapter on real hardware. Returns the previous sink, if
+    /// any.
+    ///
+    /// Messages are sent as `\r\n`-terminated lines using blocking writes, so
+    /// pick a high baud rate to keep logging from stalling the game.
+    pub fn set_uart_sink(&self, uart: Option<Uart<'static>>) -> Option<Uart<'static>> {
+        critical_section::with(|cs| self.uart.replace(cs, uart.map(UartSink)))
+            .map(|UartSink(old)| old)
+    }
     pub fn id_from_framecount(&self) -> Result<(), u16> {
         self.set_id(self.framecounter.load(Ordering::Relaxed))
     }
@@ -35,9 +86,6 @@ impl Logger {
     }
     pub fn log(&self, level: DebugLevel, msg: fmt::Arguments) -> Result<(), fmt::Error> {
         use DebugLevel::*;
-        let Some(mut mgba) = Mgba::new() else {
-            return Ok(());
-        };
         let mapped_level = match level {
             Fatal => "FATAL",
             Error => "ERROR",
@@ -45,16 +93,39 @@ impl Logger {
             Info => "INFO ",
             Debug => "DEBUG",
         };
-        mgba.print(
-            format_args!(
-                "[{:010}] [{:03}] [{}] {}",
-                self.framecounter.load(Ordering::Acquire),
-                self.id.load(Ordering::Acquire),
-                mapped_level,
-                msg
-            ),
-            level,
-        )
+        let frame = self.framecounter.load(Ordering::Acquire);
+        let id = self.id.load(Ordering::Acquire);
+        let line = LogLine {
+            frame,
+            id,
+            level: mapped_level,
+            msg,
+        };
+        if level as u8 <= self.uart_level.load(Ordering::Relaxed) {
+            self.log_uart(&line)?;
+        }
+        if !self.mgba_enabled.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let Some(mut mgba) = Mgba::new() else {
+            return Ok(());
+        };
+        mgba.print(format_args!("{}", line), level)
+    }
+    fn log_uart(&self, line: &LogLine) -> Result<(), fmt::Error> {
+        // Take the sink out while writing so that we aren't sitting in a
+        // critical section (and blocking interrupts) for the whole line. Any
+        // logs from interrupts in the meantime only go to mgba.
+        let Some(UartSink(mut uart)) =
+            critical_section::with(|cs| self.uart.borrow_ref_mut(cs).take())
+        else {
+            return Ok(());
+        };
+        let res = writeln!(uart.writer(), "{}", line);
+        critical_section::with(|cs| {
+            self.uart.borrow_ref_mut(cs).get_or_insert(UartSink(uart));
+        });
+        res
     }
 }
 
@@ -67,4 +138,14 @@ macro_rules! println {
             format_args!($($x,)*)
         );
     }};
-}
\ No newline at end of file
+}
+
+#[macro_export]
+macro_rules! debug {
+    ( $( $x:expr ),*) => {{
+        let _ = $crate::logs::Logger::get().log(
+            agb::mgba::DebugLevel::Debug,
+            format_args!($($x,)*)
+        );
+    }};
+}
@@ -0,0 +1,213 @@
+//! A simple logic analyzer, for debugging homemade link hardware.
+//!
+//! [LogicAnalyzer::capture] samples all 4 pins at a fixed rate into a buffer
+//! in IWRAM, so that even fast rates aren't slowed down by EWRAM's wait
+//! states. The trace can then be inspected with [LogicAnalyzer::with_trace],
+//! or dumped to mGBA's log with [LogicAnalyzer::dump_to_mgba].
+//!
+//! The buffer is a static, so any program using the analyzer gives up
+//! [TRACE_BYTES] of the GBA's 32KB of IWRAM for it. Captures run with
+//! interrupts disabled, so they're limited to [MAX_CAPTURE_CYCLES].
+
+use core::cell::RefCell;
+
+use agb::{
+    external::critical_section::{self, Mutex},
+    mgba::{DebugLevel, Mgba},
+    timer::Timer,
+};
+
+use super::timing::CycleTimer;
+use super::{GeneralPurpose, PinState};
+use crate::utils::{CPU_HZ, CYCLES_PER_FRAME};
+
+/// The number of bytes of IWRAM set aside for the trace.
+pub const TRACE_BYTES: usize = 4096;
+/// The most samples a single capture can hold; each takes half a byte.
+pub const MAX_SAMPLES: usize = TRACE_BYTES * 2;
+/// The longest a single capture can take, in CPU cycles: 4 frames, or about
+/// 67ms.
+pub const MAX_CAPTURE_CYCLES: u32 = 4 * CYCLES_PER_FRAME;
+
+/// The samples from a single capture, oldest first.
+pub struct Trace {
+    samples: [u8; TRACE_BYTES],
+    len: usize,
+    sample_hz: u32,
+}
+
+impl Trace {
+    const fn new() -> Self {
+        Self {
+            samples: [0; TRACE_BYTES],
+            len: 0,
+            sample_hz: 0,
+        }
+    }
+    fn clear(&mut self, sample_hz: u32) {
+        self.len = 0;
+        self.sample_hz = sample_hz;
+    }
+    fn push(&mut self, pins: PinState) {
+        let byte = &mut self.samples[self.len / 2];
+        if self.len % 2 == 0 {
+            *byte = pins.state;
+        } else {
+            *byte |= pins.state << 4;
+        }
+        self.len += 1;
+    }
+    /// The number of samples captured.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// The rate the samples were taken at.
+    pub const fn sample_hz(&self) -> u32 {
+        self.sample_hz
+    }
+    /// The state of the pins in the `idx`th sample.
+    pub const fn get(&self, idx: usize) -> Option<PinState> {
+        if idx >= self.len {
+            return None;
+        }
+        let byte = self.samples[idx / 2];
+        let nibble = if idx % 2 == 0 { byte } else { byte >> 4 };
+        Some(PinState::from_rcnt(nibble as u16))
+    }
+    /// Every sample, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = PinState> + '_ {
+        (0..self.len).filter_map(|idx| self.get(idx))
+    }
+    /// The first sample & every sample that differs from the one before it,
+    /// along with their indices.
+    pub fn changes(&self) -> impl Iterator<Item = (usize, PinState)> + '_ {
+        let mut previous = None;
+        self.iter().enumerate().filter(move |&(_, pins)| {
+            let changed = previous != Some(pins);
+            previous = Some(pins);
+            changed
+        })
+    }
+}
+
+#[link_section = ".iwram"]
+static TRACE: Mutex<RefCell<Trace>> = Mutex::new(RefCell::new(Trace::new()));
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CaptureError {
+    /// The sample rate is too slow for the timer, or faster than the pins can
+    /// be read & stored.
+    InvalidRate,
+    /// More than [MAX_SAMPLES] samples were asked for, or taking them at
+    /// the requested rate would take longer than [MAX_CAPTURE_CYCLES].
+    TooLong,
+}
+
+pub struct LogicAnalyzer<'a> {
+    gpio: GeneralPurpose<'a>,
+    timer: CycleTimer,
+}
+
+impl<'a> LogicAnalyzer<'a> {
+    /// Uses `timer` to pace the samples. The pins are left as they are, so
+    /// outputs are sampled at the level they are driving.
+    pub fn new(gpio: GeneralPurpose<'a>, timer: Timer) -> Self {
+        Self {
+            gpio,
+            timer: CycleTimer::new(timer),
+        }
+    }
+    /// Takes `samples` samples of every pin at `sample_hz` samples per second,
+    /// replacing the previous trace.
+    ///
+    /// This blocks with interrupts disabled for the whole capture, so that
+    /// the samples are evenly spaced; that's why it can last at most
+    /// [MAX_CAPTURE_CYCLES]. Any interrupts that would've happened meanwhile
+    /// (including VBlank) are delayed until it's done.
+    pub fn capture(&mut self, sample_hz: u32, samples: usize) -> Result<(), CaptureError> {
+        if samples > MAX_SAMPLES {
+            return Err(CaptureError::TooLong);
+        }
+        let period = CPU_HZ / sample_hz.max(1);
+        if period == 0 || period > u16::MAX as u32 {
+            return Err(CaptureError::InvalidRate);
+        }
+        if period.saturating_mul(samples as u32) > MAX_CAPTURE_CYCLES {
+            return Err(CaptureError::TooLong);
+        }
+        critical_section::with(|cs| {
+            let mut trace = TRACE.borrow_ref_mut(cs);
+            trace.clear(sample_hz);
+            self.timer.restart();
+            for _ in 0..samples {
+                trace.push(self.gpio.pins());
+                self.timer.wait(period as u16);
+            }
+        });
+        Ok(())
+    }
+    /// Calls `cb` with the last trace captured.
+    pub fn with_trace<R>(&self, cb: impl FnOnce(&Trace) -> R) -> R {
+        critical_section::with(|cs| cb(&TRACE.borrow_ref(cs)))
+    }
+    /// Logs the last trace to mGBA's debug log, 1 line per change in the
+    /// pins. Returns `false` if not running in mGBA.
+    pub fn dump_to_mgba(&self) -> bool {
+        let Some(mut mgba) = Mgba::new() else {
+            return false;
+        };
+        self.with_trace(|trace| {
+            let _ = mgba.print(
+                format_args!("trace: {} samples at {}Hz", trace.len(), trace.sample_hz()),
+                DebugLevel::Info,
+            );
+            for (idx, pins) in trace.changes() {
+                let _ = mgba.print(
+                    format_args!(
+                        "{:>5}: SC={} SD={} SI={} SO={}",
+                        idx,
+                        pins.sc() as u8,
+                        pins.sd() as u8,
+                        pins.si() as u8,
+                        pins.so() as u8
+                    ),
+                    DebugLevel::Info,
+                );
+            }
+        });
+        true
+    }
+    /// Hands back the GPIO port & timer.
+    pub fn into_inner(self) -> (GeneralPurpose<'a>, Timer) {
+        (self.gpio, self.timer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_trace(_gba: &mut Gba) {
+        let low = PinState::default();
+        let high = PinState::default().with_sc(true).with_so(true);
+        // The trace is too big for the stack, so use the real one.
+        critical_section::with(|cs| {
+            let mut trace = TRACE.borrow_ref_mut(cs);
+            trace.clear(1000);
+            for pins in [low, low, high, high, low] {
+                trace.push(pins);
+            }
+            assert_eq!(trace.len(), 5);
+            assert_eq!(trace.get(2), Some(high));
+            assert_eq!(trace.get(5), None);
+            let changes: Vec<_> = trace.changes().collect();
+            assert_eq!(changes, [(0, low), (2, high), (4, low)]);
+        });
+    }
+}
@@ -0,0 +1,80 @@
+//! Debouncing for mechanical switches wired to the link port.
+//!
+//! A [Debouncer] is fed 1 sample at a time, usually once per frame (or from a
+//! timer interrupt for faster response), and only reports a new level once
+//! that many samples in a row agree.
+
+use super::edge::Edge;
+use super::GeneralPurpose;
+use crate::serial::Pin;
+
+/// Filters out the bouncing of a single pin.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Debouncer {
+    pin: Pin,
+    samples: u8,
+    stable: bool,
+    streak: u8,
+}
+
+impl Debouncer {
+    /// Debounces `pin`, which must read the same level for `samples` samples
+    /// in a row before the change is reported. `initial` is the level assumed
+    /// before the first sample.
+    pub const fn new(pin: Pin, samples: u8, initial: bool) -> Self {
+        Self {
+            pin,
+            samples: if samples == 0 { 1 } else { samples },
+            stable: initial,
+            streak: 0,
+        }
+    }
+    pub const fn pin(&self) -> Pin {
+        self.pin
+    }
+    /// The last level that was held for long enough.
+    pub const fn level(&self) -> bool {
+        self.stable
+    }
+    /// Feeds in a single sample of the pin, returning the edge if this sample
+    /// made a new level stable.
+    pub fn update(&mut self, sample: bool) -> Option<Edge> {
+        if sample == self.stable {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < self.samples {
+            return None;
+        }
+        self.streak = 0;
+        let edge = Edge::between(self.stable, sample);
+        self.stable = sample;
+        edge
+    }
+    /// Samples the pin from `gpio`; see [Self::update].
+    pub fn poll(&mut self, gpio: &GeneralPurpose) -> Option<Edge> {
+        self.update(gpio.read_pin(self.pin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_debouncer(_gba: &mut Gba) {
+        let mut debouncer = Debouncer::new(Pin::SI, 3, true);
+        // Bouncing never holds LOW for long enough.
+        for sample in [false, true, false, false, true] {
+            assert_eq!(debouncer.update(sample), None);
+        }
+        assert!(debouncer.level());
+        assert_eq!(debouncer.update(false), None);
+        assert_eq!(debouncer.update(false), None);
+        assert_eq!(debouncer.update(false), Some(Edge::Falling));
+        assert!(!debouncer.level());
+        assert_eq!(debouncer.update(false), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Separate rising & falling edge callbacks for each GPIO pin.
+//!
+//! The hardware can only raise an interrupt for SI, so edges are found by
+//! comparing RCNT against the pin states seen last time. This happens in the
+//! serial interrupt, and whenever [GeneralPurpose::poll_edges] is called;
+//! edges on the other pins are only seen by the latter, so call it regularly
+//! (such as from a VBlank interrupt) when watching them.
+//!
+//! If a pin toggles more than once between checks only its final state is
+//! seen, so short pulses can be missed entirely.
+
+use alloc::boxed::Box;
+
+use agb::{
+    external::critical_section::{self, CriticalSection},
+    interrupt::{add_interrupt_handler, Interrupt},
+};
+
+use super::{GeneralPurpose, PinState};
+use crate::serial::{Pin, RcntWrapper};
+use crate::utils::GbaCell;
+
+/// A change in a pin's level.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Edge {
+    /// The pin went from LOW to HIGH.
+    Rising = 0,
+    /// The pin went from HIGH to LOW.
+    Falling = 1,
+}
+
+impl Edge {
+    /// The edge between 2 successive levels of a pin, if there was one.
+    pub const fn between(previous: bool, current: bool) -> Option<Self> {
+        match (previous, current) {
+            (false, true) => Some(Self::Rising),
+            (true, false) => Some(Self::Falling),
+            _ => None,
+        }
+    }
+}
+
+const PINS: [Pin; 4] = [Pin::SC, Pin::SD, Pin::SI, Pin::SO];
+
+/// Every edge between 2 successive pin states, in pin order.
+pub fn edges(previous: PinState, current: PinState) -> impl Iterator<Item = (Pin, Edge)> {
+    PINS.into_iter()
+        .filter_map(move |pin| Some((pin, Edge::between(previous.pin(pin), current.pin(pin))?)))
+}
+
+type EdgeCallback = Box<dyn Fn(CriticalSection) + Send + Sync>;
+
+/// The callbacks to call for each edge on each pin.
+#[derive(Default)]
+pub struct EdgeCallbacks {
+    callbacks: [[Option<EdgeCallback>; 2]; 4],
+}
+
+impl EdgeCallbacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the callback for `edge` on `pin`, replacing any previous one.
+    pub fn on(
+        mut self,
+        pin: Pin,
+        edge: Edge,
+        cb: impl Fn(CriticalSection) + Send + Sync + 'static,
+    ) -> Self {
+        self.callbacks[pin as usize][edge as usize] = Some(Box::new(cb));
+        self
+    }
+    fn dispatch(&self, cs: CriticalSection, previous: PinState, current: PinState) {
+        for (pin, edge) in edges(previous, current) {
+            if let Some(cb) = &self.callbacks[pin as usize][edge as usize] {
+                cb(cs);
+            }
+        }
+    }
+}
+
+static CALLBACKS: GbaCell<Option<EdgeCallbacks>> = GbaCell::new(None);
+static LAST_PINS: GbaCell<PinState> = GbaCell::new(PinState { state: 0 });
+
+/// Reads RCNT once & calls the callbacks for every edge since the last call.
+fn dispatch_edges(cs: CriticalSection) {
+    let current = PinState::from_rcnt(RcntWrapper::get().read());
+    let previous = LAST_PINS.swap_in(cs, current);
+    CALLBACKS.lock_in(cs, |callbacks| {
+        if let Some(callbacks) = callbacks {
+            callbacks.dispatch(cs, previous, current);
+        }
+    });
+}
+
+impl GeneralPurpose<'_> {
+    /// Starts calling `callbacks` on edges, replacing the callback set with
+    /// [Self::set_interrupt] and enabling the SI interrupt.
+    ///
+    /// # Safety
+    /// The callbacks **must not** allocate.
+    pub unsafe fn set_edge_callbacks(&mut self, callbacks: EdgeCallbacks) {
+        LAST_PINS.swap(self.pins());
+        CALLBACKS.swap(Some(callbacks));
+        self.interrupt_handle = Some(add_interrupt_handler(Interrupt::Serial, dispatch_edges));
+        self.enable_interrupt(true);
+    }
+    /// Stops calling the edge callbacks.
+    pub fn clear_edge_callbacks(&mut self) {
+        self.enable_interrupt(false);
+        self.interrupt_handle = None;
+        CALLBACKS.swap(None);
+    }
+    /// Checks every pin for edges since the last check, calling their
+    /// callbacks. Does NOT block.
+    pub fn poll_edges(&self) {
+        critical_section::with(dispatch_edges);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_edges(_gba: &mut Gba) {
+        assert_eq!(Edge::between(false, true), Some(Edge::Rising));
+        assert_eq!(Edge::between(true, true), None);
+
+        let previous = PinState::default().with_sc(true).with_si(true);
+        let current = PinState::default().with_sd(true).with_si(true);
+        let found: Vec<_> = edges(previous, current).collect();
+        assert_eq!(found, [(Pin::SC, Edge::Falling), (Pin::SD, Edge::Rising)]);
+    }
+}
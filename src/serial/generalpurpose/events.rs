@@ -0,0 +1,186 @@
+//! A queue of timestamped pin changes, recorded from the SI interrupt so that
+//! main-loop code can process bursts of changes without missing any.
+//!
+//! Like [edge callbacks](super::edge), changes are found by comparing RCNT
+//! against the last pin states seen, so changes on pins other than SI are
+//! only recorded alongside an SI interrupt or a call to
+//! [PinEventQueue::sample].
+
+use agb::{
+    external::critical_section::{self, CriticalSection},
+    interrupt::{add_interrupt_handler, Interrupt, InterruptHandler},
+    timer::{Divider, Timer},
+};
+
+use super::edge::{edges, Edge};
+use super::{GeneralPurpose, PinState};
+use crate::serial::ringbuf::Ringbuffer;
+use crate::serial::{Pin, RcntWrapper};
+use crate::utils::GbaCell;
+
+/// A single change in a pin's level.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PinEvent {
+    pub pin: Pin,
+    pub edge: Edge,
+    /// The timer's value when the change was seen, in ticks of the divider
+    /// given to [PinEventQueue::new]; this wraps after [u16::MAX].
+    pub timestamp: u16,
+}
+
+impl Default for PinEvent {
+    fn default() -> Self {
+        Self {
+            pin: Pin::SC,
+            edge: Edge::Rising,
+            timestamp: 0,
+        }
+    }
+}
+
+/// The timer used for timestamps & the pin states seen last.
+#[derive(Default)]
+struct EventSource {
+    timer: Option<Timer>,
+    last: PinState,
+}
+
+static SOURCE: GbaCell<EventSource> = GbaCell::new(EventSource {
+    timer: None,
+    last: PinState { state: 0 },
+});
+static EVENTS: GbaCell<Ringbuffer<PinEvent>> = GbaCell::new(Ringbuffer::empty());
+/// The number of events dropped because the queue was full.
+static DROPPED: GbaCell<u32> = GbaCell::new(0);
+
+/// Reads RCNT once & queues an event for every change since the last call.
+fn record_events(cs: CriticalSection) {
+    let current = PinState::from_rcnt(RcntWrapper::get().read());
+    let Some((timestamp, previous)) = SOURCE.lock_mut_in(cs, |source| {
+        let timestamp = source.timer.as_ref()?.value();
+        Some((timestamp, core::mem::replace(&mut source.last, current)))
+    }) else {
+        return;
+    };
+    let dropped = EVENTS.lock_in(cs, |events| {
+        queue_events(events, cs, previous, current, timestamp)
+    });
+    if dropped > 0 {
+        DROPPED.swap_in(cs, DROPPED.get_copy_in(cs).saturating_add(dropped));
+    }
+}
+
+/// Pushes an event for every edge between 2 pin states, returning the number
+/// that didn't fit.
+fn queue_events(
+    events: &Ringbuffer<PinEvent>,
+    cs: CriticalSection,
+    previous: PinState,
+    current: PinState,
+    timestamp: u16,
+) -> u32 {
+    let mut dropped = 0;
+    for (pin, edge) in edges(previous, current) {
+        let event = PinEvent {
+            pin,
+            edge,
+            timestamp,
+        };
+        if events.push(event, cs).is_err() {
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+/// Records pin changes in the background.
+pub struct PinEventQueue<'a> {
+    gpio: GeneralPurpose<'a>,
+    _interrupt: InterruptHandler,
+}
+
+impl<'a> PinEventQueue<'a> {
+    /// Starts recording up to `capacity` unread events, timestamped with
+    /// `timer` ticking at the rate set by `divider`.
+    pub fn new(
+        mut gpio: GeneralPurpose<'a>,
+        mut timer: Timer,
+        divider: Divider,
+        capacity: usize,
+    ) -> Self {
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_interrupt(false)
+            .set_divider(divider)
+            .set_overflow_amount(0)
+            .set_enabled(true);
+        EVENTS.swap(Ringbuffer::new(capacity));
+        DROPPED.swap(0);
+        SOURCE.swap(EventSource {
+            timer: Some(timer),
+            last: gpio.pins(),
+        });
+        // #SAFETY
+        //
+        // The interrupt doesn't allocate.
+        let interrupt = unsafe { add_interrupt_handler(Interrupt::Serial, record_events) };
+        gpio.enable_interrupt(true);
+        Self {
+            gpio,
+            _interrupt: interrupt,
+        }
+    }
+    /// Checks every pin for changes now, such as from a VBlank interrupt to
+    /// catch changes on pins other than SI. Does NOT block.
+    pub fn sample(&self) {
+        critical_section::with(record_events);
+    }
+    /// Takes every event recorded so far, oldest first, including any that
+    /// arrive while iterating.
+    pub fn poll_events(&mut self) -> impl Iterator<Item = PinEvent> + '_ {
+        core::iter::from_fn(|| {
+            critical_section::with(|cs| EVENTS.lock_in(cs, |events| events.pop(cs)))
+        })
+    }
+    /// The number of events dropped because the queue was full, since the
+    /// last call.
+    pub fn take_dropped(&mut self) -> u32 {
+        DROPPED.swap(0)
+    }
+    /// Stops recording, handing back the GPIO port & timer.
+    pub fn stop(self) -> (GeneralPurpose<'a>, Timer) {
+        let Self { mut gpio, .. } = self;
+        gpio.enable_interrupt(false);
+        EVENTS.swap(Ringbuffer::empty());
+        let mut timer = SOURCE
+            .swap(EventSource::default())
+            .timer
+            .expect("only `stop` takes the event timer");
+        timer.set_enabled(false);
+        (gpio, timer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_queue_events(_gba: &mut Gba) {
+        let events = Ringbuffer::new(2);
+        let previous = PinState::default().with_sc(true);
+        let current = PinState::default().with_sd(true).with_so(true);
+        critical_section::with(|cs| {
+            assert_eq!(queue_events(&events, cs, previous, current, 7), 1);
+            let first = events.pop(cs).unwrap();
+            assert_eq!(
+                (first.pin, first.edge, first.timestamp),
+                (Pin::SC, Edge::Falling, 7)
+            );
+            assert_eq!(events.pop(cs).map(|event| event.pin), Some(Pin::SD));
+            assert_eq!(events.pop(cs), None);
+        });
+    }
+}
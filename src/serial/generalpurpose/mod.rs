@@ -1,5 +1,20 @@
 //! The GBA allows the serial port to be used as a 4-pin GPIO parallel port,
 //! which each pin being able to be used as either an input or an output.
+//!
+//! Rising & falling edges on each pin can also be watched with
+//! [GeneralPurpose::set_edge_callbacks] or queued up with timestamps as
+//! [events], and [pulse] widths & frequencies can be measured. The pins can
+//! also bit-bang a [UART](softuart), an [SPI master](softspi), an
+//! [I2C master](softi2c) or a [parallel bus](nibble) between 2 GBAs.
+//!
+//! For type-checked access to single pins, [GeneralPurpose::split] the port
+//! into [pins]; switches wired to them can be cleaned up with a
+//! [Debouncer](debounce::Debouncer). To see what the pins are actually doing,
+//! a [logic analyzer](analyzer) can capture & log a trace of them.
+//!
+//! Dropping a [GeneralPurpose] restores the RCNT & SIOCNT registers to what
+//! they were before it was created, so GPIO mode can be borrowed briefly
+//! without losing another mode's configuration.
 
 use core::marker::PhantomData;
 
@@ -8,10 +23,24 @@ use agb::{
     interrupt::{add_interrupt_handler, Interrupt, InterruptHandler},
 };
 
-use crate::utils::{read_bit_u8, write_bit_u8};
+use crate::utils::{read_bit_u8, write_bit_u8, VBlankTimeout};
+
+use edge::Edge;
 
 use super::*;
 
+pub mod analyzer;
+pub mod debounce;
+pub mod edge;
+pub mod events;
+pub mod nibble;
+pub mod pins;
+pub mod pulse;
+pub mod softi2c;
+pub mod softspi;
+pub mod softuart;
+mod timing;
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
 #[repr(u8)]
 pub enum GpioDirection {
@@ -39,14 +68,22 @@ impl GpioDirection {
 pub struct GeneralPurpose<'a> {
     _handle: PhantomData<&'a mut Serial>,
     interrupt_handle: Option<InterruptHandler>,
+    /// The value of RCNT before switching to GPIO mode.
+    prior_rcnt: u16,
+    /// The value of SIOCNT before switching to GPIO mode.
+    prior_siocnt: u16,
 }
 
 impl<'a> GeneralPurpose<'a> {
     pub fn new(_handle: &'a mut Serial) -> Self {
+        let prior_rcnt = RcntWrapper::get().read();
+        let prior_siocnt = SiocntWrapper::get().read();
         RcntWrapper::get().set_mode(SerialMode::Gpio);
         Self {
             _handle: PhantomData,
             interrupt_handle: None,
+            prior_rcnt,
+            prior_siocnt,
         }
     }
     pub fn gpio_config(&self) -> GpioConfig {
@@ -91,12 +128,110 @@ impl<'a> GeneralPurpose<'a> {
     pub fn read_pin(&self, pin: Pin) -> bool {
         RcntWrapper::get().read_bit(pin as u8)
     }
+    /// Busy-waits until `pin` reads `high`, for up to `timeout_frames` frames
+    /// (or forever, if `None`).
+    ///
+    /// Returns `false` if the timeout expired first.
+    pub fn wait_for_level(&self, pin: Pin, high: bool, timeout_frames: Option<u32>) -> bool {
+        let mut timeout = VBlankTimeout::new(timeout_frames);
+        while self.read_pin(pin) != high {
+            if timeout.expired() {
+                return false;
+            }
+        }
+        true
+    }
+    /// Busy-waits for `edge` on `pin`, for up to `timeout_frames` frames (or
+    /// forever, if `None`). An edge that happened before the call is ignored.
+    ///
+    /// Returns `false` if the timeout expired first.
+    pub fn wait_for_edge(&self, pin: Pin, edge: Edge, timeout_frames: Option<u32>) -> bool {
+        let mut timeout = VBlankTimeout::new(timeout_frames);
+        let mut previous = self.read_pin(pin);
+        loop {
+            let current = self.read_pin(pin);
+            if Edge::between(previous, current) == Some(edge) {
+                return true;
+            }
+            previous = current;
+            if timeout.expired() {
+                return false;
+            }
+        }
+    }
+    /// Makes a single pin an output driving `high`.
+    ///
+    /// The level & direction are written together, since reading an input
+    /// pin gives the line's level rather than the level it would drive.
+    pub fn set_output(&mut self, pin: Pin, high: bool) {
+        make_output(pin, high)
+    }
+    /// Makes a single pin an input.
+    pub fn set_input(&mut self, pin: Pin) {
+        make_input(pin)
+    }
+
+    /// Drives `pin` LOW like an open-drain output, by making it an output.
+    pub fn pull_low(&mut self, pin: Pin) {
+        make_output(pin, false)
+    }
+    /// Lets go of `pin` like an open-drain output, by making it an input so
+    /// that a pull-up resistor (or another device) decides its level.
+    pub fn release(&mut self, pin: Pin) {
+        make_input(pin)
+    }
+    /// Writes `pin` like an open-drain output, which can only ever pull its
+    /// line LOW; `high` releases it instead.
+    ///
+    /// Unlike [Self::write_pin] this never fights another device on a shared
+    /// line, such as a multi-master bus or a line between 2 GBAs.
+    pub fn write_open_drain(&mut self, pin: Pin, high: bool) {
+        if high {
+            self.release(pin)
+        } else {
+            self.pull_low(pin)
+        }
+    }
 
     pub fn state(&self) -> GpioState {
         GpioState::from_rcnt(RcntWrapper::get().read())
     }
 }
 
+impl Drop for GeneralPurpose<'_> {
+    fn drop(&mut self) {
+        self.enable_interrupt(false);
+        self.interrupt_handle = None;
+        // SIOCNT only takes effect once RCNT leaves GPIO mode, so it has to be
+        // restored first.
+        SiocntWrapper::get().write(restorable_siocnt(self.prior_siocnt));
+        RcntWrapper::get().write(self.prior_rcnt);
+    }
+}
+
+/// Clears the start bit of a saved SIOCNT value in Normal & Multiplayer mode,
+/// so restoring it doesn't kick off a new transfer; any transfer that was
+/// interrupted by switching to GPIO mode is lost.
+const fn restorable_siocnt(siocnt: u16) -> u16 {
+    let is_uart = read_bit(siocnt, 12) && read_bit(siocnt, 13);
+    if is_uart {
+        siocnt
+    } else {
+        write_bit(siocnt, 7, false)
+    }
+}
+
+/// Makes `pin` an output driving `high`; see [GeneralPurpose::set_output].
+fn make_output(pin: Pin, high: bool) {
+    let rcnt = RcntWrapper::get();
+    let value = write_bit(rcnt.read(), pin as u8, high);
+    rcnt.write(write_bit(value, pin as u8 + 4, true));
+}
+
+fn make_input(pin: Pin) {
+    RcntWrapper::get().write_bit(pin as u8 + 4, false)
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
 pub struct GpioConfig {
     value: u8,
@@ -132,6 +267,15 @@ impl GpioConfig {
         let value = write_bit_u8(self.value, 7, dir.is_output());
         Self { value }
     }
+    /// Gets the direction of a single pin.
+    pub const fn pin(&self, pin: Pin) -> GpioDirection {
+        GpioDirection::from_is_output(read_bit_u8(self.value, pin as u8 + 4))
+    }
+    /// Sets the direction of a single pin.
+    pub const fn with_pin(self, pin: Pin, dir: GpioDirection) -> Self {
+        let value = write_bit_u8(self.value, pin as u8 + 4, dir.is_output());
+        Self { value }
+    }
     const fn from_rcnt(value: u16) -> Self {
         Self {
             value: (value & Self::MASK) as u8,
@@ -188,6 +332,10 @@ impl PinState {
     pub const fn so(&self) -> bool {
         read_bit_u8(self.state, 3)
     }
+    /// Gets the state of a single pin.
+    pub const fn pin(&self, pin: Pin) -> bool {
+        read_bit_u8(self.state, pin as u8)
+    }
     pub fn set_so(&mut self, value: bool) {
         self.state = write_bit_u8(self.state, 3, value);
     }
@@ -218,3 +366,17 @@ impl GpioState {
         self.value as u16
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_restorable_siocnt(_gba: &mut Gba) {
+        // Multiplayer mode with a transfer in progress.
+        assert_eq!(restorable_siocnt(0x2083), 0x2003);
+        // Bit 7 is the data length in UART mode.
+        assert_eq!(restorable_siocnt(0x3C87), 0x3C87);
+    }
+}
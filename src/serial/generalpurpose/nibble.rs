@@ -0,0 +1,218 @@
+//! A simple parallel bus between 2 GBAs, with data on 3 pins & a strobe on the
+//! 4th, for turn-based exchanges that need very little latency per word.
+//!
+//! The sender puts the next 3 bits on the data pins, then toggles the strobe;
+//! the receiver latches the data pins whenever it sees the strobe change. Each
+//! `u16` is sent as [SYMBOLS_PER_WORD] of these 3-bit symbols, most
+//! significant first.
+//!
+//! There's no line left over for the receiver to acknowledge anything, so:
+//! * The sender paces symbols with a hardware timer, and the receiver has to
+//!   be busy-waiting in [NibbleBus::receive] to keep up.
+//! * Only 1 side may send at a time. Between sends every pin is an input, and
+//!   the sender waits [TURNAROUND_SYMBOLS] symbol periods before its first
+//!   symbol so that the other side has time to start receiving.
+//!
+//! The official link cable doesn't connect every pin, so this needs a
+//! homemade cable; crossed wiring can be handled by giving each side a
+//! different [NibblePins].
+
+use agb::timer::Timer;
+
+use super::timing::CycleTimer;
+use super::GeneralPurpose;
+use crate::serial::Pin;
+use crate::utils::{VBlankTimeout, CPU_HZ};
+
+/// The number of 3-bit symbols needed to send a single `u16`.
+pub const SYMBOLS_PER_WORD: usize = 6;
+/// How many symbol periods the sender idles for before its first symbol.
+pub const TURNAROUND_SYMBOLS: u16 = 4;
+
+/// Which pin carries each signal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NibblePins {
+    pub strobe: Pin,
+    /// The data pins, least significant bit first.
+    pub data: [Pin; 3],
+}
+
+impl NibblePins {
+    /// Whether no pin is used for more than 1 signal.
+    pub const fn is_valid(&self) -> bool {
+        let used = (1u8 << self.strobe as u8)
+            | (1 << self.data[0] as u8)
+            | (1 << self.data[1] as u8)
+            | (1 << self.data[2] as u8);
+        used.count_ones() == 4
+    }
+}
+
+impl Default for NibblePins {
+    fn default() -> Self {
+        Self {
+            strobe: Pin::SC,
+            data: [Pin::SD, Pin::SI, Pin::SO],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NibbleBusError {
+    /// The same pin was given for more than 1 signal.
+    PinConflict,
+    /// The symbol rate is too slow for the timer, or too fast to toggle the
+    /// pins at.
+    InvalidRate,
+    /// The timeout expired after receiving this many whole words.
+    Timeout(usize),
+}
+
+/// Splits a word into the symbols to send, most significant first.
+const fn split_word(word: u16) -> [u8; SYMBOLS_PER_WORD] {
+    let mut symbols = [0; SYMBOLS_PER_WORD];
+    let mut idx = 0;
+    while idx < SYMBOLS_PER_WORD {
+        let shift = 3 * (SYMBOLS_PER_WORD - 1 - idx);
+        symbols[idx] = ((word as u32 >> shift) & 0b111) as u8;
+        idx += 1;
+    }
+    symbols
+}
+
+/// Rebuilds a word from the symbols given by [split_word].
+const fn join_word(symbols: [u8; SYMBOLS_PER_WORD]) -> u16 {
+    let mut word = 0u32;
+    let mut idx = 0;
+    while idx < SYMBOLS_PER_WORD {
+        word = (word << 3) | (symbols[idx] & 0b111) as u32;
+        idx += 1;
+    }
+    word as u16
+}
+
+pub struct NibbleBus<'a> {
+    gpio: GeneralPurpose<'a>,
+    timer: CycleTimer,
+    pins: NibblePins,
+    symbol_cycles: u16,
+}
+
+impl<'a> NibbleBus<'a> {
+    /// Makes every pin an input & starts pacing sends at `symbol_hz` symbols
+    /// per second with `timer`.
+    ///
+    /// Both sides must use the same rate. The receiver needs a few hundred
+    /// cycles to notice each symbol, so rates much above 50kHz are unreliable.
+    pub fn new(
+        mut gpio: GeneralPurpose<'a>,
+        timer: Timer,
+        pins: NibblePins,
+        symbol_hz: u32,
+    ) -> Result<Self, NibbleBusError> {
+        if !pins.is_valid() {
+            return Err(NibbleBusError::PinConflict);
+        }
+        let symbol_cycles = CPU_HZ / symbol_hz.max(1);
+        if symbol_cycles == 0 || symbol_cycles > u16::MAX as u32 {
+            return Err(NibbleBusError::InvalidRate);
+        }
+        gpio.set_input(pins.strobe);
+        for pin in pins.data {
+            gpio.set_input(pin);
+        }
+        Ok(Self {
+            gpio,
+            timer: CycleTimer::new(timer),
+            pins,
+            symbol_cycles: symbol_cycles as u16,
+        })
+    }
+    /// Sends every word in `words`, blocking until the last symbol has been
+    /// held for a full period & every pin is an input again.
+    pub fn send(&mut self, words: &[u16]) {
+        let NibblePins { strobe, data } = self.pins;
+        // Take over the strobe without changing its level.
+        let mut strobe_level = self.gpio.read_pin(strobe);
+        self.gpio.set_output(strobe, strobe_level);
+        for pin in data {
+            self.gpio.set_output(pin, false);
+        }
+        self.timer.restart();
+        for _ in 0..TURNAROUND_SYMBOLS {
+            self.timer.wait(self.symbol_cycles);
+        }
+        for symbol in words.iter().flat_map(|&word| split_word(word)) {
+            for (idx, pin) in data.into_iter().enumerate() {
+                self.gpio.write_pin(pin, symbol & (1 << idx) != 0);
+            }
+            strobe_level = !strobe_level;
+            self.gpio.write_pin(strobe, strobe_level);
+            self.timer.wait(self.symbol_cycles);
+        }
+        self.gpio.set_input(strobe);
+        for pin in data {
+            self.gpio.set_input(pin);
+        }
+    }
+    /// Fills `buffer` with words from the other side, busy-waiting for up to
+    /// `timeout_frames` frames (or forever, if `None`) for all of them.
+    ///
+    /// This must be called before the other side starts sending.
+    pub fn receive(
+        &mut self,
+        buffer: &mut [u16],
+        timeout_frames: Option<u32>,
+    ) -> Result<(), NibbleBusError> {
+        let NibblePins { strobe, data } = self.pins;
+        let mut timeout = VBlankTimeout::new(timeout_frames);
+        let mut strobe_level = self.gpio.read_pin(strobe);
+        for (received, word) in buffer.iter_mut().enumerate() {
+            let mut symbols = [0; SYMBOLS_PER_WORD];
+            for symbol in symbols.iter_mut() {
+                let state = loop {
+                    // Read every pin at once, so the data matches the strobe.
+                    let state = self.gpio.pins();
+                    if state.pin(strobe) != strobe_level {
+                        break state;
+                    }
+                    if timeout.expired() {
+                        return Err(NibbleBusError::Timeout(received));
+                    }
+                };
+                strobe_level = !strobe_level;
+                *symbol = data
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, pin)| (state.pin(pin) as u8) << idx)
+                    .sum();
+            }
+            *word = join_word(symbols);
+        }
+        Ok(())
+    }
+    /// Hands back the GPIO port & timer.
+    pub fn into_inner(self) -> (GeneralPurpose<'a>, Timer) {
+        (self.gpio, self.timer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_symbols(_gba: &mut Gba) {
+        assert_eq!(split_word(0xBEEF), [1, 3, 7, 3, 5, 7]);
+        for word in [0, 1, 0x8000, 0xBEEF, u16::MAX] {
+            assert_eq!(join_word(split_word(word)), word);
+        }
+        assert!(NibblePins::default().is_valid());
+        let doubled = NibblePins {
+            strobe: Pin::SD,
+            ..Default::default()
+        };
+        assert!(!doubled.is_valid());
+    }
+}
@@ -0,0 +1,237 @@
+//! Typestate handles for the individual GPIO pins, so that reading an output
+//! or driving an input is a compile error.
+//!
+//! [GeneralPurpose::split] hands out an [InputPin] for each pin, which can be
+//! turned into an [OutputPin] or an [OpenDrainPin] (and back) by value. With
+//! the `embedded-hal` feature each implements the matching
+//! [embedded_hal::digital] traits.
+
+use core::marker::PhantomData;
+
+use super::{make_input, make_output, GeneralPurpose, RcntWrapper};
+use crate::serial::Pin;
+
+/// A pin configured as an input.
+pub struct InputPin<'g> {
+    pin: Pin,
+    _gpio: PhantomData<&'g mut ()>,
+}
+
+/// A pin configured as an output.
+pub struct OutputPin<'g> {
+    pin: Pin,
+    _gpio: PhantomData<&'g mut ()>,
+}
+
+/// A pin emulating an open-drain output, which can be read while released;
+/// see [GeneralPurpose::write_open_drain].
+pub struct OpenDrainPin<'g> {
+    pin: Pin,
+    _gpio: PhantomData<&'g mut ()>,
+}
+
+/// Every GPIO pin, as returned by [GeneralPurpose::split].
+pub struct Pins<'g> {
+    pub sc: InputPin<'g>,
+    pub sd: InputPin<'g>,
+    pub si: InputPin<'g>,
+    pub so: InputPin<'g>,
+}
+
+impl<'g> InputPin<'g> {
+    fn new(pin: Pin) -> Self {
+        make_input(pin);
+        Self {
+            pin,
+            _gpio: PhantomData,
+        }
+    }
+    /// Which physical pin this is.
+    pub fn pin(&self) -> Pin {
+        self.pin
+    }
+    pub fn is_high(&self) -> bool {
+        RcntWrapper::get().read_bit(self.pin as u8)
+    }
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+    /// Makes the pin an output driving `high`.
+    pub fn into_output(self, high: bool) -> OutputPin<'g> {
+        OutputPin::new(self.pin, high)
+    }
+    /// Makes the pin an open-drain output, released if `high`.
+    pub fn into_open_drain(self, high: bool) -> OpenDrainPin<'g> {
+        OpenDrainPin::new(self.pin, high)
+    }
+}
+
+impl<'g> OutputPin<'g> {
+    fn new(pin: Pin, high: bool) -> Self {
+        make_output(pin, high);
+        Self {
+            pin,
+            _gpio: PhantomData,
+        }
+    }
+    /// Which physical pin this is.
+    pub fn pin(&self) -> Pin {
+        self.pin
+    }
+    pub fn set_level(&mut self, high: bool) {
+        RcntWrapper::get().write_bit(self.pin as u8, high)
+    }
+    pub fn set_high(&mut self) {
+        self.set_level(true)
+    }
+    pub fn set_low(&mut self) {
+        self.set_level(false)
+    }
+    pub fn toggle(&mut self) {
+        self.set_level(!self.is_set_high())
+    }
+    /// Whether the pin is currently driving HIGH.
+    pub fn is_set_high(&self) -> bool {
+        RcntWrapper::get().read_bit(self.pin as u8)
+    }
+    /// Makes the pin an input.
+    pub fn into_input(self) -> InputPin<'g> {
+        InputPin::new(self.pin)
+    }
+    /// Makes the pin an open-drain output, released if `high`.
+    pub fn into_open_drain(self, high: bool) -> OpenDrainPin<'g> {
+        OpenDrainPin::new(self.pin, high)
+    }
+}
+
+impl<'g> OpenDrainPin<'g> {
+    fn new(pin: Pin, high: bool) -> Self {
+        let mut out = Self {
+            pin,
+            _gpio: PhantomData,
+        };
+        out.set_level(high);
+        out
+    }
+    /// Which physical pin this is.
+    pub fn pin(&self) -> Pin {
+        self.pin
+    }
+    /// Pulls the line LOW, or releases it if `high`.
+    pub fn set_level(&mut self, high: bool) {
+        if high {
+            make_input(self.pin)
+        } else {
+            make_output(self.pin, false)
+        }
+    }
+    /// Releases the line.
+    pub fn set_high(&mut self) {
+        self.set_level(true)
+    }
+    /// Pulls the line LOW.
+    pub fn set_low(&mut self) {
+        self.set_level(false)
+    }
+    /// Whether the pin is currently released.
+    pub fn is_set_high(&self) -> bool {
+        !RcntWrapper::get().read_bit(self.pin as u8 + 4)
+    }
+    /// The line's actual level, which another device may be pulling LOW even
+    /// while this pin is released.
+    pub fn is_high(&self) -> bool {
+        RcntWrapper::get().read_bit(self.pin as u8)
+    }
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+    /// Makes the pin an input.
+    pub fn into_input(self) -> InputPin<'g> {
+        InputPin::new(self.pin)
+    }
+}
+
+impl GeneralPurpose<'_> {
+    /// Splits the port into a handle per pin, making every pin an input.
+    pub fn split(&mut self) -> Pins<'_> {
+        Pins {
+            sc: InputPin::new(Pin::SC),
+            sd: InputPin::new(Pin::SD),
+            si: InputPin::new(Pin::SI),
+            so: InputPin::new(Pin::SO),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+mod hal {
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::{self, ErrorType, StatefulOutputPin};
+
+    use super::{InputPin, OpenDrainPin, OutputPin};
+
+    impl ErrorType for InputPin<'_> {
+        type Error = Infallible;
+    }
+    impl digital::InputPin for InputPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(InputPin::is_high(self))
+        }
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(InputPin::is_low(self))
+        }
+    }
+
+    impl ErrorType for OutputPin<'_> {
+        type Error = Infallible;
+    }
+    impl digital::OutputPin for OutputPin<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            OutputPin::set_low(self);
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            OutputPin::set_high(self);
+            Ok(())
+        }
+    }
+    impl StatefulOutputPin for OutputPin<'_> {
+        fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(OutputPin::is_set_high(self))
+        }
+        fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!OutputPin::is_set_high(self))
+        }
+    }
+
+    impl ErrorType for OpenDrainPin<'_> {
+        type Error = Infallible;
+    }
+    impl digital::InputPin for OpenDrainPin<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(OpenDrainPin::is_high(self))
+        }
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(OpenDrainPin::is_low(self))
+        }
+    }
+    impl digital::OutputPin for OpenDrainPin<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            OpenDrainPin::set_low(self);
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            OpenDrainPin::set_high(self);
+            Ok(())
+        }
+    }
+    impl StatefulOutputPin for OpenDrainPin<'_> {
+        fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(OpenDrainPin::is_set_high(self))
+        }
+        fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!OpenDrainPin::is_set_high(self))
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Measuring pulse widths & frequencies on input pins, for reading external
+//! sensors or decoding simple remote signals.
+//!
+//! [PulseCapture] timestamps every SI interrupt with a free-running hardware
+//! timer, giving the period (and so the frequency) of a signal on SI without
+//! blocking. It can also measure each pulse's width from the interrupt; see
+//! [PulseCapture::measure_widths]. [GeneralPurpose::measure_pulse] instead
+//! blocks while timing a single pulse on any pin.
+//!
+//! The hardware only interrupts on 1 edge of SI, so widths are timed by
+//! polling SI from inside the interrupt until it changes back, which holds up
+//! every other interrupt (and the game) for up to the pulse's width.
+//!
+//! Times are measured in timer ticks, whose length depends on the [Divider]
+//! used; anything longer than [u16::MAX] ticks wraps around, so pick a divider
+//! slow enough for the longest expected period.
+
+use agb::{
+    external::critical_section::{self, CriticalSection},
+    interrupt::{add_interrupt_handler, Interrupt, InterruptHandler},
+    timer::{Divider, Timer},
+};
+
+use super::GeneralPurpose;
+use crate::serial::{Pin, RcntWrapper};
+use crate::utils::{GbaCell, CPU_HZ};
+
+/// The number of CPU cycles in a single tick of a timer using `divider`.
+pub const fn divider_cycles(divider: Divider) -> u32 {
+    match divider {
+        Divider::Divider1 => 1,
+        Divider::Divider64 => 64,
+        Divider::Divider256 => 256,
+        Divider::Divider1024 => 1024,
+    }
+}
+
+/// Statistics about a series of measured times, such as the periods between
+/// successive SI interrupts.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PulseStats {
+    /// The number of periods measured.
+    pub count: u32,
+    /// The most recent period, in ticks.
+    pub last: u16,
+    /// The shortest period, in ticks.
+    pub min: u16,
+    /// The longest period, in ticks.
+    pub max: u16,
+    total: u32,
+}
+
+impl Default for PulseStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PulseStats {
+    pub const fn new() -> Self {
+        Self {
+            count: 0,
+            last: 0,
+            min: u16::MAX,
+            max: 0,
+            total: 0,
+        }
+    }
+    fn record(&mut self, ticks: u16) {
+        self.count = self.count.saturating_add(1);
+        self.last = ticks;
+        self.min = self.min.min(ticks);
+        self.max = self.max.max(ticks);
+        self.total = self.total.saturating_add(ticks as u32);
+    }
+    /// The mean period in ticks, or `None` if nothing was measured.
+    pub const fn average(&self) -> Option<u32> {
+        match self.count {
+            0 => None,
+            count => Some(self.total / count),
+        }
+    }
+    /// The mean frequency in Hz, given the divider of the timer used.
+    pub const fn frequency_hz(&self, divider: Divider) -> Option<u32> {
+        match self.average() {
+            Some(0) | None => None,
+            Some(ticks) => Some(CPU_HZ / (ticks * divider_cycles(divider))),
+        }
+    }
+}
+
+/// The timer being used for capturing & the tick of the last interrupt.
+#[derive(Default)]
+struct CaptureState {
+    timer: Option<Timer>,
+    last: Option<u16>,
+    /// The longest pulse width to wait for in the interrupt, or 0 to not
+    /// measure widths.
+    max_width: u16,
+}
+
+static CAPTURE: GbaCell<CaptureState> = GbaCell::new(CaptureState {
+    timer: None,
+    last: None,
+    max_width: 0,
+});
+/// The periods between SI interrupts.
+static STATS: GbaCell<PulseStats> = GbaCell::new(PulseStats::new());
+/// The widths of the pulses starting at each SI interrupt.
+static WIDTHS: GbaCell<PulseStats> = GbaCell::new(PulseStats::new());
+
+fn capture_interrupt(cs: CriticalSection) {
+    CAPTURE.lock_mut_in(cs, |state| {
+        let Some(timer) = state.timer.as_ref() else {
+            return;
+        };
+        let now = timer.value();
+        if let Some(last) = state.last.replace(now) {
+            STATS.lock_mut_in(cs, |stats| stats.record(now.wrapping_sub(last)));
+        }
+        if state.max_width == 0 {
+            return;
+        }
+        let rcnt = RcntWrapper::get();
+        let level = rcnt.read_bit(Pin::SI as u8);
+        while rcnt.read_bit(Pin::SI as u8) == level {
+            if timer.value().wrapping_sub(now) >= state.max_width {
+                return;
+            }
+        }
+        let width = timer.value().wrapping_sub(now);
+        WIDTHS.lock_mut_in(cs, |widths| widths.record(width));
+    });
+}
+
+/// Measures the period of the signal on SI in the background.
+pub struct PulseCapture<'a> {
+    gpio: GeneralPurpose<'a>,
+    divider: Divider,
+    _interrupt: InterruptHandler,
+}
+
+impl<'a> PulseCapture<'a> {
+    /// Makes SI an input & starts timestamping SI interrupts with `timer`,
+    /// ticking at the rate set by `divider`.
+    pub fn new(mut gpio: GeneralPurpose<'a>, mut timer: Timer, divider: Divider) -> Self {
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_interrupt(false)
+            .set_divider(divider)
+            .set_overflow_amount(0)
+            .set_enabled(true);
+        gpio.set_input(Pin::SI);
+        STATS.swap(PulseStats::new());
+        WIDTHS.swap(PulseStats::new());
+        CAPTURE.swap(CaptureState {
+            timer: Some(timer),
+            last: None,
+            max_width: 0,
+        });
+        // #SAFETY
+        //
+        // The interrupt doesn't allocate.
+        let interrupt = unsafe { add_interrupt_handler(Interrupt::Serial, capture_interrupt) };
+        gpio.enable_interrupt(true);
+        Self {
+            gpio,
+            divider,
+            _interrupt: interrupt,
+        }
+    }
+    /// The statistics measured so far.
+    pub fn stats(&self) -> PulseStats {
+        STATS.get_copy()
+    }
+    /// Starts (or stops, if `max_ticks` is 0) measuring the width of the
+    /// pulse that starts at each SI interrupt, in ticks.
+    ///
+    /// The interrupt polls SI until it changes back, giving up on pulses
+    /// longer than `max_ticks`; see the [module-level docs](self). Each pulse
+    /// is timed from when the interrupt runs rather than from the edge itself,
+    /// so widths come out short by the interrupt's latency.
+    pub fn measure_widths(&mut self, max_ticks: u16) {
+        CAPTURE.lock_mut(|state| state.max_width = max_ticks);
+    }
+    /// The pulse widths measured so far; see [Self::measure_widths].
+    pub fn widths(&self) -> PulseStats {
+        WIDTHS.get_copy()
+    }
+    /// The mean frequency of the signal so far, in Hz.
+    pub fn frequency_hz(&self) -> Option<u32> {
+        self.stats().frequency_hz(self.divider)
+    }
+    /// Clears the statistics, so that the next interrupt starts a new period.
+    pub fn reset(&mut self) {
+        critical_section::with(|cs| {
+            STATS.swap_in(cs, PulseStats::new());
+            WIDTHS.swap_in(cs, PulseStats::new());
+            CAPTURE.lock_mut_in(cs, |state| state.last = None);
+        });
+    }
+    /// Stops capturing, handing back the GPIO port & timer.
+    pub fn stop(self) -> (GeneralPurpose<'a>, Timer) {
+        let Self { mut gpio, .. } = self;
+        gpio.enable_interrupt(false);
+        let mut timer = CAPTURE
+            .swap(CaptureState::default())
+            .timer
+            .expect("only `stop` takes the capture timer");
+        timer.set_enabled(false);
+        (gpio, timer)
+    }
+}
+
+impl GeneralPurpose<'_> {
+    /// Waits for `pin` to reach the given level, then times how long it stays
+    /// there, blocking throughout.
+    ///
+    /// `timer` is run with `divider` & disabled again before this returns.
+    /// Returns the pulse's width in ticks, or `None` if the pulse didn't both
+    /// start & end within `timeout` ticks.
+    pub fn measure_pulse(
+        &self,
+        pin: Pin,
+        high: bool,
+        timer: &mut Timer,
+        divider: Divider,
+        timeout: u16,
+    ) -> Option<u16> {
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_interrupt(false)
+            .set_divider(divider)
+            .set_overflow_amount(0)
+            .set_enabled(true);
+        let start = timer.value();
+        let within = |timer: &Timer| timer.value().wrapping_sub(start) < timeout;
+        let mut result = None;
+        // Wait for the current pulse (if any) to end, then the next to start.
+        while self.read_pin(pin) == high && within(timer) {}
+        while self.read_pin(pin) != high && within(timer) {}
+        let pulse_start = timer.value();
+        while self.read_pin(pin) == high && within(timer) {}
+        if within(timer) {
+            result = Some(timer.value().wrapping_sub(pulse_start));
+        }
+        timer.set_enabled(false);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_pulse_stats(_gba: &mut Gba) {
+        let mut stats = PulseStats::new();
+        assert_eq!(stats.average(), None);
+        for ticks in [256, 250, 262] {
+            stats.record(ticks);
+        }
+        assert_eq!(stats.count, 3);
+        assert_eq!((stats.min, stats.max, stats.last), (250, 262, 262));
+        assert_eq!(stats.average(), Some(256));
+        // 256 ticks of 64 cycles each is 1/1024th of a second.
+        assert_eq!(stats.frequency_hz(Divider::Divider64), Some(1024));
+    }
+}
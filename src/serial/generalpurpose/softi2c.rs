@@ -0,0 +1,237 @@
+//! A bit-banged I2C master, usually on SC (as SCL) & SD (as SDA), so that
+//! cheap I2C peripherals can be attached to the link port.
+//!
+//! Both lines are treated as open-drain (see
+//! [GeneralPurpose::write_open_drain]), so the bus's pull-up resistors bring
+//! released lines HIGH. The pull-ups must be provided externally.
+//!
+//! Devices that need more time can hold SCL LOW (clock stretching); the
+//! master waits up to [MAX_STRETCH_PERIODS] clock periods for SCL to be
+//! released before giving up.
+
+use agb::timer::Timer;
+
+use super::timing::CycleTimer;
+use super::GeneralPurpose;
+use crate::serial::Pin;
+use crate::utils::CPU_HZ;
+
+/// The standard-mode I2C clock.
+pub const STANDARD_HZ: u32 = 100_000;
+/// The fast-mode I2C clock.
+pub const FAST_HZ: u32 = 400_000;
+/// How many clock periods a device can stretch the clock for before
+/// [SoftI2cError::ClockStretchTimeout].
+pub const MAX_STRETCH_PERIODS: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SoftI2cError {
+    /// The same pin was given for both SCL & SDA.
+    PinConflict,
+    /// The clock is too slow for the timer, or faster than the pins can be
+    /// toggled.
+    InvalidClock,
+    /// The address doesn't fit in 7 bits.
+    InvalidAddress,
+    /// No device acknowledged the address.
+    AddressNack,
+    /// The device didn't acknowledge the data byte at this index.
+    DataNack(usize),
+    /// A device held SCL LOW for too long.
+    ClockStretchTimeout,
+}
+
+/// The first byte of a transaction with the device at `address`.
+const fn address_byte(address: u8, read: bool) -> Result<u8, SoftI2cError> {
+    if address > 0x7F {
+        return Err(SoftI2cError::InvalidAddress);
+    }
+    Ok((address << 1) | read as u8)
+}
+
+pub struct SoftI2c<'a> {
+    gpio: GeneralPurpose<'a>,
+    timer: CycleTimer,
+    scl: Pin,
+    sda: Pin,
+    half_period: u16,
+}
+
+impl<'a> SoftI2c<'a> {
+    /// Releases both lines & starts timing the clock at `hz` with `timer`.
+    pub fn new(
+        mut gpio: GeneralPurpose<'a>,
+        timer: Timer,
+        scl: Pin,
+        sda: Pin,
+        hz: u32,
+    ) -> Result<Self, SoftI2cError> {
+        if scl == sda {
+            return Err(SoftI2cError::PinConflict);
+        }
+        let half_period = CPU_HZ / hz.max(1) / 2;
+        if half_period == 0 || half_period > u16::MAX as u32 {
+            return Err(SoftI2cError::InvalidClock);
+        }
+        gpio.release(scl);
+        gpio.release(sda);
+        Ok(Self {
+            gpio,
+            timer: CycleTimer::new(timer),
+            scl,
+            sda,
+            half_period: half_period as u16,
+        })
+    }
+
+    /// Writes `data` to the device at `address`.
+    pub fn write(&mut self, address: u8, data: &[u8]) -> Result<(), SoftI2cError> {
+        let result = self.write_inner(address, data);
+        self.finish(result)
+    }
+    /// Reads enough bytes to fill `buffer` from the device at `address`.
+    pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), SoftI2cError> {
+        let result = self.read_inner(address, buffer);
+        self.finish(result)
+    }
+    /// Writes `data` to the device at `address`, then reads enough bytes to
+    /// fill `buffer` after a repeated start, such as for reading a register.
+    pub fn write_read(
+        &mut self,
+        address: u8,
+        data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), SoftI2cError> {
+        let result = self
+            .write_inner(address, data)
+            .and_then(|_| self.read_inner(address, buffer));
+        self.finish(result)
+    }
+    /// Checks whether any device acknowledges `address`.
+    pub fn probe(&mut self, address: u8) -> Result<bool, SoftI2cError> {
+        match self.write(address, &[]) {
+            Ok(()) => Ok(true),
+            Err(SoftI2cError::AddressNack) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Hands back the GPIO port & timer.
+    pub fn into_inner(self) -> (GeneralPurpose<'a>, Timer) {
+        (self.gpio, self.timer.into_inner())
+    }
+
+    fn write_inner(&mut self, address: u8, data: &[u8]) -> Result<(), SoftI2cError> {
+        let address = address_byte(address, false)?;
+        self.start()?;
+        if !self.write_byte(address)? {
+            return Err(SoftI2cError::AddressNack);
+        }
+        for (idx, &byte) in data.iter().enumerate() {
+            if !self.write_byte(byte)? {
+                return Err(SoftI2cError::DataNack(idx));
+            }
+        }
+        Ok(())
+    }
+    fn read_inner(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), SoftI2cError> {
+        let address = address_byte(address, true)?;
+        self.start()?;
+        if !self.write_byte(address)? {
+            return Err(SoftI2cError::AddressNack);
+        }
+        let len = buffer.len();
+        for (idx, slot) in buffer.iter_mut().enumerate() {
+            // The last byte is NACKed to tell the device we're done.
+            *slot = self.read_byte(idx + 1 < len)?;
+        }
+        Ok(())
+    }
+    /// Sends a stop condition no matter how the transaction went.
+    fn finish(&mut self, result: Result<(), SoftI2cError>) -> Result<(), SoftI2cError> {
+        let stopped = self.stop();
+        result.and(stopped)
+    }
+
+    fn delay(&mut self) {
+        self.timer.wait(self.half_period);
+    }
+    /// Releases SCL & waits for any device stretching the clock to let go.
+    fn release_scl(&mut self) -> Result<(), SoftI2cError> {
+        self.gpio.release(self.scl);
+        self.timer.restart();
+        let mut periods = 0;
+        while !self.gpio.read_pin(self.scl) {
+            if self.timer.elapsed() >= self.half_period.saturating_mul(2) {
+                self.timer.restart();
+                periods += 1;
+                if periods >= MAX_STRETCH_PERIODS {
+                    return Err(SoftI2cError::ClockStretchTimeout);
+                }
+            }
+        }
+        self.timer.restart();
+        Ok(())
+    }
+
+    /// Sends a (possibly repeated) start condition, leaving SCL LOW.
+    fn start(&mut self) -> Result<(), SoftI2cError> {
+        self.gpio.release(self.sda);
+        self.release_scl()?;
+        self.delay();
+        self.gpio.pull_low(self.sda);
+        self.delay();
+        self.gpio.pull_low(self.scl);
+        Ok(())
+    }
+    /// Sends a stop condition, leaving both lines released.
+    fn stop(&mut self) -> Result<(), SoftI2cError> {
+        self.gpio.pull_low(self.sda);
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.gpio.release(self.sda);
+        self.delay();
+        Ok(())
+    }
+    /// Clocks a single bit out (or in, when `bit` is HIGH & the device drives
+    /// SDA), returning the level of SDA while SCL was HIGH.
+    fn clock_bit(&mut self, bit: bool) -> Result<bool, SoftI2cError> {
+        self.gpio.write_open_drain(self.sda, bit);
+        self.delay();
+        self.release_scl()?;
+        let level = self.gpio.read_pin(self.sda);
+        self.delay();
+        self.gpio.pull_low(self.scl);
+        Ok(level)
+    }
+    /// Sends a byte, returning whether the device acknowledged it.
+    fn write_byte(&mut self, byte: u8) -> Result<bool, SoftI2cError> {
+        for idx in (0..8).rev() {
+            self.clock_bit(byte & (1 << idx) != 0)?;
+        }
+        Ok(!self.clock_bit(true)?)
+    }
+    /// Receives a byte, acknowledging it if `ack` is set.
+    fn read_byte(&mut self, ack: bool) -> Result<u8, SoftI2cError> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.clock_bit(true)? as u8;
+        }
+        self.clock_bit(!ack)?;
+        Ok(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_address_byte(_gba: &mut Gba) {
+        assert_eq!(address_byte(0x50, false), Ok(0xA0));
+        assert_eq!(address_byte(0x50, true), Ok(0xA1));
+        assert_eq!(address_byte(0x80, true), Err(SoftI2cError::InvalidAddress));
+    }
+}
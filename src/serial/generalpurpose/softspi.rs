@@ -0,0 +1,291 @@
+//! A bit-banged SPI master on the GPIO pins, for wiring external sensors or
+//! flash chips to the link port.
+//!
+//! Any pin can be used for any signal, and chip select is optional so that
+//! all 4 pins aren't needed for devices without one. Bytes are shifted MSB
+//! first. By default the clock runs as fast as the CPU can toggle the pins;
+//! [SoftSpi::set_clock] slows it down for devices that can't keep up.
+
+use agb::timer::Timer;
+
+use super::timing::CycleTimer;
+use super::GeneralPurpose;
+use crate::serial::Pin;
+use crate::utils::CPU_HZ;
+
+/// The clock polarity & phase, using the standard SPI mode numbers.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum SpiMode {
+    /// Clock idles LOW; data is sampled on the rising edge.
+    #[default]
+    Mode0 = 0,
+    /// Clock idles LOW; data is sampled on the falling edge.
+    Mode1 = 1,
+    /// Clock idles HIGH; data is sampled on the falling edge.
+    Mode2 = 2,
+    /// Clock idles HIGH; data is sampled on the rising edge.
+    Mode3 = 3,
+}
+
+impl SpiMode {
+    /// The level the clock idles at (CPOL).
+    pub const fn idle_high(self) -> bool {
+        matches!(self, Self::Mode2 | Self::Mode3)
+    }
+    /// Whether data is sampled on the second clock edge of each bit (CPHA).
+    pub const fn sample_second_edge(self) -> bool {
+        matches!(self, Self::Mode1 | Self::Mode3)
+    }
+}
+
+/// Which pin carries each SPI signal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SpiPins {
+    pub sck: Pin,
+    pub mosi: Pin,
+    pub miso: Pin,
+    /// The active-LOW chip select, if the device has one.
+    pub cs: Option<Pin>,
+}
+
+impl SpiPins {
+    /// Whether no pin is used for more than 1 signal.
+    pub const fn is_valid(&self) -> bool {
+        let mut used = (1u8 << self.sck as u8) | (1 << self.mosi as u8) | (1 << self.miso as u8);
+        let mut count = 3;
+        if let Some(cs) = self.cs {
+            used |= 1 << cs as u8;
+            count += 1;
+        }
+        used.count_ones() == count
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SoftSpiError {
+    /// The same pin was given for more than 1 signal.
+    PinConflict,
+    /// The clock is too slow for the timer, or faster than the pins can be
+    /// toggled.
+    InvalidClock,
+}
+
+/// A single action while shifting a bit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Step {
+    /// Drive the clock to this level.
+    Clock(bool),
+    /// Drive MOSI to this level.
+    Data(bool),
+    /// Wait half a clock period.
+    Delay,
+    /// Read MISO.
+    Sample,
+}
+
+/// Exchanges `out` for a byte, driving the pins through `step`, which returns
+/// the level of MISO for [Step::Sample] & is ignored otherwise.
+fn shift_byte(mode: SpiMode, out: u8, mut step: impl FnMut(Step) -> bool) -> u8 {
+    let idle = mode.idle_high();
+    let mut retvl = 0;
+    for idx in (0..8).rev() {
+        let bit = out & (1 << idx) != 0;
+        let sampled = if mode.sample_second_edge() {
+            step(Step::Clock(!idle));
+            step(Step::Data(bit));
+            step(Step::Delay);
+            step(Step::Clock(idle));
+            let sampled = step(Step::Sample);
+            step(Step::Delay);
+            sampled
+        } else {
+            step(Step::Data(bit));
+            step(Step::Delay);
+            step(Step::Clock(!idle));
+            let sampled = step(Step::Sample);
+            step(Step::Delay);
+            step(Step::Clock(idle));
+            sampled
+        };
+        retvl |= (sampled as u8) << idx;
+    }
+    retvl
+}
+
+pub struct SoftSpi<'a> {
+    gpio: GeneralPurpose<'a>,
+    pins: SpiPins,
+    mode: SpiMode,
+    clock: Option<(CycleTimer, u16)>,
+}
+
+impl<'a> SoftSpi<'a> {
+    /// Sets up the pins for `mode`, with the clock idling & the device
+    /// deselected.
+    pub fn new(
+        mut gpio: GeneralPurpose<'a>,
+        pins: SpiPins,
+        mode: SpiMode,
+    ) -> Result<Self, SoftSpiError> {
+        if !pins.is_valid() {
+            return Err(SoftSpiError::PinConflict);
+        }
+        gpio.set_output(pins.sck, mode.idle_high());
+        gpio.set_output(pins.mosi, false);
+        gpio.set_input(pins.miso);
+        if let Some(cs) = pins.cs {
+            gpio.set_output(cs, true);
+        }
+        Ok(Self {
+            gpio,
+            pins,
+            mode,
+            clock: None,
+        })
+    }
+    /// Switches to a different mode, moving the clock to its new idle level.
+    pub fn set_mode(&mut self, mode: SpiMode) {
+        self.mode = mode;
+        self.gpio.write_pin(self.pins.sck, mode.idle_high());
+    }
+    /// Slows the clock down to at most `hz`, timed with `timer`, returning the
+    /// timer used previously if there was one.
+    pub fn set_clock(&mut self, timer: Timer, hz: u32) -> Result<Option<Timer>, SoftSpiError> {
+        let half_period = CPU_HZ / hz.max(1) / 2;
+        if half_period == 0 || half_period > u16::MAX as u32 {
+            return Err(SoftSpiError::InvalidClock);
+        }
+        let previous = self.clock.take().map(|(timer, _)| timer.into_inner());
+        self.clock = Some((CycleTimer::new(timer), half_period as u16));
+        Ok(previous)
+    }
+    /// Goes back to running the clock as fast as possible, returning the
+    /// timer that was in use (if any).
+    pub fn clear_clock(&mut self) -> Option<Timer> {
+        self.clock.take().map(|(timer, _)| timer.into_inner())
+    }
+
+    /// Drives chip select, if there is one; `true` selects the device.
+    pub fn select(&mut self, selected: bool) {
+        if let Some(cs) = self.pins.cs {
+            self.gpio.write_pin(cs, !selected);
+        }
+    }
+    /// Exchanges a single byte, without touching chip select.
+    pub fn transfer_byte(&mut self, out: u8) -> u8 {
+        let Self {
+            gpio,
+            pins,
+            mode,
+            clock,
+        } = self;
+        if let Some((timer, _)) = clock {
+            timer.restart();
+        }
+        shift_byte(*mode, out, |step| match step {
+            Step::Clock(level) => {
+                gpio.write_pin(pins.sck, level);
+                false
+            }
+            Step::Data(level) => {
+                gpio.write_pin(pins.mosi, level);
+                false
+            }
+            Step::Delay => {
+                if let Some((timer, half_period)) = clock {
+                    timer.wait(*half_period);
+                }
+                false
+            }
+            Step::Sample => gpio.read_pin(pins.miso),
+        })
+    }
+    /// Selects the device, replaces every byte in `data` with the byte
+    /// received while sending it, then deselects the device.
+    pub fn transfer(&mut self, data: &mut [u8]) {
+        self.select(true);
+        for byte in data {
+            *byte = self.transfer_byte(*byte);
+        }
+        self.select(false);
+    }
+    /// Selects the device, sends `data` while ignoring what comes back, then
+    /// deselects the device.
+    pub fn write(&mut self, data: &[u8]) {
+        self.select(true);
+        for &byte in data {
+            self.transfer_byte(byte);
+        }
+        self.select(false);
+    }
+
+    /// Hands back the GPIO port, and the timer if one was in use.
+    pub fn into_inner(mut self) -> (GeneralPurpose<'a>, Option<Timer>) {
+        let timer = self.clear_clock();
+        (self.gpio, timer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    /// Shifts `out` into a simulated device that answers with `answer`,
+    /// returning the byte each side received.
+    fn simulate(mode: SpiMode, out: u8, answer: u8) -> (u8, u8) {
+        let mut clock = mode.idle_high();
+        let mut mosi = false;
+        let mut device_in = 0u8;
+        let mut device_out = answer;
+        let mut edges = 0;
+        let received = shift_byte(mode, out, |step| {
+            match step {
+                Step::Clock(level) if level != clock => {
+                    clock = level;
+                    edges += 1;
+                    // The device samples on the same edge as the master, and
+                    // shifts its next bit out on the other one; its first bit
+                    // is already out before the first edge.
+                    let sample_edge = if mode.sample_second_edge() { 0 } else { 1 };
+                    if edges % 2 == sample_edge {
+                        device_in = (device_in << 1) | mosi as u8;
+                    } else if edges > 1 {
+                        device_out <<= 1;
+                    }
+                }
+                Step::Data(level) => mosi = level,
+                _ => {}
+            }
+            device_out & 0x80 != 0
+        });
+        (received, device_in)
+    }
+
+    #[test_case]
+    fn test_spi_shifting(_gba: &mut Gba) {
+        for mode in [
+            SpiMode::Mode0,
+            SpiMode::Mode1,
+            SpiMode::Mode2,
+            SpiMode::Mode3,
+        ] {
+            assert_eq!(simulate(mode, 0xA5, 0x3C), (0x3C, 0xA5), "{mode:?}");
+        }
+
+        let pins = SpiPins {
+            sck: Pin::SC,
+            mosi: Pin::SO,
+            miso: Pin::SI,
+            cs: Some(Pin::SD),
+        };
+        assert!(pins.is_valid());
+        assert!(!SpiPins {
+            cs: Some(Pin::SC),
+            ..pins
+        }
+        .is_valid());
+        assert!(SpiPins { cs: None, ..pins }.is_valid());
+    }
+}
@@ -0,0 +1,181 @@
+//! A bit-banged UART on any 2 GPIO pins, for talking to simple external
+//! hardware that doesn't fit the hardware [UART](crate::serial::uart) pinout.
+//!
+//! Frames are 8N1: a LOW start bit, 8 data bits LSB first, and a HIGH stop
+//! bit, with the line idling HIGH. Each bit is timed with a hardware timer, so
+//! only low baud rates (up to [MAX_BAUD]) are supported; everything blocks the
+//! CPU while a byte is sent or received.
+//!
+//! Interrupts that run in the middle of a byte delay the next bit, so long
+//! interrupt handlers can corrupt bytes at higher baud rates. Wrap transfers
+//! in a critical section if that's a problem.
+
+use agb::timer::Timer;
+
+use super::timing::CycleTimer;
+use super::GeneralPurpose;
+use crate::serial::Pin;
+use crate::utils::CPU_HZ;
+
+/// The slowest baud rate supported, limited by the timer's range.
+pub const MIN_BAUD: u32 = CPU_HZ / u16::MAX as u32 + 1;
+/// The fastest baud rate supported; any faster & the time spent reading or
+/// writing a pin is too large a part of each bit.
+pub const MAX_BAUD: u32 = 57_600;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SoftUartError {
+    /// The baud rate is outside of [MIN_BAUD]..=[MAX_BAUD].
+    InvalidBaud,
+    /// The same pin was given for both TX & RX.
+    SamePin,
+    /// No start bit arrived in time.
+    Timeout,
+    /// The stop bit was LOW, so the byte is probably corrupt.
+    FrameError,
+}
+
+/// The number of CPU cycles per bit at `baud`.
+const fn bit_cycles(baud: u32) -> Result<u16, SoftUartError> {
+    if baud < MIN_BAUD || baud > MAX_BAUD {
+        return Err(SoftUartError::InvalidBaud);
+    }
+    Ok(((CPU_HZ + baud / 2) / baud) as u16)
+}
+
+/// The levels of a whole frame for `byte`, in the order they're sent.
+const fn frame_bits(byte: u8) -> [bool; 10] {
+    let mut bits = [true; 10];
+    bits[0] = false;
+    let mut idx = 0;
+    while idx < 8 {
+        bits[idx + 1] = byte & (1 << idx) != 0;
+        idx += 1;
+    }
+    bits
+}
+
+pub struct SoftUart<'a> {
+    gpio: GeneralPurpose<'a>,
+    timer: CycleTimer,
+    tx: Pin,
+    rx: Pin,
+    bit_cycles: u16,
+}
+
+impl<'a> SoftUart<'a> {
+    /// Sets `tx` as an output idling HIGH & `rx` as an input, timing bits at
+    /// `baud` with `timer`.
+    pub fn new(
+        mut gpio: GeneralPurpose<'a>,
+        timer: Timer,
+        tx: Pin,
+        rx: Pin,
+        baud: u32,
+    ) -> Result<Self, SoftUartError> {
+        if tx == rx {
+            return Err(SoftUartError::SamePin);
+        }
+        let bit_cycles = bit_cycles(baud)?;
+        gpio.set_output(tx, true);
+        gpio.set_input(rx);
+        Ok(Self {
+            gpio,
+            timer: CycleTimer::new(timer),
+            tx,
+            rx,
+            bit_cycles,
+        })
+    }
+    /// Changes the baud rate for future bytes.
+    pub fn set_baud(&mut self, baud: u32) -> Result<(), SoftUartError> {
+        self.bit_cycles = bit_cycles(baud)?;
+        Ok(())
+    }
+
+    /// Sends a single byte, blocking until its stop bit is done.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.timer.restart();
+        for bit in frame_bits(byte) {
+            self.gpio.write_pin(self.tx, bit);
+            self.timer.wait(self.bit_cycles);
+        }
+    }
+    /// Sends every byte in `bytes`.
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    /// Waits up to `timeout_bits` bit periods for a byte to start, then
+    /// receives it.
+    pub fn read_byte(&mut self, timeout_bits: u32) -> Result<u8, SoftUartError> {
+        let half_bit = self.bit_cycles / 2;
+        let mut waited = 0;
+        self.timer.restart();
+        loop {
+            if !self.gpio.read_pin(self.rx) {
+                // Check the middle of the start bit, to skip over glitches.
+                self.timer.restart();
+                self.timer.wait(half_bit);
+                if !self.gpio.read_pin(self.rx) {
+                    break;
+                }
+            }
+            if self.timer.elapsed() >= self.bit_cycles {
+                self.timer.restart();
+                waited += 1;
+                if waited >= timeout_bits {
+                    return Err(SoftUartError::Timeout);
+                }
+            }
+        }
+        let mut byte = 0;
+        for idx in 0..8 {
+            self.timer.wait(self.bit_cycles);
+            byte |= (self.gpio.read_pin(self.rx) as u8) << idx;
+        }
+        self.timer.wait(self.bit_cycles);
+        if !self.gpio.read_pin(self.rx) {
+            return Err(SoftUartError::FrameError);
+        }
+        Ok(byte)
+    }
+    /// Fills `buffer`, waiting up to `timeout_bits` bit periods for each byte
+    /// and returning how many bytes were received before any error.
+    pub fn read(&mut self, buffer: &mut [u8], timeout_bits: u32) -> (usize, Option<SoftUartError>) {
+        for (idx, slot) in buffer.iter_mut().enumerate() {
+            match self.read_byte(timeout_bits) {
+                Ok(byte) => *slot = byte,
+                Err(e) => return (idx, Some(e)),
+            }
+        }
+        (buffer.len(), None)
+    }
+
+    /// Hands back the GPIO port & timer.
+    pub fn into_inner(self) -> (GeneralPurpose<'a>, Timer) {
+        (self.gpio, self.timer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_soft_uart_framing(_gba: &mut Gba) {
+        assert_eq!(bit_cycles(9600), Ok(1748));
+        assert_eq!(bit_cycles(57_600), Ok(291));
+        assert_eq!(bit_cycles(115_200), Err(SoftUartError::InvalidBaud));
+        assert_eq!(bit_cycles(100), Err(SoftUartError::InvalidBaud));
+        assert_eq!(bit_cycles(MIN_BAUD).map(|_| ()), Ok(()));
+
+        assert_eq!(
+            frame_bits(0b1010_0011),
+            [false, true, true, false, false, false, true, false, true, true]
+        );
+    }
+}
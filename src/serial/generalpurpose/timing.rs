@@ -0,0 +1,48 @@
+//! Cycle-accurate waits for the bit-banged protocols, using a free-running
+//! hardware timer.
+
+use agb::timer::{Divider, Timer};
+
+/// Paces waits from a running timer ticking once per CPU cycle.
+///
+/// Each wait is measured from the end of the previous one rather than from
+/// when it was called, so a series of waits doesn't drift. A single wait can
+/// be at most [u16::MAX] cycles.
+pub struct CycleTimer {
+    timer: Timer,
+    last: u16,
+}
+
+impl CycleTimer {
+    /// Starts `timer` free-running.
+    pub fn new(mut timer: Timer) -> Self {
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_interrupt(false)
+            .set_divider(Divider::Divider1)
+            .set_overflow_amount(0)
+            .set_enabled(true);
+        let last = timer.value();
+        Self { timer, last }
+    }
+    /// Measures the next wait from now.
+    pub fn restart(&mut self) {
+        self.last = self.timer.value();
+    }
+    /// The cycles since the end of the last wait, wrapping after
+    /// [u16::MAX].
+    pub fn elapsed(&self) -> u16 {
+        self.timer.value().wrapping_sub(self.last)
+    }
+    /// Waits until `cycles` cycles after the end of the last wait.
+    pub fn wait(&mut self, cycles: u16) {
+        while self.elapsed() < cycles {}
+        self.last = self.last.wrapping_add(cycles);
+    }
+    /// Stops the timer & hands it back.
+    pub fn into_inner(mut self) -> Timer {
+        self.timer.set_enabled(false);
+        self.timer
+    }
+}
@@ -0,0 +1,162 @@
+//! Background streaming of fixed buffers to & from a JOY Bus host, for hosts
+//! that poll the GBA at a high rate.
+//!
+//! Despite the similar use, this does NOT use DMA: the GBA's DMA units can't
+//! be triggered by JOY Bus commands. Instead the serial interrupt moves each
+//! word directly between the user's buffers and JOY_TRANS / JOY_RECV. This
+//! skips the handlers & event dispatching used elsewhere in [super], keeping
+//! the CPU cost to a few dozen instructions per command.
+
+use core::marker::PhantomData;
+use core::ptr;
+
+use agb::external::critical_section::CriticalSection;
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+
+use crate::utils::GbaCell;
+
+use super::{JoyCnt, Joybus, JOY_RECV, JOY_TRANS, STATS};
+
+/// The in-progress background stream.
+static BACKGROUND_STATE: GbaCell<BackgroundState> = GbaCell::new(BackgroundState::empty());
+
+/// The buffers being streamed by [Joybus::transfer_background], and how far into them
+/// we are.
+struct BackgroundState {
+    send: *const u32,
+    send_len: usize,
+    sent: usize,
+    recv: *mut u32,
+    recv_len: usize,
+    received: usize,
+}
+
+/// #SAFETY
+///
+/// The pointers are only ever dereferenced by the serial interrupt while the
+/// [BackgroundTransfer] that borrows the underlying buffers is alive.
+unsafe impl Send for BackgroundState {}
+
+impl BackgroundState {
+    const fn empty() -> Self {
+        Self {
+            send: ptr::null(),
+            send_len: 0,
+            sent: 0,
+            recv: ptr::null_mut(),
+            recv_len: 0,
+            received: 0,
+        }
+    }
+}
+
+impl Default for BackgroundState {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// A handle to a stream started with [Joybus::transfer_background].
+///
+/// Dropping the handle stops the stream, even if not all words have been
+/// exchanged.
+pub struct BackgroundTransfer<'t> {
+    _buffers: PhantomData<&'t mut [u32]>,
+    _joybus: PhantomData<&'t mut Joybus<'t>>,
+    _interrupt: InterruptHandler,
+}
+
+impl BackgroundTransfer<'_> {
+    /// How many words have yet to be read by the host.
+    pub fn remaining_send(&self) -> usize {
+        BACKGROUND_STATE.lock(|state| state.send_len - state.sent)
+    }
+    /// How many words have yet to be written by the host.
+    pub fn remaining_recv(&self) -> usize {
+        BACKGROUND_STATE.lock(|state| state.recv_len - state.received)
+    }
+    /// Whether or not both buffers have been fully streamed.
+    pub fn is_done(&self) -> bool {
+        self.remaining_send() == 0 && self.remaining_recv() == 0
+    }
+    /// Blocks until both buffers have been fully streamed.
+    pub fn wait(self) {
+        while !self.is_done() {}
+    }
+}
+
+impl Drop for BackgroundTransfer<'_> {
+    fn drop(&mut self) {
+        JoyCnt::get().enable_irq(false);
+        BACKGROUND_STATE.swap(BackgroundState::empty());
+    }
+}
+
+impl<'a> Joybus<'a> {
+    /// Starts streaming the words in `send` to the host's reads, and the
+    /// host's writes into `recv`, in the background; returns a handle that can
+    /// be polled for completion.
+    ///
+    /// Unlike Normal mode the two directions are independent, so `send` and
+    /// `recv` can be different lengths. Once `send` runs out the host keeps
+    /// reading its last word; once `recv` is full further writes are left in
+    /// JOY_RECV. Any other event handler is replaced while streaming.
+    ///
+    /// # Safety
+    /// The returned [BackgroundTransfer] **must not** be leaked (eg via
+    /// [core::mem::forget]), since the interrupt would then continue accessing
+    /// the buffers after they are no longer borrowed.
+    pub unsafe fn transfer_background<'t>(
+        &'t mut self,
+        send: &'t [u32],
+        recv: &'t mut [u32],
+    ) -> BackgroundTransfer<'t> {
+        self.clear_event_handler();
+        BACKGROUND_STATE.swap(BackgroundState {
+            send: send.as_ptr(),
+            send_len: send.len(),
+            sent: 0,
+            recv: recv.as_mut_ptr(),
+            recv_len: recv.len(),
+            received: 0,
+        });
+        let joycnt = JoyCnt::get();
+        joycnt.acknowledge(joycnt.flags());
+        if let Some(&first) = send.first() {
+            JOY_TRANS.write(first);
+        }
+        let interrupt = add_interrupt_handler(Interrupt::Serial, background_interrupt_callback);
+        joycnt.enable_irq(true);
+        BackgroundTransfer {
+            _buffers: PhantomData,
+            _joybus: PhantomData,
+            _interrupt: interrupt,
+        }
+    }
+}
+
+/// The interrupt callback that stores the word the host wrote and loads the
+/// next word for it to read.
+fn background_interrupt_callback(cs: CriticalSection<'_>) {
+    let joycnt = JoyCnt::get();
+    let flags = joycnt.flags();
+    joycnt.acknowledge(flags);
+    STATS.lock_mut_in(cs, |stats| stats.record(flags));
+    BACKGROUND_STATE.lock_mut_in(cs, |state| {
+        if flags.received && state.received < state.recv_len {
+            // #SAFETY
+            //
+            // `state.received < state.recv_len`, and the buffers are kept
+            // alive by the `BackgroundTransfer` that owns this interrupt.
+            unsafe { state.recv.add(state.received).write(JOY_RECV.read()) };
+            state.received += 1;
+        }
+        if flags.sent && state.sent < state.send_len {
+            state.sent += 1;
+            if state.sent < state.send_len {
+                let next = unsafe { state.send.add(state.sent).read() };
+                JOY_TRANS.write(next);
+            }
+        }
+    });
+}
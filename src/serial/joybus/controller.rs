@@ -0,0 +1,262 @@
+//! Lets the GBA act as a GameCube controller over a [HomebrewLink] session, by
+//! turning [ButtonController] state into 8-byte reports in the GameCube's
+//! controller input layout.
+//!
+//! Since the session uses our own [protocol](super::homebrew), this only
+//! works with a host program written to read these reports, not with
+//! commercial titles.
+//!
+//! Since JOY Bus reads are 4 bytes at a time each report is sent as 2 words,
+//! first the buttons & main stick and then the C-stick & triggers; see
+//! [GcControllerReport::to_words].
+
+use agb::input::{Button, ButtonController};
+
+use super::homebrew::{HomebrewLink, HomebrewLinkEvent};
+
+/// GameCube controller buttons, laid out as in the first 2 bytes of a report.
+pub mod buttons {
+    pub const A: u16 = 1 << 8;
+    pub const B: u16 = 1 << 9;
+    pub const X: u16 = 1 << 10;
+    pub const Y: u16 = 1 << 11;
+    pub const START: u16 = 1 << 12;
+    pub const DPAD_LEFT: u16 = 1 << 0;
+    pub const DPAD_RIGHT: u16 = 1 << 1;
+    pub const DPAD_DOWN: u16 = 1 << 2;
+    pub const DPAD_UP: u16 = 1 << 3;
+    pub const Z: u16 = 1 << 4;
+    pub const R: u16 = 1 << 5;
+    pub const L: u16 = 1 << 6;
+    /// Always set by real controllers.
+    pub const ALWAYS_SET: u16 = 1 << 7;
+}
+
+/// The value of a centered stick axis.
+pub const STICK_CENTER: u8 = 0x80;
+/// How far from [STICK_CENTER] a fully tilted stick reads.
+pub const STICK_RANGE: u8 = 0x7F;
+
+/// What the GBA's D-pad controls.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum DpadMode {
+    /// The main analog stick, fully tilted in the pressed direction.
+    #[default]
+    MainStick,
+    /// The GameCube controller's own D-pad.
+    Dpad,
+}
+
+/// A single GameCube controller input report.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct GcControllerReport {
+    /// A bitmask of [buttons].
+    pub buttons: u16,
+    pub stick_x: u8,
+    pub stick_y: u8,
+    pub cstick_x: u8,
+    pub cstick_y: u8,
+    pub l_analog: u8,
+    pub r_analog: u8,
+}
+
+impl Default for GcControllerReport {
+    fn default() -> Self {
+        Self::NEUTRAL
+    }
+}
+
+impl GcControllerReport {
+    /// No buttons pressed, with both sticks centered.
+    pub const NEUTRAL: Self = Self {
+        buttons: buttons::ALWAYS_SET,
+        stick_x: STICK_CENTER,
+        stick_y: STICK_CENTER,
+        cstick_x: STICK_CENTER,
+        cstick_y: STICK_CENTER,
+        l_analog: 0,
+        r_analog: 0,
+    };
+
+    /// Maps the pressed GBA buttons onto a report.
+    ///
+    /// A, B, Start, L & R map to their GameCube equivalents (with L & R also
+    /// fully pressing the analog triggers), Select maps to Z, and the D-pad is
+    /// mapped according to `dpad`.
+    pub fn from_buttons(pressed: Button, dpad: DpadMode) -> Self {
+        let mut retvl = Self::NEUTRAL;
+        let direct = [
+            (Button::A, buttons::A),
+            (Button::B, buttons::B),
+            (Button::START, buttons::START),
+            (Button::SELECT, buttons::Z),
+            (Button::L, buttons::L),
+            (Button::R, buttons::R),
+        ];
+        for (gba, gc) in direct {
+            if pressed.contains(gba) {
+                retvl.buttons |= gc;
+            }
+        }
+        if pressed.contains(Button::L) {
+            retvl.l_analog = 0xFF;
+        }
+        if pressed.contains(Button::R) {
+            retvl.r_analog = 0xFF;
+        }
+        match dpad {
+            DpadMode::MainStick => {
+                retvl.stick_x = stick_axis(pressed, Button::LEFT, Button::RIGHT);
+                // Up is positive on the GameCube.
+                retvl.stick_y = stick_axis(pressed, Button::DOWN, Button::UP);
+            }
+            DpadMode::Dpad => {
+                let dirs = [
+                    (Button::LEFT, buttons::DPAD_LEFT),
+                    (Button::RIGHT, buttons::DPAD_RIGHT),
+                    (Button::DOWN, buttons::DPAD_DOWN),
+                    (Button::UP, buttons::DPAD_UP),
+                ];
+                for (gba, gc) in dirs {
+                    if pressed.contains(gba) {
+                        retvl.buttons |= gc;
+                    }
+                }
+            }
+        }
+        retvl
+    }
+    /// Maps the buttons currently held on `input` onto a report; see
+    /// [Self::from_buttons].
+    pub fn from_controller(input: &ButtonController, dpad: DpadMode) -> Self {
+        let all = [
+            Button::A,
+            Button::B,
+            Button::SELECT,
+            Button::START,
+            Button::RIGHT,
+            Button::LEFT,
+            Button::UP,
+            Button::DOWN,
+            Button::R,
+            Button::L,
+        ];
+        let pressed = all
+            .into_iter()
+            .filter(|button| input.is_pressed(*button))
+            .fold(Button::empty(), |acc, button| acc | button);
+        Self::from_buttons(pressed, dpad)
+    }
+
+    /// The raw 8-byte report, in the order a controller sends it.
+    pub const fn to_bytes(self) -> [u8; 8] {
+        let [hi, lo] = self.buttons.to_be_bytes();
+        [
+            hi,
+            lo,
+            self.stick_x,
+            self.stick_y,
+            self.cstick_x,
+            self.cstick_y,
+            self.l_analog,
+            self.r_analog,
+        ]
+    }
+    /// The report split into the 2 words sent over JOY Bus, each holding 4
+    /// bytes of [Self::to_bytes] with the earliest byte most significant.
+    pub const fn to_words(self) -> [u32; 2] {
+        let b = self.to_bytes();
+        [
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+        ]
+    }
+}
+
+/// Maps a pair of opposing D-pad directions onto a stick axis.
+fn stick_axis(pressed: Button, neg: Button, pos: Button) -> u8 {
+    match (pressed.contains(neg), pressed.contains(pos)) {
+        (true, false) => STICK_CENTER - STICK_RANGE,
+        (false, true) => STICK_CENTER + STICK_RANGE,
+        _ => STICK_CENTER,
+    }
+}
+
+/// Streams [GcControllerReport]s to the host over an established [HomebrewLink].
+pub struct GcController<'a> {
+    link: HomebrewLink<'a>,
+    dpad: DpadMode,
+    words: [u32; 2],
+    next: usize,
+}
+
+impl<'a> GcController<'a> {
+    pub fn new(link: HomebrewLink<'a>, dpad: DpadMode) -> Self {
+        Self {
+            link,
+            dpad,
+            words: GcControllerReport::NEUTRAL.to_words(),
+            next: 0,
+        }
+    }
+    /// Updates the report sent to the host from `input`, and loads the next
+    /// word for the host to read if possible. Should be called once per frame,
+    /// after [ButtonController::update]. Does NOT block.
+    ///
+    /// A report that's halfway sent is finished before the new one starts, so
+    /// the host never sees 2 halves of different reports. Any data words the
+    /// host writes are discarded.
+    pub fn update(&mut self, input: &ButtonController) {
+        if self.link.poll() == Some(HomebrewLinkEvent::Lost) {
+            self.next = 0;
+        }
+        if self.next == 0 {
+            self.words = GcControllerReport::from_controller(input, self.dpad).to_words();
+        }
+        if self.link.send(self.words[self.next]) {
+            self.next = (self.next + 1) % self.words.len();
+        }
+    }
+    /// Gives access to the underlying session.
+    pub fn link(&mut self) -> &mut HomebrewLink<'a> {
+        &mut self.link
+    }
+    pub fn into_inner(self) -> HomebrewLink<'a> {
+        self.link
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_report_mapping(_gba: &mut Gba) {
+        assert_eq!(
+            GcControllerReport::from_buttons(Button::empty(), DpadMode::MainStick),
+            GcControllerReport::NEUTRAL
+        );
+        assert_eq!(
+            GcControllerReport::NEUTRAL.to_words(),
+            [0x0080_8080, 0x8080_0000]
+        );
+
+        let pressed = Button::A | Button::SELECT | Button::L | Button::UP | Button::LEFT;
+        let stick = GcControllerReport::from_buttons(pressed, DpadMode::MainStick);
+        assert_eq!(
+            stick.buttons,
+            buttons::ALWAYS_SET | buttons::A | buttons::Z | buttons::L
+        );
+        assert_eq!((stick.stick_x, stick.stick_y), (0x01, 0xFF));
+        assert_eq!((stick.l_analog, stick.r_analog), (0xFF, 0));
+
+        let dpad = GcControllerReport::from_buttons(pressed, DpadMode::Dpad);
+        assert_eq!(
+            dpad.buttons,
+            stick.buttons | buttons::DPAD_UP | buttons::DPAD_LEFT
+        );
+        assert_eq!((dpad.stick_x, dpad.stick_y), (STICK_CENTER, STICK_CENTER));
+        assert_eq!(dpad.to_bytes()[..2], [0x01, 0xD9]);
+    }
+}
@@ -0,0 +1,392 @@
+//! The GBA side of a custom GameCube-to-GBA link session protocol, built on
+//! [Joybus].
+//!
+//! This is NOT the protocol used by any commercial title, and won't work with
+//! them; those each shipped their own undocumented protocol. It's a small one
+//! of our own which the GameCube (or Dolphin) side has to implement too, such
+//! as in a homebrew host program. Every step is a single 32-bit JOY Bus transfer: the host
+//! writes a word with [JoybusCommand::Write](super::JoybusCommand::Write) and
+//! reads the GBA's answer back with
+//! [JoybusCommand::Read](super::JoybusCommand::Read).
+//!
+//! | Step         | Host writes              | GBA answers
+//! | :--          | :--                      | :--
+//! | Sync         | [HOST_HELLO]             | [GBA_HELLO]
+//! | Key exchange | The host's random key    | The GBA's random key
+//! | Confirm      | [CONFIRM] ^ session key  | [CONFIRM] ^ session key rotated left by 1
+//!
+//! The session key is [session_key]`(host_key, gba_key)`. Once the session is
+//! established every data word is XORed with a keystream generated from the
+//! session key (see [Keystream]), separately for each direction, so stray
+//! writes from a host that isn't taking part in the session are ignored
+//! rather than misread. The host can restart the session at any time by
+//! writing [HOST_HELLO] again or by sending a reset command.
+//!
+//! For most uses [HomebrewSession::establish] takes care of the whole
+//! handshake, returning a [HomebrewSession] once both sides agree.
+//!
+//! Since [HOST_HELLO] is checked before unscrambling, the host should skip
+//! (or pad) any data word that would happen to scramble into it.
+
+use agb::timer::Timer;
+
+use super::{Compatibility, Joybus};
+use crate::utils::TimerTimeout;
+
+/// Written by the host to start (or restart) a session; "GCN1".
+pub const HOST_HELLO: u32 = 0x4743_4E31;
+/// The GBA's answer to [HOST_HELLO]; "GBA1".
+pub const GBA_HELLO: u32 = 0x4742_4131;
+/// Combined with the session key to confirm both sides agree on it; "OKOK".
+pub const CONFIRM: u32 = 0x4F4B_4F4B;
+
+/// Derives the session key from the keys picked by each side.
+pub const fn session_key(host_key: u32, gba_key: u32) -> u32 {
+    let mixed = host_key.rotate_left(7) ^ gba_key.wrapping_mul(0x9E37_79B9);
+    // Never let the keystream start at 0, where xorshift gets stuck.
+    if mixed == 0 {
+        CONFIRM
+    } else {
+        mixed
+    }
+}
+
+/// An xorshift32 keystream used to scramble data words once a session is
+/// established.
+///
+/// The host to GBA stream starts at the session key, while the GBA to host
+/// stream starts at the session key with its halves swapped.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Keystream {
+    state: u32,
+}
+
+impl Keystream {
+    /// The keystream for words written by the host.
+    pub const fn host_to_gba(key: u32) -> Self {
+        Self { state: key }
+    }
+    /// The keystream for words read by the host.
+    pub const fn gba_to_host(key: u32) -> Self {
+        Self {
+            state: key.rotate_left(16),
+        }
+    }
+    /// Scrambles (or unscrambles) the next word in the stream.
+    pub fn apply(&mut self, word: u32) -> u32 {
+        let retvl = word ^ self.state;
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        retvl
+    }
+}
+
+/// Where we are in the session handshake.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HomebrewLinkState {
+    /// Waiting for the host to write [HOST_HELLO].
+    WaitingForHost,
+    /// Answered the hello; waiting for the host's key.
+    KeyExchange,
+    /// Sent our key; waiting for the host to confirm the session key.
+    Confirming { key: u32 },
+    /// The session is up and data can flow.
+    Established,
+}
+
+/// Something that happened on the link, as returned by [HomebrewLink::poll].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HomebrewLinkEvent {
+    /// The handshake finished.
+    Established,
+    /// The host sent this (already unscrambled) data word.
+    Data(u32),
+    /// The host reset the device or restarted the handshake, ending the
+    /// session.
+    Lost,
+}
+
+/// The hardware-independent half of [HomebrewLink].
+struct Handshake {
+    state: HomebrewLinkState,
+    compatibility: Compatibility,
+    seed: u32,
+    key: u32,
+    rx: Keystream,
+    tx: Keystream,
+}
+
+/// What [Handshake::receive] wants done in response to a word.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Step {
+    reply: Option<u32>,
+    event: Option<HomebrewLinkEvent>,
+}
+
+impl Handshake {
+    const fn new(seed: u32, compatibility: Compatibility) -> Self {
+        Self {
+            state: HomebrewLinkState::WaitingForHost,
+            compatibility,
+            seed,
+            key: 0,
+            rx: Keystream::host_to_gba(0),
+            tx: Keystream::gba_to_host(0),
+        }
+    }
+    fn next_gba_key(&mut self) -> u32 {
+        let mut stream = Keystream::host_to_gba(self.seed | 1);
+        stream.apply(0);
+        self.seed = stream.state;
+        self.seed
+    }
+    /// Handles a reset command from the host.
+    fn host_reset(&mut self) -> Option<HomebrewLinkEvent> {
+        let mid_handshake = !matches!(
+            self.state,
+            HomebrewLinkState::WaitingForHost | HomebrewLinkState::Established
+        );
+        if mid_handshake && self.compatibility.ignores_stray_resets() {
+            return None;
+        }
+        self.reset()
+    }
+    fn reset(&mut self) -> Option<HomebrewLinkEvent> {
+        let was_established = self.state == HomebrewLinkState::Established;
+        self.state = HomebrewLinkState::WaitingForHost;
+        was_established.then_some(HomebrewLinkEvent::Lost)
+    }
+    fn receive(&mut self, word: u32) -> Step {
+        if word == HOST_HELLO {
+            let event = self.reset();
+            self.state = HomebrewLinkState::KeyExchange;
+            return Step {
+                reply: Some(GBA_HELLO),
+                event,
+            };
+        }
+        match self.state {
+            HomebrewLinkState::WaitingForHost => Step {
+                reply: None,
+                event: None,
+            },
+            HomebrewLinkState::KeyExchange => {
+                let gba_key = self.next_gba_key();
+                self.state = HomebrewLinkState::Confirming {
+                    key: session_key(word, gba_key),
+                };
+                Step {
+                    reply: Some(gba_key),
+                    event: None,
+                }
+            }
+            HomebrewLinkState::Confirming { key } if word == CONFIRM ^ key => {
+                self.state = HomebrewLinkState::Established;
+                self.key = key;
+                self.rx = Keystream::host_to_gba(key);
+                self.tx = Keystream::gba_to_host(key);
+                Step {
+                    reply: Some(CONFIRM ^ key.rotate_left(1)),
+                    event: Some(HomebrewLinkEvent::Established),
+                }
+            }
+            HomebrewLinkState::Confirming { .. } => {
+                if !self.compatibility.retries_handshake() {
+                    self.state = HomebrewLinkState::WaitingForHost;
+                }
+                Step {
+                    reply: None,
+                    event: None,
+                }
+            }
+            HomebrewLinkState::Established => Step {
+                reply: None,
+                event: Some(HomebrewLinkEvent::Data(self.rx.apply(word))),
+            },
+        }
+    }
+}
+
+/// A session with a GameCube host running the custom protocol; see the
+/// [module-level docs](self).
+pub struct HomebrewLink<'a> {
+    joybus: Joybus<'a>,
+    handshake: Handshake,
+}
+
+impl<'a> HomebrewLink<'a> {
+    /// Starts waiting for a host to begin the handshake.
+    ///
+    /// `seed` is used to pick the GBA's keys, so it should differ between
+    /// boots; the frame count when the user pressed start works well.
+    pub fn new(joybus: Joybus<'a>, seed: u32) -> Self {
+        let compatibility = joybus.compatibility();
+        Self {
+            joybus,
+            handshake: Handshake::new(seed, compatibility),
+        }
+    }
+    /// The current handshake state.
+    pub fn state(&self) -> HomebrewLinkState {
+        self.handshake.state
+    }
+    /// Whether or not the session is up.
+    pub fn is_established(&self) -> bool {
+        self.handshake.state == HomebrewLinkState::Established
+    }
+
+    /// Handles whatever the host has done since the last call, advancing the
+    /// handshake as needed. Does NOT block.
+    ///
+    /// This needs to be called at least once per host transfer, or words
+    /// written by the host will be missed.
+    pub fn poll(&mut self) -> Option<HomebrewLinkEvent> {
+        let flags = self.joybus.take_flags();
+        let mut event = None;
+        if flags.reset {
+            event = self.handshake.host_reset();
+        }
+        if let Some(word) = self.joybus.take_received() {
+            let step = self.handshake.receive(word);
+            if let Some(reply) = step.reply {
+                self.joybus.set_reply(reply);
+            }
+            event = step.event.or(event);
+        }
+        event
+    }
+    /// Loads `word` for the host's next read, returning `false` if the session
+    /// isn't up or the previous word hasn't been read yet.
+    pub fn send(&mut self, word: u32) -> bool {
+        if !self.is_established() || self.joybus.reply_pending() {
+            return false;
+        }
+        let scrambled = self.handshake.tx.apply(word);
+        self.joybus.set_reply(scrambled);
+        true
+    }
+    /// Ends the session, returning the underlying [Joybus] handle.
+    pub fn into_inner(self) -> Joybus<'a> {
+        self.joybus
+    }
+}
+
+/// The host ended a [HomebrewSession], either by resetting the device or by
+/// restarting the handshake.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SessionLost;
+
+/// A [HomebrewLink] whose handshake has completed.
+///
+/// The handshake is our own (see the [module-level docs](self)), so this
+/// only completes against a host that implements it, never a commercial
+/// title.
+pub struct HomebrewSession<'a> {
+    link: HomebrewLink<'a>,
+}
+
+impl<'a> HomebrewSession<'a> {
+    /// Runs the handshake to completion, blocking until the host has set up a
+    /// session.
+    pub fn establish(mut link: HomebrewLink<'a>) -> Self {
+        while !link.is_established() {
+            link.poll();
+        }
+        Self { link }
+    }
+    /// Like [Self::establish], but gives up after `frames` frames and returns
+    /// the link instead. `timer` is used to measure the timeout, and will be
+    /// disabled again before this returns.
+    pub fn establish_with_timeout(
+        mut link: HomebrewLink<'a>,
+        frames: u32,
+        timer: &mut Timer,
+    ) -> Result<Self, HomebrewLink<'a>> {
+        let mut timeout = TimerTimeout::new(timer, frames);
+        while !link.is_established() {
+            if timeout.expired() {
+                return Err(link);
+            }
+            link.poll();
+        }
+        Ok(Self { link })
+    }
+
+    /// The session key both sides agreed on.
+    pub fn key(&self) -> u32 {
+        self.link.handshake.key
+    }
+    /// Handles whatever the host has done since the last call, returning the
+    /// next data word if one arrived. Does NOT block.
+    ///
+    /// Once this returns [SessionLost] the session is over, and a new one
+    /// needs to be established from the link returned by [Self::into_link].
+    pub fn poll(&mut self) -> Result<Option<u32>, SessionLost> {
+        match self.link.poll() {
+            Some(HomebrewLinkEvent::Data(word)) => Ok(Some(word)),
+            Some(HomebrewLinkEvent::Lost) => Err(SessionLost),
+            _ if !self.link.is_established() => Err(SessionLost),
+            _ => Ok(None),
+        }
+    }
+    /// Loads `word` for the host's next read; see [HomebrewLink::send].
+    pub fn send(&mut self, word: u32) -> Result<bool, SessionLost> {
+        if !self.link.is_established() {
+            return Err(SessionLost);
+        }
+        Ok(self.link.send(word))
+    }
+    pub fn into_link(self) -> HomebrewLink<'a> {
+        self.link
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_handshake(_gba: &mut Gba) {
+        let mut gba = Handshake::new(1234, Compatibility::Hardware);
+        let mut step = |word| gba.receive(word);
+
+        // Junk before the hello is ignored.
+        assert_eq!(step(0xDEAD_BEEF).reply, None);
+        assert_eq!(step(HOST_HELLO).reply, Some(GBA_HELLO));
+
+        let host_key = 0x1357_9BDF;
+        let gba_key = step(host_key).reply.unwrap();
+        let key = session_key(host_key, gba_key);
+        let confirmed = step(CONFIRM ^ key);
+        assert_eq!(confirmed.reply, Some(CONFIRM ^ key.rotate_left(1)));
+        assert_eq!(confirmed.event, Some(HomebrewLinkEvent::Established));
+
+        let mut host_tx = Keystream::host_to_gba(key);
+        for data in [0, 1, 0xFFFF_FFFF, HOST_HELLO ^ 1] {
+            let event = step(host_tx.apply(data)).event;
+            assert_eq!(event, Some(HomebrewLinkEvent::Data(data)));
+        }
+
+        // Restarting the handshake drops the session.
+        let restart = step(HOST_HELLO);
+        assert_eq!(restart.event, Some(HomebrewLinkEvent::Lost));
+        let next_key = step(host_key).reply.unwrap();
+        assert_ne!(next_key, gba_key);
+        assert_eq!(step(CONFIRM).event, None);
+        assert_eq!(gba.state, HomebrewLinkState::WaitingForHost);
+
+        // Dolphin mode retries a bad confirmation & ignores resets meanwhile.
+        let mut gba = Handshake::new(1234, Compatibility::Dolphin);
+        gba.receive(HOST_HELLO);
+        let gba_key = gba.receive(host_key).reply.unwrap();
+        let key = session_key(host_key, gba_key);
+        assert_eq!(gba.receive(host_key).event, None);
+        assert_eq!(gba.host_reset(), None);
+        let confirmed = gba.receive(CONFIRM ^ key);
+        assert_eq!(confirmed.event, Some(HomebrewLinkEvent::Established));
+    }
+}
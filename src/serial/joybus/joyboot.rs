@@ -0,0 +1,300 @@
+//! Receiving a program from a GameCube using the JoyBoot protocol, the JOY Bus
+//! equivalent of multiboot.
+//!
+//! Normally this is handled by the GBA's BIOS when booting without a
+//! cartridge; this module implements the same protocol in software so that a
+//! running program can receive (for example) an update or a mini-game.
+//!
+//! All words in this module are "host words", IE as the big-endian GameCube
+//! sees them; the byte swapping needed to read & write the JOY Bus registers
+//! is done internally.
+//!
+//! # Protocol
+//! 1. The GBA loads its random session key, XORed with [SESSION_MAGIC], for
+//!    the host to read.
+//! 2. The host writes a key encoding the length of the program; see
+//!    [length_key].
+//! 3. The host writes the 0xC0-byte program header as-is.
+//! 4. The host writes the rest of the program, 1 word at a time. Each word is
+//!    XORed with the next value of the session key's
+//!    [LCG](next_session_key), the negated destination address, and
+//!    [DATA_MAGIC].
+//! 5. The host writes a CRC of the (decrypted) words from step 4, combined
+//!    with the length and encrypted the same way as a data word; see
+//!    [crc_step].
+
+use super::Joybus;
+
+/// XORed with the GBA's session key before it is sent to the host; "sedo".
+pub const SESSION_MAGIC: u32 = 0x7365_646F;
+/// XORed with every encrypted word; " by ".
+pub const DATA_MAGIC: u32 = 0x2079_6220;
+/// The multiplier of the session key's LCG; "awaK".
+pub const SESSION_MULTIPLIER: u32 = 0x6177_614B;
+/// Where the BIOS would place the program, used to encrypt each word.
+pub const LOAD_ADDRESS: u32 = 0x0200_0000;
+/// The size of the unencrypted program header.
+pub const HEADER_LEN: usize = 0xC0;
+/// The smallest program the length key can describe.
+pub const MIN_PROGRAM_LEN: usize = 0x200;
+/// The largest program the length key can describe, matching the size of
+/// EWRAM.
+pub const MAX_PROGRAM_LEN: usize = 0x4_0000;
+/// The starting value of the CRC.
+pub const CRC_SEED: u32 = 0x15A0;
+
+/// Advances the session key to the value used for the next word.
+pub const fn next_session_key(key: u32) -> u32 {
+    key.wrapping_mul(SESSION_MULTIPLIER).wrapping_add(1)
+}
+
+/// Encrypts (or decrypts) the word at `offset` bytes into the program, using
+/// an already-advanced session `key`.
+pub const fn crypt_word(word: u32, key: u32, offset: usize) -> u32 {
+    let address = LOAD_ADDRESS.wrapping_add(offset as u32);
+    word ^ key ^ address.wrapping_neg() ^ DATA_MAGIC
+}
+
+/// Folds a single data word into the running CRC.
+pub const fn crc_step(mut crc: u32, mut word: u32) -> u32 {
+    let mut bit = 0;
+    while bit < 32 {
+        crc = if (crc ^ word) & 1 != 0 {
+            (crc >> 1) ^ 0xA1C1
+        } else {
+            crc >> 1
+        };
+        word >>= 1;
+        bit += 1;
+    }
+    crc
+}
+
+/// The key the host sends to describe a program of `len` bytes, which must be
+/// a multiple of 8 between [MIN_PROGRAM_LEN] and [MAX_PROGRAM_LEN].
+pub const fn length_key(len: usize) -> u32 {
+    let size = ((len - MIN_PROGRAM_LEN) >> 3) as u32;
+    let res2 = ((size & 0x3F80) << 1) | ((size & 0x4000) << 2) | (size & 0x7F) | 0x38_0000;
+    let res3 = (((res2 >> 8) + (res2 >> 16) + res2) << 24) | res2 | 0x8080_8080;
+    let magic = if res3 & 0x200 == 0 {
+        u32::from_le_bytes(*b"Kawa")
+    } else {
+        u32::from_le_bytes(*b"sedo")
+    };
+    (res3 ^ magic).swap_bytes()
+}
+
+/// Recovers the program length from a [length_key], or `None` if `key` isn't
+/// a valid length key.
+pub const fn decode_length_key(key: u32) -> Option<usize> {
+    let res3 = key.swap_bytes();
+    let mut magic_idx = 0;
+    let magics = [u32::from_le_bytes(*b"Kawa"), u32::from_le_bytes(*b"sedo")];
+    while magic_idx < magics.len() {
+        let raw = res3 ^ magics[magic_idx];
+        let size = (raw & 0x7F) | ((raw >> 1) & 0x3F80) | ((raw >> 2) & 0x4000);
+        let len = MIN_PROGRAM_LEN + ((size as usize) << 3);
+        if len <= MAX_PROGRAM_LEN && length_key(len) == key {
+            return Some(len);
+        }
+        magic_idx += 1;
+    }
+    None
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JoyBootError {
+    /// The host's length key didn't describe a valid program length.
+    InvalidLengthKey,
+    /// The program is larger than the buffer it's being received into.
+    TooLarge(usize),
+    /// The program's CRC didn't match what the host sent.
+    ChecksumMismatch,
+    /// The host reset the device partway through the transfer.
+    Reset,
+}
+
+/// Where [Receiver] is in the transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage {
+    LengthKey,
+    Data { len: usize, offset: usize },
+    Crc { len: usize },
+    Done { len: usize },
+}
+
+/// The hardware-independent half of [JoyBoot::receive].
+struct Receiver {
+    key: u32,
+    crc: u32,
+    stage: Stage,
+}
+
+impl Receiver {
+    const fn new(session_key: u32) -> Self {
+        Self {
+            key: session_key,
+            crc: CRC_SEED,
+            stage: Stage::LengthKey,
+        }
+    }
+    /// The first word the host reads.
+    const fn greeting(&self) -> u32 {
+        self.key ^ SESSION_MAGIC
+    }
+    /// Handles the next word from the host, returning the program length once
+    /// the transfer is complete.
+    fn receive(&mut self, word: u32, buf: &mut [u8]) -> Result<Option<usize>, JoyBootError> {
+        match self.stage {
+            Stage::LengthKey => {
+                let len = decode_length_key(word).ok_or(JoyBootError::InvalidLengthKey)?;
+                if len > buf.len() {
+                    return Err(JoyBootError::TooLarge(len));
+                }
+                self.stage = Stage::Data { len, offset: 0 };
+            }
+            Stage::Data { len, offset } => {
+                let plain = if offset < HEADER_LEN {
+                    word
+                } else {
+                    self.key = next_session_key(self.key);
+                    let plain = crypt_word(word, self.key, offset);
+                    self.crc = crc_step(self.crc, plain);
+                    plain
+                };
+                buf[offset..offset + 4].copy_from_slice(&plain.to_be_bytes());
+                let offset = offset + 4;
+                self.stage = if offset < len {
+                    Stage::Data { len, offset }
+                } else {
+                    Stage::Crc { len }
+                };
+            }
+            Stage::Crc { len } => {
+                self.key = next_session_key(self.key);
+                let expected = self.crc | ((len as u32) << 16);
+                if crypt_word(word, self.key, len) != expected {
+                    return Err(JoyBootError::ChecksumMismatch);
+                }
+                self.stage = Stage::Done { len };
+                return Ok(Some(len));
+            }
+            Stage::Done { len } => return Ok(Some(len)),
+        }
+        Ok(None)
+    }
+}
+
+/// Receives programs sent over JoyBoot.
+pub struct JoyBoot<'a> {
+    joybus: Joybus<'a>,
+}
+
+impl<'a> JoyBoot<'a> {
+    pub fn new(joybus: Joybus<'a>) -> Self {
+        Self { joybus }
+    }
+    /// Receives a program into `buf`, blocking until the host has sent all of
+    /// it, and returns its length.
+    ///
+    /// `session_key` should be random, such as the frame count when the user
+    /// pressed start. The received program is NOT run; it can be copied to
+    /// EWRAM & jumped to, or just treated as data.
+    pub fn receive(&mut self, session_key: u32, buf: &mut [u8]) -> Result<usize, JoyBootError> {
+        let mut receiver = Receiver::new(session_key);
+        self.joybus.take_flags();
+        self.joybus.set_reply(receiver.greeting().swap_bytes());
+        loop {
+            let compatibility = self.joybus.compatibility();
+            if self.joybus.take_flags().reset && !compatibility.ignores_stray_resets() {
+                return Err(JoyBootError::Reset);
+            }
+            let Some(word) = self.joybus.take_received() else {
+                continue;
+            };
+            match receiver.receive(word.swap_bytes(), buf) {
+                Ok(Some(len)) => return Ok(len),
+                Ok(None) => {}
+                Err(e) => {
+                    if e == JoyBootError::ChecksumMismatch {
+                        self.joybus.record_checksum_failure();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+    pub fn into_inner(self) -> Joybus<'a> {
+        self.joybus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// Encrypts `program` the way the host does.
+    fn host_words(program: &[u8], session_key: u32) -> Vec<u32> {
+        let mut retvl = vec![length_key(program.len())];
+        let mut key = session_key;
+        let mut crc = CRC_SEED;
+        for (idx, chunk) in program.chunks_exact(4).enumerate() {
+            let offset = idx * 4;
+            let plain = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            if offset < HEADER_LEN {
+                retvl.push(plain);
+            } else {
+                key = next_session_key(key);
+                crc = crc_step(crc, plain);
+                retvl.push(crypt_word(plain, key, offset));
+            }
+        }
+        key = next_session_key(key);
+        let crc = crc | ((program.len() as u32) << 16);
+        retvl.push(crypt_word(crc, key, program.len()));
+        retvl
+    }
+
+    #[test_case]
+    fn test_length_key(_gba: &mut Gba) {
+        for len in [MIN_PROGRAM_LEN, 0x208, 0x1234 * 8, MAX_PROGRAM_LEN] {
+            assert_eq!(decode_length_key(length_key(len)), Some(len));
+        }
+        assert_eq!(decode_length_key(0), None);
+    }
+
+    #[test_case]
+    fn test_receive(_gba: &mut Gba) {
+        let program: Vec<u8> = (0..0x208u32).map(|n| (n * 7) as u8).collect();
+        let session_key = 0x1234_5678;
+        let words = host_words(&program, session_key);
+
+        let mut buf = vec![0; 0x400];
+        let mut receiver = Receiver::new(session_key);
+        let (last, rest) = words.split_last().unwrap();
+        for word in rest {
+            assert_eq!(receiver.receive(*word, &mut buf), Ok(None));
+        }
+        assert_eq!(receiver.receive(*last, &mut buf), Ok(Some(program.len())));
+        assert_eq!(&buf[..program.len()], &program[..]);
+
+        let mut receiver = Receiver::new(session_key);
+        let mut corrupted = words.clone();
+        corrupted[HEADER_LEN / 4 + 3] ^= 1;
+        let result = corrupted
+            .iter()
+            .map(|word| receiver.receive(*word, &mut buf))
+            .find(|res| *res != Ok(None));
+        assert_eq!(result, Some(Err(JoyBootError::ChecksumMismatch)));
+
+        let mut small = [0; MIN_PROGRAM_LEN];
+        let mut receiver = Receiver::new(session_key);
+        assert_eq!(
+            receiver.receive(words[0], &mut small),
+            Err(JoyBootError::TooLarge(0x208))
+        );
+    }
+}
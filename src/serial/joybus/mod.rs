@@ -0,0 +1,504 @@
+//! JOY Bus mode, the protocol used by the GameCube to talk to a GBA plugged
+//! into one of its controller ports.
+//!
+//! Unlike the other modes the GBA is always the device here: the console host
+//! sends 1-byte commands, and the hardware answers them on its own using the
+//! JOY_TRANS, JOY_RECV & JOYSTAT registers. Software only finds out after the
+//! fact, via flags in JOYCNT.
+//!
+//! | Command | Name   | Host sends | GBA replies                  | JOYCNT flag
+//! | :--     | :--    | :--        | :--                          | :--
+//! | `0x00`  | Status | -          | `0x00 0x04`, JOYSTAT         | -
+//! | `0xFF`  | Reset  | -          | `0x00 0x04`, JOYSTAT         | Reset
+//! | `0x14`  | Read   | -          | JOY_TRANS (4 bytes), JOYSTAT | Send complete
+//! | `0x15`  | Write  | 4 bytes    | JOYSTAT                      | Receive complete
+
+use super::*;
+
+use agb::{
+    external::critical_section::CriticalSection,
+    interrupt::{add_interrupt_handler, Interrupt, InterruptHandler},
+};
+use core::marker::PhantomData;
+
+use crate::utils::GbaCell;
+
+pub mod background;
+pub mod controller;
+pub mod homebrew;
+pub mod joyboot;
+pub mod responder;
+pub mod rpc;
+
+/// JOY Bus control register; see [JoyCnt].
+const JOYCNT: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(0x4000140) };
+/// The last word written by the host with a [JoybusCommand::Write].
+const JOY_RECV: VolAddress<u32, Safe, Safe> = unsafe { VolAddress::new(0x4000150) };
+/// The word the host will get from its next [JoybusCommand::Read].
+const JOY_TRANS: VolAddress<u32, Safe, Safe> = unsafe { VolAddress::new(0x4000154) };
+/// The status byte sent to the host after every command.
+const JOYSTAT: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(0x4000158) };
+
+/// Counters for everything the driver has seen; see [Joybus::stats].
+static STATS: GbaCell<JoybusStats> = GbaCell::new(JoybusStats::new());
+
+/// The top-level handle for acting as a JOY Bus device.
+pub struct Joybus<'a> {
+    _handle: PhantomData<&'a mut Serial>,
+    event_interrupt: Option<InterruptHandler>,
+    compatibility: Compatibility,
+}
+
+/// Which JOY Bus implementation we expect to be talking to.
+///
+/// Dolphin's emulation of the GBA link may be laxer than real hardware, which
+/// the higher level JOY Bus drivers can work around when told to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Compatibility {
+    /// A real GameCube (or an emulator that matches it closely).
+    Hardware,
+    /// Dolphin's GBA link emulation. In this mode:
+    ///
+    /// * The receive complete flag isn't trusted, in case Dolphin delivers a
+    ///   write without setting it; [Joybus::take_flags] also checks JOYSTAT.
+    /// * Reset commands in the middle of a handshake or transfer are ignored,
+    ///   in case Dolphin re-probes devices with them while resyncing its
+    ///   emulated link.
+    /// * A wrong confirmation during the
+    ///   [HomebrewLink](homebrew::HomebrewLink) handshake is retried instead
+    ///   of restarting the handshake, in case Dolphin delivers a stale write
+    ///   first.
+    ///
+    /// These quirks are UNVERIFIED: none of them has been confirmed against
+    /// Dolphin's source or a particular Dolphin version. Each workaround only
+    /// loosens a check, so if Dolphin does behave like hardware the only cost
+    /// is recovering more slowly from a genuine reset or bad write.
+    Dolphin,
+}
+
+impl Compatibility {
+    /// [Compatibility::Dolphin] if the `dolphin` feature is enabled, otherwise
+    /// [Compatibility::Hardware].
+    pub const DEFAULT: Self = if cfg!(feature = "dolphin") {
+        Compatibility::Dolphin
+    } else {
+        Compatibility::Hardware
+    };
+
+    /// Whether or not resets should be ignored partway through a handshake or
+    /// transfer.
+    pub const fn ignores_stray_resets(self) -> bool {
+        matches!(self, Compatibility::Dolphin)
+    }
+    /// Whether or not a bad handshake step should be retried rather than
+    /// restarting the whole handshake.
+    pub const fn retries_handshake(self) -> bool {
+        matches!(self, Compatibility::Dolphin)
+    }
+}
+
+impl Default for Compatibility {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A command sent by the JOY Bus host.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JoybusCommand {
+    /// Asks for the device ID & JOYSTAT; used by the host to probe for
+    /// connected devices.
+    Status = 0x00,
+    /// Like [JoybusCommand::Status], but also tells the device to reset.
+    Reset = 0xFF,
+    /// Reads the 4 bytes in JOY_TRANS.
+    Read = 0x14,
+    /// Writes 4 bytes into JOY_RECV.
+    Write = 0x15,
+}
+
+impl JoybusCommand {
+    /// Parses a raw command byte, returning `None` for commands the GBA
+    /// doesn't respond to.
+    pub const fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(JoybusCommand::Status),
+            0xFF => Some(JoybusCommand::Reset),
+            0x14 => Some(JoybusCommand::Read),
+            0x15 => Some(JoybusCommand::Write),
+            _ => None,
+        }
+    }
+    /// The raw command byte sent over the wire.
+    pub const fn byte(self) -> u8 {
+        self as u8
+    }
+}
+
+/// A snapshot of JOYSTAT, the status byte sent to the host after every
+/// command.
+///
+/// # GBATEK Table of Bits
+/// | Bit |  Explanation          | Notes |
+/// | :-- | :--                   | :--   |
+/// | 0   | Not used              |
+/// | 1   | Receive Status Flag   | (Set when the host writes JOY_RECV, cleared when it's read) (Read Only)
+/// | 2   | Not used              |
+/// | 3   | Send Status Flag      | (Set when JOY_TRANS is written, cleared when the host reads it) (Read Only)
+/// | 4-5 | General Purpose Flag  | (Not assigned, may be used for whatever purpose)
+/// | 6-7 | Not used              |
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct JoyStat {
+    value: u16,
+}
+
+impl JoyStat {
+    /// The largest value that fits in the general purpose bits.
+    pub const GENERAL_PURPOSE_MAX: u8 = 0b11;
+
+    pub const fn from_bits(value: u16) -> Self {
+        Self { value }
+    }
+    pub const fn bits(self) -> u16 {
+        self.value
+    }
+    /// Whether the host has written a word to JOY_RECV that we haven't read
+    /// yet.
+    pub const fn receive_pending(self) -> bool {
+        read_bit(self.value, 1)
+    }
+    /// Whether the word we loaded into JOY_TRANS hasn't been read by the host
+    /// yet.
+    pub const fn send_pending(self) -> bool {
+        read_bit(self.value, 3)
+    }
+    /// The 2 general purpose bits, whose meaning is up to the application.
+    pub const fn general_purpose(self) -> u8 {
+        ((self.value >> 4) & 0b11) as u8
+    }
+    /// Replaces the general purpose bits, ignoring anything in `flags` past the
+    /// lowest 2 bits.
+    pub const fn with_general_purpose(self, flags: u8) -> Self {
+        let cleared = self.value & !(0b11 << 4);
+        Self {
+            value: cleared | (((flags & Self::GENERAL_PURPOSE_MAX) as u16) << 4),
+        }
+    }
+}
+
+/// The device ID the GBA's hardware reports in response to
+/// [JoybusCommand::Status] & [JoybusCommand::Reset].
+pub const DEVICE_ID: u16 = 0x0004;
+
+/// Something the host did, as delivered to the handler registered with
+/// [Joybus::on_event].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JoybusEvent {
+    /// The host sent a [JoybusCommand::Reset].
+    Reset,
+    /// The host sent a [JoybusCommand::Write] containing this word.
+    Received(u32),
+    /// The host sent a [JoybusCommand::Read], consuming the word loaded with
+    /// [Joybus::set_reply].
+    Sent,
+}
+
+/// Running totals of JOY Bus traffic, for debugging flaky links.
+///
+/// Commands are counted as they are noticed, either by [Joybus::take_flags]
+/// or by the [Joybus::on_event] handler; if the host sends the same command
+/// more than once in between the extra ones are missed. Status commands are
+/// answered by the hardware without telling software, so they aren't counted.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct JoybusStats {
+    /// The total number of commands received.
+    pub commands: u32,
+    /// Words written by the host.
+    pub writes: u32,
+    /// Replies read by the host.
+    pub replies: u32,
+    /// Reset commands received.
+    pub resets: u32,
+    /// Messages rejected by a higher level protocol because their checksum
+    /// didn't match.
+    pub checksum_failures: u32,
+}
+
+impl JoybusStats {
+    pub const fn new() -> Self {
+        Self {
+            commands: 0,
+            writes: 0,
+            replies: 0,
+            resets: 0,
+            checksum_failures: 0,
+        }
+    }
+    fn record(&mut self, flags: JoybusFlags) {
+        let count = |flag: bool| flag as u32;
+        self.resets = self.resets.wrapping_add(count(flags.reset));
+        self.writes = self.writes.wrapping_add(count(flags.received));
+        self.replies = self.replies.wrapping_add(count(flags.sent));
+        let total = count(flags.reset) + count(flags.received) + count(flags.sent);
+        self.commands = self.commands.wrapping_add(total);
+    }
+}
+
+/// Which commands have completed since the flags were last cleared.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct JoybusFlags {
+    /// The host sent a [JoybusCommand::Reset].
+    pub reset: bool,
+    /// The host sent a [JoybusCommand::Write], so JOY_RECV has new data.
+    pub received: bool,
+    /// The host sent a [JoybusCommand::Read], so JOY_TRANS has been consumed.
+    pub sent: bool,
+}
+
+impl JoybusFlags {
+    /// Whether or not any command has completed.
+    pub const fn any(self) -> bool {
+        self.reset || self.received || self.sent
+    }
+    const fn from_joycnt(value: u16) -> Self {
+        Self {
+            reset: read_bit(value, 0),
+            received: read_bit(value, 1),
+            sent: read_bit(value, 2),
+        }
+    }
+    const fn into_joycnt(self) -> u16 {
+        (self.reset as u16) | ((self.received as u16) << 1) | ((self.sent as u16) << 2)
+    }
+}
+
+impl<'a> Joybus<'a> {
+    /// Enters JOY Bus mode, clearing any stale command flags.
+    pub fn new(_handle: &'a mut Serial) -> Self {
+        RcntWrapper::get().set_mode(SerialMode::Joybus);
+        let joycnt = JoyCnt::get();
+        joycnt.acknowledge(joycnt.flags());
+        Self {
+            _handle: PhantomData,
+            event_interrupt: None,
+            compatibility: Compatibility::DEFAULT,
+        }
+    }
+
+    /// Retrieves and clears the flags of any commands that have completed
+    /// since the last call. Does NOT block.
+    pub fn take_flags(&mut self) -> JoybusFlags {
+        let joycnt = JoyCnt::get();
+        let mut flags = joycnt.flags();
+        joycnt.acknowledge(flags);
+        STATS.lock_mut(|stats| stats.record(flags));
+        if self.compatibility == Compatibility::Dolphin {
+            flags.received |= self.status().receive_pending();
+        }
+        flags
+    }
+
+    /// The traffic counted since startup or the last [Self::reset_stats].
+    pub fn stats(&self) -> JoybusStats {
+        STATS.get_copy()
+    }
+    /// Zeroes all of the counters in [Self::stats].
+    pub fn reset_stats(&mut self) {
+        STATS.swap(JoybusStats::new());
+    }
+    /// Counts a message rejected because of a bad checksum in
+    /// [JoybusStats::checksum_failures]; called by the higher level protocols
+    /// in this module, and available for custom ones.
+    pub fn record_checksum_failure(&mut self) {
+        STATS.lock_mut(|stats| {
+            stats.checksum_failures = stats.checksum_failures.wrapping_add(1);
+        });
+    }
+
+    /// Which JOY Bus implementation we expect to be talking to.
+    pub fn compatibility(&self) -> Compatibility {
+        self.compatibility
+    }
+    /// Changes which JOY Bus implementation we expect to be talking to, for
+    /// example after detecting that we are running under Dolphin.
+    pub fn set_compatibility(&mut self, compatibility: Compatibility) {
+        self.compatibility = compatibility;
+    }
+    /// Retrieves the word most recently written by the host, if it hasn't
+    /// already been read.
+    pub fn take_received(&mut self) -> Option<u32> {
+        // Reading JOY_RECV clears the receive status bit.
+        self.status().receive_pending().then(|| JOY_RECV.read())
+    }
+    /// Loads the word the host will get from its next
+    /// [JoybusCommand::Read].
+    pub fn set_reply(&mut self, word: u32) {
+        JOY_TRANS.write(word)
+    }
+    /// Whether or not the word loaded with [Self::set_reply] is still waiting
+    /// to be read by the host.
+    pub fn reply_pending(&self) -> bool {
+        self.status().send_pending()
+    }
+
+    /// Returns the device to its power-on state, as if no commands had been
+    /// received: clears the command flags & general purpose JOYSTAT bits,
+    /// discards any unread word from the host, and zeroes the reply.
+    pub fn reset_state(&mut self) {
+        JoyCnt::get().acknowledge(JoybusFlags {
+            reset: true,
+            received: true,
+            sent: true,
+        });
+        let _ = JOY_RECV.read();
+        JOY_TRANS.write(0);
+        self.set_general_purpose(0);
+    }
+
+    /// The current contents of JOYSTAT, as the host would see them.
+    pub fn status(&self) -> JoyStat {
+        JoyStat::from_bits(JOYSTAT.read())
+    }
+    /// Sets the general purpose JOYSTAT bits sent to the host after every
+    /// command, which applications can use to signal things like readiness.
+    /// Only the lowest 2 bits of `flags` are used.
+    pub fn set_general_purpose(&mut self, flags: u8) {
+        JOYSTAT.write(self.status().with_general_purpose(flags).bits())
+    }
+
+    /// Enables the SERIAL interrupt, which will trigger whenever the host sends
+    /// a [JoybusCommand::Reset], [JoybusCommand::Read] or
+    /// [JoybusCommand::Write].
+    pub fn enable_interrupt(&mut self, should_enable: bool) {
+        JoyCnt::get().enable_irq(should_enable)
+    }
+    /// Whether or not the SERIAL interrupt is currently enabled.
+    pub fn interrupt_enabled(&self) -> bool {
+        JoyCnt::get().irq_enabled()
+    }
+    /// Registers `cb` to be called from the SERIAL interrupt with every
+    /// [JoybusEvent], and enables the interrupt, so that there's no need to
+    /// poll [Self::take_flags] every frame.
+    ///
+    /// The flags are acknowledged by the interrupt, so [Self::take_flags] and
+    /// [Self::take_received] won't see anything while the handler is
+    /// registered. Replaces any previously registered handler.
+    ///
+    /// # Safety
+    /// The callback `cb` **must not** allocate on the heap.
+    pub unsafe fn on_event<F>(&mut self, cb: F)
+    where
+        F: Fn(CriticalSection, JoybusEvent) + Send + Sync + 'static,
+    {
+        self.event_interrupt = Some(add_interrupt_handler(Interrupt::Serial, move |cs| {
+            let joycnt = JoyCnt::get();
+            let flags = joycnt.flags();
+            joycnt.acknowledge(flags);
+            STATS.lock_mut_in(cs, |stats| stats.record(flags));
+            if flags.reset {
+                cb(cs, JoybusEvent::Reset);
+            }
+            if flags.received {
+                cb(cs, JoybusEvent::Received(JOY_RECV.read()));
+            }
+            if flags.sent {
+                cb(cs, JoybusEvent::Sent);
+            }
+        }));
+        self.enable_interrupt(true);
+    }
+    /// Disables the interrupt & removes the handler registered with
+    /// [Self::on_event], if any.
+    pub fn clear_event_handler(&mut self) {
+        self.enable_interrupt(false);
+        self.event_interrupt = None;
+    }
+}
+
+/// Newtype wrapper around the JOY Bus control register.
+///
+/// # GBATEK Table of Bits
+/// | Bit |  Explanation                   | Notes |
+/// | :-- | :--                            | :--   |
+/// | 0   | Device Reset Flag              | (Command FFh) (Read/Acknowledge)
+/// | 1   | Receive Complete Flag          | (Command 15h) (Read/Acknowledge)
+/// | 2   | Send Complete Flag             | (Command 14h) (Read/Acknowledge)
+/// | 3-5 | Not used                       |
+/// | 6   | IRQ when receiving a command   | (0=Disable, 1=Enable)
+/// | 7-15| Not used                       |
+struct JoyCnt {
+    reg: RegisterWrapper,
+}
+
+method_wraps!(JoyCnt, reg, RegisterWrapper);
+
+impl JoyCnt {
+    const fn new() -> Self {
+        Self {
+            reg: RegisterWrapper::new(JOYCNT),
+        }
+    }
+    pub const fn get() -> Self {
+        Self::new()
+    }
+    pub fn irq_enabled(&self) -> bool {
+        self.read_bit(6)
+    }
+    /// Can't use [RegisterWrapper::write_bit] here either, since writing back
+    /// a set flag would clear it.
+    pub fn enable_irq(&self, enable: bool) {
+        self.write(write_bit(0, 6, enable))
+    }
+    pub fn flags(&self) -> JoybusFlags {
+        JoybusFlags::from_joycnt(self.read())
+    }
+    /// Clears the given flags; they are cleared by writing 1 to them, so we
+    /// can't use [RegisterWrapper::write_bit] here.
+    pub fn acknowledge(&self, flags: JoybusFlags) {
+        let irq = self.read() & (1 << 6);
+        self.write(irq | flags.into_joycnt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_command_bytes(_gba: &mut Gba) {
+        for command in [
+            JoybusCommand::Status,
+            JoybusCommand::Reset,
+            JoybusCommand::Read,
+            JoybusCommand::Write,
+        ] {
+            assert_eq!(JoybusCommand::from_byte(command.byte()), Some(command));
+        }
+        assert_eq!(JoybusCommand::from_byte(0x40), None);
+
+        let flags = JoybusFlags {
+            reset: true,
+            received: false,
+            sent: true,
+        };
+        assert_eq!(flags.into_joycnt(), 0b101);
+        assert_eq!(JoybusFlags::from_joycnt(0b1000101), flags);
+        assert!(!JoybusFlags::default().any());
+
+        let mut stats = JoybusStats::new();
+        stats.record(flags);
+        stats.record(JoybusFlags {
+            received: true,
+            ..Default::default()
+        });
+        assert_eq!((stats.commands, stats.resets), (3, 1));
+        assert_eq!((stats.writes, stats.replies), (1, 1));
+
+        let stat = JoyStat::from_bits(0b1010);
+        assert!(stat.receive_pending() && stat.send_pending());
+        let stat = stat.with_general_purpose(0b110);
+        assert_eq!(stat.general_purpose(), 0b10);
+        assert_eq!(stat.bits(), 0b10_1010);
+    }
+}
@@ -0,0 +1,151 @@
+//! A JOY Bus peripheral built out of user-registered command handlers.
+//!
+//! The hardware answers [Status](JoybusCommand::Status) probes on its own, so
+//! the handlers only need to deal with the commands that move data (or reset
+//! the device):
+//!
+//! * [JoybusResponder::on_reset] for [JoybusCommand::Reset]. By default the
+//!   device's state is also reset automatically first; see
+//!   [JoybusResponder::set_auto_reset].
+//! * [JoybusResponder::on_write] for [JoybusCommand::Write], receiving the
+//!   word the host sent.
+//! * [JoybusResponder::on_read] for [JoybusCommand::Read], returning the word
+//!   the host will get from its *next* read, since the current one was
+//!   already answered by the hardware.
+//!
+//! # Examples
+//! ```
+//! let mut responder = JoybusResponder::new(Joybus::new(&mut serial));
+//! let mut counter = 0;
+//! responder.on_read(move || {
+//!     counter += 1;
+//!     counter
+//! });
+//! loop {
+//!     responder.poll();
+//!     vblank.wait_for_vblank();
+//! }
+//! ```
+
+use alloc::boxed::Box;
+
+use super::{JoyStat, Joybus, JoybusCommand};
+
+type ResetHandler<'h> = Box<dyn FnMut() + 'h>;
+type WriteHandler<'h> = Box<dyn FnMut(u32) + 'h>;
+type ReadHandler<'h> = Box<dyn FnMut() -> u32 + 'h>;
+
+pub struct JoybusResponder<'a, 'h> {
+    joybus: Joybus<'a>,
+    reset: Option<ResetHandler<'h>>,
+    write: Option<WriteHandler<'h>>,
+    read: Option<ReadHandler<'h>>,
+    auto_reset: bool,
+}
+
+impl<'a, 'h> JoybusResponder<'a, 'h> {
+    /// Creates a responder with no handlers registered; unhandled commands are
+    /// simply acknowledged.
+    pub fn new(joybus: Joybus<'a>) -> Self {
+        Self {
+            joybus,
+            reset: None,
+            write: None,
+            read: None,
+            auto_reset: true,
+        }
+    }
+    /// Registers the handler for [JoybusCommand::Reset], replacing any
+    /// previous one.
+    pub fn on_reset(&mut self, handler: impl FnMut() + 'h) -> &mut Self {
+        self.reset = Some(Box::new(handler));
+        self
+    }
+    /// Sets whether or not a [JoybusCommand::Reset] automatically resets the
+    /// device's state with [Joybus::reset_state] before the reset handler
+    /// runs, reloading the first reply from the read handler afterwards.
+    ///
+    /// This is on by default, so that the device recovers cleanly whenever the
+    /// host restarts communication. Anything the host wrote before the reset
+    /// is discarded.
+    pub fn set_auto_reset(&mut self, auto_reset: bool) -> &mut Self {
+        self.auto_reset = auto_reset;
+        self
+    }
+    /// Registers the handler for [JoybusCommand::Write], replacing any
+    /// previous one.
+    pub fn on_write(&mut self, handler: impl FnMut(u32) + 'h) -> &mut Self {
+        self.write = Some(Box::new(handler));
+        self
+    }
+    /// Registers the handler for [JoybusCommand::Read], replacing any previous
+    /// one. It is called once immediately to load the first reply.
+    pub fn on_read(&mut self, mut handler: impl FnMut() -> u32 + 'h) -> &mut Self {
+        self.joybus.set_reply(handler());
+        self.read = Some(Box::new(handler));
+        self
+    }
+
+    /// Dispatches every command that has completed since the last call to its
+    /// handler, returning the commands handled. Does NOT block.
+    ///
+    /// If the host sent the same command more than once in between calls the
+    /// handler only runs once, so this should be called at least as often as
+    /// the host polls (usually once per frame).
+    pub fn poll(&mut self) -> impl Iterator<Item = JoybusCommand> {
+        let flags = self.joybus.take_flags();
+        if flags.reset {
+            if self.auto_reset {
+                self.joybus.reset_state();
+            }
+            if let Some(handler) = self.reset.as_mut() {
+                handler();
+            }
+            if let (true, Some(handler)) = (self.auto_reset, self.read.as_mut()) {
+                let first = handler();
+                self.joybus.set_reply(first);
+            }
+        }
+        if flags.received {
+            if let Some(word) = self.joybus.take_received() {
+                if let Some(handler) = self.write.as_mut() {
+                    handler(word);
+                }
+            }
+        }
+        // After an automatic reset the first reply has already been reloaded.
+        if flags.sent && !(flags.reset && self.auto_reset) {
+            if let Some(handler) = self.read.as_mut() {
+                let next = handler();
+                self.joybus.set_reply(next);
+            }
+        }
+        [
+            (flags.reset, JoybusCommand::Reset),
+            (flags.received, JoybusCommand::Write),
+            (flags.sent, JoybusCommand::Read),
+        ]
+        .into_iter()
+        .filter_map(|(happened, command)| happened.then_some(command))
+    }
+
+    /// Sets the general purpose JOYSTAT bits sent along with every reply; see
+    /// [Joybus::set_general_purpose].
+    pub fn set_status_flags(&mut self, flags: u8) -> &mut Self {
+        self.joybus.set_general_purpose(flags);
+        self
+    }
+    /// The current JOYSTAT contents.
+    pub fn status(&self) -> JoyStat {
+        self.joybus.status()
+    }
+
+    /// Gives access to the underlying [Joybus] handle.
+    pub fn joybus(&mut self) -> &mut Joybus<'a> {
+        &mut self.joybus
+    }
+    /// Removes all handlers, returning the underlying [Joybus] handle.
+    pub fn into_inner(self) -> Joybus<'a> {
+        self.joybus
+    }
+}
@@ -0,0 +1,258 @@
+//! A small request/response RPC layer over JOY Bus, so that applications don't
+//! need to invent their own framing on top of 4-byte transfers.
+//!
+//! Every message is a header word followed by up to 255 payload words. The
+//! header holds, from most to least significant byte:
+//!
+//! | Byte | Contents
+//! | :--  | :--
+//! | 3    | [REQUEST_MAGIC] or [REPLY_MAGIC]
+//! | 2    | The command ID ([ERROR_ID] for error replies)
+//! | 1    | The number of payload words
+//! | 0    | The [checksum] of the command ID, length & payload
+//!
+//! The host writes a request with a series of
+//! [JoybusCommand::Write](super::JoybusCommand::Write)s, after which it reads
+//! the reply back with a series of
+//! [JoybusCommand::Read](super::JoybusCommand::Read)s. While no reply is ready
+//! the host reads [IDLE]. Words are as they appear in JOY_RECV & JOY_TRANS.
+//!
+//! A reply is never replaced while the host is reading it: if the host sends
+//! another request first, that request's reply is queued & sent straight
+//! after the current one, in the order the requests arrived.
+//!
+//! Failed requests get a reply with the [ERROR_ID] command ID and a single
+//! payload word holding the [RpcError] code.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::Joybus;
+
+/// The first byte of every request.
+pub const REQUEST_MAGIC: u8 = 0xA5;
+/// The first byte of every reply.
+pub const REPLY_MAGIC: u8 = 0x5A;
+/// The command ID used for error replies.
+pub const ERROR_ID: u8 = 0xFF;
+/// Read by the host while no reply is ready.
+pub const IDLE: u32 = 0;
+/// The most payload words a single message can hold.
+pub const MAX_PAYLOAD: usize = u8::MAX as usize;
+
+/// Computes the checksum byte of a message.
+pub fn checksum(id: u8, payload: &[u32]) -> u8 {
+    let sum = payload
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .fold(id.wrapping_add(payload.len() as u8), u8::wrapping_add);
+    !sum
+}
+
+/// Builds a complete message, header first.
+pub fn encode(magic: u8, id: u8, payload: &[u32]) -> impl Iterator<Item = u32> + '_ {
+    let header = u32::from_be_bytes([magic, id, payload.len() as u8, checksum(id, payload)]);
+    core::iter::once(header).chain(payload.iter().copied())
+}
+
+/// Why a request failed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RpcError {
+    /// No handler is registered for the command ID.
+    UnknownCommand = 1,
+    /// The request's checksum didn't match its contents.
+    ChecksumMismatch = 2,
+    /// The handler rejected the request.
+    Rejected = 3,
+}
+
+impl RpcError {
+    pub const fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Reassembles requests from the words written by the host.
+#[derive(Default)]
+struct RequestDecoder {
+    /// The header of the request in progress, if any.
+    header: Option<[u8; 4]>,
+    payload: Vec<u32>,
+}
+
+impl RequestDecoder {
+    /// Handles the next word, returning the command ID & checksum result once a
+    /// full request has arrived. Words outside a request that aren't a valid
+    /// header are ignored, so the decoder resyncs on the next header.
+    fn push(&mut self, word: u32) -> Option<Result<u8, RpcError>> {
+        let header = match self.header {
+            Some(header) => {
+                self.payload.push(word);
+                header
+            }
+            None => {
+                let header = word.to_be_bytes();
+                if header[0] != REQUEST_MAGIC {
+                    return None;
+                }
+                self.header = Some(header);
+                self.payload.clear();
+                header
+            }
+        };
+        let [_, id, len, sum] = header;
+        if self.payload.len() < len as usize {
+            return None;
+        }
+        self.header = None;
+        if checksum(id, &self.payload) != sum {
+            Some(Err(RpcError::ChecksumMismatch))
+        } else {
+            Some(Ok(id))
+        }
+    }
+}
+
+/// A handler for a single command, given the request payload and a buffer to
+/// fill with the reply payload (at most [MAX_PAYLOAD] words are sent).
+pub type RpcHandler<'h> = Box<dyn FnMut(&[u32], &mut Vec<u32>) -> Result<(), RpcError> + 'h>;
+
+/// Serves RPC requests from a JOY Bus host.
+pub struct RpcServer<'a, 'h> {
+    joybus: Joybus<'a>,
+    handlers: Vec<(u8, RpcHandler<'h>)>,
+    decoder: RequestDecoder,
+    /// The reply being sent, and how many words of it the host has read.
+    reply: Vec<u32>,
+    sent: usize,
+    /// Replies waiting for the host to finish reading [Self::reply].
+    queued: VecDeque<Vec<u32>>,
+}
+
+impl<'a, 'h> RpcServer<'a, 'h> {
+    pub fn new(mut joybus: Joybus<'a>) -> Self {
+        joybus.set_reply(IDLE);
+        Self {
+            joybus,
+            handlers: Vec::new(),
+            decoder: RequestDecoder::default(),
+            reply: Vec::new(),
+            sent: 0,
+            queued: VecDeque::new(),
+        }
+    }
+    /// Registers the handler for command `id`, replacing any previous one.
+    ///
+    /// [ERROR_ID] is reserved and can't be registered.
+    pub fn register(
+        &mut self,
+        id: u8,
+        handler: impl FnMut(&[u32], &mut Vec<u32>) -> Result<(), RpcError> + 'h,
+    ) -> &mut Self {
+        if id != ERROR_ID {
+            self.handlers.retain(|(existing, _)| *existing != id);
+            self.handlers.push((id, Box::new(handler)));
+        }
+        self.handlers.sort_by_key(|(id, _)| *id);
+        self
+    }
+
+    /// Handles whatever the host has done since the last call, running
+    /// handlers for any requests that have fully arrived. Does NOT block.
+    ///
+    /// This needs to be called at least once per host transfer, or words will
+    /// be missed.
+    pub fn poll(&mut self) {
+        let flags = self.joybus.take_flags();
+        if flags.reset {
+            self.decoder = RequestDecoder::default();
+            self.reply.clear();
+            self.queued.clear();
+            self.sent = 0;
+            self.joybus.set_reply(IDLE);
+        }
+        if flags.sent && self.sent < self.reply.len() {
+            self.sent += 1;
+            match self.reply.get(self.sent) {
+                Some(&next) => self.joybus.set_reply(next),
+                None => self.start_next_reply(),
+            }
+        }
+        if let Some(word) = self.joybus.take_received() {
+            if let Some(result) = self.decoder.push(word) {
+                if result == Err(RpcError::ChecksumMismatch) {
+                    self.joybus.record_checksum_failure();
+                }
+                self.dispatch(result);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, request: Result<u8, RpcError>) {
+        let mut payload = Vec::new();
+        let result = request.and_then(|id| {
+            let idx = self
+                .handlers
+                .binary_search_by_key(&id, |(id, _)| *id)
+                .map_err(|_| RpcError::UnknownCommand)?;
+            (self.handlers[idx].1)(&self.decoder.payload, &mut payload).map(|_| id)
+        });
+        let (id, payload) = match result {
+            Ok(id) => {
+                payload.truncate(MAX_PAYLOAD);
+                (id, payload)
+            }
+            Err(e) => (ERROR_ID, alloc::vec![e.code()]),
+        };
+        self.queued
+            .push_back(encode(REPLY_MAGIC, id, &payload).collect());
+        if self.sent >= self.reply.len() {
+            self.start_next_reply();
+        }
+    }
+
+    /// Starts sending the oldest queued reply, or [IDLE] if there isn't one.
+    fn start_next_reply(&mut self) {
+        self.reply = self.queued.pop_front().unwrap_or_default();
+        self.sent = 0;
+        self.joybus
+            .set_reply(self.reply.first().copied().unwrap_or(IDLE));
+    }
+
+    pub fn into_inner(self) -> Joybus<'a> {
+        self.joybus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_request_decoding(_gba: &mut Gba) {
+        let payload = [0x0102_0304, 0xFFFF_FFFF];
+        let frame: Vec<u32> = encode(REQUEST_MAGIC, 7, &payload).collect();
+        assert_eq!(frame.len(), 3);
+        assert_eq!(frame[0] >> 8, 0xA5_07_02);
+
+        let mut decoder = RequestDecoder::default();
+        // Junk before the header is skipped.
+        assert_eq!(decoder.push(0x1234_5678), None);
+        assert_eq!(decoder.push(frame[0]), None);
+        assert_eq!(decoder.push(frame[1]), None);
+        assert_eq!(decoder.push(frame[2]), Some(Ok(7)));
+        assert_eq!(decoder.payload, payload);
+
+        assert_eq!(decoder.push(frame[0]), None);
+        assert_eq!(decoder.push(frame[1] ^ 0x100), None);
+        assert_eq!(
+            decoder.push(frame[2]),
+            Some(Err(RpcError::ChecksumMismatch))
+        );
+
+        let empty: Vec<u32> = encode(REQUEST_MAGIC, 3, &[]).collect();
+        assert_eq!(decoder.push(empty[0]), Some(Ok(3)));
+    }
+}
@@ -8,7 +8,16 @@ use voladdress::{Safe, VolAddress};
 use crate::utils::{read_bit, write_bit};
 
 pub mod generalpurpose;
+pub mod joybus;
+pub mod multiboot;
 pub mod multiplayer;
+pub mod normal;
+pub mod printer;
+pub mod probe;
+mod ringbuf;
+pub mod transport;
+pub mod uart;
+pub mod wireless;
 
 #[derive(Default)]
 pub struct Serial {
@@ -25,6 +34,7 @@ impl Serial {
 const RCNT: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(0x4000134) };
 const SIOCNT: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(0x4000128) };
 const SIOMLT_SEND: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(0x400012A) };
+const SIODATA8: VolAddress<u8, Safe, Safe> = unsafe { VolAddress::new(0x400012A) };
 
 #[derive(PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Clone, Copy)]
 pub enum Pin {
@@ -286,46 +296,3 @@ impl SiocntWrapper {
         self.reg.write_bit(14, v)
     }
 }
-
-/*
-===============
-= NORMAL MODE =
-===============
-  Bit   Expl.
-  0     Shift Clock (SC)        (0=External, 1=Internal)
-  1     Internal Shift Clock    (0=256KHz, 1=2MHz)
-  2     SI State (opponents SO) (0=Low, 1=High/None) --- (Read Only)
-  3     SO during inactivity    (0=Low, 1=High) (applied ONLY when Bit7=0)
-  4-6   Not used                (Read only, always 0 ???)
-  7     Start Bit               (0=Inactive/Ready, 1=Start/Active)
-  8-11  Not used                (R/W, should be 0)
-  12    Transfer Length         (0=8bit, 1=32bit)
-  13    Must be "0" for Normal Mode
-  14    IRQ Enable              (0=Disable, 1=Want IRQ upon completion)
-  15    Not used                (Read only, always 0)
-
-
-  */
-/*
-=============
-= UART MODE =
-=============
-
-  Bit   Expl.
-  0-1   Baud Rate  (0-3: 9600,38400,57600,115200 bps)
-  2     CTS Flag   (0=Send always/blindly, 1=Send only when SC=LOW)
-  3     Parity Control (0=Even, 1=Odd)
-  4     Send Data Flag      (0=Not Full,  1=Full)    (Read Only)
-  5     Receive Data Flag   (0=Not Empty, 1=Empty)   (Read Only)
-  6     Error Flag          (0=No Error,  1=Error)   (Read Only)
-  7     Data Length         (0=7bits,   1=8bits)
-  8     FIFO Enable Flag    (0=Disable, 1=Enable)
-  9     Parity Enable Flag  (0=Disable, 1=Enable)
-  10    Send Enable Flag    (0=Disable, 1=Enable)
-  11    Receive Enable Flag (0=Disable, 1=Enable)
-  12    Must be "1" for UART mode
-  13    Must be "1" for UART mode
-  14    IRQ Enable          (0=Disable, 1=IRQ when any Bit 4/5/6 become set)
-  15    Not used            (Read only, always 0)
-
-*/
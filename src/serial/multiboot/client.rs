@@ -0,0 +1,221 @@
+//! The receiving side of the [multiplayer](super::multiplayer) multiboot
+//! handshake, for programs that are already running but want to accept a
+//! multiboot-style data push from a parent.
+//!
+//! This answers the parent's handshake words the same way a client's BIOS
+//! would, so that the parent can use the standard sender. Once the handshake
+//! is done the header, palette & handshake data are handed back, and the link
+//! is left in multiplayer mode for whatever data channel the two programs
+//! agree on next.
+//!
+//! Each answer has to be loaded before the parent's next transfer, so the
+//! handshake is answered from the serial interrupt rather than by polling.
+
+use agb::external::critical_section::CriticalSection;
+use agb::interrupt::{add_interrupt_handler, Interrupt};
+use agb::syscall;
+
+use super::{CancelToken, ClientSet, MultibootError, HEADER_LEN};
+use crate::serial::multiplayer::{
+    MultiplayerCommReg, MultiplayerSerial, MultiplayerSiocnt, PlayerId, NO_DATA,
+};
+use crate::serial::SIOMLT_SEND;
+use crate::utils::GbaCell;
+
+/// Where [ClientHandshake] is in the handshake.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage {
+    /// Answering the parent's search for clients.
+    Waiting,
+    /// Receiving the header halfword at this index.
+    Header(usize),
+    /// Waiting for the parent to finish the header.
+    HeaderDone,
+    /// Waiting for the parent to confirm the clients that got the header.
+    Confirm,
+    /// Answering the palette with our client data.
+    ClientData,
+    /// The handshake is done.
+    Done,
+}
+
+/// The hardware-independent half of
+/// [MultiplayerSerial::receive_multiboot_handshake].
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct ClientHandshake {
+    client_data: u8,
+    stage: Stage,
+    header: [u8; HEADER_LEN],
+    palette: u8,
+    handshake_data: u8,
+}
+
+impl ClientHandshake {
+    const fn new(client_data: u8) -> Self {
+        Self {
+            client_data,
+            stage: Stage::Waiting,
+            header: [0; HEADER_LEN],
+            palette: 0,
+            handshake_data: 0,
+        }
+    }
+    /// Handles the next word from the parent, returning the answer to load for
+    /// the parent's next transfer.
+    ///
+    /// `bit` is our protocol bit; see [ClientSet::bit].
+    fn receive(&mut self, bit: u8, word: u16) -> u16 {
+        let bit = bit as u16;
+        let ack_handshake = 0x7200 | bit;
+        let ack_response = 0x7300 | self.client_data as u16;
+        let (stage, reply) = match (self.stage, word >> 8) {
+            (Stage::Header(idx), _) => {
+                self.header[idx * 2..idx * 2 + 2].copy_from_slice(&word.to_le_bytes());
+                let remaining = HEADER_LEN / 2 - (idx + 1);
+                let next = if remaining == 0 {
+                    Stage::HeaderDone
+                } else {
+                    Stage::Header(idx + 1)
+                };
+                (next, ((remaining as u16) << 8) | bit)
+            }
+            (Stage::Done, _) => (Stage::Done, ack_response),
+            (Stage::Waiting, 0x61) if word & bit != 0 => (Stage::Header(0), 0x6000 | bit),
+            (Stage::HeaderDone, 0x62) if word & 0xFF == 0 => (Stage::Confirm, ack_handshake),
+            (Stage::Confirm, 0x62) if word & bit != 0 => (Stage::ClientData, ack_response),
+            (Stage::ClientData, 0x63) => {
+                self.palette = word as u8;
+                (Stage::ClientData, ack_response)
+            }
+            (Stage::ClientData, 0x64) => {
+                self.handshake_data = word as u8;
+                (Stage::Done, ack_response)
+            }
+            // Anything unexpected means the parent restarted the handshake or
+            // we missed a word, so go back to waiting to be found.
+            _ => (Stage::Waiting, ack_handshake),
+        };
+        self.stage = stage;
+        reply
+    }
+    const fn is_done(&self) -> bool {
+        matches!(self.stage, Stage::Done)
+    }
+}
+
+/// The handshake being answered by [handshake_interrupt], if any.
+static HANDSHAKE: GbaCell<Option<ClientHandshake>> = GbaCell::new(None);
+
+/// Answers the parent's word from the transfer that just finished.
+fn handshake_interrupt(cs: CriticalSection<'_>) {
+    HANDSHAKE.lock_mut_in(cs, |handshake| {
+        let Some(handshake) = handshake else {
+            return;
+        };
+        let siocnt = MultiplayerSiocnt::get();
+        if siocnt.busy() {
+            return;
+        }
+        let word = MultiplayerCommReg::get(PlayerId::P0).raw_read();
+        // The ID is valid now that a transfer has finished.
+        let bit = ClientSet::bit(siocnt.id());
+        SIOMLT_SEND.write(handshake.receive(bit, word));
+    });
+}
+
+/// What the parent sent during the handshake.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ReceivedHandshake {
+    /// The program header.
+    pub header: [u8; HEADER_LEN],
+    /// The boot logo palette; see [palette](super::palette).
+    pub palette: u8,
+    /// The [handshake_data](super::handshake_data) derived from every client's
+    /// client data.
+    pub handshake_data: u8,
+}
+
+impl MultiplayerSerial<'_> {
+    /// Acts as a multiboot client, answering the parent's handshake until it's
+    /// done, blocking until then or until `cancel` is cancelled.
+    ///
+    /// `client_data` is the random byte used by the parent to derive its
+    /// encryption keys; anything other than 0xFF works.
+    ///
+    /// The serial interrupt is enabled while this runs, & restored to its
+    /// previous state afterwards. This sleeps between transfers, so `cancel`
+    /// is only checked when an interrupt fires; make sure VBlank or another
+    /// regular interrupt is enabled if the parent might never show up.
+    pub fn receive_multiboot_handshake(
+        &mut self,
+        client_data: u8,
+        cancel: &CancelToken,
+    ) -> Result<ReceivedHandshake, MultibootError> {
+        if self.is_parent() {
+            return Err(MultibootError::NotClient);
+        }
+        // We don't know our ID, and so our bit, until the first transfer.
+        self.write_send_reg(NO_DATA);
+        HANDSHAKE.swap(Some(ClientHandshake::new(client_data)));
+        let was_enabled = self.interrupt_enabled();
+        // #SAFETY
+        //
+        // The interrupt doesn't allocate.
+        let interrupt = unsafe { add_interrupt_handler(Interrupt::Serial, handshake_interrupt) };
+        self.enable_interrupt(true);
+        let res = loop {
+            if HANDSHAKE.lock(|handshake| handshake.as_ref().is_some_and(ClientHandshake::is_done))
+            {
+                break HANDSHAKE.swap(None).ok_or(MultibootError::HandshakeFailed);
+            }
+            if cancel.is_cancelled() {
+                break Err(MultibootError::Cancelled);
+            }
+            syscall::halt();
+        };
+        self.enable_interrupt(was_enabled);
+        drop(interrupt);
+        HANDSHAKE.swap(None);
+        let handshake = res?;
+        Ok(ReceivedHandshake {
+            header: handshake.header,
+            palette: handshake.palette,
+            handshake_data: handshake.handshake_data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_client_handshake(_gba: &mut Gba) {
+        let bit = ClientSet::bit(PlayerId::P2);
+        let mut handshake = ClientHandshake::new(0x42);
+
+        assert_eq!(handshake.receive(bit, 0x6200), 0x7204);
+        assert_eq!(handshake.receive(bit, 0x6200), 0x7204);
+        // Confirmed as a client.
+        assert_eq!(handshake.receive(bit, 0x610E), 0x6004);
+        for idx in 0..HEADER_LEN / 2 {
+            let expected = (((HEADER_LEN / 2 - idx - 1) as u16) << 8) | 0x04;
+            assert_eq!(handshake.receive(bit, idx as u16), expected);
+        }
+        assert_eq!(&handshake.header[..4], &[0, 0, 1, 0]);
+        assert_eq!(handshake.receive(bit, 0x6200), 0x7204);
+        assert_eq!(handshake.receive(bit, 0x6204), 0x7342);
+        assert_eq!(handshake.receive(bit, 0x6393), 0x7342);
+        assert!(!handshake.is_done());
+        assert_eq!(handshake.receive(bit, 0x6412), 0x7342);
+        assert!(handshake.is_done());
+        assert_eq!(handshake.palette, 0x93);
+        assert_eq!(handshake.handshake_data, 0x12);
+
+        // A confirmation for other clients is ignored.
+        let mut handshake = ClientHandshake::new(0x42);
+        assert_eq!(handshake.receive(bit, 0x6102), 0x7204);
+        assert_eq!(handshake.stage, Stage::Waiting);
+    }
+}
@@ -0,0 +1,458 @@
+//! Sending a program to cartridge-less GBAs using the BIOS multiboot protocol.
+//!
+//! When a GBA is turned on without a cartridge (or with START + SELECT held)
+//! its BIOS waits for a parent GBA to upload a program of up to 256KB over the
+//! link cable, then runs it from EWRAM. The upload happens in 3 stages:
+//!
+//! 1. A handshake, in which the parent finds the clients & sends them the
+//!    program's 0xC0 byte header.
+//! 2. An exchange of random "client data" bytes, which are used to derive the
+//!    keys used to encrypt the rest of the program.
+//! 3. The encrypted payload & its checksum.
+//!
+//! The protocol has a [multiplayer] variant that can send to up to 3 clients,
+//! and a faster [normal] variant for a single client. The [client] module
+//! covers the other end of the multiplayer variant, for programs that want to
+//! accept a data push from a parent after they've booted.
+//!
+//! The protocol details follow GBATEK's "BIOS Multi Boot (Single Game Pak)"
+//! section.
+
+use agb::interrupt::VBlank;
+
+use super::multiplayer::PlayerId;
+use crate::utils::GbaCell;
+
+pub mod client;
+pub mod multiplayer;
+pub mod normal;
+
+/// The size of the program header, which is sent unencrypted.
+pub const HEADER_LEN: usize = 0xC0;
+/// The smallest program (including the header) the BIOS accepts.
+pub const MIN_ROM_LEN: usize = HEADER_LEN + 0x100;
+/// The largest program (including the header) the BIOS accepts, limited by the
+/// size of EWRAM.
+pub const MAX_ROM_LEN: usize = 0x4_0000;
+/// The payload after the header must be a multiple of this many bytes.
+pub const ROM_ALIGN: usize = 0x10;
+
+/// The palette data used if none is given, making the BIOS logo slide in from
+/// the right in a light blue.
+pub const DEFAULT_PALETTE: u8 = 0x93;
+
+/// Derives the palette byte shown by the clients' BIOS boot logo animation.
+///
+/// `color` can be 0-6, `direction` 0-1 and `speed` 0-3; larger values are
+/// clamped.
+pub const fn palette(color: u8, direction: u8, speed: u8) -> u8 {
+    let color = if color > 6 { 6 } else { color };
+    let direction = if direction > 1 { 1 } else { direction };
+    let speed = if speed > 3 { 3 } else { speed };
+    0x81 + color * 0x10 + direction * 8 + speed * 2
+}
+
+/// Computes the handshake byte sent after the client data exchange, from the
+/// client data bytes of clients 1-3 (0xFF for missing clients).
+pub const fn handshake_data(client_data: [u8; 3]) -> u8 {
+    0x11u8
+        .wrapping_add(client_data[0])
+        .wrapping_add(client_data[1])
+        .wrapping_add(client_data[2])
+}
+
+/// A set of clients, stored as the bit mask used by the protocol: bit 1 for
+/// [PlayerId::P1], bit 2 for [PlayerId::P2] and bit 3 for [PlayerId::P3].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ClientSet {
+    bits: u8,
+}
+
+impl ClientSet {
+    pub const EMPTY: Self = Self { bits: 0 };
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            bits: bits & 0b1110,
+        }
+    }
+    pub const fn bits(self) -> u8 {
+        self.bits
+    }
+    /// The protocol bit for `player`, or 0 for the parent.
+    pub const fn bit(player: PlayerId) -> u8 {
+        match player {
+            PlayerId::P0 => 0,
+            other => 1 << (other as u8),
+        }
+    }
+    pub const fn contains(self, player: PlayerId) -> bool {
+        let bit = Self::bit(player);
+        bit != 0 && self.bits & bit != 0
+    }
+    pub fn insert(&mut self, player: PlayerId) {
+        self.bits |= Self::bit(player);
+    }
+    pub fn remove(&mut self, player: PlayerId) {
+        self.bits &= !Self::bit(player);
+    }
+    pub const fn is_empty(self) -> bool {
+        self.bits == 0
+    }
+    pub const fn len(self) -> usize {
+        self.bits.count_ones() as usize
+    }
+    /// The clients in the set, in order.
+    pub fn iter(self) -> impl Iterator<Item = PlayerId> {
+        PlayerId::ALL
+            .into_iter()
+            .filter(move |player| self.contains(*player))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MultibootError {
+    /// The program's length isn't between [MIN_ROM_LEN] and [MAX_ROM_LEN], or
+    /// its payload isn't a multiple of [ROM_ALIGN] bytes.
+    InvalidLength,
+    /// Only the parent GBA can send a program.
+    NotParent,
+    /// Only a client GBA can answer the parent's handshake.
+    NotClient,
+    /// The link isn't running at the speed required by the protocol.
+    WrongBaudRate,
+    /// No clients answered the handshake.
+    NoClients,
+    /// The client stopped following the protocol partway through the
+    /// handshake; when sending to several clients, every one of them did.
+    HandshakeFailed,
+    /// The payload transfer failed, or the client never confirmed receiving it.
+    TransferFailed,
+    /// The transfer was stopped using a [CancelToken].
+    Cancelled,
+}
+
+/// Which clients a transfer to several clients at once reached.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct MultibootReport {
+    /// The clients that were sent the program.
+    pub succeeded: ClientSet,
+    /// The clients that answered the first handshake, but then stopped
+    /// following the protocol & were dropped from the transfer.
+    pub failed: ClientSet,
+}
+
+/// Options for sending a program.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MultibootOptions {
+    /// The boot logo palette; see [palette].
+    pub palette: u8,
+    /// How many times to look for clients before giving up, waiting 1/16th of
+    /// a second between each try.
+    pub detect_attempts: u32,
+}
+
+impl Default for MultibootOptions {
+    fn default() -> Self {
+        Self {
+            palette: DEFAULT_PALETTE,
+            detect_attempts: 16,
+        }
+    }
+}
+
+/// A source for a program being sent, read a piece at a time.
+///
+/// This lets a program be sent from somewhere other than a single slice in
+/// memory, such as being decompressed on the fly or read from a save chip.
+pub trait ChunkProvider {
+    /// The length of the full program, including the header.
+    fn total_len(&self) -> usize;
+    /// Fills `buf` with the program's bytes starting `offset` bytes in.
+    ///
+    /// Reads are always in order and never go past [Self::total_len].
+    fn read(&mut self, offset: usize, buf: &mut [u8]);
+}
+
+impl ChunkProvider for &[u8] {
+    fn total_len(&self) -> usize {
+        self.len()
+    }
+    fn read(&mut self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self[offset..offset + buf.len()]);
+    }
+}
+
+/// Which step of the protocol a transfer is on.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MultibootPhase {
+    /// Looking for clients.
+    Detecting,
+    /// Sending the unencrypted header.
+    Header,
+    /// Exchanging the client data used to derive the encryption keys.
+    ClientData,
+    /// Sending the encrypted payload.
+    Payload,
+    /// Waiting for the clients to check the payload's CRC.
+    Checksum,
+    /// The program was sent successfully.
+    Done,
+}
+
+/// A snapshot of a transfer's progress, passed to the progress callback.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MultibootProgress {
+    pub phase: MultibootPhase,
+    /// The clients still taking part in the transfer.
+    pub clients: ClientSet,
+    /// The clients that dropped out of the transfer partway through.
+    pub failed: ClientSet,
+    /// How many bytes of the program (including the header) have been sent.
+    pub sent: usize,
+    /// The length of the program.
+    pub total: usize,
+}
+
+/// Lets a transfer be stopped from elsewhere, such as an interrupt handler
+/// noticing the cable was pulled.
+///
+/// Cancellation is cooperative: the sender checks the token between
+/// transfers, and then returns [MultibootError::Cancelled]. The clients will
+/// time out & go back to waiting for a parent on their own.
+pub struct CancelToken {
+    cancelled: GbaCell<bool>,
+}
+
+impl CancelToken {
+    pub const fn new() -> Self {
+        Self {
+            cancelled: GbaCell::new(false),
+        }
+    }
+    /// Asks the transfer using this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.swap(true);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get_copy()
+    }
+    /// Clears a previous cancellation, so the token can be reused.
+    pub fn reset(&self) {
+        self.cancelled.swap(false);
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tracks a transfer's progress, reporting it to the user's callback &
+/// checking for cancellation.
+struct Monitor<'c, F> {
+    cancel: &'c CancelToken,
+    on_progress: F,
+    progress: MultibootProgress,
+}
+
+impl<F> Monitor<'_, F> {
+    /// Fails with [MultibootError::Cancelled] if the transfer was cancelled.
+    fn check(&self) -> Result<(), MultibootError> {
+        if self.cancel.is_cancelled() {
+            Err(MultibootError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'c, F: FnMut(&MultibootProgress)> Monitor<'c, F> {
+    fn new(total: usize, cancel: &'c CancelToken, on_progress: F) -> Self {
+        Self {
+            cancel,
+            on_progress,
+            progress: MultibootProgress {
+                phase: MultibootPhase::Detecting,
+                clients: ClientSet::EMPTY,
+                failed: ClientSet::EMPTY,
+                sent: 0,
+                total,
+            },
+        }
+    }
+    /// Moves on to `phase`, reporting the new progress.
+    fn phase(&mut self, phase: MultibootPhase) -> Result<(), MultibootError> {
+        self.progress.phase = phase;
+        self.report()
+    }
+    fn set_clients(&mut self, clients: ClientSet, failed: ClientSet) {
+        self.progress.clients = clients;
+        self.progress.failed = failed;
+    }
+    /// Records that `sent` bytes have been sent, reporting the new progress.
+    fn sent(&mut self, sent: usize) -> Result<(), MultibootError> {
+        self.progress.sent = sent;
+        self.report()
+    }
+    fn report(&mut self) -> Result<(), MultibootError> {
+        (self.on_progress)(&self.progress);
+        self.check()
+    }
+}
+
+/// Checks that `len` is a program length the BIOS can receive.
+fn validate_len(len: usize) -> Result<(), MultibootError> {
+    if (MIN_ROM_LEN..=MAX_ROM_LEN).contains(&len) && (len - HEADER_LEN) % ROM_ALIGN == 0 {
+        Ok(())
+    } else {
+        Err(MultibootError::InvalidLength)
+    }
+}
+
+/// Waits roughly 1/16th of a second, as required between some protocol steps.
+fn wait_sixteenth() {
+    let vblank = VBlank::get();
+    for _ in 0..4 {
+        vblank.wait_for_vblank();
+    }
+}
+
+/// Which variant of the protocol the BIOS should use to send the payload.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[allow(unused)]
+enum BiosTransferMode {
+    Normal256KHz = 0,
+    Multiplayer = 1,
+    Normal2MHz = 2,
+}
+
+/// The parameter block passed to the BIOS `MultiBoot` software interrupt.
+#[repr(C)]
+struct MultibootParam {
+    _reserved1: [u32; 5],
+    handshake_data: u8,
+    _padding: u8,
+    handshake_timeout: u16,
+    probe_count: u8,
+    client_data: [u8; 3],
+    palette_data: u8,
+    response_bit: u8,
+    client_bit: u8,
+    _reserved2: u8,
+    boot_srcp: *const u8,
+    boot_endp: *const u8,
+    masterp: *const u8,
+    _reserved3: [*const u8; 3],
+    _system_work2: [u32; 4],
+    sendflag: u8,
+    probe_target_bit: u8,
+    check_wait: u8,
+    server_type: u8,
+}
+
+impl MultibootParam {
+    fn new(rom: &[u8], palette: u8, clients: ClientSet, client_data: [u8; 3]) -> Self {
+        let range = rom.as_ptr_range();
+        Self {
+            _reserved1: [0; 5],
+            handshake_data: handshake_data(client_data),
+            _padding: 0,
+            handshake_timeout: 0,
+            probe_count: 0,
+            client_data,
+            palette_data: palette,
+            response_bit: 0,
+            client_bit: clients.bits(),
+            _reserved2: 0,
+            boot_srcp: range.start.wrapping_add(HEADER_LEN),
+            boot_endp: range.end,
+            masterp: range.start,
+            _reserved3: [core::ptr::null(); 3],
+            _system_work2: [0; 4],
+            sendflag: 0,
+            probe_target_bit: 0,
+            check_wait: 0,
+            server_type: 0,
+        }
+    }
+}
+
+/// Has the BIOS send the encrypted payload described by `param`, blocking
+/// until it's done.
+///
+/// # Safety
+/// `param` must point to a valid program, and the handshake must have been
+/// completed using `mode`'s variant of the protocol.
+unsafe fn bios_multiboot(
+    param: &MultibootParam,
+    mode: BiosTransferMode,
+) -> Result<(), MultibootError> {
+    let failed: u32;
+    core::arch::asm!(
+        "swi 0x25",
+        inlateout("r0") param as *const MultibootParam => failed,
+        inlateout("r1") mode as u32 => _,
+        clobber_abi("C"),
+    );
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(MultibootError::TransferFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_protocol_values(_gba: &mut Gba) {
+        assert_eq!(palette(1, 0, 1), DEFAULT_PALETTE);
+        assert_eq!(palette(9, 9, 9), 0xEF);
+        assert_eq!(handshake_data([0xFF, 0xFF, 0xFF]), 0x0E);
+        assert_eq!(handshake_data([0x12, 0xFF, 0xFF]), 0x11 + 0x12 - 2);
+
+        let mut clients = ClientSet::EMPTY;
+        clients.insert(PlayerId::P0);
+        assert!(clients.is_empty());
+        clients.insert(PlayerId::P3);
+        clients.insert(PlayerId::P1);
+        assert_eq!(clients.bits(), 0b1010);
+        assert_eq!(clients.len(), 2);
+        clients.remove(PlayerId::P3);
+        assert!(clients.iter().eq([PlayerId::P1]));
+
+        assert_eq!(core::mem::size_of::<MultibootParam>(), 0x4C);
+        assert_eq!(validate_len(MIN_ROM_LEN), Ok(()));
+        assert_eq!(
+            validate_len(MIN_ROM_LEN - ROM_ALIGN),
+            Err(MultibootError::InvalidLength)
+        );
+    }
+
+    #[test_case]
+    fn test_monitor(_gba: &mut Gba) {
+        let cancel = CancelToken::new();
+        let mut reports = 0;
+        let mut monitor = Monitor::new(MIN_ROM_LEN, &cancel, |progress: &MultibootProgress| {
+            assert_eq!(progress.total, MIN_ROM_LEN);
+            reports += 1;
+        });
+        assert_eq!(monitor.phase(MultibootPhase::Header), Ok(()));
+        assert_eq!(monitor.sent(HEADER_LEN), Ok(()));
+        assert_eq!(monitor.progress.sent, HEADER_LEN);
+        cancel.cancel();
+        assert_eq!(monitor.check(), Err(MultibootError::Cancelled));
+        assert_eq!(
+            monitor.phase(MultibootPhase::Payload),
+            Err(MultibootError::Cancelled)
+        );
+        assert_eq!(reports, 3);
+        cancel.reset();
+        assert!(!cancel.is_cancelled());
+    }
+}
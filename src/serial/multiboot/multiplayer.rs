@@ -0,0 +1,293 @@
+//! The multiplayer mode variant of the multiboot protocol, which can send a
+//! program to up to 3 clients at once.
+//!
+//! The link must be running at [BaudRate::B115200], and every transfer is a
+//! single `u16` from the parent answered by each client:
+//!
+//! | Parent sends         | Clients answer                | Notes
+//! | :--                  | :--                           | :--
+//! | `0x6200`             | `0x720X`                      | Repeated until all clients answer; X is the client's bit
+//! | `0x610Y`             | `0x720X`                      | Y is the set of clients found
+//! | Header halfword `n`  | `((0x60 - n) << 8) \| X`      | For each of the 0x60 header halfwords
+//! | `0x6200`             | `0x000X`                      |
+//! | `0x620Y`             | `0x720X`                      |
+//! | `0x63PP`             | `0x73CC`                      | PP is the palette; repeated until all clients answer with their client data CC
+//! | `0x64HH`             | `0x73UU`                      | HH is the [handshake_data]
+//!
+//! After waiting 1/16th of a second the BIOS then takes over to send the
+//! encrypted payload.
+//!
+//! A client that gives the wrong answer at any step is dropped from the
+//! transfer, and is left out of every later step.
+
+use super::{
+    bios_multiboot, handshake_data, validate_len, wait_sixteenth, BiosTransferMode, CancelToken,
+    ClientSet, Monitor, MultibootError, MultibootOptions, MultibootParam, MultibootPhase,
+    MultibootProgress, MultibootReport, HEADER_LEN,
+};
+use crate::serial::multiplayer::{BaudRate, MultiplayerSerial, PlayerId, TransferError};
+
+/// Sent by the parent to look for clients.
+const HANDSHAKE: u16 = 0x6200;
+/// The answer to [HANDSHAKE] from a client ready to boot, ORed with its bit.
+const ACK_HANDSHAKE: u16 = 0x7200;
+/// Sent by the parent to confirm which clients it found.
+const CONFIRM_CLIENTS: u16 = 0x6100;
+/// Sent by the parent along with the palette during the client data exchange.
+const SEND_PALETTE: u16 = 0x6300;
+/// The answer to [SEND_PALETTE] once a client has picked its client data.
+const ACK_RESPONSE: u16 = 0x7300;
+/// Sent by the parent along with the [handshake_data].
+const CONFIRM_HANDSHAKE_DATA: u16 = 0x6400;
+
+/// The clients' players, in the order of the client data array.
+const CLIENTS: [PlayerId; 3] = [PlayerId::P1, PlayerId::P2, PlayerId::P3];
+
+/// How many times the palette is sent before giving up on a client picking
+/// its client data.
+const PALETTE_ATTEMPTS: u32 = 32;
+
+/// How many times SIOCNT is polled between transfers, giving the clients'
+/// BIOS time to load its next answer.
+const TRANSFER_GAP_POLLS: u32 = 400;
+
+/// Sends `value` to every client, returning what each of them answered.
+fn exchange(serial: &mut MultiplayerSerial, value: u16) -> [u16; 3] {
+    for _ in 0..TRANSFER_GAP_POLLS {
+        core::hint::black_box(serial.is_busy());
+    }
+    serial.write_send_reg(value);
+    // Not every client has to be ready; the missing ones are dropped by the
+    // callers when they don't answer.
+    while let Err(TransferError::AlreadyInProgress) = serial.start_transfer() {}
+    while serial.is_busy() {}
+    CLIENTS.map(|player| serial.read_player_reg_raw(player))
+}
+
+/// The per-client state of a transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct Clients {
+    /// The clients still following the protocol.
+    active: ClientSet,
+    /// The clients that have been dropped from the transfer.
+    failed: ClientSet,
+}
+
+impl Clients {
+    const fn new(found: ClientSet) -> Self {
+        Self {
+            active: found,
+            failed: ClientSet::EMPTY,
+        }
+    }
+    /// Drops every active client that didn't answer `expected` ORed with its
+    /// bit, failing once none are left.
+    fn expect(&mut self, answers: [u16; 3], expected: u16) -> Result<(), MultibootError> {
+        for (player, answer) in CLIENTS.into_iter().zip(answers) {
+            if self.active.contains(player) && answer != expected | ClientSet::bit(player) as u16 {
+                self.drop_client(player);
+            }
+        }
+        self.ensure_any()
+    }
+    fn drop_client(&mut self, player: PlayerId) {
+        self.active.remove(player);
+        self.failed.insert(player);
+    }
+    fn ensure_any(&self) -> Result<(), MultibootError> {
+        if self.active.is_empty() {
+            Err(MultibootError::HandshakeFailed)
+        } else {
+            Ok(())
+        }
+    }
+    const fn report(self) -> MultibootReport {
+        MultibootReport {
+            succeeded: self.active,
+            failed: self.failed,
+        }
+    }
+}
+
+impl MultiplayerSerial<'_> {
+    /// Sends `rom` to every client waiting in its BIOS' multiboot screen,
+    /// blocking until the transfer is done.
+    ///
+    /// Each client is tracked separately: one that stops following the
+    /// protocol partway through the handshake is dropped, and the rest are
+    /// still sent the program. The returned report lists which clients were
+    /// sent the program and which were dropped.
+    ///
+    /// `rom` is the full program including its header, as it would be laid out
+    /// in EWRAM; it must be between [MIN_ROM_LEN](super::MIN_ROM_LEN) and
+    /// [MAX_ROM_LEN](super::MAX_ROM_LEN) bytes long.
+    ///
+    /// Since the BIOS reads the payload straight from memory, this variant
+    /// can't use a [ChunkProvider](super::ChunkProvider). `rom` doesn't need
+    /// to be in RAM though: a slice of the cartridge ROM works just as well.
+    pub fn send_multiboot(
+        &mut self,
+        rom: &[u8],
+        options: MultibootOptions,
+    ) -> Result<MultibootReport, MultibootError> {
+        self.send_multiboot_with(rom, options, &CancelToken::new(), |_| {})
+    }
+
+    /// Like [Self::send_multiboot], but reports the transfer's progress to
+    /// `on_progress` and stops early if `cancel` is cancelled.
+    ///
+    /// The payload itself is sent by the BIOS, which can't be interrupted;
+    /// progress jumps straight from the start to the end of the payload, and
+    /// cancellation is only checked before & after it.
+    pub fn send_multiboot_with(
+        &mut self,
+        rom: &[u8],
+        options: MultibootOptions,
+        cancel: &CancelToken,
+        on_progress: impl FnMut(&MultibootProgress),
+    ) -> Result<MultibootReport, MultibootError> {
+        validate_len(rom.len())?;
+        if !self.is_parent() {
+            return Err(MultibootError::NotParent);
+        }
+        if self.baud_rate() != BaudRate::B115200 {
+            return Err(MultibootError::WrongBaudRate);
+        }
+        let mut monitor = Monitor::new(rom.len(), cancel, on_progress);
+
+        monitor.phase(MultibootPhase::Detecting)?;
+        let mut clients = Clients::new(self.detect_clients(options.detect_attempts, &monitor)?);
+        let answers = self.exchange(CONFIRM_CLIENTS | clients.active.bits() as u16);
+        clients.expect(answers, ACK_HANDSHAKE)?;
+        monitor.set_clients(clients.active, clients.failed);
+
+        monitor.phase(MultibootPhase::Header)?;
+        for (idx, chunk) in rom[..HEADER_LEN].chunks_exact(2).enumerate() {
+            monitor.check()?;
+            let halfword = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let answers = self.exchange(halfword);
+            let remaining = (HEADER_LEN / 2 - idx) as u16;
+            clients.expect(answers, remaining << 8)?;
+        }
+        let answers = self.exchange(HANDSHAKE);
+        clients.expect(answers, 0)?;
+        let answers = self.exchange(HANDSHAKE | clients.active.bits() as u16);
+        clients.expect(answers, ACK_HANDSHAKE)?;
+        monitor.set_clients(clients.active, clients.failed);
+        monitor.sent(HEADER_LEN)?;
+
+        monitor.phase(MultibootPhase::ClientData)?;
+        let client_data = self.exchange_client_data(&mut clients, options.palette, &monitor)?;
+        self.exchange(CONFIRM_HANDSHAKE_DATA | handshake_data(client_data) as u16);
+        wait_sixteenth();
+        monitor.set_clients(clients.active, clients.failed);
+
+        monitor.phase(MultibootPhase::Payload)?;
+        let param = MultibootParam::new(rom, options.palette, clients.active, client_data);
+        // #SAFETY
+        //
+        // The handshake above followed the multiplayer variant of the protocol,
+        // and `param` points into `rom` which outlives the call.
+        unsafe { bios_multiboot(&param, BiosTransferMode::Multiplayer)? };
+        monitor.sent(rom.len())?;
+        monitor.phase(MultibootPhase::Done)?;
+        Ok(clients.report())
+    }
+
+    fn exchange(&mut self, value: u16) -> [u16; 3] {
+        exchange(self, value)
+    }
+
+    /// Sends [HANDSHAKE] until at least 1 client answers and every client
+    /// that answered once keeps answering.
+    fn detect_clients<F>(
+        &mut self,
+        attempts: u32,
+        monitor: &Monitor<'_, F>,
+    ) -> Result<ClientSet, MultibootError> {
+        let mut found = ClientSet::EMPTY;
+        for _ in 0..attempts {
+            monitor.check()?;
+            let answers = self.exchange(HANDSHAKE);
+            let mut now = ClientSet::EMPTY;
+            for (player, answer) in CLIENTS.into_iter().zip(answers) {
+                if answer == ACK_HANDSHAKE | ClientSet::bit(player) as u16 {
+                    now.insert(player);
+                }
+            }
+            if !now.is_empty() && now == found {
+                return Ok(found);
+            }
+            found = now;
+            wait_sixteenth();
+        }
+        Err(MultibootError::NoClients)
+    }
+
+    /// Sends the palette until every client has answered with its client data,
+    /// dropping any client that still hasn't after [PALETTE_ATTEMPTS] tries.
+    ///
+    /// Dropped clients' client data is left as 0xFF, as if they were missing.
+    fn exchange_client_data<F>(
+        &mut self,
+        clients: &mut Clients,
+        palette: u8,
+        monitor: &Monitor<'_, F>,
+    ) -> Result<[u8; 3], MultibootError> {
+        let mut client_data = [0xFF; 3];
+        let mut answered = ClientSet::EMPTY;
+        for _ in 0..PALETTE_ATTEMPTS {
+            monitor.check()?;
+            let answers = self.exchange(SEND_PALETTE | palette as u16);
+            for (idx, player) in CLIENTS.into_iter().enumerate() {
+                if clients.active.contains(player) && answers[idx] & 0xFF00 == ACK_RESPONSE {
+                    client_data[idx] = answers[idx] as u8;
+                    answered.insert(player);
+                }
+            }
+            if answered == clients.active {
+                return Ok(client_data);
+            }
+        }
+        for player in clients.active.iter() {
+            if !answered.contains(player) {
+                clients.drop_client(player);
+            }
+        }
+        clients.ensure_any()?;
+        Ok(client_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_client_tracking(_gba: &mut Gba) {
+        let mut clients = Clients::new(ClientSet::from_bits(0b1110));
+        assert_eq!(
+            clients.expect([0x7202, 0x7204, 0x7208], ACK_HANDSHAKE),
+            Ok(())
+        );
+        assert_eq!(clients.active.bits(), 0b1110);
+
+        // P2 answers something else & is dropped, the rest carry on.
+        assert_eq!(clients.expect([0x6002, 0xFFFF, 0x6008], 0x6000), Ok(()));
+        assert_eq!(clients.active.bits(), 0b1010);
+        assert_eq!(clients.failed.bits(), 0b0100);
+
+        // A dropped client answering correctly again isn't brought back.
+        assert_eq!(clients.expect([0x5F02, 0x5F04, 0x5F08], 0x5F00), Ok(()));
+        assert_eq!(clients.active.bits(), 0b1010);
+
+        assert_eq!(
+            clients.expect([0, 0, 0], 0x5E00),
+            Err(MultibootError::HandshakeFailed)
+        );
+        let report = clients.report();
+        assert!(report.succeeded.is_empty());
+        assert_eq!(report.failed.bits(), 0b1110);
+    }
+}
@@ -0,0 +1,312 @@
+//! The Normal mode variant of the multiboot protocol, which sends a program to
+//! a single client using 32-bit transfers.
+//!
+//! Only 1 client can be connected, but since each transfer moves a full word
+//! at 256KHz instead of a halfword at 115200 baud this is much faster than the
+//! [multiplayer](super::multiplayer) variant. Unlike that variant the encrypted
+//! payload is sent by this module rather than the BIOS.
+//!
+//! The client's BIOS reloads its data register in software after each
+//! transfer, so every transfer is spaced out by at least a scanline (about
+//! 73µs) to give it time.
+//!
+//! The client answers in the upper halfword of each transfer:
+//!
+//! | Parent sends         | Client answers    | Notes
+//! | :--                  | :--               | :--
+//! | `0x6202`             | `0x7202`          | Repeated until the client answers
+//! | `0x6102`             |                   |
+//! | Header halfwords     |                   | For each of the 0x60 header halfwords
+//! | `0x6200`, `0x6202`   |                   |
+//! | `0x63PP`             | `0x73CC`          | PP is the palette, CC the client data; repeated until the client answers
+//! | `0x64HH`             |                   | HH is derived from CC
+//! | Payload length       | `0x73KK`          | KK is the client's half of the CRC key
+//! | Encrypted payload    |                   | 1 word at a time
+//! | `0x65`               | `0x75`            | Repeated until the client has checked the payload
+//! | `0x66`, CRC          |                   |
+
+use super::{
+    validate_len, wait_sixteenth, CancelToken, ChunkProvider, ClientSet, Monitor, MultibootError,
+    MultibootOptions, MultibootPhase, MultibootProgress, HEADER_LEN,
+};
+use crate::serial::multiplayer::PlayerId;
+use crate::serial::normal::NormalSerial;
+use crate::utils::{wait_next_scanline, VBlankTimeout};
+
+/// Sent by the parent to look for the client.
+const HANDSHAKE: u32 = 0x6202;
+/// The client's answer to [HANDSHAKE].
+const ACK_HANDSHAKE: u32 = 0x7202;
+/// Sent by the parent to confirm it found the client.
+const CONFIRM_CLIENT: u32 = 0x6102;
+/// Sent after the header to tell the client it's done.
+const HEADER_DONE: u32 = 0x6200;
+/// Sent by the parent along with the palette.
+const SEND_PALETTE: u32 = 0x6300;
+/// The top byte of the client's answer to [SEND_PALETTE].
+const ACK_RESPONSE: u32 = 0x73;
+/// Sent by the parent along with the handshake byte.
+const CONFIRM_HANDSHAKE_DATA: u32 = 0x6400;
+/// Sent by the parent while waiting for the client to check the payload.
+const WAIT_CHECK: u32 = 0x65;
+/// The client's answer to [WAIT_CHECK] once it's ready for the CRC.
+const ACK_CHECK: u32 = 0x75;
+/// Sent by the parent right before the CRC.
+const SEND_CRC: u32 = 0x66;
+
+/// How many frames [SEND_PALETTE] is repeated for before giving up on the
+/// client answering with its client data.
+const PALETTE_TIMEOUT_FRAMES: u32 = 60;
+/// How many times [WAIT_CHECK] is sent before giving up on the client.
+const CHECK_ATTEMPTS: u32 = 0x1000;
+
+/// The starting value of the payload CRC.
+pub const CRC_SEED: u32 = 0xC387;
+/// The polynomial of the payload CRC.
+const CRC_POLY: u32 = 0xC37B;
+/// The multiplier of the encryption key's LCG; "sedo".
+const SEED_MULTIPLIER: u32 = 0x6F64_6573;
+/// XORed with every encrypted word; "// C".
+const DATA_MAGIC: u32 = 0x4320_2F2F;
+/// Where the client's BIOS places the program, used to encrypt each word.
+const LOAD_ADDRESS: u32 = 0x0200_0000;
+
+/// Folds a single payload word into the running CRC.
+pub const fn crc_step(mut crc: u32, mut word: u32) -> u32 {
+    let mut bit = 0;
+    while bit < 32 {
+        crc = if (crc ^ word) & 1 != 0 {
+            (crc >> 1) ^ CRC_POLY
+        } else {
+            crc >> 1
+        };
+        word >>= 1;
+        bit += 1;
+    }
+    crc
+}
+
+/// Advances the encryption key to the value used for the next word.
+pub const fn next_seed(seed: u32) -> u32 {
+    seed.wrapping_mul(SEED_MULTIPLIER).wrapping_add(1)
+}
+
+/// Encrypts (or decrypts) the word at `offset` bytes into the program, using
+/// an already-advanced `seed`.
+pub const fn crypt_word(word: u32, seed: u32, offset: usize) -> u32 {
+    let address = LOAD_ADDRESS.wrapping_add(offset as u32);
+    word ^ seed ^ address.wrapping_neg() ^ DATA_MAGIC
+}
+
+/// The keys derived from the client data exchange.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Keys {
+    /// The starting encryption key.
+    seed: u32,
+    /// The parent's half of the final CRC key.
+    crc_a: u8,
+}
+
+impl Keys {
+    const fn new(client_data: u8, palette: u8) -> Self {
+        Self {
+            seed: 0xFFFF_0000 | ((client_data as u32) << 8) | palette as u32,
+            crc_a: client_data.wrapping_add(0xF),
+        }
+    }
+    /// The CRC sent after the payload, given the client's half of the key.
+    const fn final_crc(self, crc: u32, crc_b: u8) -> u32 {
+        let key = 0xFFFF_0000 | ((crc_b as u32) << 8) | self.crc_a as u32;
+        crc_step(crc, key)
+    }
+}
+
+/// How many payload bytes are sent between progress reports.
+const PROGRESS_INTERVAL: usize = 0x400;
+/// How many payload bytes are pulled from the [ChunkProvider] at a time.
+const CHUNK_LEN: usize = 0x100;
+
+impl NormalSerial<'_> {
+    /// Sends `rom` to the client waiting in its BIOS' multiboot screen,
+    /// blocking until the transfer is done.
+    ///
+    /// We need to be the master, and the client needs to be connected with a
+    /// standard link cable. `rom` is the full program including its header, as
+    /// it would be laid out in EWRAM; it must be between
+    /// [MIN_ROM_LEN](super::MIN_ROM_LEN) and [MAX_ROM_LEN](super::MAX_ROM_LEN)
+    /// bytes long.
+    pub fn send_multiboot(
+        &mut self,
+        rom: &[u8],
+        options: MultibootOptions,
+    ) -> Result<(), MultibootError> {
+        self.send_multiboot_with(rom, options, &CancelToken::new(), |_| {})
+    }
+
+    /// Like [Self::send_multiboot], but reports the transfer's progress to
+    /// `on_progress` and stops early if `cancel` is cancelled.
+    ///
+    /// Progress is reported at the start of each phase, and then every
+    /// [PROGRESS_INTERVAL] bytes of the payload.
+    pub fn send_multiboot_with(
+        &mut self,
+        rom: &[u8],
+        options: MultibootOptions,
+        cancel: &CancelToken,
+        on_progress: impl FnMut(&MultibootProgress),
+    ) -> Result<(), MultibootError> {
+        self.send_multiboot_from(&mut &*rom, options, cancel, on_progress)
+    }
+
+    /// Like [Self::send_multiboot_with], but pulls the program from `provider`
+    /// a piece at a time instead of needing all of it in memory at once.
+    pub fn send_multiboot_from<P: ChunkProvider + ?Sized>(
+        &mut self,
+        provider: &mut P,
+        options: MultibootOptions,
+        cancel: &CancelToken,
+        on_progress: impl FnMut(&MultibootProgress),
+    ) -> Result<(), MultibootError> {
+        let len = provider.total_len();
+        validate_len(len)?;
+        if !self.clock().is_internal() {
+            return Err(MultibootError::NotParent);
+        }
+        let mut monitor = Monitor::new(len, cancel, on_progress);
+        let mut header = [0; HEADER_LEN];
+        provider.read(0, &mut header);
+
+        monitor.phase(MultibootPhase::Detecting)?;
+        self.detect_client(options.detect_attempts, &monitor)?;
+        monitor.set_clients(
+            ClientSet::from_bits(ClientSet::bit(PlayerId::P1)),
+            ClientSet::EMPTY,
+        );
+        self.send_word(CONFIRM_CLIENT);
+
+        monitor.phase(MultibootPhase::Header)?;
+        for chunk in header.chunks_exact(2) {
+            monitor.check()?;
+            let halfword = u16::from_le_bytes([chunk[0], chunk[1]]);
+            self.send_word(halfword as u32);
+        }
+        monitor.sent(HEADER_LEN)?;
+        self.send_word(HEADER_DONE);
+        self.send_word(HANDSHAKE);
+
+        monitor.phase(MultibootPhase::ClientData)?;
+        let answer = self.exchange_client_data(options.palette, &monitor)?;
+        let keys = Keys::new((answer >> 16) as u8, options.palette);
+        self.send_word(CONFIRM_HANDSHAKE_DATA | keys.crc_a as u32);
+        let crc_b = (self.send_word(((len - 0x190) / 4) as u32) >> 16) as u8;
+
+        monitor.phase(MultibootPhase::Payload)?;
+        let mut seed = keys.seed;
+        let mut crc = CRC_SEED;
+        let mut chunk = [0; CHUNK_LEN];
+        for start in (HEADER_LEN..len).step_by(CHUNK_LEN) {
+            if (start - HEADER_LEN) % PROGRESS_INTERVAL == 0 {
+                monitor.sent(start)?;
+            }
+            let chunk = &mut chunk[..CHUNK_LEN.min(len - start)];
+            provider.read(start, chunk);
+            for (idx, bytes) in chunk.chunks_exact(4).enumerate() {
+                let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                crc = crc_step(crc, word);
+                seed = next_seed(seed);
+                self.send_word(crypt_word(word, seed, start + idx * 4));
+            }
+        }
+        monitor.sent(len)?;
+        let crc = keys.final_crc(crc, crc_b);
+
+        monitor.phase(MultibootPhase::Checksum)?;
+        self.send_word(WAIT_CHECK);
+        let mut checked = false;
+        for _ in 0..CHECK_ATTEMPTS {
+            monitor.check()?;
+            if self.send_word(WAIT_CHECK) >> 16 == ACK_CHECK {
+                checked = true;
+                break;
+            }
+        }
+        if !checked {
+            return Err(MultibootError::TransferFailed);
+        }
+        self.send_word(SEND_CRC);
+        self.send_word(crc);
+        monitor.phase(MultibootPhase::Done)?;
+        Ok(())
+    }
+
+    /// Sends the palette until the client answers with its client data,
+    /// returning the whole answer.
+    fn exchange_client_data<F>(
+        &mut self,
+        palette: u8,
+        monitor: &Monitor<'_, F>,
+    ) -> Result<u32, MultibootError> {
+        let mut timeout = VBlankTimeout::new(Some(PALETTE_TIMEOUT_FRAMES));
+        loop {
+            monitor.check()?;
+            let answer = self.send_word(SEND_PALETTE | palette as u32);
+            if answer >> 24 == ACK_RESPONSE {
+                return Ok(answer);
+            }
+            if timeout.expired() {
+                return Err(MultibootError::HandshakeFailed);
+            }
+        }
+    }
+
+    /// Exchanges a single word with the client, after leaving it time to
+    /// reload its data register since the last transfer.
+    fn send_word(&mut self, word: u32) -> u32 {
+        // Crossing 2 scanline boundaries waits at least 1 full line.
+        wait_next_scanline();
+        wait_next_scanline();
+        self.exchange_u32(word)
+    }
+
+    /// Sends [HANDSHAKE] until the client answers.
+    fn detect_client<F>(
+        &mut self,
+        attempts: u32,
+        monitor: &Monitor<'_, F>,
+    ) -> Result<(), MultibootError> {
+        for _ in 0..attempts {
+            monitor.check()?;
+            if self.send_word(HANDSHAKE) >> 16 == ACK_HANDSHAKE {
+                return Ok(());
+            }
+            wait_sixteenth();
+        }
+        Err(MultibootError::NoClients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_crypto(_gba: &mut Gba) {
+        let keys = Keys::new(0x12, 0xD1);
+        assert_eq!(keys.seed, 0xFFFF_12D1);
+        assert_eq!(keys.crc_a, 0x21);
+
+        let seed = next_seed(keys.seed);
+        let word = 0xDEAD_BEEF;
+        let encrypted = crypt_word(word, seed, HEADER_LEN);
+        assert_ne!(encrypted, word);
+        assert_eq!(crypt_word(encrypted, seed, HEADER_LEN), word);
+        assert_ne!(crypt_word(encrypted, seed, HEADER_LEN + 4), word);
+
+        assert_eq!(crc_step(0, 0), 0);
+        assert_eq!(crc_step(1, 1), 0);
+        let crc = crc_step(CRC_SEED, word);
+        assert_ne!(crc, crc_step(CRC_SEED, word ^ 0x100));
+        assert!(crc <= 0xFFFF);
+    }
+}
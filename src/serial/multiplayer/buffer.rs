@@ -33,6 +33,9 @@ pub struct TransferBuffer {
     /// (`self.read_idx == self.write_idx`) from the "full" (`self.read_idx +
     /// self.bufflen == self.write_idx`).
     write_idx: Mutex<Cell<usize>>,
+    /// Whether or not `self.buffer` was allocated by us & needs to be freed
+    /// on drop, rather than borrowed from a `static`.
+    owned: bool,
 }
 
 /// #SAFETY
@@ -53,7 +56,7 @@ impl Default for TransferBuffer {
 }
 impl Drop for TransferBuffer {
     fn drop(&mut self) {
-        if self.buffer.is_null() {
+        if self.buffer.is_null() || !self.owned {
             return;
         }
         unsafe {
@@ -75,6 +78,7 @@ impl TransferBuffer {
             bufflen: 0,
             read_idx: Mutex::new(Cell::new(0)),
             write_idx: Mutex::new(Cell::new(0)),
+            owned: false,
         }
     }
     /// Checks whether or not this is a real `TransferBuffer` or just an empty
@@ -92,6 +96,22 @@ impl TransferBuffer {
             bufflen: cap,
             read_idx: Mutex::new(Cell::new(0)),
             write_idx: Mutex::new(Cell::new(0)),
+            owned: true,
+        }
+    }
+
+    /// Constructs a new multiplayer bulk transfer buffer using `storage`
+    /// instead of a heap allocation, with a capacity (per player) of a
+    /// quarter of `storage.len()`.
+    pub fn from_static(storage: &'static mut [u16]) -> Self {
+        let cap = storage.len() / 4;
+        storage.fill(NO_DATA);
+        Self {
+            buffer: storage.as_mut_ptr(),
+            bufflen: cap,
+            read_idx: Mutex::new(Cell::new(0)),
+            write_idx: Mutex::new(Cell::new(0)),
+            owned: false,
         }
     }
 
@@ -147,21 +167,44 @@ impl TransferBuffer {
     /// Returns the words in the transfer, or `None` if the buffer is empty.
     pub fn pop(&self) -> Option<[u16; 4]> {
         critical_section::with(|cs| {
-            let retvl = self.peak_in(cs);
+            let retvl = self.peek_in(cs)?;
             let raw_ridx = self.read_idx.borrow(cs).get();
             self.read_idx
                 .borrow(cs)
                 .replace((raw_ridx + 1) % (2 * self.bufflen));
-            retvl
+            Some(retvl)
+        })
+    }
+
+    /// The number of transfers the buffer can hold.
+    pub const fn capacity(&self) -> usize {
+        self.bufflen
+    }
+    /// The number of transfers currently stored in the buffer.
+    pub fn len(&self) -> usize {
+        if self.is_placeholder() {
+            return 0;
+        }
+        critical_section::with(|cs| {
+            let raw_ridx = self.read_idx.borrow(cs).get();
+            let raw_widx = self.write_idx.borrow(cs).get();
+            len(raw_ridx, raw_widx, self.bufflen)
+        })
+    }
+    /// Drops every transfer currently stored in the buffer.
+    pub fn clear(&self) {
+        critical_section::with(|cs| {
+            let raw_widx = self.write_idx.borrow(cs).get();
+            self.read_idx.borrow(cs).set(raw_widx);
         })
     }
 
-    /// Peaks at the next data in the ringbuffer without consuming it.
-    pub fn peak(&self) -> Option<[u16; 4]> {
-        critical_section::with(|cs| self.peak_in(cs))
+    /// Peeks at the next data in the ringbuffer without consuming it.
+    pub fn peek(&self) -> Option<[u16; 4]> {
+        critical_section::with(|cs| self.peek_in(cs))
     }
 
-    fn peak_in(&self, cs: CriticalSection) -> Option<[u16; 4]> {
+    fn peek_in(&self, cs: CriticalSection) -> Option<[u16; 4]> {
         let raw_ridx = self.read_idx.borrow(cs).get();
         let raw_widx = self.write_idx.borrow(cs).get();
         if is_empty(raw_ridx, raw_widx, self.bufflen) {
@@ -188,6 +231,9 @@ impl TransferBuffer {
     /// it reports having read until; as such, all data in `buffers` can be
     /// considered unspecified as soon as it is passed to this function.
     pub fn read_bulk(&self, buffers: &mut [&mut [u16]; 4]) -> [usize; 4] {
+        if self.is_placeholder() {
+            return [0; 4];
+        }
         critical_section::with(|cs| {
             let ret = PlayerId::ALL.map(move |pid| {
                 let buffer = &mut buffers.as_mut()[pid as usize];
@@ -200,6 +246,22 @@ impl TransferBuffer {
             [inc; 4]
         })
     }
+    /// Reads only `player`'s words from the multiplayer buffer into `buffer`.
+    ///
+    /// Returns the number of words read. The other players' words from the
+    /// same transfers are consumed & dropped.
+    pub fn read_player(&self, player: PlayerId, buffer: &mut [u16]) -> usize {
+        if self.is_placeholder() {
+            return 0;
+        }
+        critical_section::with(|cs| {
+            let read = self.read_bulk_for_inner(cs, player, buffer);
+            let prev_ridx = self.read_idx.borrow(cs).get();
+            let next = (prev_ridx + read) % (2 * self.bufflen);
+            self.read_idx.borrow(cs).set(next);
+            read
+        })
+    }
     fn read_bulk_for_inner(
         &self,
         cs: CriticalSection<'_>,
@@ -237,16 +299,23 @@ impl TransferBuffer {
 /// Calculates the number of elements currently stored in the ringbuffer from
 /// the ringbuffer length and raw read & write indices (mod 2 * the buffer
 /// length).
+///
+/// A placeholder buffer (with a length of 0) is always empty.
 #[inline(always)]
-const fn len(ridx: usize, widx: usize, _bufflen: usize) -> usize {
-    widx - ridx
+const fn len(ridx: usize, widx: usize, bufflen: usize) -> usize {
+    if bufflen == 0 {
+        return 0;
+    }
+    ((widx + 2 * bufflen) - ridx) % (2 * bufflen)
 }
 
 /// Checks if the ringbuffer is full based on the ringbuffer length and raw read
 /// & write indices (mod 2 * the buffer length).
+///
+/// A placeholder buffer (with a length of 0) is always full.
 #[inline(always)]
 const fn is_full(ridx: usize, widx: usize, bufflen: usize) -> bool {
-    len(ridx, widx, bufflen) == bufflen
+    bufflen == 0 || len(ridx, widx, bufflen) == bufflen
 }
 
 /// Checks if the ringbuffer is empty based on the ringbuffer length and raw
@@ -268,7 +337,7 @@ mod tests {
     fn verify_size(_gba: &mut Gba) {
         assert_eq!(
             mem::size_of::<TransferBuffer>(),
-            4 * mem::size_of::<usize>()
+            5 * mem::size_of::<usize>()
         )
     }
 
@@ -305,6 +374,8 @@ mod tests {
             );
         }
         assert_eq!(buffer.pop(), None);
+        // Popping an empty buffer shouldn't move the read index.
+        assert_eq!(buffer.pop(), None);
         unsafe {
             let raw_mem = slice::from_raw_parts(buffer.buffer as *const _, buffer.bufflen * 4);
             for rawidx in 0..(BUFFER_SIZE * 4) {
@@ -358,4 +429,30 @@ mod tests {
             PlayerId::ALL.map(|pid| [39].map(|n| n + (100 * (pid as u16 + 1))))
         );
     }
+    #[test_case]
+    fn test_buffer_read_player(_gba: &mut Gba) {
+        let buffer = TransferBuffer::new(4);
+        critical_section::with(|cs| {
+            for n in 0..3 {
+                buffer.push(n + 100, n + 200, n + 300, n + 400, 0, cs).unwrap();
+            }
+        });
+        let mut outbuff = [0xFFFF; 2];
+        assert_eq!(buffer.read_player(PlayerId::P2, &mut outbuff), 2);
+        assert_eq!(outbuff, [300, 301]);
+        assert_eq!(buffer.read_player(PlayerId::P1, &mut outbuff), 1);
+        assert_eq!(outbuff[..1], [202]);
+        assert_eq!(buffer.read_player(PlayerId::P1, &mut outbuff), 0);
+    }
+    #[test_case]
+    fn test_buffer_placeholder(_gba: &mut Gba) {
+        let buffer = TransferBuffer::empty();
+        critical_section::with(|cs| {
+            assert_eq!(buffer.push(1, 2, 3, 4, 0, cs), Err(()));
+        });
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.pop(), None);
+        let mut outbuff = [0u16; 4];
+        assert_eq!(buffer.read_player(PlayerId::P1, &mut outbuff), 0);
+    }
 }
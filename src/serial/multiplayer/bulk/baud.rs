@@ -0,0 +1,588 @@
+//! Coordinated baud rate negotiation for bulk multiplayer sessions, since a
+//! unit running at a different rate than the rest silently corrupts every
+//! transfer.
+//!
+//! The parent proposes a rate, every child confirms whether it supports it, &
+//! then the parent tells everyone to switch; [BulkMultiplayer::set_baud_rate]
+//! does the same with a single rate that every unit already agreed on. The
+//! parent never switches until every child has confirmed, so a child that
+//! missed the proposal can't be left behind at the old rate. Each step goes over the link as an
+//! [escaped](super::escape) control message:
+//!
+//! | Word | Contents |
+//! | :--  | :--      |
+//! | 1    | [CONTROL_MAGIC] |
+//! | 1    | The operation |
+//! | 1    | The argument; for baud rate operations, the [BaudRate] it's about as its `SIOCNT` bits |
+//!
+//! The same control messages are also used by
+//! [host migration](super::migrate).
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::escape::{escape, Unescaper};
+use super::{BulkMultiplayer, PlayerId, QueueError};
+use crate::serial::multiplayer::{BaudRate, MultiplayerError, MultiplayerSiocnt};
+use crate::utils::FrameTimeout;
+
+/// Marks the start of every control message.
+pub const CONTROL_MAGIC: u16 = 0xBA0D;
+
+/// The number of frames the parent waits after telling everyone to switch
+/// before switching itself, so that every child has a chance to switch first.
+const SETTLE_FRAMES: u32 = 2;
+
+/// The operations a control message can carry.
+#[repr(u16)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(super) enum Op {
+    Propose = 1,
+    Accept = 2,
+    Reject = 3,
+    Switch = 4,
+    Abort = 5,
+    /// Sent during [host migration](super::migrate), with the sender's old
+    /// [PlayerId].
+    Migrate = 7,
+}
+
+impl Op {
+    const fn from_word(word: u16) -> Option<Self> {
+        match word {
+            1 => Some(Op::Propose),
+            2 => Some(Op::Accept),
+            3 => Some(Op::Reject),
+            4 => Some(Op::Switch),
+            5 => Some(Op::Abort),
+            7 => Some(Op::Migrate),
+            _ => None,
+        }
+    }
+}
+
+const fn rate_from_word(word: u16) -> Option<BaudRate> {
+    match word {
+        0 => Some(BaudRate::B9600),
+        1 => Some(BaudRate::B38400),
+        2 => Some(BaudRate::B57600),
+        3 => Some(BaudRate::B115200),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BaudError {
+    /// `player` doesn't support the proposed rate, so the session stays at the
+    /// current one.
+    Rejected {
+        player: PlayerId,
+        rate: BaudRate,
+    },
+    /// There isn't room in the outbox for a control message.
+    OutboxFull,
+    /// Not every unit answered before the timeout.
+    Timeout,
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for BaudError {
+    fn from(value: MultiplayerError) -> Self {
+        BaudError::MultiplayerError(value)
+    }
+}
+
+/// What the next decoded word from a player will be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ControlState {
+    Magic,
+    Op,
+    Arg(Op),
+}
+
+impl ControlState {
+    /// Moves on to the next state after the decoded `word`, returning the
+    /// control message it finished, if any.
+    fn advance(&mut self, word: u16) -> Option<(Op, u16)> {
+        let mut done = None;
+        *self = match *self {
+            ControlState::Magic if word == CONTROL_MAGIC => ControlState::Op,
+            ControlState::Magic => ControlState::Magic,
+            ControlState::Op => match Op::from_word(word) {
+                Some(op) => ControlState::Arg(op),
+                None => ControlState::Magic,
+            },
+            ControlState::Arg(op) => {
+                done = Some((op, word));
+                ControlState::Magic
+            }
+        };
+        done
+    }
+}
+
+/// Rebuilds the control messages sent by a single player, word by word.
+pub(super) struct ControlParser {
+    unescaper: Unescaper,
+    state: ControlState,
+    done: VecDeque<(Op, u16)>,
+}
+
+impl ControlParser {
+    pub(super) const fn new() -> Self {
+        Self {
+            unescaper: Unescaper::new(),
+            state: ControlState::Magic,
+            done: VecDeque::new(),
+        }
+    }
+    pub(super) fn feed(&mut self, word: u16) {
+        let word = match self.unescaper.push(word) {
+            None => return,
+            Some(Ok(word)) => word,
+            Some(Err(_)) => {
+                self.state = ControlState::Magic;
+                return;
+            }
+        };
+        if let Some(message) = self.state.advance(word) {
+            self.done.push_back(message);
+        }
+    }
+    /// The next control message received, with its argument.
+    pub(super) fn pop(&mut self) -> Option<(Op, u16)> {
+        self.done.pop_front()
+    }
+    /// The next control message received that's about a valid [BaudRate].
+    fn pop_rate(&mut self) -> Option<(Op, BaudRate)> {
+        while let Some((op, arg)) = self.pop() {
+            if let Some(rate) = rate_from_word(arg) {
+                return Some((op, rate));
+            }
+        }
+        None
+    }
+}
+
+/// Watches the parent's stream for [Op::Switch], without keeping anything
+/// else, so that it can run in the transfer interrupt for a
+/// [Spectator](super::spectator::Spectator).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct SwitchWatcher {
+    unescaper: Unescaper,
+    state: ControlState,
+    /// The rate the parent most recently told everyone to switch to.
+    switched: Option<BaudRate>,
+}
+
+impl SwitchWatcher {
+    pub(super) const fn new() -> Self {
+        Self {
+            unescaper: Unescaper::new(),
+            state: ControlState::Magic,
+            switched: None,
+        }
+    }
+    pub(super) fn feed(&mut self, word: u16) {
+        let word = match self.unescaper.push(word) {
+            None => return,
+            Some(Ok(word)) => word,
+            Some(Err(_)) => {
+                self.state = ControlState::Magic;
+                return;
+            }
+        };
+        if let Some((Op::Switch, arg)) = self.state.advance(word) {
+            self.switched = rate_from_word(arg).or(self.switched);
+        }
+    }
+    /// The rate the parent told everyone to switch to since the last call,
+    /// if any.
+    pub(super) fn take_switch(&mut self) -> Option<BaudRate> {
+        self.switched.take()
+    }
+}
+
+/// Builds the escaped words of a single control message.
+fn control_message(op: Op, arg: u16) -> Vec<u16> {
+    let mut message = Vec::new();
+    escape(&[CONTROL_MAGIC, op as u16, arg], &mut message);
+    message
+}
+
+/// The parent's side of a negotiation, kept apart from the link.
+struct ParentNegotiation {
+    rate: BaudRate,
+    /// The peers that haven't accepted yet, as a bitmask by [PlayerId].
+    waiting: u8,
+}
+
+impl ParentNegotiation {
+    fn new(me: PlayerId, peers: &[PlayerId], rate: BaudRate) -> Self {
+        let waiting = peers
+            .iter()
+            .filter(|&&p| p != me)
+            .fold(0, |mask, &p| mask | (1 << p as u8));
+        Self { rate, waiting }
+    }
+    /// Handles a control message from `player`, failing if they rejected the
+    /// rate.
+    fn receive(&mut self, player: PlayerId, op: Op, rate: BaudRate) -> Result<(), BaudError> {
+        match op {
+            _ if rate != self.rate => {}
+            Op::Accept => self.waiting &= !(1 << player as u8),
+            Op::Reject if self.waiting & (1 << player as u8) != 0 => {
+                return Err(BaudError::Rejected { player, rate })
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+    fn all_accepted(&self) -> bool {
+        self.waiting == 0
+    }
+}
+
+/// What a child does in response to a control message from the parent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ChildStep {
+    Reply(Op, BaudRate),
+    Switch(BaudRate),
+    Abort(BaudRate),
+}
+
+/// A child's response to a control message from the parent, if it needs one.
+fn child_step(supported: &[BaudRate], op: Op, rate: BaudRate) -> Option<ChildStep> {
+    match op {
+        Op::Propose if supported.contains(&rate) => Some(ChildStep::Reply(Op::Accept, rate)),
+        Op::Propose => Some(ChildStep::Reply(Op::Reject, rate)),
+        Op::Switch => Some(ChildStep::Switch(rate)),
+        Op::Abort => Some(ChildStep::Abort(rate)),
+        Op::Accept | Op::Reject | Op::Migrate => None,
+    }
+}
+
+impl<'a> BulkMultiplayer<'a> {
+    /// Switches the whole session to a new baud rate, if every unit supports
+    /// it. Returns the rate the session ends up running at.
+    ///
+    /// The parent proposes the first rate in `supported`; every child accepts
+    /// it if it's anywhere in their own `supported`. Every unit in `peers`
+    /// must call this at around the same time, & it blocks until the switch
+    /// is done or `timeout_frames` frames pass, calling [Self::tick] once per
+    /// frame. Everything else received in the meantime is dropped.
+    ///
+    /// Every unit is kept ready for transfers for the whole negotiation, even
+    /// with [Self::block_transfers_until_have_data] set.
+    pub fn negotiate_baud_rate(
+        &mut self,
+        peers: &[PlayerId],
+        supported: &[BaudRate],
+        timeout_frames: Option<u32>,
+    ) -> Result<BaudRate, BaudError> {
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        if self.inner.is_parent() {
+            let rate = supported.first().copied().unwrap_or(self.inner.rate);
+            self.negotiate_as_parent(peers, rate, &mut timeout)
+        } else {
+            self.negotiate_as_child(supported, &mut timeout)
+        }
+    }
+
+    /// The baud rate this unit is currently running at.
+    pub fn baud_rate(&self) -> BaudRate {
+        self.inner.baud_rate()
+    }
+    /// Switches the whole session to `rate` without asking whether every unit
+    /// supports it, e.g. to drop to [BaudRate::B9600] for a long cable or
+    /// ramp back up once the link is stable. Returns the rate the session
+    /// ends up running at.
+    ///
+    /// Every unit in `peers` must call this with the same `rate` at around
+    /// the same time. Everything already queued is sent at the old rate
+    /// first; then the parent waits for every child in `peers` to confirm
+    /// `rate` before telling them to switch, & every unit resumes at the new
+    /// rate. If any child doesn't confirm, the parent tells the others to
+    /// stay at the old rate & returns [BaudError::Timeout]. Blocks until the
+    /// switch is done or `timeout_frames` frames pass, calling [Self::tick]
+    /// once per frame. Everything received in the meantime is dropped.
+    pub fn set_baud_rate(
+        &mut self,
+        peers: &[PlayerId],
+        rate: BaudRate,
+        timeout_frames: Option<u32>,
+    ) -> Result<BaudRate, BaudError> {
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        self.drain_outbox(&mut timeout)?;
+        if self.inner.is_parent() {
+            self.negotiate_as_parent(peers, rate, &mut timeout)
+        } else {
+            self.negotiate_as_child(&[rate], &mut timeout)
+        }
+    }
+
+    fn negotiate_as_parent(
+        &mut self,
+        peers: &[PlayerId],
+        rate: BaudRate,
+        timeout: &mut FrameTimeout,
+    ) -> Result<BaudRate, BaudError> {
+        self.keeping_ready(|this| {
+            this.send_control(Op::Propose, rate)?;
+            let mut parsers = [
+                ControlParser::new(),
+                ControlParser::new(),
+                ControlParser::new(),
+                ControlParser::new(),
+            ];
+            let mut negotiation = ParentNegotiation::new(this.id(), peers, rate);
+            loop {
+                this.tick().map_err(MultiplayerError::from)?;
+                this.drain_received(|player, word| parsers[player as usize].feed(word))?;
+                for player in PlayerId::ALL {
+                    while let Some((op, answered)) = parsers[player as usize].pop_rate() {
+                        if let Err(e) = negotiation.receive(player, op, answered) {
+                            this.send_control(Op::Abort, rate)?;
+                            return Err(e);
+                        }
+                    }
+                }
+                if negotiation.all_accepted() {
+                    break;
+                }
+                if !timeout.wait() {
+                    this.send_control(Op::Abort, rate)?;
+                    return Err(BaudError::Timeout);
+                }
+            }
+            this.switch_as_parent(rate, timeout)
+        })
+    }
+
+    /// Tells every child to switch to `rate` once they've all accepted it,
+    /// waits for that to be sent, & then switches ourselves.
+    fn switch_as_parent(
+        &mut self,
+        rate: BaudRate,
+        timeout: &mut FrameTimeout,
+    ) -> Result<BaudRate, BaudError> {
+        self.send_control(Op::Switch, rate)?;
+        self.drain_outbox(timeout)?;
+        let mut settle = FrameTimeout::new(Some(SETTLE_FRAMES));
+        while settle.wait() {}
+        self.switch_baud_rate(rate);
+        Ok(rate)
+    }
+
+    /// Ticks until everything queued so far has been sent.
+    fn drain_outbox(&mut self, timeout: &mut FrameTimeout) -> Result<(), BaudError> {
+        while !self.outbox_drained() {
+            self.tick().map_err(MultiplayerError::from)?;
+            if !timeout.wait() {
+                return Err(BaudError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn negotiate_as_child(
+        &mut self,
+        supported: &[BaudRate],
+        timeout: &mut FrameTimeout,
+    ) -> Result<BaudRate, BaudError> {
+        // We won't have anything to send until the parent's proposal arrives,
+        // which it can't while we're unready.
+        self.keeping_ready(|this| {
+            let mut parser = ControlParser::new();
+            loop {
+                this.tick().map_err(MultiplayerError::from)?;
+                this.drain_received(|player, word| {
+                    if player == PlayerId::P0 {
+                        parser.feed(word)
+                    }
+                })?;
+                while let Some((op, rate)) = parser.pop_rate() {
+                    match child_step(supported, op, rate) {
+                        Some(ChildStep::Reply(op, rate)) => this.send_control(op, rate)?,
+                        Some(ChildStep::Switch(rate)) => {
+                            this.switch_baud_rate(rate);
+                            return Ok(rate);
+                        }
+                        Some(ChildStep::Abort(rate)) => {
+                            return Err(BaudError::Rejected {
+                                player: PlayerId::P0,
+                                rate,
+                            })
+                        }
+                        None => {}
+                    }
+                }
+                if !timeout.wait() {
+                    return Err(BaudError::Timeout);
+                }
+            }
+        })
+    }
+
+    fn send_control(&mut self, op: Op, rate: BaudRate) -> Result<(), BaudError> {
+        if self.queue_control(op, rate as u16)? {
+            Ok(())
+        } else {
+            Err(BaudError::OutboxFull)
+        }
+    }
+    /// Queues a control message with a raw argument word, returning `false`
+    /// without queueing anything if there isn't room for all of it.
+    pub(super) fn queue_control(&mut self, op: Op, arg: u16) -> Result<bool, MultiplayerError> {
+        let message = control_message(op, arg);
+        if self.queue_space() < message.len() {
+            return Ok(false);
+        }
+        match self.queue_send(&message) {
+            Ok(_) => Ok(true),
+            Err(QueueError::MultiplayerError(e)) => Err(e),
+            Err(QueueError::QueueNotEmpty) => Ok(false),
+        }
+    }
+
+    /// Switches this unit alone to `rate`.
+    pub(super) fn switch_baud_rate(&mut self, rate: BaudRate) {
+        self.inner.rate = rate;
+        MultiplayerSiocnt::get().set_baud_rate(rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::stays_ready;
+    use super::*;
+    use crate::serial::multiplayer::NO_DATA;
+    use agb::Gba;
+
+    /// A unit in [simulate_negotiation], running with the default
+    /// [BulkMultiplayer::block_transfers_until_have_data] of `true`.
+    struct SimUnit {
+        outbox: VecDeque<u16>,
+        ready: bool,
+        parser: ControlParser,
+    }
+
+    impl SimUnit {
+        fn new() -> Self {
+            Self {
+                outbox: VecDeque::new(),
+                // Idle units have been marked unready by the interrupt.
+                ready: false,
+                parser: ControlParser::new(),
+            }
+        }
+        fn send(&mut self, op: Op, rate: BaudRate) {
+            self.outbox.extend(control_message(op, rate as u16));
+            // Queueing re-enters multiplayer mode, which marks us ready.
+            self.ready = true;
+        }
+    }
+
+    /// Runs a negotiation between a parent & a single child over a simulated
+    /// link, returning the rate the child switched to, if any.
+    fn simulate_negotiation(keep_ready: bool, supported: &[BaudRate]) -> Option<BaudRate> {
+        let rate = BaudRate::B115200;
+        let mut parent = SimUnit::new();
+        let mut child = SimUnit::new();
+        if keep_ready {
+            parent.ready = true;
+            child.ready = true;
+        }
+        let mut negotiation = ParentNegotiation::new(PlayerId::P0, &[PlayerId::P1], rate);
+        parent.send(Op::Propose, rate);
+        for _ in 0..100 {
+            if !(parent.ready && child.ready) {
+                continue;
+            }
+            let from_parent = parent.outbox.pop_front().unwrap_or(NO_DATA);
+            let from_child = child.outbox.pop_front().unwrap_or(NO_DATA);
+            for unit in [&mut parent, &mut child] {
+                unit.ready = stays_ready(!unit.outbox.is_empty(), true, keep_ready);
+            }
+            child.parser.feed(from_parent);
+            while let Some((op, rate)) = child.parser.pop_rate() {
+                match child_step(supported, op, rate) {
+                    Some(ChildStep::Reply(op, rate)) => child.send(op, rate),
+                    Some(ChildStep::Switch(rate)) => return Some(rate),
+                    Some(ChildStep::Abort(_)) => return None,
+                    None => {}
+                }
+            }
+            parent.parser.feed(from_child);
+            while let Some((op, answered)) = parent.parser.pop_rate() {
+                if negotiation.receive(PlayerId::P1, op, answered).is_err() {
+                    parent.send(Op::Abort, rate);
+                } else if negotiation.all_accepted() {
+                    parent.send(Op::Switch, rate);
+                }
+            }
+        }
+        None
+    }
+
+    #[test_case]
+    fn test_parent_waits_for_every_child(_gba: &mut Gba) {
+        let rate = BaudRate::B57600;
+        let peers = [PlayerId::P0, PlayerId::P1, PlayerId::P2];
+        let mut negotiation = ParentNegotiation::new(PlayerId::P0, &peers, rate);
+        assert_eq!(negotiation.receive(PlayerId::P1, Op::Accept, rate), Ok(()));
+        // Answers about a different rate don't count.
+        let other = BaudRate::B9600;
+        assert_eq!(negotiation.receive(PlayerId::P2, Op::Accept, other), Ok(()));
+        assert!(!negotiation.all_accepted());
+        assert_eq!(negotiation.receive(PlayerId::P2, Op::Accept, rate), Ok(()));
+        assert!(negotiation.all_accepted());
+
+        let mut negotiation = ParentNegotiation::new(PlayerId::P0, &peers, rate);
+        assert_eq!(
+            negotiation.receive(PlayerId::P2, Op::Reject, rate),
+            Err(BaudError::Rejected {
+                player: PlayerId::P2,
+                rate
+            })
+        );
+    }
+
+    #[test_case]
+    fn test_negotiation_default_blocking(_gba: &mut Gba) {
+        let supported = [BaudRate::B9600, BaudRate::B115200];
+        assert_eq!(
+            simulate_negotiation(true, &supported),
+            Some(BaudRate::B115200)
+        );
+        // Without being kept ready, the idle child never hears the proposal.
+        assert_eq!(simulate_negotiation(false, &supported), None);
+        assert_eq!(simulate_negotiation(true, &[BaudRate::B9600]), None);
+    }
+
+    #[test_case]
+    fn test_control_parser(_gba: &mut Gba) {
+        let mut stream = alloc::vec![NO_DATA, 7];
+        escape(&[CONTROL_MAGIC, Op::Propose as u16, 3], &mut stream);
+        // Unknown operations & rates are skipped.
+        escape(&[CONTROL_MAGIC, 9, CONTROL_MAGIC, 2, 7], &mut stream);
+        escape(&[CONTROL_MAGIC, Op::Switch as u16, 0], &mut stream);
+
+        let mut parser = ControlParser::new();
+        let mut watcher = SwitchWatcher::new();
+        for word in stream {
+            parser.feed(word);
+            watcher.feed(word);
+        }
+        assert_eq!(watcher.take_switch(), Some(BaudRate::B9600));
+        assert_eq!(watcher.take_switch(), None);
+        let messages: Vec<_> = core::iter::from_fn(|| parser.pop_rate()).collect();
+        assert_eq!(
+            messages,
+            [
+                (Op::Propose, BaudRate::B115200),
+                (Op::Switch, BaudRate::B9600)
+            ]
+        );
+    }
+}
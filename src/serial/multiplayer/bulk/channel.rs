@@ -0,0 +1,180 @@
+//! Multiple independent logical channels sharing a single bulk multiplayer
+//! session, so that e.g. input sync, chat, & file transfers don't have to
+//! share one stream.
+//!
+//! Channels are carried in the [packet layer](super::packet)'s header. Each
+//! [Channel] has its own outgoing queue & its own inbox per player; the queues
+//! take turns getting packets into the bulk outbox, so that a channel with a
+//! lot queued can't starve the others. Each queue holds a limited number of
+//! packets, so a channel that sends faster than the link can keep up with
+//! gets [PacketError::OutboxFull] instead of growing without bound.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::packet::{PacketError, PacketHeader, PacketMultiplayer, CHANNELS, MAX_PACKET_BYTES};
+use super::{BulkMultiplayer, PlayerId};
+
+/// Finds the next channel after `last` (wrapping around) whose bit is set in
+/// `ready`.
+fn next_ready(ready: u16, last: u8) -> Option<u8> {
+    (1..=CHANNELS)
+        .map(|offset| (last + offset) % CHANNELS)
+        .find(|&channel| ready & (1 << channel) != 0)
+}
+
+/// The shared state behind every [Channel] on a session.
+pub struct ChannelMux<'a> {
+    inner: PacketMultiplayer<'a>,
+    outboxes: [VecDeque<Vec<u8>>; CHANNELS as usize],
+    inboxes: [[VecDeque<Vec<u8>>; 4]; CHANNELS as usize],
+    /// The most packets each channel can have queued.
+    queue_cap: usize,
+    /// The channel that most recently got a packet into the bulk outbox.
+    last_sent: u8,
+}
+
+impl<'a> ChannelMux<'a> {
+    /// Starts multiplexing `inner`, letting each channel queue up to
+    /// `queue_cap` packets that haven't made it into the bulk outbox yet.
+    pub fn new(inner: BulkMultiplayer<'a>, queue_cap: usize) -> Self {
+        Self {
+            inner: PacketMultiplayer::new(inner),
+            outboxes: Default::default(),
+            inboxes: Default::default(),
+            queue_cap,
+            last_sent: CHANNELS - 1,
+        }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// Which players have joined since the last call; see
+    /// [PacketMultiplayer::take_joined]. Drops anything left unread from
+    /// before each of them joined.
+    pub fn take_joined(&mut self) -> [bool; 4] {
+        let joined = self.inner.take_joined();
+        for inboxes in self.inboxes.iter_mut() {
+            for (inbox, _) in inboxes.iter_mut().zip(joined).filter(|(_, j)| *j) {
+                inbox.clear();
+            }
+        }
+        joined
+    }
+    /// Moves queued packets into the bulk outbox & received packets into
+    /// their channels' inboxes, then performs any per-frame maintenance
+    /// required; see [BulkMultiplayer::tick]. Call this once per frame.
+    pub fn tick(&mut self) -> Result<(), PacketError> {
+        self.flush()?;
+        self.receive()?;
+        self.inner.tick()
+    }
+    /// Removes the channel layer, dropping anything still queued or unread.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        self.inner.into_inner()
+    }
+
+    /// Sends queued packets, 1 channel at a time, until the bulk outbox is
+    /// full or every queue is empty.
+    fn flush(&mut self) -> Result<(), PacketError> {
+        loop {
+            let ready = self
+                .outboxes
+                .iter()
+                .enumerate()
+                .filter(|(_, outbox)| !outbox.is_empty())
+                .fold(0u16, |mask, (channel, _)| mask | (1 << channel));
+            let Some(channel) = next_ready(ready, self.last_sent) else {
+                return Ok(());
+            };
+            let outbox = &mut self.outboxes[channel as usize];
+            let header = PacketHeader { channel, flags: 0 };
+            let Some(data) = outbox.front() else {
+                return Ok(());
+            };
+            match self.inner.send_packet_with(header, data) {
+                Ok(()) => {}
+                Err(PacketError::OutboxFull) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            outbox.pop_front();
+            self.last_sent = channel;
+        }
+    }
+    fn receive(&mut self) -> Result<(), PacketError> {
+        let me = self.id();
+        for player in PlayerId::ALL.into_iter().filter(|&p| p != me) {
+            while let Some(packet) = self.inner.recv_packet(player)? {
+                let channel = packet.header.channel as usize;
+                self.inboxes[channel][player as usize].push_back(packet.data);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handle to channel `ID` (below [CHANNELS]) of a [ChannelMux].
+///
+/// Handles are just markers, so any number of them can exist for the same
+/// channel. They can only be made with [Channel::new], which refuses to
+/// compile for an `ID` that's out of range.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Channel<const ID: u8> {
+    _valid: (),
+}
+
+impl<const ID: u8> Channel<ID> {
+    const VALID: () = assert!(ID < CHANNELS, "channel ID must be below CHANNELS");
+
+    pub const fn new() -> Self {
+        Self {
+            _valid: Self::VALID,
+        }
+    }
+    /// Queues `data` to be sent to every other player on this channel; it's
+    /// moved into the bulk outbox on [ChannelMux::tick] when there's room.
+    ///
+    /// Fails with [PacketError::OutboxFull] if this channel already has as
+    /// many packets queued as [ChannelMux::new] allows; see [Self::queued].
+    pub fn send(&self, mux: &mut ChannelMux, data: &[u8]) -> Result<(), PacketError> {
+        if data.len() > MAX_PACKET_BYTES {
+            return Err(PacketError::TooLong);
+        }
+        let outbox = &mut mux.outboxes[ID as usize];
+        if outbox.len() >= mux.queue_cap {
+            return Err(PacketError::OutboxFull);
+        }
+        outbox.push_back(data.into());
+        Ok(())
+    }
+    /// The next packet received from `player` on this channel, if any. Does
+    /// NOT block.
+    pub fn recv(&self, mux: &mut ChannelMux, player: PlayerId) -> Option<Vec<u8>> {
+        mux.inboxes[ID as usize][player as usize].pop_front()
+    }
+    /// The number of packets still waiting to be moved into the bulk outbox.
+    pub fn queued(&self, mux: &ChannelMux) -> usize {
+        mux.outboxes[ID as usize].len()
+    }
+}
+
+impl<const ID: u8> Default for Channel<ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_next_ready(_gba: &mut Gba) {
+        assert_eq!(next_ready(0, 3), None);
+        assert_eq!(next_ready(0b1001, 0), Some(3));
+        assert_eq!(next_ready(0b1001, 3), Some(0));
+        assert_eq!(next_ready(0b1000, 3), Some(3));
+        assert_eq!(next_ready(1 << 15, 15), Some(15));
+    }
+}
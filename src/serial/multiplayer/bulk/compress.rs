@@ -0,0 +1,341 @@
+//! An optional compression layer over the [CRC layer](super::crc), since
+//! input & state sync messages tend to be highly repetitive & link bandwidth
+//! is precious at the slower [BaudRate](crate::serial::multiplayer::BaudRate)s.
+//!
+//! Each message starts with a header word of [DELTA_FLAG] and/or [RLE_FLAG],
+//! followed by the payload:
+//!
+//! * With [DELTA_FLAG], each payload word was XORed with the same word of the
+//!   previous message from the same player, so unchanged words become 0.
+//! * With [RLE_FLAG], the payload is a series of chunks, each starting with a
+//!   control word: `RUN_FLAG | n` is followed by a single word repeated `n`
+//!   times, while a plain `n` is followed by `n` literal words.
+//!
+//! Run-length encoding is only used when it actually makes the message
+//! smaller. Since a delta can only be undone with the previous message, a
+//! whole "keyframe" message is sent every so often (see
+//! [CompressedMultiplayer::set_keyframe_interval]) so that receivers can pick
+//! back up after a corrupted message or a hot-join.
+
+use alloc::vec::Vec;
+
+use super::crc::{CrcMultiplayer, MessageError, MAX_MESSAGE_WORDS};
+use super::{BulkMultiplayer, PlayerId};
+use crate::serial::multiplayer::MultiplayerError;
+
+/// Set in the header of messages that were XORed against the previous one.
+pub const DELTA_FLAG: u16 = 0x1;
+/// Set in the header of messages that were run-length encoded.
+pub const RLE_FLAG: u16 = 0x2;
+/// Set in the control word of a run, rather than a series of literals.
+const RUN_FLAG: u16 = 0x8000;
+/// The longest run or series of literals a single chunk can hold.
+const MAX_CHUNK: usize = (RUN_FLAG - 1) as usize;
+/// Runs shorter than this are cheaper to send as literals.
+const MIN_RUN: usize = 3;
+
+/// The default number of messages sent between keyframes.
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionError {
+    /// The payload is longer than a message can hold once compressed, which
+    /// is 1 word less than [MAX_MESSAGE_WORDS].
+    TooLong,
+    /// There isn't room in the outbox for the whole message right now.
+    OutboxFull,
+    /// A message from this player was corrupted or couldn't be decompressed,
+    /// and was dropped.
+    Corrupted(PlayerId),
+    /// A delta from this player arrived without the message it's based on
+    /// (because that one was corrupted, or we joined late), and was dropped.
+    /// Messages from them will be delivered again from the next keyframe.
+    MissingKeyframe(PlayerId),
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for CompressionError {
+    fn from(value: MultiplayerError) -> Self {
+        CompressionError::MultiplayerError(value)
+    }
+}
+
+impl From<MessageError> for CompressionError {
+    fn from(value: MessageError) -> Self {
+        match value {
+            MessageError::TooLong => CompressionError::TooLong,
+            MessageError::OutboxFull => CompressionError::OutboxFull,
+            MessageError::Corrupted(player) => CompressionError::Corrupted(player),
+            MessageError::MultiplayerError(e) => CompressionError::MultiplayerError(e),
+        }
+    }
+}
+
+/// Appends the run-length encoding of `words` to `out`.
+fn rle_encode(words: &[u16], out: &mut Vec<u16>) {
+    let mut literals_start = 0;
+    let mut idx = 0;
+    while idx < words.len() {
+        let run = words[idx..]
+            .iter()
+            .take(MAX_CHUNK)
+            .take_while(|&&w| w == words[idx])
+            .count();
+        if run < MIN_RUN && idx - literals_start < MAX_CHUNK {
+            idx += 1;
+            continue;
+        }
+        push_literals(&words[literals_start..idx], out);
+        if run >= MIN_RUN {
+            out.extend_from_slice(&[RUN_FLAG | run as u16, words[idx]]);
+            idx += run;
+        }
+        literals_start = idx;
+    }
+    push_literals(&words[literals_start..], out);
+}
+
+fn push_literals(literals: &[u16], out: &mut Vec<u16>) {
+    if !literals.is_empty() {
+        out.push(literals.len() as u16);
+        out.extend_from_slice(literals);
+    }
+}
+
+/// Undoes [rle_encode], or returns `None` if `words` isn't a valid encoding.
+fn rle_decode(mut words: &[u16]) -> Option<Vec<u16>> {
+    let mut out = Vec::new();
+    while let Some((&control, rest)) = words.split_first() {
+        let len = (control & !RUN_FLAG) as usize;
+        if control & RUN_FLAG != 0 {
+            let (&value, rest) = rest.split_first()?;
+            out.extend(core::iter::repeat(value).take(len));
+            words = rest;
+        } else {
+            out.extend_from_slice(rest.get(..len)?);
+            words = &rest[len..];
+        }
+        if out.len() > MAX_MESSAGE_WORDS {
+            return None;
+        }
+    }
+    Some(out)
+}
+
+/// Compresses `payload` into a whole message, XORing it against `reference`
+/// if given. `reference` must be the same length as `payload`.
+fn compress(payload: &[u16], reference: Option<&[u16]>) -> Vec<u16> {
+    let mut header = 0;
+    let mut words = payload.to_vec();
+    if let Some(reference) = reference {
+        header |= DELTA_FLAG;
+        for (word, old) in words.iter_mut().zip(reference) {
+            *word ^= old;
+        }
+    }
+    let mut encoded = Vec::with_capacity(words.len() + 1);
+    encoded.push(header | RLE_FLAG);
+    rle_encode(&words, &mut encoded);
+    if encoded.len() > words.len() + 1 {
+        encoded.clear();
+        encoded.push(header);
+        encoded.extend_from_slice(&words);
+    }
+    encoded
+}
+
+/// Why a message couldn't be decompressed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DecompressError {
+    Invalid,
+    MissingKeyframe,
+}
+
+/// Undoes [compress], given the previous payload received from the same
+/// player.
+fn decompress(message: &[u16], previous: Option<&[u16]>) -> Result<Vec<u16>, DecompressError> {
+    let (&header, body) = message.split_first().ok_or(DecompressError::Invalid)?;
+    if header & !(DELTA_FLAG | RLE_FLAG) != 0 {
+        return Err(DecompressError::Invalid);
+    }
+    let mut words = if header & RLE_FLAG != 0 {
+        rle_decode(body).ok_or(DecompressError::Invalid)?
+    } else {
+        body.to_vec()
+    };
+    if header & DELTA_FLAG != 0 {
+        let previous = previous.ok_or(DecompressError::MissingKeyframe)?;
+        if previous.len() != words.len() {
+            return Err(DecompressError::Invalid);
+        }
+        for (word, old) in words.iter_mut().zip(previous) {
+            *word ^= old;
+        }
+    }
+    Ok(words)
+}
+
+/// Sends & receives whole messages like a [CrcMultiplayer], compressing them
+/// on the way.
+pub struct CompressedMultiplayer<'a> {
+    inner: CrcMultiplayer<'a>,
+    /// Whether or not to send deltas between keyframes.
+    delta: bool,
+    keyframe_interval: u32,
+    /// The messages sent since the last keyframe.
+    since_keyframe: u32,
+    last_sent: Option<Vec<u16>>,
+    last_received: [Option<Vec<u16>>; 4],
+}
+
+impl<'a> CompressedMultiplayer<'a> {
+    pub fn new(inner: BulkMultiplayer<'a>) -> Self {
+        Self {
+            inner: CrcMultiplayer::new(inner),
+            delta: true,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            since_keyframe: 0,
+            last_sent: None,
+            last_received: [None, None, None, None],
+        }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// Sets whether or not to send messages as deltas against the previous
+    /// one, rather than only run-length encoding them. Defaults to `true`.
+    pub fn set_delta(&mut self, value: bool) {
+        self.delta = value;
+    }
+    /// Sets the number of messages sent between keyframes, which bounds how
+    /// long a receiver can go without being able to decode our deltas.
+    /// Defaults to [DEFAULT_KEYFRAME_INTERVAL].
+    pub fn set_keyframe_interval(&mut self, messages: u32) {
+        self.keyframe_interval = messages;
+    }
+    /// Queues a whole message to be sent to every other player, compressed.
+    /// Nothing is queued unless the whole message fits.
+    pub fn send_message(&mut self, payload: &[u16]) -> Result<(), CompressionError> {
+        if payload.len() >= MAX_MESSAGE_WORDS {
+            return Err(CompressionError::TooLong);
+        }
+        let reference = self
+            .last_sent
+            .as_deref()
+            .filter(|last| self.delta && last.len() == payload.len())
+            .filter(|_| self.since_keyframe < self.keyframe_interval);
+        let message = compress(payload, reference);
+        let is_delta = reference.is_some();
+        self.inner.send_message(&message)?;
+        self.since_keyframe = if is_delta { self.since_keyframe + 1 } else { 0 };
+        self.last_sent = Some(payload.to_vec());
+        Ok(())
+    }
+    /// The next whole message from `player`, decompressed, if one has
+    /// arrived.
+    ///
+    /// A message that can't be recovered is reported once as an error & then
+    /// skipped. Does NOT block.
+    pub fn recv_message(&mut self, player: PlayerId) -> Result<Option<Vec<u16>>, CompressionError> {
+        let previous = &mut self.last_received[player as usize];
+        let message = match self.inner.recv_message(player) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                if let MessageError::Corrupted(_) = e {
+                    *previous = None;
+                }
+                return Err(e.into());
+            }
+        };
+        match decompress(&message, previous.as_deref()) {
+            Ok(payload) => {
+                *previous = Some(payload.clone());
+                Ok(Some(payload))
+            }
+            Err(DecompressError::MissingKeyframe) => Err(CompressionError::MissingKeyframe(player)),
+            Err(DecompressError::Invalid) => {
+                *previous = None;
+                Err(CompressionError::Corrupted(player))
+            }
+        }
+    }
+    /// Which players have joined since the last call; see
+    /// [CrcMultiplayer::take_joined]. If anyone joined, the next message we
+    /// send is a keyframe so that they can decode it.
+    pub fn take_joined(&mut self) -> [bool; 4] {
+        let joined = self.inner.take_joined();
+        for (previous, _) in self
+            .last_received
+            .iter_mut()
+            .zip(joined)
+            .filter(|(_, j)| *j)
+        {
+            *previous = None;
+        }
+        if joined.contains(&true) {
+            self.last_sent = None;
+        }
+        joined
+    }
+    /// Perform any per-frame maintenance required; see
+    /// [BulkMultiplayer::tick].
+    pub fn tick(&mut self) -> Result<(), CompressionError> {
+        Ok(self.inner.tick()?)
+    }
+    /// Removes the compression layer, dropping any partially received
+    /// messages.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        self.inner.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_rle(_gba: &mut Gba) {
+        let words = [1, 2, 0, 0, 0, 0, 3, 3, 4, 4, 4];
+        let mut encoded = Vec::new();
+        rle_encode(&words, &mut encoded);
+        assert_eq!(
+            encoded,
+            [2, 1, 2, RUN_FLAG | 4, 0, 2, 3, 3, RUN_FLAG | 3, 4]
+        );
+        assert_eq!(rle_decode(&encoded).as_deref(), Some(&words[..]));
+
+        let mut encoded = Vec::new();
+        rle_encode(&[], &mut encoded);
+        assert!(encoded.is_empty());
+        assert_eq!(rle_decode(&[3, 1, 2]), None);
+        assert_eq!(rle_decode(&[RUN_FLAG | 3]), None);
+    }
+
+    #[test_case]
+    fn test_compress(_gba: &mut Gba) {
+        let first = [0x1234, 0x5678, 7, 7, 7, 7];
+        let second = [0x1234, 0x5679, 7, 7, 7, 7];
+
+        let keyframe = compress(&first, None);
+        assert_eq!(keyframe[0], RLE_FLAG);
+        assert_eq!(decompress(&keyframe, None).as_deref(), Ok(&first[..]));
+
+        let delta = compress(&second, Some(&first));
+        assert_eq!(delta, [DELTA_FLAG | RLE_FLAG, 2, 0, 1, RUN_FLAG | 4, 0]);
+        assert_eq!(decompress(&delta, Some(&first)).as_deref(), Ok(&second[..]));
+        assert_eq!(
+            decompress(&delta, None),
+            Err(DecompressError::MissingKeyframe)
+        );
+
+        // Incompressible payloads are sent as-is.
+        assert_eq!(
+            compress(&[1, 2, 3], Some(&[1, 2, 4])),
+            [DELTA_FLAG, 0, 0, 7]
+        );
+        assert_eq!(compress(&[1, 2, 3], None), [0, 1, 2, 3]);
+        assert_eq!(decompress(&[4, 1], None), Err(DecompressError::Invalid));
+    }
+}
@@ -0,0 +1,283 @@
+//! An optional integrity layer over [BulkMultiplayer], which sends whole
+//! messages with a CRC-16 so that corrupted ones are reported rather than
+//! silently delivered.
+//!
+//! Each message goes over the link as:
+//!
+//! | Word(s)        | Contents |
+//! | :--            | :--      |
+//! | 1              | [MESSAGE_START] |
+//! | 1              | The payload's length in words |
+//! | length         | The payload |
+//! | 1              | The CRC-16 of the payload |
+//!
+//! Every word is then [escaped](super::escape), so payloads can contain any
+//! value, including [NO_DATA](super::NO_DATA).
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::escape::{escape, EscapeError, Unescaper};
+use super::{BulkMultiplayer, LinkStats, LinkStatus, PlayerId};
+use crate::serial::multiplayer::MultiplayerError;
+
+/// Marks the start of every message, so that the receiver can find the next
+/// one after a corrupted message.
+pub const MESSAGE_START: u16 = 0xA55A;
+/// The longest payload a single message can have.
+pub const MAX_MESSAGE_WORDS: usize = 1024;
+
+/// Calculates the CRC-16/CCITT-FALSE of `words`, each taken as 2 bytes with
+/// the high byte first.
+pub const fn crc16(words: &[u16]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    let mut idx = 0;
+    while idx < words.len() {
+        let bytes = words[idx].to_be_bytes();
+        let mut byte_idx = 0;
+        while byte_idx < 2 {
+            crc ^= (bytes[byte_idx] as u16) << 8;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                };
+                bit += 1;
+            }
+            byte_idx += 1;
+        }
+        idx += 1;
+    }
+    crc
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageError {
+    /// The payload is longer than [MAX_MESSAGE_WORDS].
+    TooLong,
+    /// There isn't room in the outbox for the whole message right now.
+    OutboxFull,
+    /// A message from this player didn't match its CRC, and was dropped.
+    Corrupted(PlayerId),
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for MessageError {
+    fn from(value: MultiplayerError) -> Self {
+        MessageError::MultiplayerError(value)
+    }
+}
+
+/// Appends the whole escaped message for `payload` to `out`.
+fn encode(payload: &[u16], out: &mut Vec<u16>) {
+    escape(&[MESSAGE_START, payload.len() as u16], out);
+    escape(payload, out);
+    escape(&[crc16(payload)], out);
+}
+
+/// What the next decoded word from a player will be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DecodeState {
+    Start,
+    Length,
+    Payload { remaining: usize },
+    Crc,
+}
+
+/// Rebuilds the messages sent by a single player, word by word.
+struct Decoder {
+    unescaper: Unescaper,
+    state: DecodeState,
+    payload: Vec<u16>,
+    done: VecDeque<Result<Vec<u16>, ()>>,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Self {
+            unescaper: Unescaper::new(),
+            state: DecodeState::Start,
+            payload: Vec::new(),
+            done: VecDeque::new(),
+        }
+    }
+    fn feed(&mut self, word: u16) {
+        let word = match self.unescaper.push(word) {
+            None => return,
+            Some(Ok(word)) => word,
+            Some(Err(e)) => {
+                // A message abandoned by a sender that's rejoining isn't
+                // corrupted, just gone.
+                if self.state != DecodeState::Start && e == EscapeError::InvalidEscape {
+                    self.done.push_back(Err(()));
+                }
+                self.payload.clear();
+                self.state = DecodeState::Start;
+                return;
+            }
+        };
+        self.state = match self.state {
+            DecodeState::Start if word == MESSAGE_START => DecodeState::Length,
+            DecodeState::Start => DecodeState::Start,
+            DecodeState::Length => match word as usize {
+                0 => DecodeState::Crc,
+                len if len <= MAX_MESSAGE_WORDS => DecodeState::Payload { remaining: len },
+                _ => {
+                    self.done.push_back(Err(()));
+                    DecodeState::Start
+                }
+            },
+            DecodeState::Payload { remaining } => {
+                self.payload.push(word);
+                match remaining - 1 {
+                    0 => DecodeState::Crc,
+                    remaining => DecodeState::Payload { remaining },
+                }
+            }
+            DecodeState::Crc => {
+                let payload = core::mem::take(&mut self.payload);
+                if word == crc16(&payload) {
+                    self.done.push_back(Ok(payload));
+                } else {
+                    self.done.push_back(Err(()));
+                }
+                DecodeState::Start
+            }
+        };
+    }
+}
+
+/// Sends & receives whole messages checked with a CRC-16.
+pub struct CrcMultiplayer<'a> {
+    inner: BulkMultiplayer<'a>,
+    decoders: [Decoder; 4],
+}
+
+impl<'a> CrcMultiplayer<'a> {
+    pub fn new(inner: BulkMultiplayer<'a>) -> Self {
+        Self {
+            inner,
+            decoders: [
+                Decoder::new(),
+                Decoder::new(),
+                Decoder::new(),
+                Decoder::new(),
+            ],
+        }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// Queues a whole message to be sent to every other player. Nothing is
+    /// queued unless the whole message fits.
+    pub fn send_message(&mut self, payload: &[u16]) -> Result<(), MessageError> {
+        if payload.len() > MAX_MESSAGE_WORDS {
+            return Err(MessageError::TooLong);
+        }
+        let mut message = Vec::new();
+        encode(payload, &mut message);
+        if self.inner.queue_space() < message.len() {
+            return Err(MessageError::OutboxFull);
+        }
+        match self.inner.queue_send(&message) {
+            Ok(_) => Ok(()),
+            Err(super::QueueError::MultiplayerError(e)) => Err(e.into()),
+            Err(super::QueueError::QueueNotEmpty) => Err(MessageError::OutboxFull),
+        }
+    }
+    /// Pulls any received data out of the bulk buffers, then returns the next
+    /// whole message from `player`, if one has arrived.
+    ///
+    /// A corrupted message is reported once as [MessageError::Corrupted] &
+    /// then skipped. Does NOT block.
+    pub fn recv_message(&mut self, player: PlayerId) -> Result<Option<Vec<u16>>, MessageError> {
+        self.pump()?;
+        match self.decoders[player as usize].done.pop_front() {
+            None => Ok(None),
+            Some(Ok(payload)) => Ok(Some(payload)),
+            Some(Err(())) => Err(MessageError::Corrupted(player)),
+        }
+    }
+    /// Which players have joined since the last call; see
+    /// [BulkMultiplayer::take_joined]. Drops any partial message from before
+    /// each of them joined.
+    pub fn take_joined(&mut self) -> [bool; 4] {
+        let joined = self.inner.take_joined();
+        for (decoder, _) in self.decoders.iter_mut().zip(joined).filter(|(_, j)| *j) {
+            *decoder = Decoder::new();
+        }
+        joined
+    }
+    /// See [BulkMultiplayer::connection_status].
+    pub fn connection_status(&self) -> [LinkStatus; 4] {
+        self.inner.connection_status()
+    }
+    /// See [BulkMultiplayer::queued_words].
+    pub fn queued_words(&self) -> usize {
+        self.inner.queued_words()
+    }
+    /// See [BulkMultiplayer::stats].
+    pub fn stats(&self) -> LinkStats {
+        self.inner.stats()
+    }
+    /// Perform any per-frame maintenance required; see
+    /// [BulkMultiplayer::tick].
+    pub fn tick(&mut self) -> Result<(), MessageError> {
+        self.inner
+            .tick()
+            .map_err(|e| MultiplayerError::from(e).into())
+    }
+    /// Removes the CRC layer, dropping any partially received messages.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        self.inner
+    }
+    /// Feeds everything received so far into the decoders.
+    fn pump(&mut self) -> Result<(), MultiplayerError> {
+        let decoders = &mut self.decoders;
+        self.inner
+            .drain_received(|player, word| decoders[player as usize].feed(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::multiplayer::NO_DATA;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_crc16(_gba: &mut Gba) {
+        // "12345678", the usual check string minus its odd last byte.
+        assert_eq!(crc16(&[0x3132, 0x3334, 0x3536, 0x3738]), 0xA12B);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test_case]
+    fn test_decoder(_gba: &mut Gba) {
+        let mut stream = Vec::new();
+        encode(&[1, 2, 3], &mut stream);
+        encode(&[], &mut stream);
+        let corrupted = stream.len() + 3;
+        encode(&[4, 5], &mut stream);
+        encode(&[NO_DATA, 6], &mut stream);
+        stream[corrupted] ^= 1;
+
+        let mut decoder = Decoder::new();
+        // Idle words & garbage before the first message are skipped.
+        for word in [NO_DATA, 7, NO_DATA].into_iter().chain(stream) {
+            decoder.feed(word);
+        }
+        let messages: Vec<_> = decoder.done.into_iter().collect();
+        assert_eq!(
+            messages,
+            [
+                Ok(alloc::vec![1, 2, 3]),
+                Ok(Vec::new()),
+                Err(()),
+                Ok(alloc::vec![NO_DATA, 6])
+            ]
+        );
+    }
+}
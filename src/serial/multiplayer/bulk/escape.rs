@@ -0,0 +1,143 @@
+//! A word-stuffing codec that makes every 16-bit value legal on the link,
+//! including [NO_DATA].
+//!
+//! The hardware reports a disconnected unit as sending [NO_DATA], so any
+//! [NO_DATA] words in the raw stream have to be treated as idle & skipped.
+//! Before sending, [NO_DATA] & [ESC] words are replaced by 2-word escape
+//! sequences; an [Unescaper] on the other end skips the idle words & turns the
+//! escape sequences back into the original words.
+//!
+//! The [CRC](super::crc) & [packet](super::packet) layers already escape
+//! everything they send. When using [BulkMultiplayer](super::BulkMultiplayer)
+//! directly, pass words through [escape] before queueing them & run each
+//! player's received words through their own [Unescaper].
+
+use alloc::vec::Vec;
+
+use super::NO_DATA;
+
+/// Marks the start of an escape sequence.
+pub const ESC: u16 = 0xFFFE;
+/// Follows an [ESC] to represent a literal [ESC] word.
+pub const ESC_ESC: u16 = 0x0000;
+/// Follows an [ESC] to represent a literal [NO_DATA] word.
+pub const ESC_NO_DATA: u16 = 0x0001;
+/// Follows an [ESC] to mark that the sender has just started a new session,
+/// so that anything partially decoded from it before is stale; see
+/// [BulkMultiplayer::take_joined](super::BulkMultiplayer::take_joined).
+pub const ESC_JOIN: u16 = 0x0002;
+
+/// The words every unit sends first when it starts a session.
+pub const JOIN_ANNOUNCEMENT: [u16; 2] = [ESC, ESC_JOIN];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EscapeError {
+    /// An [ESC] word was followed by something other than [ESC_ESC],
+    /// [ESC_NO_DATA], or [ESC_JOIN].
+    InvalidEscape,
+    /// The sender started a new session, with a [JOIN_ANNOUNCEMENT].
+    Joined,
+}
+
+/// Calculates how many words [escape] will write for `words`.
+pub fn escaped_len(words: &[u16]) -> usize {
+    let escapes = words
+        .iter()
+        .filter(|w| matches!(**w, NO_DATA | ESC))
+        .count();
+    words.len() + escapes
+}
+
+/// Escapes `words`, passing each escaped word to `sink` in order.
+pub fn escape_with(words: &[u16], mut sink: impl FnMut(u16)) {
+    for word in words {
+        match *word {
+            ESC => {
+                sink(ESC);
+                sink(ESC_ESC);
+            }
+            NO_DATA => {
+                sink(ESC);
+                sink(ESC_NO_DATA);
+            }
+            other => sink(other),
+        }
+    }
+}
+
+/// Escapes `words`, appending them to `out`.
+pub fn escape(words: &[u16], out: &mut Vec<u16>) {
+    out.reserve(escaped_len(words));
+    escape_with(words, |word| out.push(word));
+}
+
+/// Incrementally decodes a single player's stream of escaped words.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Unescaper {
+    /// Whether or not the previous word was an [ESC].
+    escaped: bool,
+}
+
+impl Unescaper {
+    pub const fn new() -> Self {
+        Self { escaped: false }
+    }
+    /// Feeds a single received word into the decoder, returning the decoded
+    /// word once there is one. [NO_DATA] words are skipped.
+    pub fn push(&mut self, word: u16) -> Option<Result<u16, EscapeError>> {
+        if word == NO_DATA {
+            return None;
+        }
+        if !self.escaped {
+            if word == ESC {
+                self.escaped = true;
+                return None;
+            }
+            return Some(Ok(word));
+        }
+        self.escaped = false;
+        Some(match word {
+            ESC_ESC => Ok(ESC),
+            ESC_NO_DATA => Ok(NO_DATA),
+            ESC_JOIN => Err(EscapeError::Joined),
+            _ => Err(EscapeError::InvalidEscape),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_escape(_gba: &mut Gba) {
+        let words = [1, NO_DATA, ESC, 0, NO_DATA];
+        let mut escaped = Vec::new();
+        escape(&words, &mut escaped);
+        assert_eq!(escaped_len(&words), escaped.len());
+        assert_eq!(
+            escaped,
+            [1, ESC, ESC_NO_DATA, ESC, ESC_ESC, 0, ESC, ESC_NO_DATA]
+        );
+
+        let mut unescaper = Unescaper::new();
+        // Idle words can show up anywhere, even mid-escape.
+        let received = [NO_DATA, 1, ESC, NO_DATA, ESC_NO_DATA, ESC, ESC_ESC, 0];
+        let decoded: Vec<_> = received
+            .into_iter()
+            .filter_map(|word| unescaper.push(word))
+            .collect();
+        assert_eq!(decoded, [Ok(1), Ok(NO_DATA), Ok(ESC), Ok(0)]);
+        assert_eq!(
+            [ESC, 7].into_iter().find_map(|word| unescaper.push(word)),
+            Some(Err(EscapeError::InvalidEscape))
+        );
+        assert_eq!(
+            JOIN_ANNOUNCEMENT
+                .into_iter()
+                .find_map(|word| unescaper.push(word)),
+            Some(Err(EscapeError::Joined))
+        );
+    }
+}
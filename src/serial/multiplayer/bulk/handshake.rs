@@ -0,0 +1,301 @@
+//! An opening handshake for bulk multiplayer sessions, so that units running
+//! incompatible versions of the crate or the game fail fast instead of
+//! exchanging garbage.
+//!
+//! Each unit sends a hello over the link, [escaped](super::escape):
+//!
+//! | Word | Contents |
+//! | :--  | :--      |
+//! | 1    | [HELLO_MAGIC] |
+//! | 1    | The crate's [PROTOCOL_VERSION] |
+//! | 1    | The game's own version number |
+//!
+//! & then waits for a matching hello from every peer.
+//!
+//! A peer that finishes its handshake first may start sending game data while
+//! we're still waiting on someone else's hello. Anything a peer sends after
+//! its own hello is kept & handed back by
+//! [BulkMultiplayer::handshake](super::BulkMultiplayer::handshake), & the
+//! inbox is left untouched from the transfer that completes the last hello.
+
+use super::escape::{escape, Unescaper};
+use super::{BulkMultiplayer, PlayerId, QueueError};
+use crate::serial::multiplayer::{MultiplayerError, NO_DATA};
+use crate::utils::FrameTimeout;
+
+use alloc::vec::Vec;
+
+/// Marks the start of every hello.
+pub const HELLO_MAGIC: u16 = 0x5E55;
+/// The version of the wire protocol used by this crate's bulk multiplayer
+/// layers; bumped whenever they change incompatibly.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The versions a unit announced in its hello.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Hello {
+    pub protocol_version: u16,
+    pub app_version: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// `player` sent a hello with a different protocol or game version than
+    /// ours.
+    IncompatiblePeer {
+        player: PlayerId,
+        hello: Hello,
+    },
+    /// There isn't room in the outbox for our hello.
+    OutboxFull,
+    /// Not every peer sent a hello before the timeout.
+    Timeout,
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for HandshakeError {
+    fn from(value: MultiplayerError) -> Self {
+        HandshakeError::MultiplayerError(value)
+    }
+}
+
+/// What the next decoded word from a player will be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum HelloState {
+    Magic,
+    Protocol,
+    App { protocol_version: u16 },
+    Done(Hello),
+}
+
+/// Looks for a single player's hello, word by word.
+struct HelloParser {
+    unescaper: Unescaper,
+    state: HelloState,
+}
+
+impl HelloParser {
+    const fn new() -> Self {
+        Self {
+            unescaper: Unescaper::new(),
+            state: HelloState::Magic,
+        }
+    }
+    fn feed(&mut self, word: u16) {
+        let word = match self.unescaper.push(word) {
+            None => return,
+            Some(Ok(word)) => word,
+            Some(Err(_)) => {
+                if !matches!(self.state, HelloState::Done(_)) {
+                    self.state = HelloState::Magic;
+                }
+                return;
+            }
+        };
+        self.state = match self.state {
+            HelloState::Magic if word == HELLO_MAGIC => HelloState::Protocol,
+            HelloState::Magic => HelloState::Magic,
+            HelloState::Protocol => HelloState::App {
+                protocol_version: word,
+            },
+            HelloState::App { protocol_version } => HelloState::Done(Hello {
+                protocol_version,
+                app_version: word,
+            }),
+            done @ HelloState::Done(_) => done,
+        };
+    }
+    fn hello(&self) -> Option<Hello> {
+        match self.state {
+            HelloState::Done(hello) => Some(hello),
+            _ => None,
+        }
+    }
+}
+
+/// Collects every peer's hello from the received transfers, keeping whatever
+/// each peer sent after its own hello.
+struct HelloCollector {
+    parsers: [HelloParser; 4],
+    leftovers: [Vec<u16>; 4],
+}
+
+impl HelloCollector {
+    const fn new() -> Self {
+        Self {
+            parsers: [
+                HelloParser::new(),
+                HelloParser::new(),
+                HelloParser::new(),
+                HelloParser::new(),
+            ],
+            leftovers: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+        }
+    }
+    /// Feeds a single received transfer to the parsers of each of `peers`.
+    fn feed(&mut self, peers: &[PlayerId], transfer: [u16; 4]) {
+        for &player in peers {
+            let idx = player as usize;
+            let word = transfer[idx];
+            if self.parsers[idx].hello().is_none() {
+                self.parsers[idx].feed(word);
+            } else if word != NO_DATA {
+                self.leftovers[idx].push(word);
+            }
+        }
+    }
+    /// Whether or not every one of `peers` has sent a hello.
+    fn is_complete(&self, peers: &[PlayerId]) -> bool {
+        peers
+            .iter()
+            .all(|&player| self.parsers[player as usize].hello().is_some())
+    }
+    /// Fails if any of `peers` sent a hello that doesn't match `ours`.
+    fn check(&self, peers: &[PlayerId], ours: Hello) -> Result<(), HandshakeError> {
+        for &player in peers {
+            match self.parsers[player as usize].hello() {
+                Some(hello) if hello != ours => {
+                    return Err(HandshakeError::IncompatiblePeer { player, hello })
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> BulkMultiplayer<'a> {
+    /// Exchanges hellos with `peers`, failing if any of them is running a
+    /// different [PROTOCOL_VERSION] or `app_version` than us. Blocks until
+    /// every peer has sent a hello or `timeout_frames` frames pass, calling
+    /// [Self::tick] once per frame.
+    ///
+    /// Every unit should call this right after [BulkMultiplayer::new], before
+    /// queueing anything else. Anything received before a peer's hello is
+    /// dropped.
+    ///
+    /// Returns the words each player sent after its hello that had to be
+    /// consumed while waiting on the other peers' hellos, indexed by
+    /// [PlayerId]. They're still escaped & should be handled before anything
+    /// else read from the inbox, which is left untouched from the transfer
+    /// that completed the last hello.
+    pub fn handshake(
+        &mut self,
+        peers: &[PlayerId],
+        app_version: u16,
+        timeout_frames: Option<u32>,
+    ) -> Result<[Vec<u16>; 4], HandshakeError> {
+        let ours = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            app_version,
+        };
+        let mut hello = Vec::new();
+        escape(
+            &[HELLO_MAGIC, ours.protocol_version, ours.app_version],
+            &mut hello,
+        );
+        if self.queue_space() < hello.len() {
+            return Err(HandshakeError::OutboxFull);
+        }
+        match self.queue_send(&hello) {
+            Ok(_) => {}
+            Err(QueueError::MultiplayerError(e)) => return Err(e.into()),
+            Err(QueueError::QueueNotEmpty) => return Err(HandshakeError::OutboxFull),
+        }
+
+        let me = self.id();
+        let peers: Vec<PlayerId> = peers.iter().copied().filter(|&p| p != me).collect();
+        let mut collector = HelloCollector::new();
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        loop {
+            self.tick().map_err(MultiplayerError::from)?;
+            // Stop as soon as the last hello arrives, so that whatever follows
+            // it stays in the inbox.
+            while !collector.is_complete(&peers) {
+                let Some(transfer) = self.transfers().next() else {
+                    break;
+                };
+                collector.feed(&peers, transfer);
+            }
+            collector.check(&peers, ours)?;
+            if collector.is_complete(&peers) {
+                return Ok(collector.leftovers);
+            }
+            if !timeout.wait() {
+                return Err(HandshakeError::Timeout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::multiplayer::NO_DATA;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_hello_parser(_gba: &mut Gba) {
+        let mut stream = alloc::vec![NO_DATA, 7];
+        escape(&[HELLO_MAGIC, PROTOCOL_VERSION, NO_DATA], &mut stream);
+
+        let mut parser = HelloParser::new();
+        for word in stream {
+            assert_eq!(parser.hello(), None);
+            parser.feed(word);
+        }
+        let hello = Some(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            app_version: NO_DATA,
+        });
+        assert_eq!(parser.hello(), hello);
+        parser.feed(HELLO_MAGIC);
+        assert_eq!(parser.hello(), hello);
+    }
+
+    #[test_case]
+    fn test_hello_then_data(_gba: &mut Gba) {
+        let mut hello = Vec::new();
+        escape(&[HELLO_MAGIC, PROTOCOL_VERSION, 3], &mut hello);
+        // P1 sends its hello & then data in the same batch, while P2's hello
+        // is still arriving.
+        let mut p1 = hello.clone();
+        p1.extend([0x1234, NO_DATA, 0x5678]);
+        let mut p2 = alloc::vec![NO_DATA, NO_DATA, NO_DATA];
+        p2.extend(&hello);
+        p2.push(0x9ABC);
+
+        let peers = [PlayerId::P1, PlayerId::P2];
+        let mut collector = HelloCollector::new();
+        let mut consumed = 0;
+        for (&w1, &w2) in p1.iter().zip(&p2) {
+            if collector.is_complete(&peers) {
+                break;
+            }
+            collector.feed(&peers, [NO_DATA, w1, w2, NO_DATA]);
+            consumed += 1;
+        }
+        assert!(collector.is_complete(&peers));
+        let ours = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            app_version: 3,
+        };
+        assert_eq!(collector.check(&peers, ours), Ok(()));
+        // P2's data is left for the next read.
+        assert_eq!(consumed, p2.len() - 1);
+        assert_eq!(collector.leftovers[PlayerId::P1 as usize], [0x1234, 0x5678]);
+        assert!(collector.leftovers[PlayerId::P2 as usize].is_empty());
+
+        let theirs = Hello {
+            app_version: 4,
+            ..ours
+        };
+        assert_eq!(
+            collector.check(&peers, theirs),
+            Err(HandshakeError::IncompatiblePeer {
+                player: PlayerId::P1,
+                hello: ours
+            })
+        );
+    }
+}
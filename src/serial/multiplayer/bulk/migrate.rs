@@ -0,0 +1,223 @@
+//! Host migration for when the parent drops out of a bulk multiplayer session.
+//!
+//! Only [PlayerId::P0] can start transfers, & which unit that is depends
+//! solely on who holds the cable's parent plug; so when the parent is lost
+//! (cable pulled, power off) the others can't carry on by themselves. Instead:
+//!
+//! 1. Every remaining unit notices via [BulkMultiplayer::parent_lost].
+//! 2. The game asks the players to re-plug the cable so that the unit named by
+//!    [BulkMultiplayer::successor] holds the parent plug.
+//! 3. Every remaining unit calls [BulkMultiplayer::migrate_host], which picks
+//!    up the new wiring & the unit's new [PlayerId], then exchanges old IDs
+//!    (as [escaped](super::escape) control messages) so that each game can
+//!    hand its per-player state over to the new numbering.
+//!
+//! The hardware has no flag for a missing parent, so it's inferred from the
+//! link staying idle while the `SD` line says every unit is ready: a parent
+//! that's still there would have started a transfer by then. A unit that's
+//! holding up transfers until it has data keeps `SD` low, so the loss isn't
+//! noticed until it has something to send.
+
+use super::baud::{ControlParser, Op};
+use super::{BulkMultiplayer, LinkStatus, LinkTracker, PlayerId, TRANSFER_COUNTER};
+use crate::serial::multiplayer::{MultiplayerError, MultiplayerSiocnt};
+use crate::utils::FrameTimeout;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationError {
+    /// There isn't room in the outbox for our old ID.
+    OutboxFull,
+    /// The cable wasn't re-plugged, or not every remaining unit reported its
+    /// old ID, before the timeout.
+    Timeout,
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for MigrationError {
+    fn from(value: MultiplayerError) -> Self {
+        MigrationError::MultiplayerError(value)
+    }
+}
+
+/// How often [BulkMultiplayer::migrate_host] repeats our old ID, in frames,
+/// for the units that started migrating after we first sent it.
+const ANNOUNCE_FRAMES: u32 = 30;
+
+/// Watches for the link going idle while every unit says it's ready, which
+/// only happens once the parent has stopped starting transfers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct ParentWatch {
+    /// [TRANSFER_COUNTER] as of the previous tick.
+    last_transfers: u32,
+    /// How many ticks in a row every unit was ready but nothing was
+    /// transferred.
+    stalled_ticks: u32,
+}
+
+impl ParentWatch {
+    pub(super) const fn new() -> Self {
+        Self {
+            last_transfers: 0,
+            stalled_ticks: 0,
+        }
+    }
+    /// Records a tick, given [TRANSFER_COUNTER] & whether or not the `SD`
+    /// line says every unit is ready.
+    pub(super) fn record(&mut self, transfers: u32, all_ready: bool) {
+        if transfers != self.last_transfers || !all_ready {
+            self.stalled_ticks = 0;
+        } else {
+            self.stalled_ticks = self.stalled_ticks.saturating_add(1);
+        }
+        self.last_transfers = transfers;
+    }
+    fn lost(&self, timeout_ticks: u32) -> bool {
+        self.stalled_ticks >= timeout_ticks
+    }
+}
+
+/// Picks the unit that should hold the parent plug next: the lowest numbered
+/// child that we've ever heard from, or ourselves.
+fn successor_of(me: PlayerId, status: [LinkStatus; 4]) -> PlayerId {
+    PlayerId::ALL
+        .into_iter()
+        .skip(1)
+        .find(|&p| p == me || status[p as usize] != LinkStatus::Unknown)
+        .unwrap_or(me)
+}
+
+impl<'a> BulkMultiplayer<'a> {
+    /// Whether or not the parent seems to have dropped out of the session,
+    /// meaning that nothing more will be transferred until the cable is
+    /// re-plugged. Always `false` on the parent itself.
+    ///
+    /// This is only `true` once every unit has been ready for
+    /// [Self::disconnect_timeout] ticks without a transfer; see the
+    /// [module docs](self).
+    pub fn parent_lost(&self) -> bool {
+        !self.inner.is_parent() && self.parent_watch.lost(self.disconnect_ticks)
+    }
+    /// The unit that should hold the cable's parent plug once it's
+    /// re-plugged: the lowest numbered child seen so far. Every remaining unit
+    /// agrees on this as long as they've all heard from each other.
+    pub fn successor(&self) -> PlayerId {
+        successor_of(self.id(), self.connection_status())
+    }
+    /// Rejoins the session after the cable has been re-plugged without the
+    /// old parent, taking on whatever role the new wiring gives us.
+    ///
+    /// Every one of the remaining units must call this at around the same
+    /// time; it blocks until `remaining_peers` other units have reported
+    /// their old IDs or `timeout_frames` frames pass, calling [Self::tick]
+    /// once per frame. Anything received before or during the migration is
+    /// dropped, though our own outbox is kept.
+    ///
+    /// Our old ID is only sent once the new wiring is up, & is repeated every
+    /// so often until we've heard from everyone, so a unit that gets here a
+    /// little later than us still hears it.
+    ///
+    /// Returns each unit's old [PlayerId], indexed by their new one.
+    pub fn migrate_host(
+        &mut self,
+        remaining_peers: usize,
+        timeout_frames: Option<u32>,
+    ) -> Result<[Option<PlayerId>; 4], MigrationError> {
+        self.keeping_ready(|this| this.migrate_host_ready(remaining_peers, timeout_frames))
+    }
+    fn migrate_host_ready(
+        &mut self,
+        remaining_peers: usize,
+        timeout_frames: Option<u32>,
+    ) -> Result<[Option<PlayerId>; 4], MigrationError> {
+        let old = self.id();
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        self.clear_inbox();
+        self.links = LinkTracker::new();
+
+        // Step 1 is to wait for the new wiring, which we can only be sure of
+        // after the first transfer.
+        let old_count = TRANSFER_COUNTER.get_copy();
+        while TRANSFER_COUNTER.get_copy() == old_count {
+            // Re-reads whether or not we're the parent now.
+            self.inner.mark_ready();
+            // Errors are expected while the cable is being re-plugged.
+            self.tick().ok();
+            if !timeout.wait() {
+                return Err(MigrationError::Timeout);
+            }
+        }
+        self.inner.playerid = Some(MultiplayerSiocnt::get().id());
+        self.parent_watch = ParentWatch::new();
+
+        // Step 2 is to tell everyone who we used to be, now that they can hear
+        // us, & to find out who they used to be.
+        let me = self.id();
+        let mut old_ids = [None; 4];
+        old_ids[me as usize] = Some(old);
+        let mut parsers = [
+            ControlParser::new(),
+            ControlParser::new(),
+            ControlParser::new(),
+            ControlParser::new(),
+        ];
+        let mut frames_since_announce = ANNOUNCE_FRAMES;
+        loop {
+            if frames_since_announce >= ANNOUNCE_FRAMES {
+                if !self.queue_control(Op::Migrate, old as u16)? {
+                    return Err(MigrationError::OutboxFull);
+                }
+                frames_since_announce = 0;
+            }
+            frames_since_announce += 1;
+            self.drain_received(|player, word| parsers[player as usize].feed(word))?;
+            for player in PlayerId::ALL.into_iter().filter(|&p| p != me) {
+                while let Some((op, arg)) = parsers[player as usize].pop() {
+                    if let (Op::Migrate, Some(&old)) = (op, PlayerId::ALL.get(arg as usize)) {
+                        old_ids[player as usize] = Some(old);
+                    }
+                }
+            }
+            if old_ids.iter().flatten().count() > remaining_peers {
+                return Ok(old_ids);
+            }
+            self.tick().map_err(MultiplayerError::from)?;
+            if !timeout.wait() {
+                return Err(MigrationError::Timeout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use LinkStatus::*;
+
+    #[test_case]
+    fn test_successor(_gba: &mut Gba) {
+        let status = [Disconnected, Unknown, Disconnected, Connected];
+        assert_eq!(successor_of(PlayerId::P3, status), PlayerId::P2);
+        assert_eq!(successor_of(PlayerId::P1, status), PlayerId::P1);
+        let alone = [Disconnected, Unknown, Unknown, Connected];
+        assert_eq!(successor_of(PlayerId::P3, alone), PlayerId::P3);
+    }
+
+    #[test_case]
+    fn test_parent_watch(_gba: &mut Gba) {
+        let mut watch = ParentWatch::new();
+        // Idle because someone's holding up transfers isn't a lost parent.
+        for _ in 0..10 {
+            watch.record(0, false);
+        }
+        assert!(!watch.lost(3));
+        watch.record(1, true);
+        watch.record(1, true);
+        watch.record(1, true);
+        assert!(!watch.lost(3));
+        watch.record(1, true);
+        assert!(watch.lost(3));
+        watch.record(2, true);
+        assert!(!watch.lost(3));
+    }
+}
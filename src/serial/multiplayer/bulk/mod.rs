@@ -0,0 +1,1221 @@
+//! Provides a higher-level easy-to-use interface for dealing with multiplayer
+//! communication on the GBA.
+//!
+//! # Basic Usage
+//!
+//! 1. Start "bulk multiplayer" mode by calling [BulkMultiplayer::new]. This
+//!    will initialize the player IDs, allocated the read & write buffers, and
+//!    configure the necessary interrupts.
+//! 2. Set the value of [BulkMultiplayer::block_transfers_until_have_data]
+//!    depending on your usecase. If `true` (default) then transfers will only
+//!    happen when EVERY SINGLE unit in the session has data available to send;
+//!    otherwise transfers will happen when ANY unit is sending data. Note that
+//!    any individual unit setting this to `true` will block the transfer for
+//!    all other units as well!
+//! 3. Load data to be sent to other units with [BulkMultiplayer::queue_send]
+//!    and/or read data other players have sent with
+//!    [BulkMultiplayer::read_bulk].
+//! 4. Make sure [BulkMultiplayer::tick] is called during your main game loop.
+//!
+//! # Notes
+//! * Due to GBA hardware quirks it is impossible to distinguish between a unit
+//!   not being connected and a unit sending a [NO_DATA]. As such be sure to not
+//!   send that value as part of your transfer if you don't want to lose
+//!   information, or pass your data through the [escape] codec.
+//! * [BulkMultiplayer::connection_status] reports which players haven't sent
+//!   anything recently, so that a game can tell a disconnected player apart
+//!   from a stalled session.
+//! * Call [BulkMultiplayer::handshake] right after starting the session to
+//!   make sure every unit is running a compatible version.
+//! * Use [BulkMultiplayer::negotiate_baud_rate] to move the whole session to a
+//!   different [BaudRate](super::BaudRate) together, or
+//!   [BulkMultiplayer::set_baud_rate] to switch to a rate every unit already
+//!   agreed on.
+//! * Small time-sensitive messages (pings, acknowledgements, pause requests)
+//!   can skip ahead of the messages already queued with
+//!   [BulkMultiplayer::queue_send_priority].
+//! * [BulkMultiplayer::stats] counts transfers, words, & errors for debugging.
+//! * Units that only watch the session can use a
+//!   [Spectator](spectator::Spectator), which never holds up transfers.
+//! * If the parent drops out, see [migrate] for how to carry on without it.
+//! * For whole messages that are checked for corruption, wrap this in a
+//!   [CrcMultiplayer](crc::CrcMultiplayer); to also have them acknowledged &
+//!   retransmitted until they arrive, use a
+//!   [ReliableLink](reliable::ReliableLink). To send messages to a single
+//!   player, use a [RoutedMultiplayer](routed::RoutedMultiplayer). For
+//!   repetitive messages like input or state sync, a
+//!   [CompressedMultiplayer](compress::CompressedMultiplayer) saves bandwidth.
+//! * For variable-length packets of bytes instead of raw words, wrap this in a
+//!   [PacketMultiplayer](packet::PacketMultiplayer). To keep e.g. input sync,
+//!   chat, & file transfers apart, send each on its own
+//!   [Channel](channel::Channel) of a [ChannelMux](channel::ChannelMux).
+//! * A unit that powers on late or reboots can join an ongoing session by
+//!   calling [BulkMultiplayer::new] as usual, which starts its stream with an
+//!   [escape::JOIN_ANNOUNCEMENT]; the other units will see it in
+//!   [BulkMultiplayer::take_joined] & should reset any per-player state they
+//!   keep for it. Every layer in this module does so itself. Going quiet for
+//!   a while doesn't count as rejoining. Raw streams that don't use the
+//!   [escape] codec will see the 2 announcement words, & must never send them
+//!   back to back themselves.
+
+use agb::external::critical_section::{self, CriticalSection};
+use agb::interrupt::{add_interrupt_handler, Interrupt};
+
+use crate::utils::{FrameTimeout, GbaCell};
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::serial::ringbuf::Ringbuffer;
+use baud::SwitchWatcher;
+use migrate::ParentWatch;
+use escape::{EscapeError, Unescaper, JOIN_ANNOUNCEMENT};
+use super::{
+    buffer::TransferBuffer, mark_unready, MultiplayerCommReg, MultiplayerError, MultiplayerSerial,
+    MultiplayerSiocnt, PlayerId, NO_DATA, SIOMLT_SEND,
+};
+use super::{enter_multiplayer, TransferError};
+
+pub mod baud;
+pub mod channel;
+pub mod compress;
+pub mod crc;
+pub mod escape;
+pub mod handshake;
+pub mod migrate;
+pub mod packet;
+pub mod reliable;
+pub mod routed;
+pub mod spectator;
+
+/// The data buffer to store communicated words in.
+static BUFFER_SLOT: GbaCell<TransferBuffer> = GbaCell::new(TransferBuffer::empty());
+
+/// The data buffer for words we will communicate out to the other units in the
+/// session.
+static OUTBUFFER: GbaCell<Ringbuffer> = GbaCell::new(Ringbuffer::empty());
+
+/// Time-sensitive words that should be sent before anything in [OUTBUFFER].
+static PRIORITY_OUTBUFFER: GbaCell<Ringbuffer> = GbaCell::new(Ringbuffer::empty());
+
+/// The size of [PRIORITY_OUTBUFFER]; priority messages are expected to be
+/// small.
+pub const PRIORITY_QUEUE_LEN: usize = 32;
+
+/// Where the messages in [OUTBUFFER] end, so that [PRIORITY_OUTBUFFER] only
+/// ever cuts in between them.
+static MESSAGE_BOUNDS: GbaCell<MessageBounds> = GbaCell::new(MessageBounds::new());
+
+/// If true, all data transfers for all other GBAs in the session will be
+/// blocked until we ourselves also write data to be sent out.
+static BLOCK_TRANSFER_UNTIL_SEND: GbaCell<bool> = GbaCell::new(true);
+
+/// If true, we stay ready for transfers even with nothing to send, regardless
+/// of [BLOCK_TRANSFER_UNTIL_SEND]; see [BulkMultiplayer::keeping_ready].
+static KEEP_READY: GbaCell<bool> = GbaCell::new(false);
+
+static TRANSFER_COUNTER: GbaCell<u32> = GbaCell::new(0);
+
+/// The players we've received a non-[NO_DATA] word from since the last call to
+/// [BulkMultiplayer::tick], as a bitmask by [PlayerId].
+static HEARD_FROM: GbaCell<u8> = GbaCell::new(0);
+
+/// Watches each player's stream for a [JOIN_ANNOUNCEMENT].
+static JOIN_SCANNERS: GbaCell<[Unescaper; 4]> = GbaCell::new([Unescaper::new(); 4]);
+
+/// The players that have sent a [JOIN_ANNOUNCEMENT] since the last call to
+/// [BulkMultiplayer::tick], as a bitmask by [PlayerId].
+static ANNOUNCED: GbaCell<u8> = GbaCell::new(0);
+
+/// Watches the parent's words for baud rate switches while we're a
+/// [Spectator](spectator::Spectator); `None` otherwise.
+static SWITCH_WATCHER: GbaCell<Option<SwitchWatcher>> = GbaCell::new(None);
+
+/// The running [LinkStats] for the session.
+static STATS: GbaCell<LinkStats> = GbaCell::new(LinkStats::new());
+
+/// The default number of [BulkMultiplayer::tick] calls a player can go without
+/// sending anything before they're considered disconnected.
+pub const DEFAULT_DISCONNECT_TICKS: u32 = 60;
+
+pub struct BulkMultiplayer<'a> {
+    inner: MultiplayerSerial<'a>,
+    links: LinkTracker,
+    parent_watch: ParentWatch,
+    disconnect_ticks: u32,
+    inbox_watermark: Option<u8>,
+    outbox_watermark: Option<u8>,
+    pressure: Backpressure,
+    auto_recover: bool,
+}
+
+/// Which buffers were filled past their watermark as of the last
+/// [BulkMultiplayer::tick]; see [BulkMultiplayer::set_inbox_watermark] &
+/// [BulkMultiplayer::set_outbox_watermark].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Backpressure {
+    /// Received transfers are piling up faster than they're being read.
+    pub inbox: bool,
+    /// Words are being queued faster than they can be sent.
+    pub outbox: bool,
+}
+
+/// Whether or not a unit should stay ready for the next transfer after this
+/// one, given whether it has another word to send.
+const fn stays_ready(has_next: bool, block_until_send: bool, keep_ready: bool) -> bool {
+    has_next || !block_until_send || keep_ready
+}
+
+/// Whether `len` out of `capacity` is at or past `watermark` percent full.
+const fn past_watermark(len: usize, capacity: usize, watermark: Option<u8>) -> bool {
+    match watermark {
+        Some(percent) => len * 100 >= capacity * percent as usize,
+        None => false,
+    }
+}
+
+/// Counters describing how the session has been going, for debugging &
+/// diagnostics. All of them wrap around on overflow.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct LinkStats {
+    /// The number of transfers completed, including empty ones.
+    pub transfers: u32,
+    /// The number of words we sent out of our outbox.
+    pub words_sent: u32,
+    /// The number of non-[NO_DATA] words received, per [PlayerId]; our own
+    /// slot counts the words we sent.
+    pub words_received: [u32; 4],
+    /// The number of transfers where every unit sent [NO_DATA], either
+    /// skipped by the interrupt or by [BulkMultiplayer::skip_empty_transfers].
+    pub empty_transfers_skipped: u32,
+    /// The number of transfers dropped because the inbox was full.
+    pub buffer_overflows: u32,
+    /// The number of times [BulkMultiplayer::tick] found the error flag set.
+    pub error_flags: u32,
+    /// The number of calls to [BulkMultiplayer::recover], including automatic
+    /// ones.
+    pub recoveries: u32,
+}
+
+impl LinkStats {
+    const fn new() -> Self {
+        Self {
+            transfers: 0,
+            words_sent: 0,
+            words_received: [0; 4],
+            empty_transfers_skipped: 0,
+            buffer_overflows: 0,
+            error_flags: 0,
+            recoveries: 0,
+        }
+    }
+}
+
+/// Whether or not a player in the session seems to be connected.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LinkStatus {
+    /// Nothing has been received from this player yet.
+    Unknown,
+    /// This player has sent data within the disconnect timeout.
+    Connected,
+    /// This player has sent data before, but not within the disconnect
+    /// timeout.
+    Disconnected,
+}
+
+/// Tracks how many ticks it's been since each player last sent data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct LinkTracker {
+    /// `None` until we first hear from the player.
+    idle_ticks: [Option<u32>; 4],
+    /// The players that have (re)joined since the last call to
+    /// [Self::take_joined], as a bitmask by [PlayerId].
+    joined: u8,
+}
+
+impl LinkTracker {
+    const fn new() -> Self {
+        Self {
+            idle_ticks: [None; 4],
+            joined: 0,
+        }
+    }
+    /// Records a tick, where `heard` is the bitmask of players we've received
+    /// data from since the previous one & `announced` is the bitmask of
+    /// players that sent a [JOIN_ANNOUNCEMENT].
+    ///
+    /// Only an announcement counts as joining; a player that's merely been
+    /// quiet for a while is still in the same session.
+    fn record(&mut self, heard: u8, announced: u8) {
+        self.joined |= announced;
+        for (idx, idle) in self.idle_ticks.iter_mut().enumerate() {
+            if heard & (1 << idx) != 0 {
+                *idle = Some(0);
+            } else if let Some(ticks) = idle {
+                *ticks = ticks.saturating_add(1);
+            }
+        }
+    }
+    fn status(&self, timeout_ticks: u32) -> [LinkStatus; 4] {
+        self.idle_ticks.map(|idle| match idle {
+            None => LinkStatus::Unknown,
+            Some(ticks) if ticks < timeout_ticks => LinkStatus::Connected,
+            Some(_) => LinkStatus::Disconnected,
+        })
+    }
+    fn take_joined(&mut self) -> [bool; 4] {
+        let joined = core::mem::take(&mut self.joined);
+        [0, 1, 2, 3].map(|idx| joined & (1 << idx) != 0)
+    }
+}
+
+/// The number of messages [MessageBounds] tracks separately; any more than
+/// this are merged into the newest one.
+const MAX_TRACKED_MESSAGES: usize = 16;
+
+/// Tracks the lengths of the messages queued in the outbox, so we know when
+/// the next word sent starts a new one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct MessageBounds {
+    /// The length of each queued message, oldest first, starting at `head`.
+    lens: [usize; MAX_TRACKED_MESSAGES],
+    head: usize,
+    count: usize,
+    /// How much of the oldest message has already been sent.
+    sent: usize,
+    /// Whether the newest message only partly fit, so the next words queued
+    /// continue it rather than starting a new one.
+    open: bool,
+}
+
+impl MessageBounds {
+    const fn new() -> Self {
+        Self {
+            lens: [0; MAX_TRACKED_MESSAGES],
+            head: 0,
+            count: 0,
+            sent: 0,
+            open: false,
+        }
+    }
+    /// Records that `len` words were queued, which finished the message if
+    /// `complete` is set.
+    fn push(&mut self, len: usize, complete: bool) {
+        if len == 0 {
+            return;
+        }
+        if self.open || self.count == MAX_TRACKED_MESSAGES {
+            let newest = (self.head + self.count - 1) % MAX_TRACKED_MESSAGES;
+            self.lens[newest] += len;
+        } else {
+            let idx = (self.head + self.count) % MAX_TRACKED_MESSAGES;
+            self.lens[idx] = len;
+            self.count += 1;
+        }
+        self.open = !complete;
+    }
+    /// Records that the next word of the oldest message was sent.
+    fn pop(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        self.sent += 1;
+        // An open message stays partway sent until the rest is queued.
+        let waiting_for_rest = self.open && self.count == 1;
+        if self.sent >= self.lens[self.head] && !waiting_for_rest {
+            self.head = (self.head + 1) % MAX_TRACKED_MESSAGES;
+            self.count -= 1;
+            self.sent = 0;
+        }
+    }
+    /// Whether we're in between messages, rather than partway through one.
+    fn at_boundary(&self) -> bool {
+        self.sent == 0
+    }
+}
+
+impl Default for MessageBounds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Picks the next word to send, taking it from `priority` if we're in between
+/// messages & from `normal` otherwise.
+fn next_outgoing(
+    bounds: &mut MessageBounds,
+    priority: impl FnOnce() -> Option<u16>,
+    normal: impl FnOnce() -> Option<u16>,
+) -> Option<u16> {
+    if bounds.at_boundary() {
+        if let Some(word) = priority() {
+            return Some(word);
+        }
+    }
+    let word = normal();
+    if word.is_some() {
+        bounds.pop();
+    }
+    word
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulkInitError {
+    AlreadyInitialized,
+    /// The requested capacity was 0.
+    ZeroCapacity,
+    TransferError(TransferError),
+}
+impl From<TransferError> for BulkInitError {
+    fn from(value: TransferError) -> Self {
+        BulkInitError::TransferError(value)
+    }
+}
+
+/// An error that can happen during per-frame processing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulkTickError {
+    /// The serial I/O error bit was flagged during per-frame processing.
+    FailedOkayCheck,
+}
+
+impl From<BulkTickError> for MultiplayerError {
+    fn from(value: BulkTickError) -> Self {
+        match value {
+            BulkTickError::FailedOkayCheck => MultiplayerError::FailedOkayCheck,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ResizeError {
+    /// The new capacity was 0.
+    ZeroCapacity,
+    /// There is more unread data in the inbox or outbox than would fit in the
+    /// new capacity.
+    TooSmall,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueError {
+    QueueNotEmpty,
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for QueueError {
+    fn from(value: MultiplayerError) -> Self {
+        QueueError::MultiplayerError(value)
+    }
+}
+
+/// Statically allocated storage for [BulkMultiplayer::new_static], holding
+/// `CAP` words per player in the inbox & `CAP` words in the outbox, plus the
+/// priority outbox.
+///
+/// Declare it as a `static mut` to keep the buffers, which make up nearly all
+/// of bulk mode's memory use, off the heap. A heap is still needed, since
+/// `agb` boxes the serial interrupt handler; that's a few words regardless of
+/// `CAP`.
+pub struct StaticBuffers<const CAP: usize> {
+    inbox: [[u16; CAP]; 4],
+    outbox: [u16; CAP],
+    priority: [u16; PRIORITY_QUEUE_LEN],
+}
+
+impl<const CAP: usize> StaticBuffers<CAP> {
+    pub const fn new() -> Self {
+        Self {
+            inbox: [[NO_DATA; CAP]; 4],
+            outbox: [NO_DATA; CAP],
+            priority: [NO_DATA; PRIORITY_QUEUE_LEN],
+        }
+    }
+}
+
+impl<const CAP: usize> Default for StaticBuffers<CAP> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> BulkMultiplayer<'a> {
+    pub fn new(inner: MultiplayerSerial<'a>, cap: usize) -> Result<Self, BulkInitError> {
+        // The total heap usage is 5 * cap; 1 inbox for each player + the outbox.
+        // The priority outbox is small enough to not count.
+        Self::with_buffers(
+            inner,
+            TransferBuffer::new(cap),
+            Ringbuffer::new(cap),
+            Ringbuffer::new(PRIORITY_QUEUE_LEN),
+        )
+    }
+    /// Like [BulkMultiplayer::new], but using `buffers` instead of allocating
+    /// the inbox & outbox on the heap. The interrupt handler is still
+    /// allocated; see [StaticBuffers].
+    ///
+    /// `CAP` must be non-zero; this is checked at compile time.
+    pub fn new_static<const CAP: usize>(
+        inner: MultiplayerSerial<'a>,
+        buffers: &'static mut StaticBuffers<CAP>,
+    ) -> Result<Self, BulkInitError> {
+        const { assert!(CAP > 0, "StaticBuffers must have a non-zero capacity") };
+        let StaticBuffers {
+            inbox,
+            outbox,
+            priority,
+        } = buffers;
+        let inbox = TransferBuffer::from_static(inbox.as_flattened_mut());
+        Self::with_buffers(
+            inner,
+            inbox,
+            Ringbuffer::from_static(outbox),
+            Ringbuffer::from_static(priority),
+        )
+    }
+    fn with_buffers(
+        mut inner: MultiplayerSerial<'a>,
+        nbuff: TransferBuffer,
+        nout: Ringbuffer,
+        npriority: Ringbuffer,
+    ) -> Result<Self, BulkInitError> {
+        // A zero-length buffer is indistinguishable from the uninitialized
+        // placeholder, so it would pass the checks below & then break the
+        // first transfer.
+        if nbuff.is_placeholder() || nout.is_placeholder() {
+            return Err(BulkInitError::ZeroCapacity);
+        }
+        // Step 1 is make sure we know what player we are.
+        //
+        // Technically not necessary but it makes things usage easier since
+        // there's no worries about whether or not we know who we are.
+        initialize_id(&mut inner)?;
+
+        // Step 2 is to initialize the static buffers.
+        BUFFER_SLOT
+            .swap_if(nbuff, |old| old.is_placeholder())
+            .map_err(|_| BulkInitError::AlreadyInitialized)?;
+        OUTBUFFER
+            .swap_if(nout, |old| old.is_placeholder())
+            // Shouldn't be possible if the previous check passed, but still
+            .map_err(|_| BulkInitError::AlreadyInitialized)?;
+        PRIORITY_OUTBUFFER
+            .swap_if(npriority, |old| old.is_placeholder())
+            .map_err(|_| BulkInitError::AlreadyInitialized)?;
+
+        // Step 3 is to set up the interrupts for reading & writing our data.
+        inner.buffer_interrupt = unsafe {
+            Some(add_interrupt_handler(
+                Interrupt::Serial,
+                bulk_mode_interrupt_callback,
+            ))
+        };
+        HEARD_FROM.swap(0);
+        JOIN_SCANNERS.swap([Unescaper::new(); 4]);
+        ANNOUNCED.swap(0);
+        STATS.swap(LinkStats::new());
+        inner.enable_interrupt(true);
+
+        // Step 4 is to let everyone else know that anything they had from
+        // us before is stale.
+        critical_section::with(|cs| {
+            MESSAGE_BOUNDS.swap_in(cs, MessageBounds::new());
+            queue_message(&JOIN_ANNOUNCEMENT, cs)
+        });
+        inner.mark_ready();
+
+        Ok(Self {
+            inner,
+            links: LinkTracker::new(),
+            parent_watch: ParentWatch::new(),
+            disconnect_ticks: DEFAULT_DISCONNECT_TICKS,
+            inbox_watermark: None,
+            outbox_watermark: None,
+            pressure: Backpressure::default(),
+            auto_recover: false,
+        })
+    }
+
+    pub fn id(&self) -> PlayerId {
+        // #SAFETY
+        //
+        // The only way we can create a [BulkMultiplayer] instance is via
+        // [BulkMultiplayer::new], which only succeeds if
+        // [MultiplayerSerial::initialize_id] succeeds (which would mean
+        // `self.inner.player_id` is populated).
+        unsafe { self.inner.playerid.unwrap_unchecked() }
+    }
+
+    /// Helper method to skip any transfers where no other unit sent data.
+    /// Returns the number of skipped transfers.
+    ///
+    /// Due to how GBA multiplayer communication works, it is entirely possible
+    /// to have a data transfer where only a single unit blasts data to up to 3
+    /// other units without those units sending anything back; this is
+    /// equivalent to all other units sending a [NO_DATA], which would also be
+    /// the case if they were, say, initializing their ID bits or attempting to
+    /// eavesdrop. This means the inbox will quickly fill itself with the data
+    /// from the outbox without actually storing any meaningful data, possibly
+    /// losing some in the process if things get really bad.
+    ///
+    /// To help deal with this problem we have this function that will skip any
+    /// transfer where all units aside from us sent over [NO_DATA].
+    pub fn skip_empty_transfers(&mut self) -> usize {
+        let mut retvl = 0;
+        loop {
+            let Some(next) = BUFFER_SLOT.lock(|tbuf| tbuf.peek()) else {
+                break;
+            };
+
+            let is_empty = next
+                .into_iter()
+                .enumerate()
+                .all(|(idx, n)| n == NO_DATA || idx == (self.id() as usize));
+            if !is_empty {
+                break;
+            }
+            BUFFER_SLOT.lock(|tbuf| tbuf.pop());
+            retvl += 1;
+        }
+        STATS.lock_mut(|stats| {
+            stats.empty_transfers_skipped = stats.empty_transfers_skipped.wrapping_add(retvl as u32)
+        });
+        retvl
+    }
+    /// Pulls data from the multiplayer buffer into the provided data buffers. Returns the number of words read, per player.
+    pub fn read_bulk(
+        &mut self,
+        buffers: &mut [&mut [u16]; 4],
+    ) -> Result<[usize; 4], MultiplayerError> {
+        BUFFER_SLOT.lock(|tbuf| Ok(tbuf.read_bulk(buffers)))
+    }
+    /// Drains the multiplayer buffer one transfer at a time, yielding the word
+    /// from each player (including us) in [PlayerId] order. Stops once the
+    /// buffer is empty; does NOT block.
+    pub fn transfers(&mut self) -> impl Iterator<Item = [u16; 4]> + '_ {
+        core::iter::from_fn(|| BUFFER_SLOT.lock(|tbuf| tbuf.pop()))
+    }
+    /// The next transfer in the multiplayer buffer, without consuming it, or
+    /// `None` if the buffer is empty. Does NOT block.
+    pub fn peek_transfer(&self) -> Option<[u16; 4]> {
+        BUFFER_SLOT.lock(|tbuf| tbuf.peek())
+    }
+    /// Pulls only `player`'s data from the multiplayer buffer into `buffer`.
+    /// Returns the number of words read.
+    ///
+    /// The other players' words from the same transfers are dropped, so only
+    /// use this when a single peer's stream matters. Does NOT block.
+    pub fn read_player(&mut self, player: PlayerId, buffer: &mut [u16]) -> usize {
+        BUFFER_SLOT.lock(|tbuf| tbuf.read_player(player, buffer))
+    }
+    /// Drains the multiplayer buffer, calling `cb` with each word received
+    /// from every player other than us, in order.
+    fn drain_received(
+        &mut self,
+        mut cb: impl FnMut(PlayerId, u16),
+    ) -> Result<(), MultiplayerError> {
+        let me = self.id();
+        let mut scratch = [[NO_DATA; 32]; 4];
+        loop {
+            let [p0, p1, p2, p3] = &mut scratch;
+            let read = self.read_bulk(&mut [&mut p0[..], p1, p2, p3])?[0];
+            if read == 0 {
+                return Ok(());
+            }
+            for player in PlayerId::ALL.into_iter().filter(|&p| p != me) {
+                for &word in &scratch[player as usize][..read] {
+                    cb(player, word);
+                }
+            }
+        }
+    }
+    /// Pulls data from the multiplayer buffer into the provided data buffers,
+    /// looping until all buffers are filled with data.
+    pub fn read_all(&mut self, buffers: &mut [&mut [u16]; 4]) -> Result<(), MultiplayerError> {
+        let to_read = buffers[0].len();
+        for buff in buffers.iter() {
+            if buff.len() != to_read {
+                return Err(MultiplayerError::BufferLengthMismatch);
+            }
+        }
+        let mut read = 0;
+        let [first, second, third, fourth] = buffers;
+        while read < to_read {
+            let cur_buffs = &mut [
+                &mut first[read..],
+                &mut second[read..],
+                &mut third[read..],
+                &mut fourth[read..],
+            ];
+
+            let read_raw = self.read_bulk(cur_buffs)?;
+            let read_this_time = read_raw[0];
+            for other in &read_raw[1..] {
+                if *other != read_this_time {
+                    unreachable!("BulkMultiplayer::read_bulk should only read a fixed amount from all 4 players!");
+                }
+            }
+            read += read_this_time;
+            self.tick()?;
+        }
+        Ok(())
+    }
+
+    /// Exits "bulk transfer mode", returning to low-level multiplayer serial
+    /// mode. Any unread or unsent data is discarded.
+    ///
+    /// Bulk mode can be entered again afterwards with a fresh call to
+    /// [BulkMultiplayer::new]; dropping the [BulkMultiplayer] instead also
+    /// allows this.
+    pub fn leave(self) -> MultiplayerSerial<'a> {
+        let mut this = ManuallyDrop::new(self);
+        this.end_session();
+        // #SAFETY
+        //
+        // `this` is never used or dropped again, so `inner` is only moved out
+        // once; every other field is plain data with nothing to drop.
+        unsafe { ptr::read(&this.inner) }
+    }
+    /// Stops the interrupt & resets all of the bulk mode global state, so that
+    /// the next session starts from scratch.
+    fn end_session(&mut self) {
+        self.inner.enable_interrupt(false);
+        self.inner.buffer_interrupt = None;
+        critical_section::with(|cs| {
+            BUFFER_SLOT.swap_in(cs, TransferBuffer::empty());
+            OUTBUFFER.swap_in(cs, Ringbuffer::empty());
+            PRIORITY_OUTBUFFER.swap_in(cs, Ringbuffer::empty());
+            MESSAGE_BOUNDS.swap_in(cs, MessageBounds::new());
+            BLOCK_TRANSFER_UNTIL_SEND.swap_in(cs, true);
+            KEEP_READY.swap_in(cs, false);
+            HEARD_FROM.swap_in(cs, 0);
+            ANNOUNCED.swap_in(cs, 0);
+            SWITCH_WATCHER.swap_in(cs, None);
+        });
+    }
+
+    /// Swaps the inbox & outbox for new heap allocations holding `new_cap`
+    /// words (per player for the inbox), moving any unread data across. Fails
+    /// without changing anything if that data doesn't fit.
+    ///
+    /// This also works on buffers from [Self::new_static], though they are
+    /// then replaced by heap allocations.
+    pub fn resize_buffers(&mut self, new_cap: usize) -> Result<(), ResizeError> {
+        if new_cap == 0 {
+            return Err(ResizeError::ZeroCapacity);
+        }
+        let nbuff = TransferBuffer::new(new_cap);
+        let nout = Ringbuffer::new(new_cap);
+        let (old_buff, old_out) = critical_section::with(|cs| {
+            let inbox_len = BUFFER_SLOT.lock_in(cs, |tbuf| tbuf.len());
+            let outbox_len = OUTBUFFER.lock_in(cs, |outbuff| outbuff.len(cs));
+            if inbox_len > new_cap || outbox_len > new_cap {
+                return Err(ResizeError::TooSmall);
+            }
+            BUFFER_SLOT.lock_in(cs, |tbuf| {
+                while let Some([p0, p1, p2, p3]) = tbuf.pop() {
+                    // Can't fail; we checked the length above.
+                    let _res = nbuff.push(p0, p1, p2, p3, 0, cs);
+                }
+            });
+            OUTBUFFER.lock_in(cs, |outbuff| {
+                while let Some(word) = outbuff.pop(cs) {
+                    let _res = nout.push(word, cs);
+                }
+            });
+            Ok((BUFFER_SLOT.swap_in(cs, nbuff), OUTBUFFER.swap_in(cs, nout)))
+        })?;
+        // Free the old buffers outside of the critical section.
+        drop(old_buff);
+        drop(old_out);
+        Ok(())
+    }
+
+    /// Whether or not all data transfers for all other GBAs in the session will be
+    /// blocked until we ourselves also write data to be sent out.
+    pub fn will_block_transfers(&self) -> bool {
+        BLOCK_TRANSFER_UNTIL_SEND.get_copy()
+    }
+    /// Sets whether or not all data transfers for all other GBAs in the session will be
+    /// blocked until we ourselves also write data to be sent out.
+    pub fn block_transfers_until_have_data(&mut self, value: bool) {
+        BLOCK_TRANSFER_UNTIL_SEND.swap(value);
+    }
+    /// Runs `f` with this unit held ready for transfers even while it has
+    /// nothing to send, so that an idle unit still hears the control messages
+    /// of a blocking exchange like [Self::negotiate_baud_rate]. Otherwise a
+    /// unit waiting to be spoken to in the default blocking mode would never
+    /// let a transfer happen.
+    fn keeping_ready<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        KEEP_READY.swap(true);
+        self.inner.mark_ready();
+        let retvl = f(self);
+        KEEP_READY.swap(false);
+        retvl
+    }
+    /// The number of words that can currently be queued with
+    /// [Self::queue_send] before the outbox is full.
+    pub fn queue_space(&self) -> usize {
+        critical_section::with(|cs| {
+            OUTBUFFER.lock_in(cs, |outbuff| outbuff.capacity() - outbuff.len(cs))
+        })
+    }
+    /// The number of words queued with [Self::queue_send] that haven't been
+    /// sent yet. Does NOT block.
+    pub fn queued_words(&self) -> usize {
+        critical_section::with(|cs| OUTBUFFER.lock_in(cs, |outbuff| outbuff.len(cs)))
+    }
+    /// The number of words that can currently be queued with
+    /// [Self::queue_send_priority] before the priority outbox is full.
+    pub fn priority_queue_space(&self) -> usize {
+        critical_section::with(|cs| {
+            PRIORITY_OUTBUFFER.lock_in(cs, |priority| priority.capacity() - priority.len(cs))
+        })
+    }
+    /// Drops every transfer received so far that hasn't been read yet.
+    pub fn clear_inbox(&mut self) {
+        BUFFER_SLOT.lock(|tbuf| tbuf.clear());
+    }
+    /// Drops every word queued with [Self::queue_send] or
+    /// [Self::queue_send_priority] that hasn't been sent yet. The word already
+    /// loaded for the next transfer is still sent.
+    pub fn clear_outbox(&mut self) {
+        critical_section::with(|cs| {
+            OUTBUFFER.lock_in(cs, |outbuff| outbuff.clear(cs));
+            PRIORITY_OUTBUFFER.lock_in(cs, |priority| priority.clear(cs));
+            MESSAGE_BOUNDS.swap_in(cs, MessageBounds::new());
+        });
+    }
+    /// Ticks once per frame until everything queued has been sent, or
+    /// `timeout_frames` frames pass. Returns whether or not the outbox was
+    /// emptied in time.
+    pub fn flush_outbox(&mut self, timeout_frames: Option<u32>) -> Result<bool, BulkTickError> {
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        while !self.outbox_drained() {
+            self.tick()?;
+            if !timeout.wait() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    /// Whether or not everything queued has been sent & no transfer is in
+    /// progress.
+    fn outbox_drained(&self) -> bool {
+        let queued = critical_section::with(|cs| {
+            OUTBUFFER.lock_in(cs, |outbuff| outbuff.len(cs))
+                + PRIORITY_OUTBUFFER.lock_in(cs, |priority| priority.len(cs))
+        });
+        queued == 0 && !self.inner.is_busy()
+    }
+    /// Queues as much of `buffer` as fits in the outbox, returning how many
+    /// words were queued. Does NOT block.
+    ///
+    /// Each call is treated as 1 message, which words from
+    /// [Self::queue_send_priority] never interrupt. If only part of `buffer`
+    /// fits, the next call is treated as the rest of the same message, & no
+    /// priority words are sent until it's been queued.
+    pub fn queue_send(&mut self, buffer: &[u16]) -> Result<usize, QueueError> {
+        let res = critical_section::with(|cs| queue_message(buffer, cs));
+        enter_multiplayer(self.inner.rate)?;
+        Ok(res)
+    }
+    /// Queues `buffer` in the priority outbox, to be sent ahead of anything
+    /// queued with [Self::queue_send]. Returns `false` without queueing
+    /// anything if the whole buffer doesn't fit, so that priority messages are
+    /// never split up.
+    ///
+    /// Priority messages only ever cut in between the messages queued with
+    /// [Self::queue_send], never partway through one, so they can use the
+    /// same framing as everything else. Does NOT block.
+    pub fn queue_send_priority(&mut self, buffer: &[u16]) -> Result<bool, QueueError> {
+        let fits = critical_section::with(|cs| {
+            PRIORITY_OUTBUFFER.lock_in(cs, |priority| {
+                if priority.capacity() - priority.len(cs) < buffer.len() {
+                    return false;
+                }
+                priority.write_bulk(buffer, cs);
+                true
+            })
+        });
+        enter_multiplayer(self.inner.rate)?;
+        Ok(fits)
+    }
+
+    /// Whether or not each player seems to still be connected, based on when
+    /// we last received anything other than [NO_DATA] from them. We're always
+    /// [LinkStatus::Connected] to ourselves.
+    ///
+    /// A player with nothing to send looks the same as a disconnected one, so
+    /// games that can go quiet for longer than the timeout should send a
+    /// periodic keep-alive word. Does NOT block.
+    pub fn connection_status(&self) -> [LinkStatus; 4] {
+        let mut retvl = self.links.status(self.disconnect_ticks);
+        retvl[self.id() as usize] = LinkStatus::Connected;
+        retvl
+    }
+    /// The number of [Self::tick] calls a player can go without sending
+    /// anything before they're considered disconnected.
+    pub fn disconnect_timeout(&self) -> u32 {
+        self.disconnect_ticks
+    }
+    /// Sets the number of [Self::tick] calls a player can go without sending
+    /// anything before they're considered disconnected; defaults to
+    /// [DEFAULT_DISCONNECT_TICKS].
+    pub fn set_disconnect_timeout(&mut self, ticks: u32) {
+        self.disconnect_ticks = ticks;
+    }
+
+    /// Which players have started a session since the last call, by sending
+    /// the [JOIN_ANNOUNCEMENT] that [BulkMultiplayer::new] queues. This
+    /// includes every unit that started after us, & any unit that rebooted
+    /// & rejoined; a unit that just stalled for a while doesn't count. We
+    /// never count as joining ourselves.
+    ///
+    /// Use this to reset any per-player state, since a rejoining unit has
+    /// lost its own. Does NOT block.
+    pub fn take_joined(&mut self) -> [bool; 4] {
+        let mut retvl = self.links.take_joined();
+        retvl[self.id() as usize] = false;
+        retvl
+    }
+
+    /// Sets how full the inbox can get, as a percentage of its capacity,
+    /// before [Self::backpressure] reports it; `None` (the default) never
+    /// reports it.
+    pub fn set_inbox_watermark(&mut self, percent: Option<u8>) {
+        self.inbox_watermark = percent;
+    }
+    /// Sets how full the outbox can get, as a percentage of its capacity,
+    /// before [Self::backpressure] reports it; `None` (the default) never
+    /// reports it.
+    pub fn set_outbox_watermark(&mut self, percent: Option<u8>) {
+        self.outbox_watermark = percent;
+    }
+    /// Which buffers were past their watermarks as of the last [Self::tick],
+    /// so that the game can throttle how much it sends (or read more often)
+    /// before data starts being dropped.
+    pub fn backpressure(&self) -> Backpressure {
+        self.pressure
+    }
+    /// A snapshot of the session's [LinkStats] so far.
+    pub fn stats(&self) -> LinkStats {
+        STATS.get_copy()
+    }
+    /// Resets every [LinkStats] counter to 0.
+    pub fn reset_stats(&mut self) {
+        STATS.swap(LinkStats::new());
+    }
+
+    /// Queues the whole of `buffer` as 1 message, ticking once per frame
+    /// until there's room for all of it in the outbox.
+    ///
+    /// Either all of `buffer` is queued or none of it is, so a timeout never
+    /// leaves half a message behind. Returns `false` without queueing
+    /// anything if it didn't fit within `timeout_frames` frames, or if it's
+    /// longer than the outbox can ever hold.
+    pub fn queue_send_all(
+        &mut self,
+        buffer: &[u16],
+        timeout_frames: Option<u32>,
+    ) -> Result<bool, QueueError> {
+        if buffer.len() > self.queue_space() + self.queued_words() {
+            return Ok(false);
+        }
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        while self.queue_space() < buffer.len() {
+            self.tick().map_err(MultiplayerError::from)?;
+            if !timeout.wait() {
+                return Ok(false);
+            }
+        }
+        self.queue_send(buffer)?;
+        Ok(true)
+    }
+
+    /// Gets the link going again after the error flag trips (reported as
+    /// [BulkTickError::FailedOkayCheck]).
+    ///
+    /// This only resets our own hardware: it re-enters multiplayer mode &
+    /// re-arms the serial interrupt, & buffered operation then resumes on the
+    /// next [Self::tick]. Nothing is sent to the other units, so the streams
+    /// of any layers on top (like [PacketMultiplayer](packet::PacketMultiplayer))
+    /// carry on undisturbed. Unread & queued data is kept, though the words
+    /// in the transfer that failed may be lost. Does NOT block.
+    pub fn recover(&mut self) {
+        self.inner.mark_unready();
+        self.inner.mark_ready();
+        self.inner.enable_interrupt(true);
+        STATS.lock_mut(|stats| stats.recoveries = stats.recoveries.wrapping_add(1));
+    }
+    /// Sets whether or not [Self::tick] should call [Self::recover] itself
+    /// when it finds the error flag set, instead of returning
+    /// [BulkTickError::FailedOkayCheck]. Defaults to `false`.
+    pub fn set_auto_recover(&mut self, value: bool) {
+        self.auto_recover = value;
+    }
+
+    /// Perform any per-frame maintenance required for bulk multiplayer mode.
+    pub fn tick(&mut self) -> Result<(), BulkTickError> {
+        self.links.record(HEARD_FROM.swap(0), ANNOUNCED.swap(0));
+        self.parent_watch
+            .record(TRANSFER_COUNTER.get_copy(), self.inner.all_ready());
+        let (inbox_len, inbox_cap) = BUFFER_SLOT.lock(|tbuf| (tbuf.len(), tbuf.capacity()));
+        let (outbox_len, outbox_cap) = critical_section::with(|cs| {
+            OUTBUFFER.lock_in(cs, |outbuff| (outbuff.len(cs), outbuff.capacity()))
+        });
+        self.pressure = Backpressure {
+            inbox: past_watermark(inbox_len, inbox_cap, self.inbox_watermark),
+            outbox: past_watermark(outbox_len, outbox_cap, self.outbox_watermark),
+        };
+        match self.inner.start_transfer() {
+            Err(TransferError::FailedOkayCheck) => {
+                STATS.lock_mut(|stats| stats.error_flags = stats.error_flags.wrapping_add(1));
+                if self.auto_recover {
+                    self.recover();
+                    return Ok(());
+                }
+                Err(BulkTickError::FailedOkayCheck)
+            }
+            Ok(())
+            | Err(TransferError::AlreadyInProgress)
+            | Err(TransferError::FailedReadyCheck) => Ok(()),
+        }
+    }
+}
+
+impl Drop for BulkMultiplayer<'_> {
+    fn drop(&mut self) {
+        self.end_session();
+    }
+}
+
+/// Subroutine to make sure the [PlayerId] bits are valid & set on the provided
+/// [MultiplayerSerial] instance by forcing a single data transfer with a
+/// sentinel value.
+fn initialize_id(inner: &mut MultiplayerSerial) -> Result<(), TransferError> {
+    inner.mark_unready();
+    inner.write_send_reg(NO_DATA);
+    let interrupt_handle = unsafe {
+        add_interrupt_handler(Interrupt::Serial, |cs| {
+            TRANSFER_COUNTER.lock_mut_in(cs, |n| {
+                *n = n.wrapping_add(1);
+            });
+        })
+    };
+    inner.enable_interrupt(true);
+    let old_count = TRANSFER_COUNTER.get_copy();
+    inner.mark_ready();
+    loop {
+        {
+            match inner.start_transfer() {
+                Ok(()) => {}
+                Err(TransferError::AlreadyInProgress) => {
+                    // Parent beat us to it; let it keep going
+                }
+                Err(TransferError::FailedReadyCheck) => {
+                    // Others are lagging; wait for them
+                }
+                Err(other) => {
+                    return Err(other);
+                }
+            };
+        }
+
+        let new_count = TRANSFER_COUNTER.get_copy();
+        if old_count != new_count {
+            let my_id = MultiplayerSiocnt::get().id();
+            inner.playerid = Some(my_id);
+            break;
+        }
+    }
+    drop(interrupt_handle);
+    inner.mark_unready();
+    Ok(())
+}
+
+/// Queues as much of `buffer` in [OUTBUFFER] as fits as a single message,
+/// returning how many words were queued.
+fn queue_message(buffer: &[u16], cs: CriticalSection<'_>) -> usize {
+    let written = OUTBUFFER.lock_in(cs, |outbuff| outbuff.write_bulk(buffer, cs));
+    MESSAGE_BOUNDS.lock_mut_in(cs, |bounds| bounds.push(written, written == buffer.len()));
+    written
+}
+
+/// The interrupt callback called every time the parent unit (with
+/// [PlayerId::P0]) sends data with [MultiplayerSerial::start_transfer].
+fn bulk_mode_interrupt_callback(cs: CriticalSection<'_>) {
+    TRANSFER_COUNTER.lock_mut_in(cs, |n| {
+        *n = n.wrapping_add(1);
+    });
+    let siocnt = MultiplayerSiocnt::get();
+    let flags = (siocnt.read() & 0xFF) as u8;
+    let p0 = MultiplayerCommReg::get(PlayerId::P0).raw_read();
+    let p1 = MultiplayerCommReg::get(PlayerId::P1).raw_read();
+    let p2 = MultiplayerCommReg::get(PlayerId::P2).raw_read();
+    let p3 = MultiplayerCommReg::get(PlayerId::P3).raw_read();
+
+    let heard = [p0, p1, p2, p3]
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, word)| word != NO_DATA)
+        .fold(0u8, |mask, (idx, _)| mask | (1 << idx));
+    HEARD_FROM.lock_mut_in(cs, |n| *n |= heard);
+    JOIN_SCANNERS.lock_mut_in(cs, |scanners| {
+        let announced = [p0, p1, p2, p3]
+            .into_iter()
+            .zip(scanners.iter_mut())
+            .map(|(word, scanner)| scanner.push(word) == Some(Err(EscapeError::Joined)))
+            .enumerate()
+            .filter(|&(_, joined)| joined)
+            .fold(0u8, |mask, (idx, _)| mask | (1 << idx));
+        ANNOUNCED.lock_mut_in(cs, |n| *n |= announced);
+    });
+    SWITCH_WATCHER.lock_mut_in(cs, |watcher| {
+        if let Some(watcher) = watcher {
+            watcher.feed(p0);
+        }
+    });
+    STATS.lock_mut_in(cs, |stats| {
+        stats.transfers = stats.transfers.wrapping_add(1);
+        for (idx, count) in stats.words_received.iter_mut().enumerate() {
+            if heard & (1 << idx) != 0 {
+                *count = count.wrapping_add(1);
+            }
+        }
+        if heard == 0 {
+            stats.empty_transfers_skipped = stats.empty_transfers_skipped.wrapping_add(1);
+        }
+    });
+
+    if !(p0 == NO_DATA && p1 == NO_DATA && p2 == NO_DATA && p3 == NO_DATA) {
+        // This will only happen if NONE of the units had data to send,
+        // INCLUDING US, and ALL of them set `block_transfers_until_have_data`
+        // to `false`. In that case we'd hit this case every time the parent
+        // unit hit `BulkMultiplayer::tick`, so to not waste cycles and memory
+        // we don't write the all-sentinel case down.
+        BUFFER_SLOT.lock_in(cs, |tbuff| {
+            debug_assert!(!tbuff.is_placeholder());
+            if tbuff.push(p0, p1, p2, p3, flags, cs).is_err() {
+                STATS.lock_mut_in(cs, |stats| {
+                    stats.buffer_overflows = stats.buffer_overflows.wrapping_add(1)
+                });
+            }
+        });
+    }
+
+    OUTBUFFER.lock_in(cs, |outbuff| {
+        let next = MESSAGE_BOUNDS.lock_mut_in(cs, |bounds| {
+            next_outgoing(
+                bounds,
+                || PRIORITY_OUTBUFFER.lock_in(cs, |priority| priority.pop(cs)),
+                || outbuff.pop(cs),
+            )
+        });
+        if next.is_some() {
+            STATS.lock_mut_in(cs, |stats| {
+                stats.words_sent = stats.words_sent.wrapping_add(1)
+            });
+        }
+        SIOMLT_SEND.write(next.unwrap_or(NO_DATA));
+        let block = BLOCK_TRANSFER_UNTIL_SEND.get_copy_in(cs);
+        if !stays_ready(next.is_some(), block, KEEP_READY.get_copy_in(cs)) {
+            mark_unready()
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_link_tracker(_gba: &mut Gba) {
+        let mut links = LinkTracker::new();
+        links.record(0b0011, 0b0010);
+        links.record(0b0001, 0);
+        links.record(0b0001, 0);
+        assert_eq!(links.take_joined(), [false, true, false, false]);
+        assert_eq!(
+            links.status(2),
+            [
+                LinkStatus::Connected,
+                LinkStatus::Disconnected,
+                LinkStatus::Unknown,
+                LinkStatus::Unknown
+            ]
+        );
+        assert_eq!(links.status(3)[1], LinkStatus::Connected);
+        // Coming back after a stall isn't a rejoin...
+        links.record(0b0010, 0);
+        assert_eq!(links.status(2)[..2], [LinkStatus::Connected; 2]);
+        assert_eq!(links.take_joined(), [false; 4]);
+        // ...but announcing a new session is, even without a stall.
+        links.record(0b0011, 0b0001);
+        assert_eq!(links.take_joined(), [true, false, false, false]);
+        assert_eq!(links.take_joined(), [false; 4]);
+    }
+
+    /// An outbox & priority outbox feeding the interrupt handler's choice of
+    /// [next_outgoing].
+    #[derive(Default)]
+    struct OutboxSim {
+        bounds: MessageBounds,
+        normal: VecDeque<u16>,
+        priority: VecDeque<u16>,
+        sent: Vec<u16>,
+    }
+
+    impl OutboxSim {
+        fn queue(&mut self, words: &[u16], complete: bool) {
+            self.normal.extend(words);
+            self.bounds.push(words.len(), complete);
+        }
+        fn transfers(&mut self, count: usize) {
+            for _ in 0..count {
+                let word = next_outgoing(
+                    &mut self.bounds,
+                    || self.priority.pop_front(),
+                    || self.normal.pop_front(),
+                );
+                self.sent.extend(word);
+            }
+        }
+    }
+
+    #[test_case]
+    fn test_priority_between_messages(_gba: &mut Gba) {
+        let mut sim = OutboxSim::default();
+        sim.queue(&[1, 2, 3], true);
+        sim.queue(&[4, 5], true);
+        sim.transfers(1);
+        sim.priority.extend([100, 101]);
+        sim.transfers(4);
+        // A message that only partly fit holds off priority words until the
+        // rest is queued, even once everything queued so far is sent.
+        sim.queue(&[6, 7], false);
+        sim.transfers(4);
+        sim.priority.push_back(102);
+        sim.transfers(1);
+        sim.queue(&[8], true);
+        sim.transfers(3);
+        assert_eq!(sim.sent, [1, 2, 3, 100, 101, 4, 5, 6, 7, 8, 102]);
+    }
+
+    #[test_case]
+    fn test_stays_ready(_gba: &mut Gba) {
+        assert!(stays_ready(true, true, false));
+        assert!(!stays_ready(false, true, false));
+        assert!(stays_ready(false, false, false));
+        assert!(stays_ready(false, true, true));
+    }
+
+    #[test_case]
+    fn test_past_watermark(_gba: &mut Gba) {
+        assert!(!past_watermark(74, 100, Some(75)));
+        assert!(past_watermark(3, 4, Some(75)));
+        assert!(!past_watermark(4, 4, None));
+        assert!(past_watermark(0, 0, Some(0)));
+    }
+}
@@ -0,0 +1,299 @@
+//! Variable-length byte packets over [BulkMultiplayer]'s raw word stream, so
+//! that callers don't need to hand-roll their own sentinels to find where each
+//! message ends.
+//!
+//! Each packet goes over the link as:
+//!
+//! | Word(s)          | Contents |
+//! | :--              | :--      |
+//! | 1                | `0xB000`, the flags in bits 4-11 & the channel in bits 0-3 |
+//! | 1                | The payload's length in bytes |
+//! | (length + 1) / 2 | The payload, 2 bytes per word with the first in the high byte |
+//!
+//! Every word is then [escaped](super::escape), so payloads can contain any
+//! bytes.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::escape::{escape_with, Unescaper};
+use super::{BulkMultiplayer, PlayerId, QueueError};
+use crate::serial::multiplayer::MultiplayerError;
+
+/// The longest payload a single packet can have.
+pub const MAX_PACKET_BYTES: usize = 2048;
+/// The number of channels packets can be sent on.
+pub const CHANNELS: u8 = 16;
+
+/// Marks the top nibble of every packet's first word.
+const HEADER_MARKER: u16 = 0xB000;
+const HEADER_MARKER_MASK: u16 = 0xF000;
+
+/// The metadata sent along with each packet.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PacketHeader {
+    /// Which of the [CHANNELS] channels the packet is on, so that unrelated
+    /// streams of packets can share the link.
+    pub channel: u8,
+    /// 8 bits free for the game to use.
+    pub flags: u8,
+}
+
+impl PacketHeader {
+    const fn into_word(self) -> u16 {
+        HEADER_MARKER | (self.flags as u16) << 4 | (self.channel & 0xF) as u16
+    }
+    const fn from_word(word: u16) -> Option<Self> {
+        if word & HEADER_MARKER_MASK != HEADER_MARKER {
+            return None;
+        }
+        Some(Self {
+            channel: (word & 0xF) as u8,
+            flags: (word >> 4) as u8,
+        })
+    }
+}
+
+/// A whole packet received from another player.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Packet {
+    pub header: PacketHeader,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketError {
+    /// The payload is longer than [MAX_PACKET_BYTES].
+    TooLong,
+    /// The channel isn't below [CHANNELS].
+    InvalidChannel,
+    /// There isn't room in the outbox for the whole packet right now.
+    OutboxFull,
+    MultiplayerError(MultiplayerError),
+}
+
+impl From<MultiplayerError> for PacketError {
+    fn from(value: MultiplayerError) -> Self {
+        PacketError::MultiplayerError(value)
+    }
+}
+
+/// Appends the whole escaped packet to `out`.
+fn encode(header: PacketHeader, data: &[u8], out: &mut Vec<u16>) {
+    let mut sink = |word| out.push(word);
+    escape_with(&[header.into_word(), data.len() as u16], &mut sink);
+    for pair in data.chunks(2) {
+        let word = match *pair {
+            [high, low] => u16::from_be_bytes([high, low]),
+            [high] => u16::from_be_bytes([high, 0]),
+            _ => unreachable!(),
+        };
+        escape_with(&[word], &mut sink);
+    }
+}
+
+/// What the next decoded word from a player will be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DecodeState {
+    Header,
+    Length(PacketHeader),
+    Payload { header: PacketHeader, len: usize },
+}
+
+/// Rebuilds the packets sent by a single player, word by word.
+struct Decoder {
+    unescaper: Unescaper,
+    state: DecodeState,
+    data: Vec<u8>,
+    done: VecDeque<Packet>,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Self {
+            unescaper: Unescaper::new(),
+            state: DecodeState::Header,
+            data: Vec::new(),
+            done: VecDeque::new(),
+        }
+    }
+    fn feed(&mut self, word: u16) {
+        let word = match self.unescaper.push(word) {
+            None => return,
+            Some(Ok(word)) => word,
+            Some(Err(_)) => {
+                // Drop the packet, & look for the start of the next one.
+                self.data.clear();
+                self.state = DecodeState::Header;
+                return;
+            }
+        };
+        self.state = match self.state {
+            DecodeState::Header => match PacketHeader::from_word(word) {
+                Some(header) => DecodeState::Length(header),
+                None => DecodeState::Header,
+            },
+            DecodeState::Length(header) => match word as usize {
+                len if len > MAX_PACKET_BYTES => DecodeState::Header,
+                len => self.payload_word(header, len, None),
+            },
+            DecodeState::Payload { header, len } => self.payload_word(header, len, Some(word)),
+        };
+    }
+    /// Adds the next payload word (if any), finishing the packet if it's now
+    /// `len` bytes long.
+    fn payload_word(&mut self, header: PacketHeader, len: usize, word: Option<u16>) -> DecodeState {
+        if let Some(word) = word {
+            let [high, low] = word.to_be_bytes();
+            self.data.push(high);
+            if self.data.len() < len {
+                self.data.push(low);
+            }
+        }
+        if self.data.len() < len {
+            return DecodeState::Payload { header, len };
+        }
+        let data = core::mem::take(&mut self.data);
+        self.done.push_back(Packet { header, data });
+        DecodeState::Header
+    }
+}
+
+/// Sends & receives variable-length packets of bytes.
+pub struct PacketMultiplayer<'a> {
+    inner: BulkMultiplayer<'a>,
+    decoders: [Decoder; 4],
+}
+
+impl<'a> PacketMultiplayer<'a> {
+    pub fn new(inner: BulkMultiplayer<'a>) -> Self {
+        Self {
+            inner,
+            decoders: [
+                Decoder::new(),
+                Decoder::new(),
+                Decoder::new(),
+                Decoder::new(),
+            ],
+        }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// Queues `data` to be sent to every other player on channel 0, without
+    /// any flags.
+    pub fn send_packet(&mut self, data: &[u8]) -> Result<(), PacketError> {
+        self.send_packet_with(PacketHeader::default(), data)
+    }
+    /// Queues `data` to be sent to every other player with the given header.
+    /// Nothing is queued unless the whole packet fits.
+    pub fn send_packet_with(
+        &mut self,
+        header: PacketHeader,
+        data: &[u8],
+    ) -> Result<(), PacketError> {
+        if data.len() > MAX_PACKET_BYTES {
+            return Err(PacketError::TooLong);
+        }
+        if header.channel >= CHANNELS {
+            return Err(PacketError::InvalidChannel);
+        }
+        let mut words = Vec::new();
+        encode(header, data, &mut words);
+        if self.inner.queue_space() < words.len() {
+            return Err(PacketError::OutboxFull);
+        }
+        match self.inner.queue_send(&words) {
+            Ok(_) => Ok(()),
+            Err(QueueError::MultiplayerError(e)) => Err(e.into()),
+            Err(QueueError::QueueNotEmpty) => Err(PacketError::OutboxFull),
+        }
+    }
+    /// Pulls any received data out of the bulk buffers, then returns the next
+    /// whole packet from `player`, if one has arrived. Does NOT block.
+    pub fn recv_packet(&mut self, player: PlayerId) -> Result<Option<Packet>, PacketError> {
+        let decoders = &mut self.decoders;
+        self.inner
+            .drain_received(|player, word| decoders[player as usize].feed(word))?;
+        Ok(self.decoders[player as usize].done.pop_front())
+    }
+    /// Which players have joined since the last call; see
+    /// [BulkMultiplayer::take_joined]. Drops any partial packet from before
+    /// each of them joined.
+    pub fn take_joined(&mut self) -> [bool; 4] {
+        let joined = self.inner.take_joined();
+        for (decoder, _) in self.decoders.iter_mut().zip(joined).filter(|(_, j)| *j) {
+            *decoder = Decoder::new();
+        }
+        joined
+    }
+    /// Perform any per-frame maintenance required; see
+    /// [BulkMultiplayer::tick].
+    pub fn tick(&mut self) -> Result<(), PacketError> {
+        self.inner
+            .tick()
+            .map_err(|e| MultiplayerError::from(e).into())
+    }
+    /// Removes the packet layer, dropping any partially received packets.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::multiplayer::NO_DATA;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_packets(_gba: &mut Gba) {
+        let header = PacketHeader {
+            channel: 3,
+            flags: 0xA5,
+        };
+        assert_eq!(header.into_word(), 0xBA53);
+        assert_eq!(PacketHeader::from_word(0xBA53), Some(header));
+        assert_eq!(PacketHeader::from_word(0x1234), None);
+
+        let mut stream = alloc::vec![NO_DATA, 0x1234];
+        encode(header, b"hello", &mut stream);
+        stream.push(NO_DATA);
+        encode(PacketHeader::default(), &[], &mut stream);
+        encode(PacketHeader::default(), &[0xFF, 0xFF, 1], &mut stream);
+
+        let mut decoder = Decoder::new();
+        for word in stream {
+            decoder.feed(word);
+        }
+        let packets: Vec<_> = decoder.done.into_iter().collect();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].header, header);
+        assert_eq!(packets[0].data, b"hello");
+        assert!(packets[1].data.is_empty());
+        assert_eq!(packets[2].data, [0xFF, 0xFF, 1]);
+    }
+
+    #[test_case]
+    fn test_packets_across_recover(_gba: &mut Gba) {
+        use super::super::baud::CONTROL_MAGIC;
+
+        // Anything injected into the stream between packets could be read as
+        // a header & swallow the next packet, which is why
+        // `BulkMultiplayer::recover` doesn't send anything.
+        assert!(PacketHeader::from_word(CONTROL_MAGIC).is_some());
+
+        // What a peer sees from a unit that recovered between 2 packets.
+        let mut stream = Vec::new();
+        encode(PacketHeader::default(), b"before", &mut stream);
+        stream.extend([NO_DATA, NO_DATA]);
+        encode(PacketHeader::default(), b"after", &mut stream);
+
+        let mut decoder = Decoder::new();
+        for word in stream {
+            decoder.feed(word);
+        }
+        let data: Vec<_> = decoder.done.into_iter().map(|p| p.data).collect();
+        assert_eq!(data, [&b"before"[..], &b"after"[..]]);
+    }
+}
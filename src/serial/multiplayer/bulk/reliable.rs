@@ -0,0 +1,331 @@
+//! A reliable delivery layer over [BulkMultiplayer], so that games can treat
+//! the link as lossless.
+//!
+//! Messages are sent with the [CRC layer](super::crc) & a sequence number.
+//! Every peer acknowledges each message it receives intact, and a message is
+//! sent again if any peer hasn't acknowledged it within the retransmit
+//! timeout. Duplicates caused by lost acknowledgements are dropped, so each
+//! message is delivered exactly once & in order.
+//!
+//! The timeout only starts once a copy has actually left the bulk outbox, so
+//! a backed up outbox doesn't fill with duplicates. Peers that are
+//! [Disconnected](super::LinkStatus::Disconnected) aren't waited for, & a
+//! message that still isn't acknowledged after
+//! [ReliableLink::set_max_retries] retransmits is given up on & counted in
+//! [ReliableLink::undelivered].
+//!
+//! Only 1 message is in flight at a time; the rest wait in an unbounded
+//! outbox until it has been acknowledged by every peer.
+//!
+//! Whenever a peer (re)joins the session the next sequence number from them is
+//! accepted as-is, so units don't need to start their links at the same time.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::crc::{CrcMultiplayer, MessageError, MAX_MESSAGE_WORDS};
+use super::{BulkMultiplayer, LinkStatus, PlayerId};
+
+/// Set in the header of acknowledgements; data messages leave it clear.
+const ACK_FLAG: u16 = 0x100;
+
+/// The number of retransmits before a message is given up on, unless changed
+/// with [ReliableLink::set_max_retries].
+pub const DEFAULT_MAX_RETRIES: u32 = 8;
+
+/// A message waiting for acknowledgements.
+struct InFlight {
+    seq: u8,
+    payload: Vec<u16>,
+    /// The peers that have acknowledged it, as a bitmask by [PlayerId].
+    acked: u8,
+    /// The ticks since the latest copy left the bulk outbox.
+    age: u32,
+    /// The number of copies queued so far.
+    copies: u32,
+    /// [LinkStats::words_sent](super::LinkStats::words_sent) once the latest
+    /// copy has left the bulk outbox.
+    sent_at: u32,
+}
+
+/// Whether a copy queued to leave at `sent_at` is still in the outbox, given
+/// the words sent so far; both count up with wrapping.
+const fn still_queued(sent_at: u32, words_sent: u32) -> bool {
+    (words_sent.wrapping_sub(sent_at) as i32) < 0
+}
+
+/// What to do with a data message, given its sequence number.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Received {
+    /// The next message in order; deliver it.
+    Next,
+    /// Already delivered, so our acknowledgement was lost; acknowledge it
+    /// again.
+    Duplicate,
+    /// From too far ahead to make sense of; drop it.
+    OutOfOrder,
+}
+
+impl Received {
+    /// Classifies `seq` given the sequence number expected next, advancing
+    /// `expected` if it's the next message. If nothing is expected yet, any
+    /// sequence number is the next one.
+    fn classify(expected: &mut Option<u8>, seq: u8) -> Self {
+        let next = expected.unwrap_or(seq);
+        if seq == next {
+            *expected = Some(next.wrapping_add(1));
+            Self::Next
+        } else if seq == next.wrapping_sub(1) {
+            Self::Duplicate
+        } else {
+            Self::OutOfOrder
+        }
+    }
+}
+
+/// Sends & receives messages that are acknowledged & retransmitted until
+/// every peer has them.
+pub struct ReliableLink<'a> {
+    inner: CrcMultiplayer<'a>,
+    /// The players to wait for acknowledgements from, as a bitmask.
+    peers: u8,
+    retransmit_ticks: u32,
+    max_retries: u32,
+    /// The number of messages given up on.
+    undelivered: u32,
+    next_seq: u8,
+    in_flight: Option<InFlight>,
+    outbox: VecDeque<Vec<u16>>,
+    /// Acknowledgements that didn't fit in the bulk outbox yet.
+    pending_acks: VecDeque<(PlayerId, u8)>,
+    /// The next sequence number from each player, or `None` if they've just
+    /// joined.
+    expected: [Option<u8>; 4],
+    inboxes: [VecDeque<Vec<u16>>; 4],
+}
+
+impl<'a> ReliableLink<'a> {
+    /// Starts a reliable link with `peers`, resending each message if it
+    /// hasn't been acknowledged after `retransmit_ticks` calls to
+    /// [Self::tick].
+    ///
+    /// Peers may start their links at different times; see
+    /// [BulkMultiplayer::take_joined].
+    pub fn new(inner: BulkMultiplayer<'a>, peers: &[PlayerId], retransmit_ticks: u32) -> Self {
+        let me = inner.id();
+        let peers = peers
+            .iter()
+            .filter(|&&p| p != me)
+            .fold(0, |mask, &p| mask | (1 << p as u8));
+        Self {
+            inner: CrcMultiplayer::new(inner),
+            peers,
+            retransmit_ticks,
+            max_retries: DEFAULT_MAX_RETRIES,
+            undelivered: 0,
+            next_seq: 0,
+            in_flight: None,
+            outbox: VecDeque::new(),
+            pending_acks: VecDeque::new(),
+            expected: [None; 4],
+            inboxes: Default::default(),
+        }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// Queues `payload` to be delivered to every peer.
+    pub fn send(&mut self, payload: &[u16]) -> Result<(), MessageError> {
+        if payload.len() >= MAX_MESSAGE_WORDS {
+            return Err(MessageError::TooLong);
+        }
+        self.outbox.push_back(payload.into());
+        Ok(())
+    }
+    /// The next message delivered from `player`, if any. Does NOT block.
+    pub fn recv(&mut self, player: PlayerId) -> Option<Vec<u16>> {
+        self.inboxes[player as usize].pop_front()
+    }
+    /// Whether every message sent so far has been acknowledged by every peer
+    /// (or given up on).
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_none() && self.outbox.is_empty()
+    }
+    /// Sets how many times a message is sent again before it's given up on;
+    /// defaults to [DEFAULT_MAX_RETRIES].
+    pub fn set_max_retries(&mut self, retries: u32) {
+        self.max_retries = retries;
+    }
+    /// The number of messages given up on after [Self::set_max_retries]
+    /// retransmits, which at least 1 connected peer may not have received.
+    pub fn undelivered(&self) -> u32 {
+        self.undelivered
+    }
+    /// Perform the per-frame maintenance: handling received messages,
+    /// acknowledging them, & (re)sending messages. Call this once per frame.
+    pub fn tick(&mut self) -> Result<(), MessageError> {
+        let joined = self.inner.take_joined();
+        for (expected, _) in self.expected.iter_mut().zip(joined).filter(|(_, j)| *j) {
+            *expected = None;
+        }
+        self.receive()?;
+        self.flush_acks()?;
+        self.send_next()?;
+        self.inner.tick()
+    }
+    /// Removes the reliability layer, dropping any undelivered messages.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        self.inner.into_inner()
+    }
+
+    fn receive(&mut self) -> Result<(), MessageError> {
+        let me = self.id();
+        for player in PlayerId::ALL.into_iter().filter(|&p| p != me) {
+            loop {
+                let message = match self.inner.recv_message(player) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    // The sender will retransmit it.
+                    Err(MessageError::Corrupted(_)) => continue,
+                    Err(e) => return Err(e),
+                };
+                self.handle_message(player, message);
+            }
+        }
+        Ok(())
+    }
+    fn handle_message(&mut self, player: PlayerId, mut message: Vec<u16>) {
+        let Some(&header) = message.first() else {
+            return;
+        };
+        let seq = header as u8;
+        if header & ACK_FLAG != 0 {
+            let Some(&target) = message.get(1) else {
+                return;
+            };
+            let me = self.id() as u16;
+            if let Some(in_flight) = self.in_flight.as_mut() {
+                if target == me && in_flight.seq == seq {
+                    in_flight.acked |= 1 << player as u8;
+                }
+            }
+            return;
+        }
+        match Received::classify(&mut self.expected[player as usize], seq) {
+            Received::Next => {
+                message.remove(0);
+                self.inboxes[player as usize].push_back(message);
+                self.pending_acks.push_back((player, seq));
+            }
+            Received::Duplicate => self.pending_acks.push_back((player, seq)),
+            Received::OutOfOrder => {}
+        }
+    }
+    fn flush_acks(&mut self) -> Result<(), MessageError> {
+        while let Some(&(player, seq)) = self.pending_acks.front() {
+            match self
+                .inner
+                .send_message(&[ACK_FLAG | seq as u16, player as u16])
+            {
+                Ok(()) => {}
+                Err(MessageError::OutboxFull) => break,
+                Err(e) => return Err(e),
+            }
+            self.pending_acks.pop_front();
+        }
+        Ok(())
+    }
+    /// The players that are [LinkStatus::Disconnected], as a bitmask.
+    fn disconnected(&self) -> u8 {
+        self.inner
+            .connection_status()
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, status)| status == LinkStatus::Disconnected)
+            .fold(0, |mask, (idx, _)| mask | (1 << idx))
+    }
+    fn send_next(&mut self) -> Result<(), MessageError> {
+        let gone = self.disconnected();
+        let words_sent = self.inner.stats().words_sent;
+        if let Some(in_flight) = &mut self.in_flight {
+            if (in_flight.acked | gone) & self.peers == self.peers {
+                self.in_flight = None;
+            } else if in_flight.copies > 0 && still_queued(in_flight.sent_at, words_sent) {
+                // Another copy now would only queue up behind this one.
+                in_flight.age = 0;
+                return Ok(());
+            } else {
+                in_flight.age += 1;
+                if in_flight.age < self.retransmit_ticks {
+                    return Ok(());
+                }
+                if in_flight.copies > self.max_retries {
+                    self.in_flight = None;
+                    self.undelivered = self.undelivered.wrapping_add(1);
+                }
+            }
+        }
+        if self.in_flight.is_none() {
+            let Some(payload) = self.outbox.pop_front() else {
+                return Ok(());
+            };
+            let seq = self.next_seq;
+            self.next_seq = seq.wrapping_add(1);
+            let mut framed = Vec::with_capacity(payload.len() + 1);
+            framed.push(seq as u16);
+            framed.extend_from_slice(&payload);
+            self.in_flight = Some(InFlight {
+                seq,
+                payload: framed,
+                acked: 0,
+                age: 0,
+                copies: 0,
+                sent_at: 0,
+            });
+        }
+        let Some(in_flight) = &mut self.in_flight else {
+            return Ok(());
+        };
+        match self.inner.send_message(&in_flight.payload) {
+            Err(MessageError::OutboxFull) => {
+                // Try again next tick.
+                in_flight.age = self.retransmit_ticks;
+                Ok(())
+            }
+            Err(e) => Err(e),
+            Ok(()) => {
+                in_flight.age = 0;
+                in_flight.copies += 1;
+                let queued = self.inner.queued_words() as u32;
+                in_flight.sent_at = words_sent.wrapping_add(queued);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_classify(_gba: &mut Gba) {
+        let mut expected = None;
+        assert_eq!(Received::classify(&mut expected, 255), Received::Next);
+        assert_eq!(expected, Some(0));
+        assert_eq!(Received::classify(&mut expected, 255), Received::Duplicate);
+        assert_eq!(Received::classify(&mut expected, 5), Received::OutOfOrder);
+        assert_eq!(Received::classify(&mut expected, 0), Received::Next);
+        assert_eq!(expected, Some(1));
+    }
+
+    #[test_case]
+    fn test_still_queued(_gba: &mut Gba) {
+        assert!(still_queued(10, 4));
+        assert!(!still_queued(10, 10));
+        assert!(!still_queued(10, 11));
+        assert!(still_queued(3, u32::MAX - 2));
+        assert!(!still_queued(u32::MAX, 3));
+    }
+}
@@ -0,0 +1,137 @@
+//! Addressed messaging over the [CRC layer](super::crc), so that games don't
+//! need to build their own addressing on top of every player's stream.
+//!
+//! The link is a broadcast medium, so every message still reaches every
+//! player; each one starts with a word naming its [Destination], & messages
+//! meant for someone else are dropped on receipt.
+
+use alloc::vec::Vec;
+
+use super::crc::{CrcMultiplayer, MessageError, MAX_MESSAGE_WORDS};
+use super::{BulkMultiplayer, PlayerId};
+
+/// The destination word for messages to every player.
+const BROADCAST: u16 = 0x00FF;
+
+/// Who a message is meant for.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Destination {
+    Player(PlayerId),
+    Broadcast,
+}
+
+impl Destination {
+    const fn into_word(self) -> u16 {
+        match self {
+            Destination::Player(player) => player as u16,
+            Destination::Broadcast => BROADCAST,
+        }
+    }
+    const fn from_word(word: u16) -> Option<Self> {
+        match word {
+            0 => Some(Destination::Player(PlayerId::P0)),
+            1 => Some(Destination::Player(PlayerId::P1)),
+            2 => Some(Destination::Player(PlayerId::P2)),
+            3 => Some(Destination::Player(PlayerId::P3)),
+            BROADCAST => Some(Destination::Broadcast),
+            _ => None,
+        }
+    }
+    /// Whether or not a message to this destination is meant for `player`.
+    fn includes(self, player: PlayerId) -> bool {
+        match self {
+            Destination::Player(target) => target == player,
+            Destination::Broadcast => true,
+        }
+    }
+}
+
+/// Sends whole messages to a single player or to everyone, & only delivers
+/// the messages meant for us.
+pub struct RoutedMultiplayer<'a> {
+    inner: CrcMultiplayer<'a>,
+}
+
+impl<'a> RoutedMultiplayer<'a> {
+    pub fn new(inner: BulkMultiplayer<'a>) -> Self {
+        Self {
+            inner: CrcMultiplayer::new(inner),
+        }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// Queues `payload` to be delivered only to `player`. Nothing is queued
+    /// unless the whole message fits.
+    pub fn send_to(&mut self, player: PlayerId, payload: &[u16]) -> Result<(), MessageError> {
+        self.send(Destination::Player(player), payload)
+    }
+    /// Queues `payload` to be delivered to every other player.
+    pub fn broadcast(&mut self, payload: &[u16]) -> Result<(), MessageError> {
+        self.send(Destination::Broadcast, payload)
+    }
+    /// Queues `payload` to be delivered to `destination`. Nothing is queued
+    /// unless the whole message fits.
+    pub fn send(&mut self, destination: Destination, payload: &[u16]) -> Result<(), MessageError> {
+        if payload.len() >= MAX_MESSAGE_WORDS {
+            return Err(MessageError::TooLong);
+        }
+        let mut message = Vec::with_capacity(payload.len() + 1);
+        message.push(destination.into_word());
+        message.extend_from_slice(payload);
+        self.inner.send_message(&message)
+    }
+    /// The next message from `player` that was sent to us, either directly or
+    /// as a broadcast, along with how it was addressed. Messages for other
+    /// players are skipped; see [CrcMultiplayer::recv_message] for the rest.
+    /// Does NOT block.
+    pub fn recv(
+        &mut self,
+        player: PlayerId,
+    ) -> Result<Option<(Destination, Vec<u16>)>, MessageError> {
+        let me = self.id();
+        while let Some(mut message) = self.inner.recv_message(player)? {
+            let Some(destination) = message.first().and_then(|&w| Destination::from_word(w)) else {
+                continue;
+            };
+            if destination.includes(me) {
+                message.remove(0);
+                return Ok(Some((destination, message)));
+            }
+        }
+        Ok(None)
+    }
+    /// See [CrcMultiplayer::take_joined].
+    pub fn take_joined(&mut self) -> [bool; 4] {
+        self.inner.take_joined()
+    }
+    /// Perform any per-frame maintenance required; see
+    /// [BulkMultiplayer::tick].
+    pub fn tick(&mut self) -> Result<(), MessageError> {
+        self.inner.tick()
+    }
+    /// Removes the routing layer, dropping any partially received messages.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        self.inner.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_destination(_gba: &mut Gba) {
+        for destination in [Destination::Player(PlayerId::P2), Destination::Broadcast] {
+            assert_eq!(
+                Destination::from_word(destination.into_word()),
+                Some(destination)
+            );
+        }
+        assert_eq!(Destination::from_word(4), None);
+        assert!(Destination::Broadcast.includes(PlayerId::P1));
+        assert!(Destination::Player(PlayerId::P1).includes(PlayerId::P1));
+        assert!(!Destination::Player(PlayerId::P1).includes(PlayerId::P3));
+    }
+}
@@ -0,0 +1,99 @@
+//! A receive-only view of a bulk multiplayer session, for spectator units in
+//! tournaments or debugging probes plugged into a spare port.
+//!
+//! A [Spectator] still takes part in every transfer (the hardware requires
+//! it), but only ever sends [NO_DATA](super::NO_DATA) & always reports itself
+//! as ready, so it never holds up the rest of the session. Spectators should
+//! be plugged in as children; a spectating parent would start a transfer on
+//! every [Spectator::tick].
+//!
+//! Spectators don't answer [baud rate negotiation](super::baud), so leave
+//! them out of the peers the parent waits for; they follow the parent to the
+//! new rate once it tells everyone to switch.
+
+use super::baud::SwitchWatcher;
+use super::{
+    Backpressure, BulkMultiplayer, BulkTickError, LinkStats, LinkStatus, MultiplayerError,
+    PlayerId, SWITCH_WATCHER,
+};
+
+/// A unit that watches a bulk multiplayer session without sending anything.
+pub struct Spectator<'a> {
+    inner: BulkMultiplayer<'a>,
+}
+
+impl<'a> Spectator<'a> {
+    /// Turns `inner` into a spectator, dropping anything still in its outbox.
+    ///
+    /// Automatic recovery is turned off, since [BulkMultiplayer::recover]
+    /// briefly marks us as not ready, which would hold up the session.
+    pub fn new(mut inner: BulkMultiplayer<'a>) -> Self {
+        inner.clear_outbox();
+        inner.block_transfers_until_have_data(false);
+        inner.set_auto_recover(false);
+        SWITCH_WATCHER.swap(Some(SwitchWatcher::new()));
+        inner.inner.mark_ready();
+        Self { inner }
+    }
+    pub fn id(&self) -> PlayerId {
+        self.inner.id()
+    }
+    /// See [BulkMultiplayer::read_bulk].
+    pub fn read_bulk(
+        &mut self,
+        buffers: &mut [&mut [u16]; 4],
+    ) -> Result<[usize; 4], MultiplayerError> {
+        self.inner.read_bulk(buffers)
+    }
+    /// See [BulkMultiplayer::read_player].
+    pub fn read_player(&mut self, player: PlayerId, buffer: &mut [u16]) -> usize {
+        self.inner.read_player(player, buffer)
+    }
+    /// See [BulkMultiplayer::transfers].
+    pub fn transfers(&mut self) -> impl Iterator<Item = [u16; 4]> + '_ {
+        self.inner.transfers()
+    }
+    /// See [BulkMultiplayer::peek_transfer].
+    pub fn peek_transfer(&self) -> Option<[u16; 4]> {
+        self.inner.peek_transfer()
+    }
+    /// See [BulkMultiplayer::skip_empty_transfers].
+    pub fn skip_empty_transfers(&mut self) -> usize {
+        self.inner.skip_empty_transfers()
+    }
+    /// See [BulkMultiplayer::clear_inbox].
+    pub fn clear_inbox(&mut self) {
+        self.inner.clear_inbox()
+    }
+    /// See [BulkMultiplayer::connection_status].
+    pub fn connection_status(&self) -> [LinkStatus; 4] {
+        self.inner.connection_status()
+    }
+    /// See [BulkMultiplayer::backpressure]; only the inbox is ever reported.
+    pub fn backpressure(&self) -> Backpressure {
+        self.inner.backpressure()
+    }
+    /// See [BulkMultiplayer::set_inbox_watermark].
+    pub fn set_inbox_watermark(&mut self, percent: Option<u8>) {
+        self.inner.set_inbox_watermark(percent)
+    }
+    /// See [BulkMultiplayer::stats].
+    pub fn stats(&self) -> LinkStats {
+        self.inner.stats()
+    }
+    /// Perform any per-frame maintenance required, including following the
+    /// parent to a new baud rate; see [BulkMultiplayer::tick].
+    pub fn tick(&mut self) -> Result<(), BulkTickError> {
+        let switch = SWITCH_WATCHER.lock_mut(|watcher| watcher.as_mut()?.take_switch());
+        if let Some(rate) = switch {
+            self.inner.switch_baud_rate(rate);
+        }
+        self.inner.tick()
+    }
+    /// Stops spectating, returning to a regular [BulkMultiplayer] that still
+    /// won't block transfers until it has data.
+    pub fn into_inner(self) -> BulkMultiplayer<'a> {
+        SWITCH_WATCHER.swap(None);
+        self.inner
+    }
+}
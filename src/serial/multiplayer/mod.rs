@@ -14,8 +14,7 @@ use core::{marker::PhantomData, mem};
 mod buffer;
 pub mod bulk;
 mod registers;
-mod ringbuf;
-use registers::MultiplayerCommReg;
+pub(super) use registers::MultiplayerCommReg;
 
 /// The value used by the GBA hardware to indicate either an in-progress
 /// transfer or that a slot out of the 4 available ports is currently not used
@@ -167,6 +166,21 @@ impl<'a> MultiplayerSerial<'a> {
     {
         self.buffer_interrupt = Some(add_interrupt_handler(Interrupt::Serial, cb));
     }
+    /// Whether or not a transfer is currently in progress.
+    pub fn is_busy(&self) -> bool {
+        MultiplayerSiocnt::get().busy()
+    }
+    /// Whether or not this unit is [PlayerId::P0], aka the "parent" unit.
+    ///
+    /// Unlike [Self::id], this can be called before any data transfers have
+    /// happened yet.
+    pub fn is_parent(&self) -> bool {
+        self.is_parent
+    }
+    /// The baud rate the session is running at.
+    pub fn baud_rate(&self) -> BaudRate {
+        self.rate
+    }
     /// Checks whether or not all other connected GBAs are ready for transfer.
     pub fn all_ready(&self) -> bool {
         MultiplayerSiocnt::get().gbas_ready()
@@ -232,7 +246,7 @@ pub enum MultiplayerError {
     BufferLengthMismatch,
 }
 
-/// How fast data can be transfered in multiplayer mode (measured in
+/// How fast data can be transfered in multiplayer & UART modes (measured in
 /// bits-per-second).
 #[repr(u8)]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
@@ -282,7 +296,7 @@ impl BaudRate {
 /// | 13  | Must be "1" for Multi-Player mode |
 /// | 14  | IRQ Enable         | (0=Disable, 1=Want IRQ upon completion)
 /// | 15  | Not used           | (Read only, always 0)
-struct MultiplayerSiocnt {
+pub(super) struct MultiplayerSiocnt {
     inner: SiocntWrapper,
 }
 
@@ -0,0 +1,147 @@
+//! [embedded_hal_async] SPI trait implementations, letting async drivers run
+//! over the link port with an executor on the GBA.
+//!
+//! Each transfer is started and then awaited, with the serial interrupt waking
+//! the task once it completes; see [super::hal] for how the pins map onto SPI.
+
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use agb::external::critical_section::CriticalSection;
+use agb::interrupt::{add_interrupt_handler, Interrupt};
+use embedded_hal_async::spi::SpiBus;
+
+use crate::utils::GbaCell;
+
+use super::hal::SpiError;
+use super::{NormalSerial, NormalSiocnt, SIODATA32};
+use crate::serial::SIODATA8;
+
+/// The task waiting on the current transfer, if any.
+static WAKER: GbaCell<Option<Waker>> = GbaCell::new(None);
+
+/// The byte sent while only reading.
+const DUMMY: u8 = 0xFF;
+
+/// A [NormalSerial] handle whose transfers can be awaited instead of blocking.
+pub struct AsyncNormal<'a> {
+    inner: NormalSerial<'a>,
+}
+
+impl<'a> AsyncNormal<'a> {
+    pub fn new(mut inner: NormalSerial<'a>) -> Self {
+        inner.buffer_interrupt = unsafe {
+            Some(add_interrupt_handler(
+                Interrupt::Serial,
+                async_normal_interrupt_callback,
+            ))
+        };
+        inner.enable_interrupt(true);
+        Self { inner }
+    }
+
+    /// Sends `byte` while receiving a byte from the other side, completing
+    /// once the transfer does.
+    pub async fn exchange_u8(&mut self, byte: u8) -> u8 {
+        let siocnt = NormalSiocnt::get();
+        wait_idle().await;
+        siocnt.set_32bit(false);
+        SIODATA8.write(byte);
+        siocnt.start_transfer();
+        wait_idle().await;
+        SIODATA8.read()
+    }
+    /// Sends `word` while receiving a word from the other side, completing
+    /// once the transfer does; see [NormalSerial::exchange_u32].
+    pub async fn exchange_u32(&mut self, word: u32) -> u32 {
+        let siocnt = NormalSiocnt::get();
+        wait_idle().await;
+        siocnt.set_32bit(true);
+        SIODATA32.write(word);
+        siocnt.start_transfer();
+        wait_idle().await;
+        SIODATA32.read()
+    }
+
+    /// Returns to the blocking [NormalSerial] handle.
+    pub fn leave(mut self) -> NormalSerial<'a> {
+        self.inner.enable_interrupt(false);
+        self.inner.buffer_interrupt = None;
+        WAKER.swap(None);
+        self.inner
+    }
+
+    fn check_master(&self) -> Result<(), SpiError> {
+        if !self.inner.clock().is_internal() {
+            return Err(SpiError::ExternalClock);
+        }
+        Ok(())
+    }
+}
+
+/// Completes once no transfer is in progress.
+async fn wait_idle() {
+    poll_fn(|cx| {
+        let siocnt = NormalSiocnt::get();
+        if !siocnt.busy() {
+            return Poll::Ready(());
+        }
+        WAKER.swap(Some(cx.waker().clone()));
+        // The transfer might have finished before the waker was stored.
+        if !siocnt.busy() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// The interrupt callback that wakes the waiting task once a transfer
+/// completes.
+fn async_normal_interrupt_callback(cs: CriticalSection<'_>) {
+    if let Some(waker) = WAKER.swap_in(cs, None) {
+        waker.wake();
+    }
+}
+
+impl embedded_hal_async::spi::ErrorType for AsyncNormal<'_> {
+    type Error = SpiError;
+}
+
+impl SpiBus for AsyncNormal<'_> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        for word in words {
+            *word = self.exchange_u8(DUMMY).await;
+        }
+        Ok(())
+    }
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        for word in words {
+            self.exchange_u8(*word).await;
+        }
+        Ok(())
+    }
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        let common = read.len().min(write.len());
+        let (read_common, read_rest) = read.split_at_mut(common);
+        for (inc, out) in read_common.iter_mut().zip(&write[..common]) {
+            *inc = self.exchange_u8(*out).await;
+        }
+        SpiBus::read(self, read_rest).await?;
+        SpiBus::write(self, &write[common..]).await
+    }
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        for word in words {
+            *word = self.exchange_u8(*word).await;
+        }
+        Ok(())
+    }
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        wait_idle().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! Interrupt-driven Normal mode transfers, where the serial interrupt moves
+//! each word between RAM buffers and the hardware so that large exchanges
+//! proceed in the background while the game keeps running.
+//!
+//! Transfers always happen in 32-bit words. Since Normal mode is strictly an
+//! exchange, every word sent is paired with a word received:
+//!
+//! * The master (using an internal clock) only clocks out words while it has
+//!   data queued, storing whatever the slave sent back.
+//! * The slave (using the external clock) always stays ready for the master,
+//!   sending [FILLER] whenever it has nothing queued.
+//!
+//! Both sides' interrupts fire at the same moment when a transfer completes,
+//! so the master's next transfer starts at about the same time that the slave
+//! re-arms. Keep the slave's other interrupt handlers short so it doesn't fall
+//! behind and miss words.
+//!
+//! # Basic Usage
+//!
+//! 1. Create a [NormalSerial] and convert it with
+//!    [NormalSerial::enable_buffered_mode].
+//! 2. Queue outgoing words with [BufferedNormal::write] and pull incoming
+//!    words with [BufferedNormal::read]; neither will block.
+//! 3. Call [BufferedNormal::leave] to return to the unbuffered [NormalSerial]
+//!    handle.
+
+use agb::external::critical_section::{self, CriticalSection};
+use agb::interrupt::{add_interrupt_handler, Interrupt};
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::serial::ringbuf::Ringbuffer;
+use crate::utils::GbaCell;
+
+use super::{NormalSerial, NormalSiocnt, SIODATA32};
+
+/// Words that have been received but not yet read by the user.
+static RX_BUFFER: GbaCell<Ringbuffer<u32>> = GbaCell::new(Ringbuffer::empty());
+
+/// Words that have been queued by the user but not yet sent.
+static TX_BUFFER: GbaCell<Ringbuffer<u32>> = GbaCell::new(Ringbuffer::empty());
+
+/// How many received words were thrown away because [RX_BUFFER] was full.
+static DROPPED: GbaCell<usize> = GbaCell::new(0);
+
+/// The word sent by the slave when it has nothing queued.
+pub const FILLER: u32 = 0xFFFF_FFFF;
+
+pub struct BufferedNormal<'a> {
+    inner: NormalSerial<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferedInitError {
+    AlreadyInitialized,
+}
+
+impl<'a> BufferedNormal<'a> {
+    pub fn new(mut inner: NormalSerial<'a>, cap: usize) -> Result<Self, BufferedInitError> {
+        let nrx = Ringbuffer::new(cap);
+        let ntx = Ringbuffer::new(cap);
+        RX_BUFFER
+            .swap_if(nrx, |old| old.is_placeholder())
+            .map_err(|_| BufferedInitError::AlreadyInitialized)?;
+        TX_BUFFER
+            .swap_if(ntx, |old| old.is_placeholder())
+            // Shouldn't be possible if the previous check passed, but still
+            .map_err(|_| BufferedInitError::AlreadyInitialized)?;
+        DROPPED.swap(0);
+
+        let siocnt = NormalSiocnt::get();
+        while siocnt.busy() {}
+        siocnt.set_32bit(true);
+
+        inner.buffer_interrupt = unsafe {
+            Some(add_interrupt_handler(
+                Interrupt::Serial,
+                buffered_normal_interrupt_callback,
+            ))
+        };
+        inner.enable_interrupt(true);
+        critical_section::with(start_next_transfer);
+
+        Ok(Self { inner })
+    }
+
+    /// Pulls as many received words as are available (up to the length of
+    /// `buffer`) into `buffer`. Returns the number of words read.
+    ///
+    /// Does NOT block.
+    pub fn read(&mut self, buffer: &mut [u32]) -> usize {
+        critical_section::with(|cs| RX_BUFFER.lock_in(cs, |rx| rx.read_bulk(buffer, cs)))
+    }
+
+    /// Queues as many words from `buffer` as will fit into the send buffer.
+    /// Returns the number of words queued.
+    ///
+    /// Does NOT block.
+    pub fn write(&mut self, buffer: &[u32]) -> usize {
+        critical_section::with(|cs| {
+            let res = TX_BUFFER.lock_in(cs, |tx| tx.write_bulk(buffer, cs));
+            // The interrupt only fires on completion, so we need to kick off
+            // the first transfer ourselves.
+            start_next_transfer(cs);
+            res
+        })
+    }
+
+    /// The number of received words waiting to be read.
+    pub fn available(&self) -> usize {
+        critical_section::with(|cs| RX_BUFFER.lock_in(cs, |rx| rx.len(cs)))
+    }
+    /// The number of queued words that have not yet been sent.
+    pub fn pending(&self) -> usize {
+        critical_section::with(|cs| TX_BUFFER.lock_in(cs, |tx| tx.len(cs)))
+    }
+    /// How many received words have been thrown away because the receive
+    /// buffer was full.
+    pub fn dropped(&self) -> usize {
+        DROPPED.get_copy()
+    }
+    /// Whether or not a transfer is currently in progress.
+    pub fn is_busy(&self) -> bool {
+        self.inner.is_busy()
+    }
+
+    /// Exits buffered mode, returning to the unbuffered [NormalSerial] handle.
+    ///
+    /// Waits for the in-progress transfer (if any) to finish when acting as
+    /// the master; any data still sitting in the buffers is discarded.
+    pub fn leave(self) -> NormalSerial<'a> {
+        let mut this = ManuallyDrop::new(self);
+        this.end_session();
+        // #SAFETY
+        //
+        // `this` is never used or dropped again, so `inner` is only moved out
+        // once.
+        unsafe { ptr::read(&this.inner) }
+    }
+    /// Stops the interrupt & resets the buffers, so that buffered mode can be
+    /// entered again.
+    fn end_session(&mut self) {
+        self.inner.enable_interrupt(false);
+        self.inner.buffer_interrupt = None;
+        let siocnt = NormalSiocnt::get();
+        if siocnt.clock().is_internal() {
+            while siocnt.busy() {}
+        }
+        RX_BUFFER.swap(Ringbuffer::empty());
+        TX_BUFFER.swap(Ringbuffer::empty());
+    }
+}
+
+impl Drop for BufferedNormal<'_> {
+    fn drop(&mut self) {
+        self.end_session();
+    }
+}
+
+/// Loads the next word into the hardware and starts (or, for the slave, arms)
+/// the transfer if nothing is in progress.
+fn start_next_transfer(cs: CriticalSection<'_>) {
+    let siocnt = NormalSiocnt::get();
+    if siocnt.busy() {
+        return;
+    }
+    let next = TX_BUFFER.lock_in(cs, |tx| tx.pop(cs));
+    let word = match next {
+        Some(word) => word,
+        // The slave needs to stay armed so it doesn't miss the master's
+        // transfers.
+        None if !siocnt.clock().is_internal() => FILLER,
+        None => return,
+    };
+    SIODATA32.write(word);
+    siocnt.start_transfer();
+}
+
+/// The interrupt callback called whenever a transfer completes.
+fn buffered_normal_interrupt_callback(cs: CriticalSection<'_>) {
+    let received = SIODATA32.read();
+    let res = RX_BUFFER.lock_in(cs, |rx| rx.push(received, cs));
+    if res.is_err() {
+        DROPPED.lock_mut_in(cs, |dropped| *dropped += 1);
+    }
+    start_next_transfer(cs);
+}
@@ -0,0 +1,162 @@
+//! Background transfers of large, fixed buffers (eg save data or level
+//! payloads) between two GBAs.
+//!
+//! The GBA's DMA units can't be triggered by serial transfers completing, so
+//! instead the serial interrupt moves each word directly between the user's
+//! buffers and `SIODATA32` and immediately starts the next transfer. This
+//! avoids the ring buffers used by [super::buffered] entirely, keeping the CPU
+//! cost to a few dozen instructions per 32-bit word.
+
+use core::marker::PhantomData;
+use core::ptr;
+
+use agb::external::critical_section::CriticalSection;
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+
+use crate::utils::GbaCell;
+
+use super::{NormalSerial, NormalSiocnt, TransferError, SIODATA32};
+
+/// The in-progress background transfer.
+static DMA_STATE: GbaCell<DmaState> = GbaCell::new(DmaState::empty());
+
+/// The buffers being exchanged by [NormalSerial::transfer_dma], and how far
+/// into them we are.
+struct DmaState {
+    send: *const u32,
+    recv: *mut u32,
+    len: usize,
+    done: usize,
+}
+
+/// #SAFETY
+///
+/// The pointers are only ever dereferenced by the serial interrupt while the
+/// [DmaTransfer] that borrows the underlying buffers is alive.
+unsafe impl Send for DmaState {}
+
+impl DmaState {
+    const fn empty() -> Self {
+        Self {
+            send: ptr::null(),
+            recv: ptr::null_mut(),
+            len: 0,
+            done: 0,
+        }
+    }
+    const fn remaining(&self) -> usize {
+        self.len - self.done
+    }
+}
+
+impl Default for DmaState {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// A handle to a transfer started with [NormalSerial::transfer_dma].
+///
+/// Dropping the handle stops the transfer, even if not all words have been
+/// exchanged.
+pub struct DmaTransfer<'t> {
+    _buffers: PhantomData<&'t mut [u32]>,
+    _serial: PhantomData<&'t mut NormalSerial<'t>>,
+    _interrupt: Option<InterruptHandler>,
+}
+
+impl DmaTransfer<'_> {
+    /// How many words have yet to be exchanged.
+    pub fn remaining(&self) -> usize {
+        DMA_STATE.lock(|state| state.remaining())
+    }
+    /// Whether or not every word has been exchanged.
+    pub fn is_done(&self) -> bool {
+        self.remaining() == 0
+    }
+    /// Blocks until every word has been exchanged.
+    pub fn wait(self) {
+        while !self.is_done() {}
+    }
+}
+
+impl Drop for DmaTransfer<'_> {
+    fn drop(&mut self) {
+        let siocnt = NormalSiocnt::get();
+        siocnt.enable_irq(false);
+        if siocnt.clock().is_internal() {
+            while siocnt.busy() {}
+        }
+        DMA_STATE.swap(DmaState::empty());
+    }
+}
+
+impl<'a> NormalSerial<'a> {
+    /// Starts exchanging the words in `send` for the same number of words
+    /// received into `recv` in the background, returning a handle that can be
+    /// polled for completion.
+    ///
+    /// Both sides of the link need to start a transfer of the same length; as
+    /// the master, make sure the slave has started its transfer first.
+    ///
+    /// # Safety
+    /// The returned [DmaTransfer] **must not** be leaked (eg via
+    /// [core::mem::forget]), since the interrupt would then continue accessing
+    /// the buffers after they are no longer borrowed.
+    pub unsafe fn transfer_dma<'t>(
+        &'t mut self,
+        send: &'t [u32],
+        recv: &'t mut [u32],
+    ) -> Result<DmaTransfer<'t>, TransferError> {
+        if send.len() != recv.len() {
+            return Err(TransferError::LengthMismatch);
+        }
+        let siocnt = NormalSiocnt::get();
+        while siocnt.busy() {}
+        DMA_STATE.swap(DmaState {
+            send: send.as_ptr(),
+            recv: recv.as_mut_ptr(),
+            len: send.len(),
+            done: 0,
+        });
+        let Some(&first) = send.first() else {
+            return Ok(DmaTransfer {
+                _buffers: PhantomData,
+                _serial: PhantomData,
+                _interrupt: None,
+            });
+        };
+
+        let interrupt = add_interrupt_handler(Interrupt::Serial, dma_interrupt_callback);
+        siocnt.set_32bit(true);
+        siocnt.enable_irq(true);
+        SIODATA32.write(first);
+        siocnt.start_transfer();
+        Ok(DmaTransfer {
+            _buffers: PhantomData,
+            _serial: PhantomData,
+            _interrupt: Some(interrupt),
+        })
+    }
+}
+
+/// The interrupt callback that stores the word that was just received and
+/// starts exchanging the next one.
+fn dma_interrupt_callback(cs: CriticalSection<'_>) {
+    DMA_STATE.lock_mut_in(cs, |state| {
+        if state.remaining() == 0 {
+            return;
+        }
+        // #SAFETY
+        //
+        // `state.done < state.len`, and the buffers are kept alive by the
+        // `DmaTransfer` that owns this interrupt.
+        unsafe { state.recv.add(state.done).write(SIODATA32.read()) };
+        state.done += 1;
+        if state.remaining() > 0 {
+            let next = unsafe { state.send.add(state.done).read() };
+            SIODATA32.write(next);
+            NormalSiocnt::get().start_transfer();
+        }
+    });
+}
@@ -0,0 +1,376 @@
+//! A high-throughput link between exactly 2 GBAs, offering the same
+//! queue-and-read style interface as
+//! [BulkMultiplayer](crate::serial::multiplayer::bulk::BulkMultiplayer) but
+//! built on 32-bit Normal mode transfers, for more than 4 times the bandwidth
+//! of multiplayer mode.
+//!
+//! # Basic Usage
+//!
+//! 1. Decide which GBA is the master: it should create its [NormalSerial] with
+//!    an internal [ClockConfig](super::ClockConfig), while the other uses
+//!    [ClockConfig::External](super::ClockConfig::External).
+//! 2. On both sides, start duplex mode with [DuplexLink::new].
+//! 3. Load data to be sent to the other GBA with [DuplexLink::queue_send] and
+//!    read data it has sent with [DuplexLink::read_bulk].
+//! 4. Make sure [DuplexLink::tick] is called during your main game loop.
+//!
+//! # Notes
+//! * Whenever a side has nothing queued it sends [NO_DATA] instead, which the
+//!   other side throws away. As such be sure not to send that value as part of
+//!   your data if you don't want to lose information.
+//! * The master keeps transferring back to back as long as either side has
+//!   data to send, and otherwise only polls for new data once per
+//!   [DuplexLink::tick].
+//! * The slave signals that it has loaded its next word by pulling SO low, as
+//!   with [NormalSerial::set_ready], & the master never starts a transfer
+//!   until it sees that on SI. If the slave is slow to re-arm the master
+//!   leaves the next transfer to [DuplexLink::tick] rather than waiting.
+//! * Words received while the inbox is full are dropped & counted in
+//!   [DuplexLink::dropped].
+//!
+//! # Scheduled Transfers
+//!
+//! Instead of transferring back to back whenever there is data, the master can
+//! use [DuplexLink::schedule] to hand a hardware timer over to the link, which
+//! then starts exactly 1 transfer every N scanlines. This gives both sides a
+//! fixed, jitter-free bandwidth & latency, which is useful for games that
+//! exchange input or state at a steady rate.
+
+use agb::external::critical_section::{self, CriticalSection};
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+use agb::timer::{Divider, Timer};
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::serial::ringbuf::Ringbuffer;
+use crate::utils::{GbaCell, CPU_HZ};
+
+use super::{NormalSerial, NormalSiocnt, SIODATA32};
+
+/// The value sent by a side with nothing queued.
+pub const NO_DATA: u32 = 0xFFFF_FFFF;
+
+/// Words that have been received but not yet read by the user.
+static INBOX: GbaCell<Ringbuffer<u32>> = GbaCell::new(Ringbuffer::empty());
+
+/// Words that have been queued by the user but not yet sent.
+static OUTBOX: GbaCell<Ringbuffer<u32>> = GbaCell::new(Ringbuffer::empty());
+
+/// Whether the word loaded for the next transfer is real data, as opposed to
+/// [NO_DATA] or nothing at all.
+static LOADED: GbaCell<bool> = GbaCell::new(false);
+
+/// The number of received words dropped because [INBOX] was full.
+static DROPPED: GbaCell<u32> = GbaCell::new(0);
+
+/// How many times the master polls SI after each transfer, waiting for the
+/// slave to signal that it's loaded its next word, before giving up until the
+/// next [DuplexLink::tick].
+const SLAVE_READY_POLLS: u32 = 64;
+
+/// If true, transfers are started by the timer set up by
+/// [DuplexLink::schedule] instead of back to back.
+static SCHEDULED: GbaCell<bool> = GbaCell::new(false);
+
+/// The number of CPU cycles in a single scanline, including HBlank.
+const CYCLES_PER_SCANLINE: u32 = 1232;
+
+pub struct DuplexLink<'a> {
+    inner: NormalSerial<'a>,
+    schedule: Option<Schedule>,
+}
+
+/// The timer driving scheduled transfers.
+struct Schedule {
+    timer: Timer,
+    _interrupt: InterruptHandler,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// Only the master can schedule transfers.
+    NotMaster,
+    /// The interval is shorter than a single transfer takes.
+    IntervalTooShort,
+    /// The interval is longer than the hardware timer can measure, roughly 4
+    /// seconds.
+    IntervalTooLong,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DuplexInitError {
+    AlreadyInitialized,
+}
+
+impl<'a> DuplexLink<'a> {
+    pub fn new(mut inner: NormalSerial<'a>, cap: usize) -> Result<Self, DuplexInitError> {
+        let nin = Ringbuffer::new(cap);
+        let nout = Ringbuffer::new(cap);
+        INBOX
+            .swap_if(nin, |old| old.is_placeholder())
+            .map_err(|_| DuplexInitError::AlreadyInitialized)?;
+        OUTBOX
+            .swap_if(nout, |old| old.is_placeholder())
+            // Shouldn't be possible if the previous check passed, but still
+            .map_err(|_| DuplexInitError::AlreadyInitialized)?;
+
+        let siocnt = NormalSiocnt::get();
+        while siocnt.busy() {}
+        siocnt.set_32bit(true);
+
+        inner.buffer_interrupt = unsafe {
+            Some(add_interrupt_handler(
+                Interrupt::Serial,
+                duplex_interrupt_callback,
+            ))
+        };
+        DROPPED.swap(0);
+        LOADED.swap(false);
+        inner.enable_interrupt(true);
+        if !siocnt.clock().is_internal() {
+            // The slave needs to be armed before the master's first transfer.
+            critical_section::with(|cs| load_next(cs, &siocnt));
+        }
+
+        Ok(Self {
+            inner,
+            schedule: None,
+        })
+    }
+
+    /// Whether or not we are the side driving the transfers.
+    pub fn is_master(&self) -> bool {
+        self.inner.clock().is_internal()
+    }
+
+    /// Queues as many words from `buffer` as will fit into the outbox.
+    /// Returns the number of words queued.
+    pub fn queue_send(&mut self, buffer: &[u32]) -> usize {
+        critical_section::with(|cs| OUTBOX.lock_in(cs, |outbox| outbox.write_bulk(buffer, cs)))
+    }
+    /// Pulls as many received words as are available (up to the length of
+    /// `buffer`) into `buffer`. Returns the number of words read.
+    pub fn read_bulk(&mut self, buffer: &mut [u32]) -> usize {
+        critical_section::with(|cs| INBOX.lock_in(cs, |inbox| inbox.read_bulk(buffer, cs)))
+    }
+    /// The number of received words waiting to be read.
+    pub fn available(&self) -> usize {
+        critical_section::with(|cs| INBOX.lock_in(cs, |inbox| inbox.len(cs)))
+    }
+    /// The number of queued words that have not yet been sent.
+    pub fn pending(&self) -> usize {
+        critical_section::with(|cs| OUTBOX.lock_in(cs, |outbox| outbox.len(cs)))
+    }
+    /// Whether every queued word has actually been transferred to the other
+    /// GBA, rather than just loaded for the next transfer. Does NOT block.
+    pub fn all_sent(&self) -> bool {
+        self.pending() == 0 && !LOADED.get_copy()
+    }
+    /// The number of received words dropped so far because the inbox was
+    /// full.
+    pub fn dropped(&self) -> u32 {
+        DROPPED.get_copy()
+    }
+    /// The maximum number of words each of the buffers can hold.
+    pub fn capacity(&self) -> usize {
+        critical_section::with(|cs| OUTBOX.lock_in(cs, |outbox| outbox.capacity()))
+    }
+
+    /// Starts a single transfer every `scanlines` scanlines using `timer`,
+    /// instead of transferring back to back; see the
+    /// [module-level docs](self).
+    ///
+    /// Replaces (and disables) any previously scheduled timer.
+    pub fn schedule(&mut self, mut timer: Timer, scanlines: u32) -> Result<(), ScheduleError> {
+        let clock = self.inner.clock();
+        let Some(freq) = clock.frequency() else {
+            return Err(ScheduleError::NotMaster);
+        };
+        let cycles = scanlines.saturating_mul(CYCLES_PER_SCANLINE);
+        if cycles <= transfer_cycles(freq) {
+            return Err(ScheduleError::IntervalTooShort);
+        }
+        let (divider, ticks) = timer_settings(cycles).ok_or(ScheduleError::IntervalTooLong)?;
+
+        self.unschedule();
+        let interrupt = unsafe {
+            add_interrupt_handler(timer.interrupt(), |cs| {
+                let siocnt = NormalSiocnt::get();
+                // A slot the slave isn't ready for yet is skipped.
+                if !siocnt.busy() && !siocnt.si_high() {
+                    load_next(cs, &siocnt);
+                }
+            })
+        };
+        SCHEDULED.swap(true);
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_divider(divider)
+            .set_overflow_amount(ticks)
+            .set_interrupt(true)
+            .set_enabled(true);
+        self.schedule = Some(Schedule {
+            timer,
+            _interrupt: interrupt,
+        });
+        Ok(())
+    }
+    /// Stops scheduled transfers, returning to transferring back to back and
+    /// handing back the timer that was in use (if any).
+    pub fn unschedule(&mut self) -> Option<Timer> {
+        let Schedule {
+            mut timer,
+            _interrupt,
+        } = self.schedule.take()?;
+        // Stop the timer before removing its handler, so that it can't fire
+        // without one.
+        timer.set_interrupt(false).set_enabled(false);
+        drop(_interrupt);
+        SCHEDULED.swap(false);
+        Some(timer)
+    }
+
+    /// Perform any per-frame maintenance required for duplex mode.
+    ///
+    /// On the master this starts a transfer if none is in progress, both to
+    /// send anything newly queued and to check whether the slave has anything
+    /// to send. Does nothing while transfers are scheduled.
+    pub fn tick(&mut self) {
+        let siocnt = NormalSiocnt::get();
+        if !siocnt.clock().is_internal() || self.schedule.is_some() {
+            return;
+        }
+        critical_section::with(|cs| {
+            if !siocnt.busy() && !siocnt.si_high() {
+                load_next(cs, &siocnt);
+            }
+        });
+    }
+
+    /// Exits duplex mode, returning to the unbuffered [NormalSerial] handle
+    /// along with the timer passed to [Self::schedule], if transfers were
+    /// still scheduled; the timer is stopped, as with [Self::unschedule].
+    ///
+    /// Any data still sitting in the buffers is discarded.
+    pub fn leave(self) -> (NormalSerial<'a>, Option<Timer>) {
+        let mut this = ManuallyDrop::new(self);
+        let timer = this.end_session();
+        // #SAFETY
+        //
+        // `this` is never used or dropped again, so `inner` is only moved out
+        // once; `end_session` already took the schedule, so nothing else needs
+        // dropping.
+        let inner = unsafe { ptr::read(&this.inner) };
+        (inner, timer)
+    }
+    /// Stops the scheduled timer & the interrupt and resets the buffers, so
+    /// that duplex mode can be entered again. Returns the stopped timer, if
+    /// transfers were scheduled.
+    fn end_session(&mut self) -> Option<Timer> {
+        let timer = self.unschedule();
+        self.inner.enable_interrupt(false);
+        self.inner.buffer_interrupt = None;
+        let siocnt = NormalSiocnt::get();
+        if siocnt.clock().is_internal() {
+            while siocnt.busy() {}
+        }
+        INBOX.swap(Ringbuffer::empty());
+        OUTBOX.swap(Ringbuffer::empty());
+        timer
+    }
+}
+
+impl Drop for DuplexLink<'_> {
+    fn drop(&mut self) {
+        self.end_session();
+    }
+}
+
+/// Loads the next outgoing word (or [NO_DATA]) and starts the transfer,
+/// returning whether there was real data to send.
+///
+/// On the slave this also drives the SO handshake: SO is held high while the
+/// next word is loaded & only pulled low once it's ready to be clocked out.
+fn load_next(cs: CriticalSection<'_>, siocnt: &NormalSiocnt) -> bool {
+    let is_slave = !siocnt.clock().is_internal();
+    if is_slave {
+        siocnt.set_so_idle_high(true);
+    }
+    let next = OUTBOX.lock_in(cs, |outbox| outbox.pop(cs));
+    SIODATA32.write(next.unwrap_or(NO_DATA));
+    LOADED.swap_in(cs, next.is_some());
+    siocnt.start_transfer();
+    if is_slave {
+        siocnt.set_so_idle_high(false);
+    }
+    next.is_some()
+}
+
+/// The interrupt callback called every time a transfer completes.
+fn duplex_interrupt_callback(cs: CriticalSection<'_>) {
+    let received = SIODATA32.read();
+    let got_data = received != NO_DATA;
+    LOADED.swap_in(cs, false);
+    if got_data && INBOX.lock_in(cs, |inbox| inbox.push(received, cs)).is_err() {
+        DROPPED.lock_mut_in(cs, |n| *n = n.wrapping_add(1));
+    }
+
+    let siocnt = NormalSiocnt::get();
+    if !siocnt.clock().is_internal() {
+        // The slave always re-arms so that it's ready whenever the master is.
+        load_next(cs, &siocnt);
+        return;
+    }
+    if SCHEDULED.get_copy_in(cs) {
+        return;
+    }
+    let have_data = OUTBOX.lock_in(cs, |outbox| outbox.len(cs) > 0);
+    if (got_data || have_data) && (0..SLAVE_READY_POLLS).any(|_| !siocnt.si_high()) {
+        load_next(cs, &siocnt);
+    }
+}
+
+/// How many CPU cycles a single 32-bit transfer takes with a shift clock of
+/// `freq` Hz, plus the time the slave needs to re-arm.
+const fn transfer_cycles(freq: u32) -> u32 {
+    32 * (CPU_HZ / freq) + SLAVE_READY_POLLS * 4
+}
+
+/// Picks the finest timer divider that can measure `cycles`, returning it
+/// along with the number of ticks to overflow after.
+fn timer_settings(cycles: u32) -> Option<(Divider, u16)> {
+    [
+        (Divider::Divider1, 1),
+        (Divider::Divider64, 64),
+        (Divider::Divider256, 256),
+        (Divider::Divider1024, 1024),
+    ]
+    .into_iter()
+    .find_map(|(divider, cycles_per_tick)| {
+        let ticks = cycles / cycles_per_tick;
+        (ticks <= u16::MAX as u32).then_some((divider, ticks as u16))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::normal::ClockConfig;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_timer_settings(_gba: &mut Gba) {
+        let ticks = |cycles| timer_settings(cycles).map(|(_, ticks)| ticks);
+        assert_eq!(ticks(CYCLES_PER_SCANLINE), Some(1232));
+        assert_eq!(ticks(u16::MAX as u32), Some(u16::MAX));
+        assert_eq!(ticks(228 * CYCLES_PER_SCANLINE), Some(4389));
+        assert_eq!(ticks(u16::MAX as u32 * 1024), Some(u16::MAX));
+        assert_eq!(ticks(u16::MAX as u32 * 1024 + 1024), None);
+
+        let slow = transfer_cycles(ClockConfig::Internal256KHz.frequency().unwrap());
+        let fast = transfer_cycles(ClockConfig::Internal2MHz.frequency().unwrap());
+        assert!(slow > CYCLES_PER_SCANLINE && slow < 2 * CYCLES_PER_SCANLINE);
+        assert!(fast < CYCLES_PER_SCANLINE);
+    }
+}
@@ -0,0 +1,82 @@
+//! [embedded_hal] SPI trait implementations, allowing existing SPI device
+//! drivers to be used with peripherals wired to the link port.
+//!
+//! The link port's pins map onto SPI as SC = SCK, SO = MOSI, and SI = MISO,
+//! using [SPI_MODE]. There is no chip select line, so devices that need one
+//! have to be wired to be always selected or driven some other way.
+
+use embedded_hal::spi::{self, ErrorKind, ErrorType, Mode, SpiBus, MODE_3};
+
+use super::NormalSerial;
+
+/// The SPI mode used by the hardware: the clock idles high, data is shifted
+/// out on the falling edge and sampled on the rising edge, most significant
+/// bit first.
+pub const SPI_MODE: Mode = MODE_3;
+
+/// The byte sent while only reading.
+const DUMMY: u8 = 0xFF;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SpiError {
+    /// The handle is using the external clock, so it can't act as the bus
+    /// master.
+    ExternalClock,
+}
+
+impl spi::Error for SpiError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl NormalSerial<'_> {
+    fn check_master(&self) -> Result<(), SpiError> {
+        if !self.clock().is_internal() {
+            return Err(SpiError::ExternalClock);
+        }
+        Ok(())
+    }
+}
+
+impl ErrorType for NormalSerial<'_> {
+    type Error = SpiError;
+}
+
+impl SpiBus for NormalSerial<'_> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        for word in words {
+            *word = self.exchange_u8(DUMMY);
+        }
+        Ok(())
+    }
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        for word in words {
+            self.exchange_u8(*word);
+        }
+        Ok(())
+    }
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        let common = read.len().min(write.len());
+        let (read_common, read_rest) = read.split_at_mut(common);
+        let (write_common, write_rest) = write.split_at(common);
+        // Lengths are guaranteed to match.
+        let _ = NormalSerial::transfer(self, write_common, read_common);
+        SpiBus::read(self, read_rest)?;
+        SpiBus::write(self, write_rest)
+    }
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_master()?;
+        for word in words {
+            *word = self.exchange_u8(*word);
+        }
+        Ok(())
+    }
+    /// Transfers are blocking, so this is a no-op.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,495 @@
+//! Normal mode, a simple SPI-like mode where each transfer shifts a byte out
+//! over SO while simultaneously shifting one in over SI, clocked by SC.
+//!
+//! One side of the link (the "master") drives the shift clock using its
+//! internal clock, while the other (the "slave") uses the external clock
+//! provided by the master over SC. This is the mode used by most third-party
+//! link cable peripherals, as well as for fast GBA-to-GBA transfers.
+
+use super::*;
+
+use agb::{
+    external::critical_section::CriticalSection,
+    interrupt::{add_interrupt_handler, Interrupt, InterruptHandler},
+};
+
+use buffered::{BufferedInitError, BufferedNormal};
+
+use crate::utils::TimerTimeout;
+use agb::timer::Timer;
+use core::marker::PhantomData;
+
+#[cfg(feature = "embedded-hal-async")]
+pub mod asynch;
+pub mod buffered;
+pub mod dma;
+pub mod duplex;
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
+pub mod slave;
+#[cfg(feature = "embedded-io")]
+pub mod stream;
+
+/// The data register used for 32-bit transfers, overlapping the multiplayer
+/// mode `SIOMULTI0` & `SIOMULTI1` registers.
+const SIODATA32: VolAddress<u32, Safe, Safe> = unsafe { VolAddress::new(0x4000120) };
+
+/// The top-level handle for talking over the link cable in Normal mode.
+pub struct NormalSerial<'a> {
+    _handle: PhantomData<&'a mut Serial>,
+    buffer_interrupt: Option<InterruptHandler>,
+}
+
+/// Where the shift clock comes from, and how fast it runs.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum ClockConfig {
+    /// Follow the clock provided by the master over SC, making us the slave.
+    ///
+    /// The master decides the speed, which can be up to 2MHz.
+    External,
+    /// Drive the shift clock ourselves at 256KHz, making us the master.
+    ///
+    /// This is the speed used by most games & peripherals, and works reliably
+    /// over the standard link cable.
+    #[default]
+    Internal256KHz,
+    /// Drive the shift clock ourselves at 2MHz, making us the master.
+    ///
+    /// GBATEK notes that this speed is only suitable for very short
+    /// connections, such as a peripheral plugged directly into the link port;
+    /// over a full-length link cable the signal degrades enough to corrupt
+    /// data.
+    Internal2MHz,
+}
+
+/// How the two sides of the link are physically connected, which limits which
+/// [ClockConfig]s can be used reliably.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Connection {
+    /// A standard link cable.
+    #[default]
+    LinkCable,
+    /// A peripheral plugged directly into the link port, or a similarly short
+    /// cable.
+    Direct,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ClockConfigError {
+    /// The 2MHz clock was requested over a connection that is too long to
+    /// carry it reliably.
+    TooFastForConnection,
+}
+
+impl ClockConfig {
+    /// Whether or not this clock makes us the master, IE whether we drive the
+    /// shift clock.
+    pub const fn is_internal(self) -> bool {
+        !matches!(self, ClockConfig::External)
+    }
+    /// The shift clock frequency in Hz, or `None` if it is decided by the
+    /// master.
+    pub const fn frequency(self) -> Option<u32> {
+        match self {
+            ClockConfig::External => None,
+            ClockConfig::Internal256KHz => Some(256 * 1024),
+            ClockConfig::Internal2MHz => Some(2 * 1024 * 1024),
+        }
+    }
+    /// Checks that this clock can be used reliably over `connection`.
+    pub const fn validate(self, connection: Connection) -> Result<(), ClockConfigError> {
+        match (self, connection) {
+            (ClockConfig::Internal2MHz, Connection::LinkCable) => {
+                Err(ClockConfigError::TooFastForConnection)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    const fn from_siocnt(value: u16) -> Self {
+        match (read_bit(value, 0), read_bit(value, 1)) {
+            (false, _) => ClockConfig::External,
+            (true, false) => ClockConfig::Internal256KHz,
+            (true, true) => ClockConfig::Internal2MHz,
+        }
+    }
+    const fn into_siocnt(self, prev: u16) -> u16 {
+        let fast = matches!(self, ClockConfig::Internal2MHz);
+        write_bit(write_bit(prev, 0, self.is_internal()), 1, fast)
+    }
+}
+
+/// The logic level of one of the link port's lines.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Level {
+    Low,
+    High,
+}
+
+impl Level {
+    const fn from_bit(bit: bool) -> Self {
+        if bit {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+    const fn is_high(self) -> bool {
+        matches!(self, Level::High)
+    }
+}
+
+/// An error that can happen during a multi-byte transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TransferError {
+    /// The send & receive buffers were different lengths.
+    LengthMismatch,
+    /// The transfer didn't finish before the timeout expired, most likely
+    /// because the master's clock never arrived. Contains the number of bytes
+    /// that were exchanged before giving up.
+    Timeout(usize),
+}
+
+/// Helper to switch the hardware into 8-bit Normal mode.
+fn enter_normal(clock: ClockConfig) {
+    let rcnt = RcntWrapper::get();
+    let siocnt = NormalSiocnt::get();
+
+    rcnt.set_mode(SerialMode::Normal);
+    siocnt.set_mode(SerialMode::Normal);
+    siocnt.set_32bit(false);
+    siocnt.set_clock(clock);
+}
+
+impl<'a> NormalSerial<'a> {
+    /// Enters Normal mode as the master, driving the shift clock at 256KHz.
+    pub fn new(_handle: &'a mut Serial) -> Self {
+        Self::with_clock(_handle, ClockConfig::default())
+    }
+    /// Enters Normal mode using the given shift clock.
+    pub fn with_clock(_handle: &'a mut Serial, clock: ClockConfig) -> Self {
+        enter_normal(clock);
+        Self {
+            _handle: PhantomData,
+            buffer_interrupt: None,
+        }
+    }
+
+    /// Switches to interrupt-driven buffered mode, allocating send & receive
+    /// buffers that can each hold `buffer_cap` words.
+    pub fn enable_buffered_mode(
+        self,
+        buffer_cap: usize,
+    ) -> Result<BufferedNormal<'a>, BufferedInitError> {
+        BufferedNormal::new(self, buffer_cap)
+    }
+
+    /// The currently active shift clock.
+    pub fn clock(&self) -> ClockConfig {
+        NormalSiocnt::get().clock()
+    }
+    /// Switches to a different shift clock, waiting for any in-progress
+    /// transfer to finish first.
+    ///
+    /// This does NOT check the clock against the connection; use
+    /// [ClockConfig::validate] for that.
+    pub fn set_clock(&mut self, clock: ClockConfig) {
+        let siocnt = NormalSiocnt::get();
+        while siocnt.busy() {}
+        siocnt.set_clock(clock)
+    }
+
+    /// The level SO is held at while no transfer is in progress.
+    pub fn so_idle_level(&self) -> Level {
+        Level::from_bit(NormalSiocnt::get().so_idle_high())
+    }
+    /// Sets the level SO is held at while no transfer is in progress, which
+    /// the peer can read via [Self::si_level].
+    pub fn set_so_idle_level(&mut self, level: Level) {
+        NormalSiocnt::get().set_so_idle_high(level.is_high())
+    }
+    /// The current level of SI, IE the peer's SO.
+    ///
+    /// This reads as [Level::High] when nothing is connected.
+    pub fn si_level(&self) -> Level {
+        Level::from_bit(NormalSiocnt::get().si_high())
+    }
+
+    /// Tells the peer whether or not we are ready for the next transfer.
+    ///
+    /// Following the procedure recommended by GBATEK (and used by commercial
+    /// games), the slave signals it is ready by pulling its SO line LOW after
+    /// loading its data & setting the start bit, and pulls it back HIGH once
+    /// the transfer completes.
+    pub fn set_ready(&mut self, ready: bool) {
+        let level = if ready { Level::Low } else { Level::High };
+        self.set_so_idle_level(level)
+    }
+    /// Whether or not the peer has signaled that it is ready for the next
+    /// transfer; see [Self::set_ready].
+    ///
+    /// The master should wait for this before starting each transfer.
+    pub fn peer_ready(&self) -> bool {
+        self.si_level() == Level::Low
+    }
+
+    /// Whether or not a transfer is currently in progress.
+    pub fn is_busy(&self) -> bool {
+        NormalSiocnt::get().busy()
+    }
+
+    /// Sends `byte` while receiving a byte from the other side, blocking until
+    /// the transfer completes.
+    ///
+    /// When using the external clock this will wait for as long as it takes
+    /// the master to clock the transfer.
+    pub fn exchange_u8(&mut self, byte: u8) -> u8 {
+        let siocnt = NormalSiocnt::get();
+        while siocnt.busy() {}
+        siocnt.set_32bit(false);
+        SIODATA8.write(byte);
+        siocnt.start_transfer();
+        while siocnt.busy() {}
+        SIODATA8.read()
+    }
+    /// Sends `word` while receiving a word from the other side, blocking until
+    /// the transfer completes.
+    ///
+    /// This moves 4 times as much data per transfer as [Self::exchange_u8],
+    /// but both sides of the link need to be using 32-bit transfers at the
+    /// same time. Bits are shifted out most-significant first.
+    pub fn exchange_u32(&mut self, word: u32) -> u32 {
+        let siocnt = NormalSiocnt::get();
+        while siocnt.busy() {}
+        siocnt.set_32bit(true);
+        SIODATA32.write(word);
+        siocnt.start_transfer();
+        while siocnt.busy() {}
+        SIODATA32.read()
+    }
+
+    /// Performs a full-duplex exchange, sending every byte in `send` while
+    /// receiving the same number of bytes into `recv`, blocking until done.
+    ///
+    /// To cut down on per-transfer overhead the bulk of the data is moved using
+    /// 32-bit transfers, with any remaining bytes sent 1 at a time. Both sides
+    /// have to agree on the width of every transfer, so the other side needs
+    /// to call this with the same length too (or make the same series of
+    /// [Self::exchange_u32] & [Self::exchange_u8] calls); a 32-bit transfer
+    /// can't be received by 8-bit exchanges.
+    pub fn transfer(&mut self, send: &[u8], recv: &mut [u8]) -> Result<(), TransferError> {
+        self.transfer_impl(send, recv, None)
+    }
+    /// Like [Self::transfer], but gives up after `frames` frames.
+    ///
+    /// On timeout the stuck transfer is cancelled with [Self::abort], so that
+    /// slave-side code can't hang forever on an unplugged cable. `timer` is
+    /// used to measure the timeout, and will be disabled again before this
+    /// returns.
+    pub fn transfer_with_timeout(
+        &mut self,
+        send: &[u8],
+        recv: &mut [u8],
+        frames: u32,
+        timer: &mut Timer,
+    ) -> Result<(), TransferError> {
+        let mut timeout = TimerTimeout::new(timer, frames);
+        self.transfer_impl(send, recv, Some(&mut timeout))
+    }
+
+    /// Cancels the in-progress transfer (if any) by clearing the start bit,
+    /// and stops signaling that we're ready to the peer.
+    ///
+    /// Whatever was partially shifted in is discarded.
+    pub fn abort(&mut self) {
+        let siocnt = NormalSiocnt::get();
+        siocnt.cancel_transfer();
+        siocnt.set_so_idle_high(true);
+    }
+
+    fn transfer_impl(
+        &mut self,
+        send: &[u8],
+        recv: &mut [u8],
+        mut timeout: Option<&mut TimerTimeout>,
+    ) -> Result<(), TransferError> {
+        if send.len() != recv.len() {
+            return Err(TransferError::LengthMismatch);
+        }
+        let mut done = 0;
+        let mut send_words = send.chunks_exact(4);
+        let mut recv_words = recv.chunks_exact_mut(4);
+        for (out, inc) in (&mut send_words).zip(&mut recv_words) {
+            // 32-bit transfers shift out the most significant byte first.
+            let word = u32::from_be_bytes([out[0], out[1], out[2], out[3]]);
+            let res = self
+                .exchange_timeout(true, word, timeout.as_deref_mut())
+                .ok_or(TransferError::Timeout(done))?;
+            inc.copy_from_slice(&res.to_be_bytes());
+            done += 4;
+        }
+        let send_rest = send_words.remainder();
+        let recv_rest = recv_words.into_remainder();
+        for (out, inc) in send_rest.iter().zip(recv_rest.iter_mut()) {
+            let res = self
+                .exchange_timeout(false, *out as u32, timeout.as_deref_mut())
+                .ok_or(TransferError::Timeout(done))?;
+            *inc = res as u8;
+            done += 1;
+        }
+        Ok(())
+    }
+
+    /// Performs a single 8 or 32-bit exchange, aborting it and returning `None`
+    /// if `timeout` expires first.
+    fn exchange_timeout(
+        &mut self,
+        wide: bool,
+        value: u32,
+        mut timeout: Option<&mut TimerTimeout>,
+    ) -> Option<u32> {
+        let siocnt = NormalSiocnt::get();
+        let mut wait_idle = |this: &mut Self| {
+            while siocnt.busy() {
+                if timeout.as_mut().is_some_and(|timeout| timeout.expired()) {
+                    this.abort();
+                    return false;
+                }
+            }
+            true
+        };
+        if !wait_idle(self) {
+            return None;
+        }
+        siocnt.set_32bit(wide);
+        if wide {
+            SIODATA32.write(value);
+        } else {
+            SIODATA8.write(value as u8);
+        }
+        siocnt.start_transfer();
+        if !wait_idle(self) {
+            return None;
+        }
+        if wide {
+            Some(SIODATA32.read())
+        } else {
+            Some(SIODATA8.read() as u32)
+        }
+    }
+
+    /// Enables the SERIAL interrupt, which will trigger whenever a transfer
+    /// completes.
+    pub fn enable_interrupt(&self, should_enable: bool) {
+        NormalSiocnt::get().enable_irq(should_enable)
+    }
+    /// Whether or not the SERIAL interrupt is currently enabled.
+    pub fn interrupt_enabled(&self) -> bool {
+        NormalSiocnt::get().irq_enabled()
+    }
+    /// Adds an interrupt handler that will be triggered whenever a transfer
+    /// completes, assuming you also call [Self::enable_interrupt].
+    ///
+    /// # Safety
+    /// The callback `cb` **must not** allocate on the heap.
+    pub unsafe fn add_interrupt<F>(&mut self, cb: F)
+    where
+        F: Fn(CriticalSection) + Send + Sync + 'static,
+    {
+        self.buffer_interrupt = Some(add_interrupt_handler(Interrupt::Serial, cb));
+    }
+}
+
+/// Newtype extention wrapper around the Serial I/O Control register with extra
+/// methods for Normal mode.
+///
+/// # GBATEK Table of Bits
+/// | Bit |  Explanation            | Notes |
+/// | :-- | :--                     | :--   |
+/// | 0   | Shift Clock (SC)        | (0=External, 1=Internal)
+/// | 1   | Internal Shift Clock    | (0=256KHz, 1=2MHz)
+/// | 2   | SI State (opponents SO) | (0=Low, 1=High/None) (Read Only)
+/// | 3   | SO during inactivity    | (0=Low, 1=High) (applied ONLY when Bit7=0)
+/// | 4-6 | Not used                | (Read only, always 0 ???)
+/// | 7   | Start Bit               | (0=Inactive/Ready, 1=Start/Active)
+/// | 8-11| Not used                | (R/W, should be 0)
+/// | 12  | Transfer Length         | (0=8bit, 1=32bit)
+/// | 13  | Must be "0" for Normal Mode |
+/// | 14  | IRQ Enable              | (0=Disable, 1=Want IRQ upon completion)
+/// | 15  | Not used                | (Read only, always 0)
+struct NormalSiocnt {
+    inner: SiocntWrapper,
+}
+
+method_wraps!(NormalSiocnt, inner, SiocntWrapper);
+
+impl NormalSiocnt {
+    const fn new() -> Self {
+        Self {
+            inner: SiocntWrapper::new(),
+        }
+    }
+    pub const fn get() -> Self {
+        Self::new()
+    }
+    pub fn clock(&self) -> ClockConfig {
+        ClockConfig::from_siocnt(self.read())
+    }
+    pub fn set_clock(&self, clock: ClockConfig) {
+        self.write(clock.into_siocnt(self.read()))
+    }
+    pub fn si_high(&self) -> bool {
+        self.read_bit(2)
+    }
+    pub fn so_idle_high(&self) -> bool {
+        self.read_bit(3)
+    }
+    pub fn set_so_idle_high(&self, high: bool) {
+        self.write_bit(3, high)
+    }
+    pub fn busy(&self) -> bool {
+        self.read_bit(7)
+    }
+    pub fn start_transfer(&self) {
+        self.write_bit(7, true)
+    }
+    pub fn cancel_transfer(&self) {
+        self.write_bit(7, false)
+    }
+    pub fn is_32bit(&self) -> bool {
+        self.read_bit(12)
+    }
+    pub fn set_32bit(&self, wide: bool) {
+        self.write_bit(12, wide)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_clock_config(_gba: &mut Gba) {
+        for clock in [
+            ClockConfig::External,
+            ClockConfig::Internal256KHz,
+            ClockConfig::Internal2MHz,
+        ] {
+            // Make sure the other bits are left alone.
+            let siocnt = clock.into_siocnt(0xF0F0);
+            assert_eq!(siocnt & !0b11, 0xF0F0);
+            assert_eq!(ClockConfig::from_siocnt(siocnt), clock);
+            assert_eq!(clock.validate(Connection::Direct), Ok(()));
+        }
+        assert_eq!(ClockConfig::from_siocnt(0b10), ClockConfig::External);
+        assert_eq!(
+            ClockConfig::Internal2MHz.validate(Connection::LinkCable),
+            Err(ClockConfigError::TooFastForConnection)
+        );
+        assert_eq!(
+            ClockConfig::Internal256KHz.validate(Connection::LinkCable),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! An interrupt-driven receive path for the slave (externally clocked) side of
+//! Normal mode, for reliably receiving data pushed by a master device without
+//! polling the start bit in a tight loop.
+//!
+//! Following the procedure recommended by GBATEK, the slave signals that it is
+//! ready for the next transfer by pulling SO LOW once it has re-armed the
+//! hardware, and back HIGH while it is busy storing what it just received. A
+//! master that checks its SI line before each transfer will therefore never
+//! send data that gets lost.
+//!
+//! Every transfer also sends a reply back to the master, which is the same
+//! fixed value (set with [SlaveReceiver::set_reply]) for every transfer.
+
+use agb::external::critical_section::{self, CriticalSection};
+use agb::interrupt::{add_interrupt_handler, Interrupt};
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::serial::ringbuf::Ringbuffer;
+use crate::serial::SIODATA8;
+use crate::utils::GbaCell;
+
+use super::{NormalSerial, NormalSiocnt, SIODATA32};
+
+/// Bytes that have been received but not yet read by the user.
+static RX_BUFFER: GbaCell<Ringbuffer<u8>> = GbaCell::new(Ringbuffer::empty());
+
+/// How many received bytes were thrown away because [RX_BUFFER] was full.
+static DROPPED: GbaCell<usize> = GbaCell::new(0);
+
+/// The value sent back to the master during every transfer.
+static REPLY: GbaCell<u32> = GbaCell::new(DEFAULT_REPLY);
+
+/// The reply sent to the master unless changed with
+/// [SlaveReceiver::set_reply].
+pub const DEFAULT_REPLY: u32 = 0xFFFF_FFFF;
+
+/// How many bits the master sends per transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum TransferWidth {
+    #[default]
+    Bits8,
+    Bits32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlaveInitError {
+    AlreadyInitialized,
+    /// The handle is driving the shift clock, making it the master.
+    InternalClock,
+}
+
+/// Receives data pushed by a master device in the background.
+pub struct SlaveReceiver<'a> {
+    inner: NormalSerial<'a>,
+}
+
+impl<'a> SlaveReceiver<'a> {
+    /// Starts receiving `width`-sized transfers into a buffer of `cap` bytes.
+    pub fn new(
+        mut inner: NormalSerial<'a>,
+        cap: usize,
+        width: TransferWidth,
+    ) -> Result<Self, SlaveInitError> {
+        let siocnt = NormalSiocnt::get();
+        if siocnt.clock().is_internal() {
+            return Err(SlaveInitError::InternalClock);
+        }
+        RX_BUFFER
+            .swap_if(Ringbuffer::new(cap), |old| old.is_placeholder())
+            .map_err(|_| SlaveInitError::AlreadyInitialized)?;
+        DROPPED.swap(0);
+
+        // We aren't armed yet, so tell the master to wait.
+        siocnt.set_so_idle_high(true);
+        while siocnt.busy() {}
+        siocnt.set_32bit(width == TransferWidth::Bits32);
+
+        inner.buffer_interrupt = unsafe {
+            Some(add_interrupt_handler(
+                Interrupt::Serial,
+                slave_interrupt_callback,
+            ))
+        };
+        inner.enable_interrupt(true);
+        critical_section::with(|cs| arm(cs, &siocnt));
+
+        Ok(Self { inner })
+    }
+
+    /// Pulls as many received bytes as are available (up to the length of
+    /// `buffer`) into `buffer`. Returns the number of bytes read.
+    ///
+    /// The bytes of each 32-bit transfer are stored most significant first,
+    /// matching the order they came over the wire.
+    ///
+    /// Does NOT block.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        critical_section::with(|cs| RX_BUFFER.lock_in(cs, |rx| rx.read_bulk(buffer, cs)))
+    }
+    /// The number of received bytes waiting to be read.
+    pub fn available(&self) -> usize {
+        critical_section::with(|cs| RX_BUFFER.lock_in(cs, |rx| rx.len(cs)))
+    }
+    /// How many received bytes have been thrown away because the receive
+    /// buffer was full.
+    pub fn dropped(&self) -> usize {
+        DROPPED.get_copy()
+    }
+
+    /// The value sent back to the master during every transfer.
+    pub fn reply(&self) -> u32 {
+        REPLY.get_copy()
+    }
+    /// Sets the value sent back to the master, starting with the transfer
+    /// after next; for 8-bit transfers only the lowest byte is used.
+    pub fn set_reply(&mut self, reply: u32) {
+        REPLY.swap(reply);
+    }
+
+    /// Stops receiving, returning to the unbuffered [NormalSerial] handle.
+    ///
+    /// Any data still sitting in the buffer is discarded.
+    pub fn leave(self) -> NormalSerial<'a> {
+        let mut this = ManuallyDrop::new(self);
+        this.end_session();
+        // #SAFETY
+        //
+        // `this` is never used or dropped again, so `inner` is only moved out
+        // once.
+        unsafe { ptr::read(&this.inner) }
+    }
+    /// Tells the master to wait, stops the interrupt & resets the buffer, so
+    /// that a new receiver can be started.
+    fn end_session(&mut self) {
+        let siocnt = NormalSiocnt::get();
+        siocnt.set_so_idle_high(true);
+        self.inner.enable_interrupt(false);
+        self.inner.buffer_interrupt = None;
+        RX_BUFFER.swap(Ringbuffer::empty());
+        REPLY.swap(DEFAULT_REPLY);
+    }
+}
+
+impl Drop for SlaveReceiver<'_> {
+    fn drop(&mut self) {
+        self.end_session();
+    }
+}
+
+/// Loads the reply, sets the start bit, and then tells the master we're ready.
+fn arm(cs: CriticalSection<'_>, siocnt: &NormalSiocnt) {
+    let reply = REPLY.get_copy_in(cs);
+    if siocnt.is_32bit() {
+        SIODATA32.write(reply);
+    } else {
+        SIODATA8.write(reply as u8);
+    }
+    siocnt.start_transfer();
+    siocnt.set_so_idle_high(false);
+}
+
+/// The interrupt callback called every time the master completes a transfer.
+fn slave_interrupt_callback(cs: CriticalSection<'_>) {
+    let siocnt = NormalSiocnt::get();
+    siocnt.set_so_idle_high(true);
+
+    let mut bytes = [0; 4];
+    let received = if siocnt.is_32bit() {
+        bytes = SIODATA32.read().to_be_bytes();
+        &bytes[..]
+    } else {
+        bytes[0] = SIODATA8.read();
+        &bytes[..1]
+    };
+    let written = RX_BUFFER.lock_in(cs, |rx| rx.write_bulk(received, cs));
+    if written < received.len() {
+        DROPPED.lock_mut_in(cs, |dropped| *dropped += received.len() - written);
+    }
+
+    arm(cs, &siocnt);
+}
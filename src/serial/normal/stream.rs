@@ -0,0 +1,214 @@
+//! A byte stream between 2 GBAs, implementing the [embedded_io] traits on top
+//! of a [DuplexLink].
+//!
+//! Each 32-bit word sent over the link carries up to 3 bytes of data, with the
+//! top byte holding how many of them are valid. Since that count is never
+//! `0xFF` no packed word can ever collide with
+//! [NO_DATA](super::duplex::NO_DATA), so unlike the raw
+//! [DuplexLink] any byte sequence can be sent.
+//!
+//! The blocking [Read] & [Write] methods sleep until the next interrupt
+//! between checks, which is usually the serial interrupt for the next
+//! transfer; make sure VBlank or another regular interrupt is enabled too, so
+//! that they still wake up while the other GBA isn't ready.
+
+use core::convert::Infallible;
+
+use agb::syscall;
+use agb::timer::Timer;
+use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
+
+use super::duplex::{DuplexInitError, DuplexLink};
+use super::NormalSerial;
+
+/// The number of bytes of data carried by each word.
+const BYTES_PER_WORD: usize = 3;
+
+pub struct NormalStream<'a> {
+    link: DuplexLink<'a>,
+    /// The unread bytes of the last word received.
+    partial: [u8; BYTES_PER_WORD],
+    partial_start: usize,
+    partial_end: usize,
+}
+
+impl<'a> NormalStream<'a> {
+    /// Starts a byte stream over `inner`, buffering up to `cap` words (or
+    /// `cap * 3` bytes) in each direction.
+    ///
+    /// As with [DuplexLink::new], the master is whichever side uses an
+    /// internal clock.
+    pub fn new(inner: NormalSerial<'a>, cap: usize) -> Result<Self, DuplexInitError> {
+        DuplexLink::new(inner, cap).map(Self::from_link)
+    }
+    /// Wraps an existing [DuplexLink]. The link's buffers should be empty, as
+    /// any words already received are interpreted as stream data.
+    pub fn from_link(link: DuplexLink<'a>) -> Self {
+        Self {
+            link,
+            partial: [0; BYTES_PER_WORD],
+            partial_start: 0,
+            partial_end: 0,
+        }
+    }
+    /// Gives access to the underlying link, such as to
+    /// [schedule](DuplexLink::schedule) transfers.
+    pub fn link(&mut self) -> &mut DuplexLink<'a> {
+        &mut self.link
+    }
+    /// Exits the stream, returning to the unbuffered [NormalSerial] handle
+    /// along with the scheduling timer, if any; see [DuplexLink::leave].
+    ///
+    /// Any data still sitting in the buffers is discarded.
+    pub fn leave(self) -> (NormalSerial<'a>, Option<Timer>) {
+        self.link.leave()
+    }
+
+    /// Reads as many bytes as are already available into `buf`, returning the
+    /// number of bytes read. Does NOT block.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        while read < buf.len() {
+            if self.partial_start == self.partial_end && !self.refill() {
+                break;
+            }
+            let chunk = &self.partial[self.partial_start..self.partial_end];
+            let len = chunk.len().min(buf.len() - read);
+            buf[read..read + len].copy_from_slice(&chunk[..len]);
+            self.partial_start += len;
+            read += len;
+        }
+        read
+    }
+    /// Queues as many bytes from `buf` as will fit in the outbox, returning
+    /// the number of bytes queued. Does NOT block.
+    pub fn write_available(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+        for chunk in buf.chunks(BYTES_PER_WORD) {
+            if self.link.queue_send(&[pack(chunk)]) == 0 {
+                break;
+            }
+            written += chunk.len();
+        }
+        written
+    }
+
+    /// Pulls the next received word into `partial`, returning false if
+    /// nothing has been received.
+    fn refill(&mut self) -> bool {
+        let mut word = [0];
+        if self.link.read_bulk(&mut word) == 0 {
+            return false;
+        }
+        let len = unpack(word[0], &mut self.partial);
+        self.partial_start = 0;
+        self.partial_end = len;
+        true
+    }
+}
+
+/// Packs up to 3 bytes into a single word.
+fn pack(bytes: &[u8]) -> u32 {
+    debug_assert!(bytes.len() <= BYTES_PER_WORD);
+    let mut retvl = (bytes.len() as u32) << 24;
+    for (idx, byte) in bytes.iter().enumerate() {
+        retvl |= (*byte as u32) << (16 - 8 * idx);
+    }
+    retvl
+}
+
+/// Unpacks a word created by [pack] into `out`, returning the number of valid
+/// bytes.
+fn unpack(word: u32, out: &mut [u8; BYTES_PER_WORD]) -> usize {
+    let len = ((word >> 24) as usize).min(BYTES_PER_WORD);
+    for (idx, slot) in out.iter_mut().enumerate() {
+        *slot = (word >> (16 - 8 * idx)) as u8;
+    }
+    len
+}
+
+impl ErrorType for NormalStream<'_> {
+    type Error = Infallible;
+}
+
+impl Read for NormalStream<'_> {
+    /// Blocks until at least 1 byte is available.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let read = self.read_available(buf);
+            if read > 0 {
+                return Ok(read);
+            }
+            self.link.tick();
+            syscall::halt();
+        }
+    }
+}
+
+impl ReadReady for NormalStream<'_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.partial_start != self.partial_end || self.link.available() > 0)
+    }
+}
+
+impl Write for NormalStream<'_> {
+    /// Blocks until at least 1 byte fits in the outbox.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let written = self.write_available(buf);
+            if written > 0 {
+                return Ok(written);
+            }
+            self.link.tick();
+            syscall::halt();
+        }
+    }
+    /// Blocks until every queued byte has been transferred to the other GBA;
+    /// see [DuplexLink::all_sent]. The other GBA may not have read them yet.
+    ///
+    /// On the slave this relies on the master to keep transferring.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        while !self.link.all_sent() {
+            self.link.tick();
+            syscall::halt();
+        }
+        Ok(())
+    }
+}
+
+impl WriteReady for NormalStream<'_> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.link.pending() < self.link.capacity())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::normal::duplex::NO_DATA;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_pack_unpack(_gba: &mut Gba) {
+        let mut out = [0; BYTES_PER_WORD];
+        for bytes in [
+            &[][..],
+            &[0xFF],
+            &[0xFF, 0xFF],
+            &[0xFF, 0xFF, 0xFF],
+            &[1, 2, 3],
+        ] {
+            let word = pack(bytes);
+            assert_ne!(word, NO_DATA);
+            let len = unpack(word, &mut out);
+            assert_eq!(&out[..len], bytes);
+        }
+        assert_eq!(pack(&[0xAB, 0xCD]), 0x02AB_CD00);
+    }
+}
@@ -0,0 +1,334 @@
+//! A driver for the Game Boy Printer, talking to it over the link port in
+//! 8-bit Normal mode.
+//!
+//! # Protocol
+//!
+//! Every exchange with the printer is a single packet:
+//!
+//! | Bytes | Contents |
+//! | :--   | :--      |
+//! | 2     | Magic bytes `0x88 0x33` |
+//! | 1     | Command |
+//! | 1     | Compression flag (always 0 here) |
+//! | 2     | Data length (little endian) |
+//! | N     | Data |
+//! | 2     | Checksum: the sum of every byte from the command through the data (little endian) |
+//! | 2     | Two `0x00` bytes, during which the printer replies with [ALIVE] and then its [PrinterStatus] |
+//!
+//! Printing an image is then done by sending [Command::Init], up to 9
+//! [Command::Data] packets of up to [MAX_DATA_LEN] bytes of 2bpp tiles each,
+//! an empty [Command::Data] packet to mark the end of the image, and finally a
+//! [Command::Print] packet; [Printer::print_tiles] does all of this.
+//!
+//! # Timing
+//!
+//! The printer was designed around the Game Boy's 8KHz shift clock, while the
+//! slowest clock the GBA can generate is 256KHz. To give the printer time to
+//! process each byte we pause for roughly half a millisecond after each one.
+
+use crate::utils::FrameTimeout;
+
+use super::normal::{ClockConfig, NormalSerial};
+use super::{Serial, SIOCNT};
+
+/// The two bytes starting every packet.
+const MAGIC: [u8; 2] = [0x88, 0x33];
+
+/// The byte the printer replies with to show that it is connected.
+pub const ALIVE: u8 = 0x81;
+
+/// The maximum number of data bytes in a single packet: 2 rows of 20 tiles.
+pub const MAX_DATA_LEN: usize = 640;
+
+/// The number of tiles in each row of the printed image, making it 160 pixels
+/// wide.
+pub const TILES_PER_ROW: usize = 20;
+
+/// The number of bytes in a single 8x8 2bpp tile.
+pub const TILE_BYTES: usize = 16;
+
+/// The maximum number of data packets the printer can hold for a single print.
+pub const MAX_DATA_PACKETS: usize = 9;
+
+/// How many times to poll SIOCNT between bytes, giving the printer time to
+/// process each one; a little over half a millisecond.
+const BYTE_GAP_POLLS: u32 = 1500;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Command {
+    /// Clears the printer's buffer.
+    Init = 0x01,
+    /// Prints the buffered image.
+    Print = 0x02,
+    /// Adds image data to the buffer; an empty packet marks the end of the
+    /// image.
+    Data = 0x04,
+    /// Asks for the printer's status without doing anything else.
+    Status = 0x0F,
+}
+
+/// The status byte returned at the end of every packet.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PrinterStatus {
+    value: u8,
+}
+
+impl PrinterStatus {
+    pub const fn new(value: u8) -> Self {
+        Self { value }
+    }
+    pub const fn bits(&self) -> u8 {
+        self.value
+    }
+    /// The printer received a packet with the wrong checksum.
+    pub const fn checksum_error(&self) -> bool {
+        self.value & (1 << 0) != 0
+    }
+    /// The printer is currently printing.
+    pub const fn busy(&self) -> bool {
+        self.value & (1 << 1) != 0
+    }
+    /// The printer's buffer is full.
+    pub const fn image_full(&self) -> bool {
+        self.value & (1 << 2) != 0
+    }
+    /// The printer has buffered data that hasn't been printed yet.
+    pub const fn unprocessed_data(&self) -> bool {
+        self.value & (1 << 3) != 0
+    }
+    /// The printer received a malformed packet.
+    pub const fn packet_error(&self) -> bool {
+        self.value & (1 << 4) != 0
+    }
+    /// The paper is jammed.
+    pub const fn paper_jam(&self) -> bool {
+        self.value & (1 << 5) != 0
+    }
+    /// Some other error, such as being out of paper.
+    pub const fn other_error(&self) -> bool {
+        self.value & (1 << 6) != 0
+    }
+    /// The printer's batteries are too low to print.
+    pub const fn battery_low(&self) -> bool {
+        self.value & (1 << 7) != 0
+    }
+    /// Whether or not any of the error bits are set.
+    pub const fn has_error(&self) -> bool {
+        self.value & 0b1111_0001 != 0
+    }
+}
+
+/// The settings for a [Command::Print].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PrintOptions {
+    /// How many copies to print; 0 just feeds paper.
+    pub sheets: u8,
+    /// The blank space to feed before the image, from 0 to 15.
+    pub margin_before: u8,
+    /// The blank space to feed after the image, from 0 to 15.
+    pub margin_after: u8,
+    /// Maps each 2bpp colour index to a shade, 2 bits per colour starting
+    /// from the low bits; `0xE4` is the identity mapping.
+    pub palette: u8,
+    /// The print darkness, from 0 to 0x7F.
+    pub exposure: u8,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            sheets: 1,
+            margin_before: 1,
+            margin_after: 3,
+            palette: 0xE4,
+            exposure: 0x40,
+        }
+    }
+}
+
+impl PrintOptions {
+    const fn to_bytes(self) -> [u8; 4] {
+        [
+            self.sheets,
+            ((self.margin_before & 0xF) << 4) | (self.margin_after & 0xF),
+            self.palette,
+            self.exposure & 0x7F,
+        ]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PrinterError {
+    /// The printer didn't reply with [ALIVE], so it probably isn't connected.
+    NotConnected,
+    /// The printer reported an error.
+    Status(PrinterStatus),
+    /// The data didn't fit into a single packet or print, or wasn't made of
+    /// whole rows of tiles.
+    InvalidLength,
+    /// The printer was still busy when the timeout expired.
+    Timeout,
+}
+
+/// Computes a packet's checksum.
+fn checksum(command: Command, data: &[u8]) -> u16 {
+    let len = data.len() as u16;
+    let header = [command as u8, 0, len as u8, (len >> 8) as u8];
+    header
+        .iter()
+        .chain(data)
+        .fold(0u16, |acc, &byte| acc.wrapping_add(byte as u16))
+}
+
+/// Feeds every byte of a packet, minus the 2 trailing reply bytes, to `sink`.
+fn encode_packet(command: Command, data: &[u8], mut sink: impl FnMut(u8)) {
+    let len = data.len() as u16;
+    let sum = checksum(command, data);
+    let header = [
+        MAGIC[0],
+        MAGIC[1],
+        command as u8,
+        0,
+        len as u8,
+        (len >> 8) as u8,
+    ];
+    header
+        .iter()
+        .chain(data)
+        .chain(&sum.to_le_bytes())
+        .for_each(|&byte| sink(byte));
+}
+
+/// A handle to a Game Boy Printer connected to the link port.
+pub struct Printer<'a> {
+    serial: NormalSerial<'a>,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(handle: &'a mut Serial) -> Self {
+        Self {
+            serial: NormalSerial::with_clock(handle, ClockConfig::Internal256KHz),
+        }
+    }
+
+    /// Sends a single packet, returning the printer's status.
+    pub fn send_packet(
+        &mut self,
+        command: Command,
+        data: &[u8],
+    ) -> Result<PrinterStatus, PrinterError> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(PrinterError::InvalidLength);
+        }
+        encode_packet(command, data, |byte| {
+            self.exchange(byte);
+        });
+        let alive = self.exchange(0);
+        let status = PrinterStatus::new(self.exchange(0));
+        if alive != ALIVE {
+            return Err(PrinterError::NotConnected);
+        }
+        if status.has_error() {
+            return Err(PrinterError::Status(status));
+        }
+        Ok(status)
+    }
+
+    /// Clears the printer's buffer, also checking that it's connected.
+    pub fn init(&mut self) -> Result<PrinterStatus, PrinterError> {
+        self.send_packet(Command::Init, &[])
+    }
+    /// Reads the printer's status.
+    pub fn status(&mut self) -> Result<PrinterStatus, PrinterError> {
+        self.send_packet(Command::Status, &[])
+    }
+
+    /// Prints an image made of 2bpp tiles, blocking until the printer
+    /// finishes or `timeout_frames` frames pass (if not `None`).
+    ///
+    /// Tiles are laid out row by row, [TILES_PER_ROW] tiles to a row, and
+    /// there must be an even number of rows: at most 18, or 144 pixels tall.
+    pub fn print_tiles(
+        &mut self,
+        tiles: &[u8],
+        options: PrintOptions,
+        timeout_frames: Option<u32>,
+    ) -> Result<(), PrinterError> {
+        if tiles.len() % MAX_DATA_LEN != 0 || tiles.len() > MAX_DATA_LEN * MAX_DATA_PACKETS {
+            return Err(PrinterError::InvalidLength);
+        }
+        self.init()?;
+        for chunk in tiles.chunks(MAX_DATA_LEN) {
+            self.send_packet(Command::Data, chunk)?;
+        }
+        self.send_packet(Command::Data, &[])?;
+        self.send_packet(Command::Print, &options.to_bytes())?;
+        self.wait_until_idle(timeout_frames)
+    }
+
+    /// Polls the printer once per frame until it is no longer busy.
+    pub fn wait_until_idle(&mut self, timeout_frames: Option<u32>) -> Result<(), PrinterError> {
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        loop {
+            let status = self.status()?;
+            if !status.busy() && !status.unprocessed_data() {
+                return Ok(());
+            }
+            if !timeout.wait() {
+                return Err(PrinterError::Timeout);
+            }
+        }
+    }
+
+    /// Returns the underlying [NormalSerial] handle.
+    pub fn into_inner(self) -> NormalSerial<'a> {
+        self.serial
+    }
+
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let res = self.serial.exchange_u8(byte);
+        for _ in 0..BYTE_GAP_POLLS {
+            let _ = SIOCNT.read();
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_encode_packet(_gba: &mut Gba) {
+        let mut out = Vec::new();
+        encode_packet(Command::Init, &[], |byte| out.push(byte));
+        assert_eq!(out, [0x88, 0x33, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00]);
+
+        out.clear();
+        let options = PrintOptions::default().to_bytes();
+        assert_eq!(options, [0x01, 0x13, 0xE4, 0x40]);
+        encode_packet(Command::Print, &options, |byte| out.push(byte));
+        assert_eq!(
+            out,
+            [0x88, 0x33, 0x02, 0x00, 0x04, 0x00, 0x01, 0x13, 0xE4, 0x40, 0x3E, 0x01]
+        );
+
+        let data = [0xFF; MAX_DATA_LEN];
+        let sum = (0x04 + 0x80 + 0x02 + 0xFF * MAX_DATA_LEN as u32) as u16;
+        assert_eq!(checksum(Command::Data, &data), sum);
+    }
+
+    #[test_case]
+    fn test_printer_status(_gba: &mut Gba) {
+        assert!(!PrinterStatus::new(0).has_error());
+        let busy = PrinterStatus::new(0b0000_1010);
+        assert!(busy.busy() && busy.unprocessed_data() && !busy.has_error());
+        let jammed = PrinterStatus::new(0b0010_0000);
+        assert!(jammed.paper_jam() && jammed.has_error());
+        assert!(PrinterStatus::new(0x01).checksum_error());
+        assert!(PrinterStatus::new(0x80).battery_low());
+    }
+}
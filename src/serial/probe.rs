@@ -0,0 +1,53 @@
+//! Working out what is plugged into the link port, so that games can pick the
+//! right transport without asking the player.
+//!
+//! The probe first tries to log in to a Wireless Adapter; if that fails it
+//! checks the multiplayer SI terminal, which the small plug of a link cable
+//! pulls low on the parent.
+//!
+//! Note that a child on a link cable can't be told apart from an empty port
+//! until the parent starts a transfer, since both leave SI pulled high.
+
+use super::multiplayer::{BaudRate, MultiplayerSerial};
+use super::wireless::{WirelessAdapter, WirelessError};
+use super::Serial;
+
+/// What [Serial::probe] found plugged into the link port.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LinkDevice {
+    /// A Wireless Adapter, which answered the login.
+    WirelessAdapter,
+    /// A link cable with us as the parent, IE with its small plug in our port.
+    CableParent,
+    /// Either nothing at all, or a link cable with us as a child.
+    NoParent,
+}
+
+impl LinkDevice {
+    /// Whether a link cable may be attached, in which case the game should
+    /// use [MultiplayerSerial].
+    pub const fn may_be_cable(self) -> bool {
+        matches!(self, Self::CableParent | Self::NoParent)
+    }
+}
+
+impl Serial {
+    /// Checks what is plugged into the link port, blocking for up to a few
+    /// frames.
+    ///
+    /// This leaves the port in multiplayer mode unless a Wireless Adapter was
+    /// found; either way, the chosen transport should be created from scratch
+    /// afterwards.
+    pub fn probe(&mut self) -> LinkDevice {
+        match WirelessAdapter::new(self) {
+            Ok(_) => return LinkDevice::WirelessAdapter,
+            Err(WirelessError::NotConnected | WirelessError::Timeout) => {}
+            // The login succeeded, so only the command after it failed.
+            Err(_) => return LinkDevice::WirelessAdapter,
+        }
+        match MultiplayerSerial::new(self, BaudRate::default()) {
+            Ok(serial) if serial.is_parent() => LinkDevice::CableParent,
+            _ => LinkDevice::NoParent,
+        }
+    }
+}
@@ -5,13 +5,12 @@ use agb::external::critical_section::{CriticalSection, Mutex};
 use alloc::boxed::Box;
 use alloc::vec;
 
-use super::NO_DATA;
-
-/// Ringbuffer for data transfers in multiplayer mode when using the "bulk
-/// transfer" feature.
-pub struct Ringbuffer {
+/// Ringbuffer for data that needs to be passed between the serial interrupt and
+/// regular code, such as the outbox in multiplayer mode's "bulk transfer"
+/// feature or the send & receive queues in buffered UART mode.
+pub struct Ringbuffer<T = u16> {
     /// The head of the memory block. Should always point to an allocation of exactly `self.bufflen` elements.
-    buffer: *mut u16,
+    buffer: *mut T,
     /// The maximum number of elements the buffer can store.
     bufflen: usize,
     /// The next valid location to read.
@@ -28,27 +27,30 @@ pub struct Ringbuffer {
     /// (`self.read_idx == self.write_idx`) from the "full" (`self.read_idx +
     /// self.bufflen == self.write_idx`).
     write_idx: Mutex<Cell<usize>>,
+    /// Whether or not `self.buffer` was allocated by us & needs to be freed
+    /// on drop, rather than borrowed from a `static`.
+    owned: bool,
 }
 
 /// #SAFETY
 ///
 /// All reads & writes to the data in this buffer are protected via critical
 /// sections, meaning no matter what only 1 code path can touch it at a time.
-unsafe impl Sync for Ringbuffer {}
+unsafe impl<T: Send> Sync for Ringbuffer<T> {}
 /// #SAFETY
 ///
 /// All reads & writes to the data in this buffer are protected via critical
 /// sections, meaning no matter what only 1 code path can touch it at a time.
-unsafe impl Send for Ringbuffer {}
+unsafe impl<T: Send> Send for Ringbuffer<T> {}
 
-impl Default for Ringbuffer {
+impl<T> Default for Ringbuffer<T> {
     fn default() -> Self {
         Ringbuffer::empty()
     }
 }
-impl Drop for Ringbuffer {
+impl<T> Drop for Ringbuffer<T> {
     fn drop(&mut self) {
-        if self.buffer.is_null() {
+        if self.buffer.is_null() || !self.owned {
             return;
         }
         unsafe {
@@ -58,7 +60,7 @@ impl Drop for Ringbuffer {
     }
 }
 
-impl Ringbuffer {
+impl<T> Ringbuffer<T> {
     /// Constructs an empty, nonfunctional `Ringbuffer` for use as a
     /// sentinel.
     ///
@@ -70,6 +72,7 @@ impl Ringbuffer {
             bufflen: 0,
             read_idx: Mutex::new(Cell::new(0)),
             write_idx: Mutex::new(Cell::new(0)),
+            owned: false,
         }
     }
     /// Checks whether or not this is a real `RingBuffer` or just an empty
@@ -77,19 +80,51 @@ impl Ringbuffer {
     pub const fn is_placeholder(&self) -> bool {
         self.bufflen == 0
     }
+    /// The maximum number of elements the buffer can store.
+    pub const fn capacity(&self) -> usize {
+        self.bufflen
+    }
+}
 
+impl<T: Copy + Default> Ringbuffer<T> {
     /// Constructs a new ringbuffer with the given capacity.
     pub fn new(cap: usize) -> Self {
-        let data = vec![NO_DATA; cap].into_boxed_slice();
+        let data = vec![T::default(); cap].into_boxed_slice();
 
         Self {
             buffer: Box::leak(data).as_mut_ptr(),
             bufflen: cap,
             read_idx: Mutex::new(Cell::new(0)),
             write_idx: Mutex::new(Cell::new(0)),
+            owned: true,
         }
     }
-    pub fn push(&self, p0: u16, cs: CriticalSection) -> Result<(), ()> {
+    /// Constructs a new ringbuffer using `storage` instead of a heap
+    /// allocation, with a capacity of `storage.len()`.
+    pub fn from_static(storage: &'static mut [T]) -> Self {
+        Self {
+            buffer: storage.as_mut_ptr(),
+            bufflen: storage.len(),
+            read_idx: Mutex::new(Cell::new(0)),
+            write_idx: Mutex::new(Cell::new(0)),
+            owned: false,
+        }
+    }
+    /// The number of elements currently stored in the buffer.
+    pub fn len(&self, cs: CriticalSection) -> usize {
+        if self.is_placeholder() {
+            return 0;
+        }
+        let raw_ridx = self.read_idx.borrow(cs).get();
+        let raw_widx = self.write_idx.borrow(cs).get();
+        len(raw_ridx, raw_widx, self.bufflen)
+    }
+    /// Drops everything currently stored in the buffer.
+    pub fn clear(&self, cs: CriticalSection) {
+        let raw_widx = self.write_idx.borrow(cs).get();
+        self.read_idx.borrow(cs).set(raw_widx);
+    }
+    pub fn push(&self, p0: T, cs: CriticalSection) -> Result<(), ()> {
         let raw_ridx = self.read_idx.borrow(cs).get();
         let raw_widx = self.write_idx.borrow(cs).get();
         if is_full(raw_ridx, raw_widx, self.bufflen) {
@@ -104,7 +139,7 @@ impl Ringbuffer {
             .replace((raw_widx + 1) % (2 * self.bufflen));
         Ok(())
     }
-    pub fn pop(&self, cs: CriticalSection) -> Option<u16> {
+    pub fn pop(&self, cs: CriticalSection) -> Option<T> {
         let raw_ridx = self.read_idx.borrow(cs).get();
         let raw_widx = self.write_idx.borrow(cs).get();
         if is_empty(raw_ridx, raw_widx, self.bufflen) {
@@ -121,7 +156,7 @@ impl Ringbuffer {
     /// into the provided buffer.
     ///
     /// Returns the number of values read.
-    pub fn read_bulk(&self, outbuff: &mut [u16], cs: CriticalSection<'_>) -> usize {
+    pub fn read_bulk(&self, outbuff: &mut [T], cs: CriticalSection<'_>) -> usize {
         let raw_ridx = self.read_idx.borrow(cs).get();
         let raw_widx = self.write_idx.borrow(cs).get();
         if is_empty(raw_ridx, raw_widx, self.bufflen) {
@@ -152,7 +187,7 @@ impl Ringbuffer {
             .set((raw_ridx + retvl) % (2 * self.bufflen));
         retvl
     }
-    pub fn write_bulk(&self, buff: &[u16], cs: CriticalSection<'_>) -> usize {
+    pub fn write_bulk(&self, buff: &[T], cs: CriticalSection<'_>) -> usize {
         //TODO: Implement this
         let mut retvl = 0;
         for next in buff {
@@ -197,7 +232,11 @@ mod tests {
 
     #[test_case]
     fn verify_size(_gba: &mut Gba) {
-        assert_eq!(mem::size_of::<Ringbuffer>(), 4 * mem::size_of::<usize>())
+        assert_eq!(mem::size_of::<Ringbuffer>(), 5 * mem::size_of::<usize>());
+        assert_eq!(
+            mem::size_of::<Ringbuffer<u8>>(),
+            5 * mem::size_of::<usize>()
+        )
     }
     #[test_case]
     fn test_buffer_bulk(_gba: &mut Gba) {
@@ -225,6 +264,18 @@ mod tests {
         })
     }
     #[test_case]
+    fn test_buffer_clear(_gba: &mut Gba) {
+        let buffer = Ringbuffer::new(4);
+        critical_section::with(|cs| {
+            assert_eq!(buffer.write_bulk(&[1, 2, 3], cs), 3);
+            buffer.clear(cs);
+            assert_eq!(buffer.len(cs), 0);
+            assert_eq!(buffer.pop(cs), None);
+            assert_eq!(buffer.write_bulk(&[4, 5, 6, 7], cs), 4);
+            assert_eq!(buffer.pop(cs), Some(4));
+        })
+    }
+    #[test_case]
     fn test_buffer(_gba: &mut Gba) {
         const BUFFER_SIZE: usize = 0x8F;
 
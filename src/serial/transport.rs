@@ -0,0 +1,54 @@
+//! A common interface over the ways several GBAs can exchange data, so that
+//! games can support both the link cable & the Wireless Adapter without
+//! caring which one is in use.
+//!
+//! Per-player buffers are laid out by player ID, with room for the 5 players a
+//! wireless session can have; transports with fewer players always leave the
+//! extra slots empty.
+
+use super::multiplayer::bulk::{BulkMultiplayer, QueueError};
+
+/// The most players any transport can have, including the host.
+pub const MAX_PLAYERS: usize = 5;
+
+/// A multiplayer session that exchanges streams of `u16`s between players.
+///
+/// This mirrors the interface of [BulkMultiplayer]: data is queued with
+/// [Self::queue_send], moved by calling [Self::tick] every frame, and read back
+/// with [Self::read_bulk].
+pub trait MultiplayerTransport {
+    type Error;
+    /// How many of the [MAX_PLAYERS] per-player slots this transport can ever
+    /// fill.
+    const PLAYERS: usize;
+    /// Queues as much of `buffer` as fits to be sent to every other player,
+    /// returning how many words were queued.
+    fn queue_send(&mut self, buffer: &[u16]) -> Result<usize, Self::Error>;
+    /// Pulls received data into `buffers`, 1 per player in player order,
+    /// returning how many words were read for each player.
+    fn read_bulk(
+        &mut self,
+        buffers: &mut [&mut [u16]; MAX_PLAYERS],
+    ) -> Result<[usize; MAX_PLAYERS], Self::Error>;
+    /// Performs any per-frame maintenance, such as moving queued data.
+    fn tick(&mut self) -> Result<(), Self::Error>;
+}
+
+impl MultiplayerTransport for BulkMultiplayer<'_> {
+    type Error = QueueError;
+    const PLAYERS: usize = 4;
+    fn queue_send(&mut self, buffer: &[u16]) -> Result<usize, Self::Error> {
+        BulkMultiplayer::queue_send(self, buffer)
+    }
+    fn read_bulk(
+        &mut self,
+        buffers: &mut [&mut [u16]; MAX_PLAYERS],
+    ) -> Result<[usize; MAX_PLAYERS], Self::Error> {
+        let [p0, p1, p2, p3, _] = buffers;
+        let [r0, r1, r2, r3] = BulkMultiplayer::read_bulk(self, &mut [p0, p1, p2, p3])?;
+        Ok([r0, r1, r2, r3, 0])
+    }
+    fn tick(&mut self) -> Result<(), Self::Error> {
+        BulkMultiplayer::tick(self).map_err(|e| QueueError::MultiplayerError(e.into()))
+    }
+}
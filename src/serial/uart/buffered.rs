@@ -0,0 +1,628 @@
+//! Buffered UART communication, where bytes are moved between the hardware
+//! FIFOs and RAM buffers so that nothing is dropped while the game loop is
+//! busy.
+//!
+//! The hardware can only interrupt when its send FIFO fills up, its receive
+//! FIFO empties, or an error happens; there's no interrupt for a byte
+//! arriving. So the 4 byte receive FIFO has to be polled, either by calling
+//! [BufferedUart::tick] (or any of the reading methods) often enough, or by
+//! handing a timer to [BufferedUart::poll_with_timer] to do it for you.
+//!
+//! # Basic Usage
+//!
+//! 1. Create a [Uart] and convert it with [Uart::enable_buffered_mode].
+//! 2. Either call [BufferedUart::poll_with_timer], or make sure
+//!    [BufferedUart::tick] is called at least once every 4 bytes' worth of
+//!    time.
+//! 3. Queue outgoing data with [BufferedUart::write] and pull incoming data
+//!    with [BufferedUart::read]; neither will block.
+//! 4. Call [BufferedUart::leave] to return to the unbuffered [Uart] handle.
+//!
+//! # Priority Sends
+//!
+//! Small, time-sensitive frames like ACKs and keepalives can be queued with
+//! [BufferedUart::write_priority], which puts them in a separate queue that is
+//! always drained before the regular one. This lets them jump ahead of any
+//! large payloads already waiting to go out, at the cost of possibly landing in
+//! the middle of one; the receiving side's protocol needs to be able to pull
+//! them back out, such as by making priority frames recognizable by their
+//! first byte.
+//!
+//! # Line Events
+//!
+//! Calling [BufferedUart::line_event] once per frame reports when the peer
+//! sends a break or stops sending altogether; see [super::line] for details.
+//!
+//! # Software Flow Control
+//!
+//! When the SC line can't be used for CTS-based flow control,
+//! [BufferedUart::set_xon_xoff] enables in-band XON/XOFF flow control instead:
+//! transmission pauses whenever the peer sends [XOFF] and resumes when it sends
+//! [XON], and the same characters are sent to the peer as our own receive
+//! buffer fills up and drains. Since these bytes are swallowed from the
+//! incoming stream this is only suitable for text, or for binary data that is
+//! guaranteed never to contain `0x11` or `0x13`.
+
+use agb::external::critical_section::{self, CriticalSection};
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+
+use crate::serial::ringbuf::Ringbuffer;
+use agb::timer::{Divider, Timer};
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::utils::{FrameTimeout, GbaCell, TimerTimeout, CPU_HZ};
+
+use super::line::{LineEvent, LineMonitor, DEFAULT_IDLE_FRAMES};
+use super::{ReadTimeoutError, Uart, UartError, UartSiocnt, LATCHED_ERROR, SIODATA8};
+
+/// Bytes that have been received but not yet read by the user.
+static RX_BUFFER: GbaCell<Ringbuffer<u8>> = GbaCell::new(Ringbuffer::empty());
+
+/// Bytes that have been queued by the user but not yet handed to the hardware.
+static TX_BUFFER: GbaCell<Ringbuffer<u8>> = GbaCell::new(Ringbuffer::empty());
+
+/// Time-sensitive bytes that should be sent before anything in [TX_BUFFER].
+static PRIORITY_TX_BUFFER: GbaCell<Ringbuffer<u8>> = GbaCell::new(Ringbuffer::empty());
+
+/// The size of [PRIORITY_TX_BUFFER]; priority frames are expected to be small.
+pub const PRIORITY_QUEUE_LEN: usize = 32;
+
+/// The state of the software flow control, if enabled.
+static SOFT_FLOW: GbaCell<SoftFlow> = GbaCell::new(SoftFlow::disabled());
+
+/// Tracks breaks & idle periods on the receive line.
+static LINE_MONITOR: GbaCell<LineMonitor> = GbaCell::new(LineMonitor::new(DEFAULT_IDLE_FRAMES));
+
+/// How many received bytes were thrown away because [RX_BUFFER] was full.
+static DROPPED: GbaCell<usize> = GbaCell::new(0);
+
+/// The control character asking the other side to resume sending, also known
+/// as DC1.
+pub const XON: u8 = 0x11;
+/// The control character asking the other side to stop sending, also known as
+/// DC3.
+pub const XOFF: u8 = 0x13;
+
+/// Tracks the XON/XOFF state in both directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct SoftFlow {
+    enabled: bool,
+    /// Whether the peer has asked us to stop sending.
+    paused: bool,
+    /// Whether we have asked the peer to stop sending.
+    throttled: bool,
+    /// A control character that needs to go out ahead of any queued data.
+    pending: Option<u8>,
+}
+
+impl SoftFlow {
+    const fn disabled() -> Self {
+        Self {
+            enabled: false,
+            paused: false,
+            throttled: false,
+            pending: None,
+        }
+    }
+
+    /// Processes a received byte, returning whether or not it should be passed
+    /// along to the user.
+    fn receive(&mut self, byte: u8) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        match byte {
+            XON => self.paused = false,
+            XOFF => self.paused = true,
+            _ => return true,
+        }
+        false
+    }
+
+    /// Asks the peer to pause once the receive buffer is 3/4 full, and to
+    /// resume once it has drained back down to 1/4 full.
+    ///
+    /// The remaining quarter gives the peer room to finish whatever it has
+    /// already handed off to its hardware before it sees the XOFF.
+    fn update_receive_level(&mut self, len: usize, cap: usize) {
+        if !self.enabled {
+            return;
+        }
+        if !self.throttled && len >= cap - cap / 4 {
+            self.throttled = true;
+            self.pending = Some(XOFF);
+        } else if self.throttled && len <= cap / 4 {
+            self.throttled = false;
+            self.pending = Some(XON);
+        }
+    }
+}
+
+/// How many bytes' worth of time [BufferedUart::poll_with_timer] waits between
+/// polls, leaving half of the hardware receive FIFO spare.
+const POLL_BYTES: u32 = 2;
+
+pub struct BufferedUart<'a> {
+    inner: Uart<'a>,
+    poller: Option<Poller>,
+}
+
+/// The timer polling the hardware FIFOs; see [BufferedUart::poll_with_timer].
+struct Poller {
+    timer: Timer,
+    _interrupt: InterruptHandler,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferedInitError {
+    AlreadyInitialized,
+}
+
+/// An error that can happen while waiting for a delimiter with
+/// [BufferedUart::read_until] or [BufferedUart::read_line].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadUntilError {
+    /// The delimiter did not arrive before the timeout expired. Contains the
+    /// number of bytes that were read into the buffer before giving up.
+    Timeout(usize),
+    /// The buffer filled up before the delimiter arrived.
+    BufferFull,
+    /// The hardware flagged an error. Contains the number of bytes that were
+    /// read into the buffer before the error.
+    Uart(UartError, usize),
+}
+
+impl<'a> BufferedUart<'a> {
+    pub fn new(mut inner: Uart<'a>, cap: usize) -> Result<Self, BufferedInitError> {
+        let nrx = Ringbuffer::new(cap);
+        let ntx = Ringbuffer::new(cap);
+        let npriority = Ringbuffer::new(PRIORITY_QUEUE_LEN);
+        RX_BUFFER
+            .swap_if(nrx, |old| old.is_placeholder())
+            .map_err(|_| BufferedInitError::AlreadyInitialized)?;
+        TX_BUFFER
+            .swap_if(ntx, |old| old.is_placeholder())
+            // Shouldn't be possible if the previous check passed, but still
+            .map_err(|_| BufferedInitError::AlreadyInitialized)?;
+        PRIORITY_TX_BUFFER
+            .swap_if(npriority, |old| old.is_placeholder())
+            .map_err(|_| BufferedInitError::AlreadyInitialized)?;
+        DROPPED.swap(0);
+
+        inner.buffer_interrupt = unsafe {
+            Some(add_interrupt_handler(
+                Interrupt::Serial,
+                buffered_uart_interrupt_callback,
+            ))
+        };
+        inner.enable_interrupt(true);
+
+        Ok(Self {
+            inner,
+            poller: None,
+        })
+    }
+
+    /// Pulls as many received bytes as are available (up to the length of
+    /// `buffer`) into `buffer`. Returns the number of bytes read.
+    ///
+    /// Returns an error without reading anything if the hardware has flagged an
+    /// error that hasn't been cleared with [Self::clear_errors]; the data
+    /// received before the error is kept and can be read after clearing it.
+    ///
+    /// Does NOT block.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, UartError> {
+        critical_section::with(|cs| {
+            // Grab anything that arrived since the last poll.
+            drain_receive_fifo(cs);
+            if let Some(err) = self.inner.error() {
+                return Err(err);
+            }
+            let read = RX_BUFFER.lock_in(cs, |rx| {
+                let read = rx.read_bulk(buffer, cs);
+                SOFT_FLOW.lock_mut_in(cs, |flow| {
+                    flow.update_receive_level(rx.len(cs), rx.capacity())
+                });
+                read
+            });
+            // Let the peer know if it can resume sending.
+            fill_send_fifo(cs);
+            Ok(read)
+        })
+    }
+
+    /// Reads as many bytes as are available into `buffer`, waiting up to
+    /// `frames` frames for at least 1 byte to arrive. Returns the number of
+    /// bytes read.
+    ///
+    /// `timer` is used to measure the timeout, and will be disabled again
+    /// before this returns.
+    pub fn read_timeout(
+        &mut self,
+        buffer: &mut [u8],
+        frames: u32,
+        timer: &mut Timer,
+    ) -> Result<usize, ReadTimeoutError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let mut timeout = TimerTimeout::new(timer, frames);
+        loop {
+            let read = self.read(buffer)?;
+            if read > 0 {
+                return Ok(read);
+            }
+            if timeout.expired() {
+                return Err(ReadTimeoutError::Timeout);
+            }
+        }
+    }
+
+    /// Reads bytes into `buffer` until `delim` is received, waiting for up to
+    /// `timeout_frames` frames (or forever, if `None`) for it to arrive.
+    ///
+    /// Returns the number of bytes read, including the delimiter. Bytes after
+    /// the delimiter are left in the receive buffer for the next read.
+    pub fn read_until(
+        &mut self,
+        delim: u8,
+        buffer: &mut [u8],
+        timeout_frames: Option<u32>,
+    ) -> Result<usize, ReadUntilError> {
+        let mut timeout = FrameTimeout::new(timeout_frames);
+        let mut read = 0;
+        loop {
+            while read < buffer.len() {
+                match self.read(&mut buffer[read..read + 1]) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(err) => return Err(ReadUntilError::Uart(err, read)),
+                }
+                read += 1;
+                if buffer[read - 1] == delim {
+                    return Ok(read);
+                }
+            }
+            if read >= buffer.len() {
+                return Err(ReadUntilError::BufferFull);
+            }
+            if !timeout.wait() {
+                return Err(ReadUntilError::Timeout(read));
+            }
+        }
+    }
+
+    /// Reads a single `\n`-terminated line into `buffer`; see
+    /// [Self::read_until].
+    pub fn read_line(
+        &mut self,
+        buffer: &mut [u8],
+        timeout_frames: Option<u32>,
+    ) -> Result<usize, ReadUntilError> {
+        self.read_until(b'\n', buffer, timeout_frames)
+    }
+
+    /// The error currently flagged by the hardware, if any.
+    pub fn error(&self) -> Option<UartError> {
+        self.inner.error()
+    }
+    /// Clears any errors reported by the hardware.
+    pub fn clear_errors(&mut self) {
+        self.inner.clear_errors()
+    }
+
+    /// Advances the line monitor by a frame, returning any break or change in
+    /// idle state that happened since the last call.
+    ///
+    /// Should be called exactly once per frame.
+    pub fn line_event(&mut self) -> Option<LineEvent> {
+        critical_section::with(|cs| {
+            drain_receive_fifo(cs);
+            LINE_MONITOR.lock_mut_in(cs, |line| line.tick())
+        })
+    }
+    /// Sets how many frames without data before [Self::line_event] reports
+    /// the line as idle.
+    pub fn set_idle_timeout(&mut self, frames: u32) {
+        LINE_MONITOR.lock_mut(|line| line.set_idle_after(frames))
+    }
+
+    /// Enables or disables XON/XOFF software flow control; see the
+    /// [module-level docs](self) for details.
+    ///
+    /// If we had asked the peer to pause, disabling flow control sends an XON
+    /// so that it isn't left waiting forever.
+    pub fn set_xon_xoff(&mut self, enabled: bool) {
+        critical_section::with(|cs| {
+            SOFT_FLOW.lock_mut_in(cs, |flow| {
+                if enabled {
+                    flow.enabled = true;
+                } else {
+                    let was_throttled = flow.throttled;
+                    *flow = SoftFlow::disabled();
+                    if was_throttled {
+                        flow.pending = Some(XON);
+                    }
+                }
+            });
+            fill_send_fifo(cs);
+        })
+    }
+    /// Whether or not XON/XOFF software flow control is enabled.
+    pub fn xon_xoff_enabled(&self) -> bool {
+        SOFT_FLOW.lock(|flow| flow.enabled)
+    }
+    /// Whether or not the peer has paused our transmission by sending an XOFF.
+    pub fn peer_paused(&self) -> bool {
+        SOFT_FLOW.lock(|flow| flow.paused)
+    }
+
+    /// Queues as many bytes from `buffer` as will fit into the send buffer.
+    /// Returns the number of bytes queued.
+    ///
+    /// Does NOT block.
+    pub fn write(&mut self, buffer: &[u8]) -> usize {
+        critical_section::with(|cs| {
+            let res = TX_BUFFER.lock_in(cs, |tx| tx.write_bulk(buffer, cs));
+            // The interrupt only fires on FIFO events, so we need to kick off
+            // the first few bytes ourselves.
+            fill_send_fifo(cs);
+            res
+        })
+    }
+
+    /// Queues `buffer` in the priority send queue, ahead of anything queued
+    /// with [Self::write]. Returns `false` without queueing anything if the
+    /// whole buffer doesn't fit, so that priority frames are never split up.
+    ///
+    /// Does NOT block.
+    pub fn write_priority(&mut self, buffer: &[u8]) -> bool {
+        critical_section::with(|cs| {
+            let fits = PRIORITY_TX_BUFFER.lock_in(cs, |priority| {
+                if priority.capacity() - priority.len(cs) < buffer.len() {
+                    return false;
+                }
+                priority.write_bulk(buffer, cs);
+                true
+            });
+            fill_send_fifo(cs);
+            fits
+        })
+    }
+
+    /// Moves anything waiting in the hardware receive FIFO into the receive
+    /// buffer, & as many queued bytes as will fit into the send FIFO. Does
+    /// NOT block.
+    ///
+    /// Unless [Self::poll_with_timer] is in use, this (or another reading
+    /// method) needs to be called at least once every 4 bytes' worth of time
+    /// for nothing to be dropped; see the [module-level docs](self).
+    pub fn tick(&mut self) {
+        critical_section::with(poll_fifos)
+    }
+    /// Polls the hardware FIFOs from `timer`'s interrupt, about once every 2
+    /// bytes' worth of time at the current baud rate, so that nothing is
+    /// dropped however long the game loop takes.
+    ///
+    /// Replaces (and disables) any previous polling timer. Call this again
+    /// after changing the baud rate.
+    pub fn poll_with_timer(&mut self, mut timer: Timer) {
+        self.stop_polling();
+        let ticks = poll_interval(self.inner.baud_rate().baud());
+        let interrupt = unsafe { add_interrupt_handler(timer.interrupt(), poll_fifos) };
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_divider(Divider::Divider1)
+            .set_overflow_amount(ticks)
+            .set_interrupt(true)
+            .set_enabled(true);
+        self.poller = Some(Poller {
+            timer,
+            _interrupt: interrupt,
+        });
+    }
+    /// Stops polling from a timer, handing back the timer that was in use (if
+    /// any).
+    pub fn stop_polling(&mut self) -> Option<Timer> {
+        let Poller { mut timer, .. } = self.poller.take()?;
+        timer.set_interrupt(false).set_enabled(false);
+        Some(timer)
+    }
+
+    /// Moves as many queued bytes as will currently fit into the hardware
+    /// send FIFO.
+    ///
+    /// The hardware doesn't interrupt when its send FIFO drains, so anything
+    /// waiting on [Self::pending] to drop needs to keep calling this.
+    pub fn fill_send_fifo(&mut self) {
+        critical_section::with(fill_send_fifo)
+    }
+
+    /// The number of received bytes waiting to be read.
+    pub fn available(&self) -> usize {
+        critical_section::with(|cs| {
+            drain_receive_fifo(cs);
+            RX_BUFFER.lock_in(cs, |rx| rx.len(cs))
+        })
+    }
+
+    /// The number of queued bytes, in either queue, that have not yet been
+    /// handed off to the hardware.
+    pub fn pending(&self) -> usize {
+        critical_section::with(|cs| {
+            TX_BUFFER.lock_in(cs, |tx| tx.len(cs)) + self.pending_priority_in(cs)
+        })
+    }
+    /// The number of bytes queued with [Self::write_priority] that have not
+    /// yet been handed off to the hardware.
+    pub fn pending_priority(&self) -> usize {
+        critical_section::with(|cs| self.pending_priority_in(cs))
+    }
+    fn pending_priority_in(&self, cs: CriticalSection<'_>) -> usize {
+        PRIORITY_TX_BUFFER.lock_in(cs, |priority| priority.len(cs))
+    }
+    /// How many received bytes have been thrown away because the receive
+    /// buffer was full.
+    pub fn dropped(&self) -> usize {
+        DROPPED.get_copy()
+    }
+
+    /// Exits buffered mode, returning to the unbuffered [Uart] handle.
+    ///
+    /// Any data still sitting in the buffers is discarded.
+    pub fn leave(self) -> Uart<'a> {
+        let mut this = ManuallyDrop::new(self);
+        this.end_session();
+        // #SAFETY
+        //
+        // `this` is never used or dropped again, so `inner` is only moved out
+        // once; `end_session` already took the poller, so nothing else needs
+        // dropping.
+        unsafe { ptr::read(&this.inner) }
+    }
+    /// Stops the interrupts & resets all of the buffered mode global state, so
+    /// that buffered mode can be entered again.
+    fn end_session(&mut self) {
+        self.stop_polling();
+        self.inner.enable_interrupt(false);
+        self.inner.buffer_interrupt = None;
+        critical_section::with(|cs| {
+            RX_BUFFER.swap_in(cs, Ringbuffer::empty());
+            TX_BUFFER.swap_in(cs, Ringbuffer::empty());
+            PRIORITY_TX_BUFFER.swap_in(cs, Ringbuffer::empty());
+            SOFT_FLOW.swap_in(cs, SoftFlow::disabled());
+            LINE_MONITOR.swap_in(cs, LineMonitor::default());
+        });
+    }
+}
+
+impl Drop for BufferedUart<'_> {
+    fn drop(&mut self) {
+        self.end_session();
+    }
+}
+
+/// Moves every byte currently waiting in the hardware receive FIFO into
+/// [RX_BUFFER].
+fn drain_receive_fifo(cs: CriticalSection<'_>) {
+    let siocnt = UartSiocnt::get();
+    RX_BUFFER.lock_in(cs, |rx| {
+        SOFT_FLOW.lock_mut_in(cs, |flow| {
+            let mut prev_error = LATCHED_ERROR.get_copy_in(cs);
+            while !siocnt.receive_empty() {
+                let byte = SIODATA8.read();
+                // Only blame this byte for the error if it's a new one.
+                let error = LATCHED_ERROR.get_copy_in(cs);
+                let new_error = if prev_error.is_none() { error } else { None };
+                prev_error = error;
+                LINE_MONITOR.lock_mut_in(cs, |line| line.record(byte, new_error));
+                if !flow.receive(byte) {
+                    continue;
+                }
+                if rx.push(byte, cs).is_err() {
+                    DROPPED.lock_mut_in(cs, |dropped| *dropped += 1);
+                }
+            }
+            flow.update_receive_level(rx.len(cs), rx.capacity());
+        });
+    });
+}
+
+/// Moves as many bytes from [PRIORITY_TX_BUFFER] and then [TX_BUFFER] into
+/// the hardware send FIFO as it will currently accept.
+fn fill_send_fifo(cs: CriticalSection<'_>) {
+    let siocnt = UartSiocnt::get();
+    TX_BUFFER.lock_in(cs, |tx| {
+        let paused = SOFT_FLOW.lock_mut_in(cs, |flow| {
+            if let Some(control) = flow.pending {
+                if siocnt.send_full() {
+                    // Can't send anything else until the control character
+                    // has gone out.
+                    return true;
+                }
+                SIODATA8.write(control);
+                flow.pending = None;
+            }
+            flow.paused
+        });
+        if paused {
+            return;
+        }
+        PRIORITY_TX_BUFFER.lock_in(cs, |priority| {
+            while !siocnt.send_full() {
+                let Some(next) = priority.pop(cs).or_else(|| tx.pop(cs)) else {
+                    break;
+                };
+                SIODATA8.write(next);
+            }
+        });
+    });
+}
+
+/// Moves bytes between both hardware FIFOs & their buffers.
+fn poll_fifos(cs: CriticalSection<'_>) {
+    drain_receive_fifo(cs);
+    fill_send_fifo(cs);
+}
+
+/// The number of timer ticks (at 1 per CPU cycle) between polls at `baud`
+/// bits per second, assuming the shortest 10 bit frames.
+const fn poll_interval(baud: u32) -> u16 {
+    let cycles = POLL_BYTES * 10 * (CPU_HZ / baud);
+    if cycles > u16::MAX as u32 {
+        u16::MAX
+    } else {
+        cycles as u16
+    }
+}
+
+/// The interrupt callback called whenever the UART hardware flags an error,
+/// a full send FIFO, or an empty receive FIFO.
+fn buffered_uart_interrupt_callback(cs: CriticalSection<'_>) {
+    poll_fifos(cs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_poll_interval(_gba: &mut Gba) {
+        assert_eq!(poll_interval(115200), 2900);
+        assert_eq!(poll_interval(9600), 34940);
+        // Never longer than the receive FIFO takes to fill.
+        for baud in [9600, 38400, 57600, 115200] {
+            assert!((poll_interval(baud) as u32) < 4 * 10 * CPU_HZ / baud);
+        }
+    }
+
+    #[test_case]
+    fn test_soft_flow(_gba: &mut Gba) {
+        let mut flow = SoftFlow::disabled();
+        assert!(flow.receive(XOFF));
+        flow.update_receive_level(16, 16);
+        assert_eq!(flow.pending, None);
+
+        flow.enabled = true;
+        assert!(!flow.receive(XOFF));
+        assert!(flow.paused);
+        assert!(flow.receive(b'a'));
+        assert!(!flow.receive(XON));
+        assert!(!flow.paused);
+
+        flow.update_receive_level(11, 16);
+        assert_eq!(flow.pending, None);
+        flow.update_receive_level(12, 16);
+        assert_eq!(flow.pending, Some(XOFF));
+        assert!(flow.throttled);
+        flow.pending = None;
+        flow.update_receive_level(5, 16);
+        assert_eq!(flow.pending, None);
+        flow.update_receive_level(4, 16);
+        assert_eq!(flow.pending, Some(XON));
+        assert!(!flow.throttled);
+    }
+}
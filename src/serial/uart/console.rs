@@ -0,0 +1,305 @@
+//! A remote command console, for poking at the game's state from a terminal on
+//! a PC at runtime.
+//!
+//! Commands are registered by name along with a callback; every line received
+//! over the UART is split on whitespace, with the first word picking the
+//! command and the rest being passed to the callback as [Args]. A built-in
+//! `help` command lists every registered command.
+//!
+//! # Examples
+//! ```
+//! let mut console = Console::new();
+//! console.register("hp", "hp <n>: sets the player's HP", |mut args, out| {
+//!     match args.parse::<u16>() {
+//!         Some(hp) => writeln!(out, "HP set to {}", hp),
+//!         None => writeln!(out, "usage: hp <n>"),
+//!     }
+//! });
+//! loop {
+//!     console.poll(&mut uart);
+//!     agb::display::busy_wait_for_vblank();
+//! }
+//! ```
+
+use core::fmt::{self, Write};
+use core::str::{FromStr, SplitWhitespace};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::buffered::BufferedUart;
+
+/// The default maximum length of a single command line, in bytes.
+pub const DEFAULT_MAX_LINE: usize = 128;
+
+/// The callback invoked when a command is received.
+pub type Handler<'c> = Box<dyn FnMut(Args<'_>, &mut dyn fmt::Write) -> fmt::Result + 'c>;
+
+/// An error that can happen while processing a command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// No command with the given name has been registered.
+    UnknownCommand,
+    /// The line was longer than the console's maximum line length.
+    LineTooLong,
+    /// The line was not valid UTF-8.
+    InvalidUtf8,
+    /// The command's output could not be written.
+    Output,
+}
+
+impl From<fmt::Error> for ConsoleError {
+    fn from(_value: fmt::Error) -> Self {
+        ConsoleError::Output
+    }
+}
+
+/// The whitespace-separated arguments following a command's name.
+pub struct Args<'l> {
+    inner: SplitWhitespace<'l>,
+}
+
+impl<'l> Args<'l> {
+    /// Parses the next argument, returning `None` if there isn't one or it
+    /// isn't a valid `T`.
+    pub fn parse<T: FromStr>(&mut self) -> Option<T> {
+        self.next()?.parse().ok()
+    }
+}
+
+impl<'l> Iterator for Args<'l> {
+    type Item = &'l str;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+struct Command<'c> {
+    name: &'static str,
+    help: &'static str,
+    handler: Handler<'c>,
+}
+
+/// Parses line-based commands and dispatches them to registered callbacks.
+pub struct Console<'c> {
+    commands: Vec<Command<'c>>,
+    line: Vec<u8>,
+    max_line: usize,
+    /// Whether the current line has already gone past `max_line`.
+    overflowed: bool,
+}
+
+impl Default for Console<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'c> Console<'c> {
+    pub fn new() -> Self {
+        Self::with_max_line(DEFAULT_MAX_LINE)
+    }
+    /// Creates a console that rejects lines longer than `max_line` bytes.
+    pub fn with_max_line(max_line: usize) -> Self {
+        Self {
+            commands: Vec::new(),
+            line: Vec::with_capacity(max_line),
+            max_line,
+            overflowed: false,
+        }
+    }
+
+    /// Registers a new command, replacing any existing command with the same
+    /// name. `help` is shown by the built-in `help` command.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        handler: impl FnMut(Args<'_>, &mut dyn fmt::Write) -> fmt::Result + 'c,
+    ) {
+        let command = Command {
+            name,
+            help,
+            handler: Box::new(handler),
+        };
+        match self.commands.iter_mut().find(|cmd| cmd.name == name) {
+            Some(existing) => *existing = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    /// Processes every byte that has arrived over `uart` since the last call,
+    /// running any complete commands and sending their output back over it.
+    ///
+    /// A UART error (eg a framing error from line noise) is reported back over
+    /// the UART & then cleared, dropping the partial line it corrupted.
+    ///
+    /// Does NOT block on input, but will block while sending output.
+    pub fn poll(&mut self, uart: &mut BufferedUart<'_>) {
+        let mut byte = [0];
+        loop {
+            match uart.read(&mut byte) {
+                Ok(1) => {}
+                Ok(_) => break,
+                Err(err) => {
+                    uart.clear_errors();
+                    self.line.clear();
+                    self.overflowed = false;
+                    let _ = writeln!(ConsoleOutput { uart }, "error: {:?}", err);
+                    continue;
+                }
+            }
+            let res = match byte[0] {
+                b'\r' | b'\n' => self.finish_line(&mut ConsoleOutput { uart }),
+                other => {
+                    if self.line.len() < self.max_line {
+                        self.line.push(other);
+                    } else {
+                        self.overflowed = true;
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(err) = res {
+                let _ = writeln!(ConsoleOutput { uart }, "error: {:?}", err);
+            }
+        }
+    }
+
+    /// Runs a single command line, writing its output to `out`.
+    pub fn dispatch(&mut self, line: &str, out: &mut dyn fmt::Write) -> Result<(), ConsoleError> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(());
+        };
+        let args = Args { inner: words };
+        if name == "help" {
+            for cmd in self.commands.iter() {
+                writeln!(out, "{:<12} {}", cmd.name, cmd.help)?;
+            }
+            return Ok(());
+        }
+        let cmd = self
+            .commands
+            .iter_mut()
+            .find(|cmd| cmd.name == name)
+            .ok_or(ConsoleError::UnknownCommand)?;
+        (cmd.handler)(args, out)?;
+        Ok(())
+    }
+
+    fn finish_line(&mut self, out: &mut dyn fmt::Write) -> Result<(), ConsoleError> {
+        let line = core::mem::take(&mut self.line);
+        let overflowed = core::mem::take(&mut self.overflowed);
+        let res = if overflowed {
+            Err(ConsoleError::LineTooLong)
+        } else {
+            match core::str::from_utf8(&line) {
+                Ok(text) => self.dispatch(text, out),
+                Err(_) => Err(ConsoleError::InvalidUtf8),
+            }
+        };
+        // Hold on to the allocation for the next line.
+        self.line = line;
+        self.line.clear();
+        res
+    }
+}
+
+/// Sends command output over a [BufferedUart], blocking until it has all been
+/// queued and translating `\n` into the `\r\n` that terminals expect.
+struct ConsoleOutput<'u, 'a> {
+    uart: &'u mut BufferedUart<'a>,
+}
+
+impl ConsoleOutput<'_, '_> {
+    fn send(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let written = self.uart.write(data);
+            data = &data[written..];
+        }
+    }
+}
+
+impl fmt::Write for ConsoleOutput<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (idx, chunk) in s.split('\n').enumerate() {
+            if idx > 0 {
+                self.send(b"\r\n");
+            }
+            self.send(chunk.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::string::String;
+
+    #[test_case]
+    fn test_console_dispatch(_gba: &mut Gba) {
+        let mut total = 0;
+        {
+            let mut console = Console::new();
+            console.register("add", "add <n>...", |args, out| {
+                let sum = args.filter_map(|arg| arg.parse::<u32>().ok()).sum::<u32>();
+                total += sum;
+                write!(out, "{}", sum)
+            });
+            console.register("echo", "echo <words>...", |args, out| {
+                for arg in args {
+                    write!(out, "{} ", arg)?;
+                }
+                Ok(())
+            });
+
+            let mut out = String::new();
+            assert_eq!(console.dispatch("  add 1 2   3 ", &mut out), Ok(()));
+            assert_eq!(out, "6");
+
+            out.clear();
+            assert_eq!(console.dispatch("echo a b", &mut out), Ok(()));
+            assert_eq!(out, "a b ");
+
+            out.clear();
+            assert_eq!(console.dispatch("", &mut out), Ok(()));
+            assert_eq!(
+                console.dispatch("nope", &mut out),
+                Err(ConsoleError::UnknownCommand)
+            );
+            assert_eq!(out, "");
+
+            assert_eq!(console.dispatch("help", &mut out), Ok(()));
+            assert!(out.contains("add <n>..."));
+            assert!(out.contains("echo <words>..."));
+        }
+        assert_eq!(total, 6);
+    }
+
+    #[test_case]
+    fn test_console_lines(_gba: &mut Gba) {
+        let mut console = Console::with_max_line(4);
+        console.register("ok", "", |_, out| write!(out, "ok"));
+        let mut out = String::new();
+
+        console.line.extend_from_slice(b"ok");
+        assert_eq!(console.finish_line(&mut out), Ok(()));
+        assert_eq!(out, "ok");
+
+        console.line.extend_from_slice(b"\xFF");
+        assert_eq!(
+            console.finish_line(&mut out),
+            Err(ConsoleError::InvalidUtf8)
+        );
+
+        console.overflowed = true;
+        assert_eq!(
+            console.finish_line(&mut out),
+            Err(ConsoleError::LineTooLong)
+        );
+        assert!(console.line.is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+//! Tools for figuring out why a UART link is producing garbage, most commonly
+//! because the two sides disagree on the baud rate.
+//!
+//! A baud rate mismatch shows up as a steady stream of framing (and, if
+//! enabled, parity) errors, since the stop bit is sampled at the wrong time.
+//! [BaudMonitor] tracks these errors as bytes arrive, while
+//! [Uart::diagnose_baud] and [Uart::probe_baud_rate] listen to the line
+//! directly.
+//!
+//! Note that both of these need the peer to be actively sending while they
+//! listen; a silent line looks the same at every baud rate.
+
+use agb::timer::Timer;
+
+use super::{BaudRate, Uart, UartError};
+use crate::utils::TimerTimeout;
+
+/// How many framing or parity errors in a row are taken as a sign of a baud
+/// rate mismatch, rather than just line noise.
+const CONSECUTIVE_ERROR_THRESHOLD: u32 = 4;
+
+/// The minimum number of bytes to see before judging the overall error rate.
+const MIN_SAMPLES: u32 = 16;
+
+/// Tracks received bytes & errors to detect a probable baud rate mismatch.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BaudMonitor {
+    /// How many bytes arrived without error.
+    pub clean: u32,
+    /// How many bytes were flagged with a framing or parity error.
+    pub frame_errors: u32,
+    /// How many times the receive FIFO overflowed; these don't indicate a baud
+    /// rate mismatch.
+    pub overruns: u32,
+    consecutive_errors: u32,
+    consecutive_clean: u32,
+}
+
+impl BaudMonitor {
+    pub const fn new() -> Self {
+        Self {
+            clean: 0,
+            frame_errors: 0,
+            overruns: 0,
+            consecutive_errors: 0,
+            consecutive_clean: 0,
+        }
+    }
+    /// Records the result of receiving a single byte.
+    pub fn record(&mut self, result: Result<u8, UartError>) {
+        match result {
+            Ok(_) => {
+                self.clean += 1;
+                self.consecutive_clean += 1;
+                self.consecutive_errors = 0;
+            }
+            Err(UartError::Overrun) => {
+                self.overruns += 1;
+            }
+            Err(UartError::Framing | UartError::Parity) => {
+                self.frame_errors += 1;
+                self.consecutive_errors += 1;
+                self.consecutive_clean = 0;
+            }
+        }
+    }
+    /// How many bytes in a row have arrived without error.
+    pub const fn consecutive_clean(&self) -> u32 {
+        self.consecutive_clean
+    }
+    /// Whether or not the errors seen so far point to the two sides using
+    /// different baud rates: either several bad frames in a row, or at least a
+    /// quarter of all frames being bad.
+    pub const fn probable_mismatch(&self) -> bool {
+        let total = self.clean + self.frame_errors;
+        self.consecutive_errors >= CONSECUTIVE_ERROR_THRESHOLD
+            || (total >= MIN_SAMPLES && self.frame_errors * 4 >= total)
+    }
+    /// Forgets everything recorded so far.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// The results of a [Uart::diagnose_baud].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BaudDiagnosis {
+    /// The baud rate that was listened to.
+    pub rate: BaudRate,
+    /// The tallies of everything that was received.
+    pub monitor: BaudMonitor,
+}
+
+impl BaudDiagnosis {
+    /// Whether or not the peer appears to be using a different baud rate.
+    pub const fn probable_mismatch(&self) -> bool {
+        self.monitor.probable_mismatch()
+    }
+    /// Whether or not anything was received at all.
+    pub const fn heard_anything(&self) -> bool {
+        self.monitor.clean + self.monitor.frame_errors + self.monitor.overruns > 0
+    }
+}
+
+impl Uart<'_> {
+    /// Listens to the line for up to `frames` frames (or until `samples` bytes
+    /// have arrived) at the current baud rate and reports how many of the
+    /// received bytes were malformed.
+    ///
+    /// Everything received is discarded, and any errors are cleared
+    /// afterwards. `timer` is used to measure the timeout, and will be
+    /// disabled again before this returns.
+    pub fn diagnose_baud(&mut self, samples: u32, frames: u32, timer: &mut Timer) -> BaudDiagnosis {
+        let mut monitor = BaudMonitor::new();
+        self.listen(&mut monitor, frames, timer, |monitor| {
+            monitor.clean + monitor.frame_errors >= samples
+        });
+        BaudDiagnosis {
+            rate: self.baud_rate(),
+            monitor,
+        }
+    }
+
+    /// Cycles through each supported baud rate, listening for up to
+    /// `frames_per_rate` frames at each one, until `clean_needed` bytes in a
+    /// row arrive without error.
+    ///
+    /// On success the UART is left at the detected rate, which is returned.
+    /// Otherwise the original baud rate is restored and `None` is returned.
+    ///
+    /// Since bytes sent at a lower rate can occasionally look valid at a higher
+    /// one, set `clean_needed` high enough to rule out flukes; 8 or more is a
+    /// reasonable starting point. `timer` is used to measure the timeout, and
+    /// will be disabled again before this returns.
+    pub fn probe_baud_rate(
+        &mut self,
+        clean_needed: u32,
+        frames_per_rate: u32,
+        timer: &mut Timer,
+    ) -> Option<BaudRate> {
+        use BaudRate::*;
+        let original = self.baud_rate();
+        for rate in [B9600, B38400, B57600, B115200] {
+            self.set_baud_rate(rate);
+            let mut monitor = BaudMonitor::new();
+            self.listen(&mut monitor, frames_per_rate, timer, |monitor| {
+                monitor.consecutive_clean() >= clean_needed || monitor.probable_mismatch()
+            });
+            if monitor.consecutive_clean() >= clean_needed {
+                return Some(rate);
+            }
+        }
+        self.set_baud_rate(original);
+        None
+    }
+
+    /// Feeds every received byte into `monitor` until either `done` returns
+    /// `true` or `frames` frames have passed.
+    fn listen(
+        &mut self,
+        monitor: &mut BaudMonitor,
+        frames: u32,
+        timer: &mut Timer,
+        mut done: impl FnMut(&BaudMonitor) -> bool,
+    ) {
+        // Start from a clean slate so that leftovers from the previous rate
+        // don't count against this one.
+        self.clear_errors();
+        while self.has_data() {
+            let _ = self.recv_byte();
+        }
+        self.clear_errors();
+
+        let mut timeout = TimerTimeout::new(timer, frames);
+        while !done(monitor) && !timeout.expired() {
+            let has_data = self.has_data();
+            if let Some(err) = self.error() {
+                monitor.record(Err(err));
+                // Skip the bad byte so it isn't counted twice.
+                self.clear_errors();
+                if self.has_data() {
+                    let _ = self.recv_byte();
+                }
+                self.clear_errors();
+                continue;
+            }
+            if has_data {
+                monitor.record(self.recv_byte());
+            }
+        }
+        self.clear_errors();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_baud_monitor(_gba: &mut Gba) {
+        let mut monitor = BaudMonitor::new();
+        for _ in 0..3 {
+            monitor.record(Err(UartError::Framing));
+        }
+        assert!(!monitor.probable_mismatch());
+        monitor.record(Err(UartError::Parity));
+        assert!(monitor.probable_mismatch());
+
+        monitor.reset();
+        for n in 0..20 {
+            if n % 3 == 0 {
+                monitor.record(Err(UartError::Framing));
+            } else {
+                monitor.record(Ok(n));
+            }
+        }
+        assert_eq!(monitor.consecutive_clean(), 2);
+        assert!(monitor.probable_mismatch());
+
+        monitor.reset();
+        for n in 0..20 {
+            monitor.record(Ok(n));
+            monitor.record(Err(UartError::Overrun));
+        }
+        assert_eq!(monitor.overruns, 20);
+        assert_eq!(monitor.consecutive_clean(), 20);
+        assert!(!monitor.probable_mismatch());
+    }
+}
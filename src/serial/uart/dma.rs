@@ -0,0 +1,162 @@
+//! Background transmission of large buffers (eg log dumps) without tying up
+//! the main loop.
+//!
+//! The GBA's DMA units can't be triggered by the serial port, so instead a
+//! dedicated timer interrupt tops up the send FIFO at roughly the rate the
+//! hardware drains it. This keeps the CPU cost to a handful of cycles every
+//! couple of bytes, which is about as close to a "real" DMA transfer as the
+//! hardware allows.
+
+use core::marker::PhantomData;
+use core::ptr;
+
+use agb::external::critical_section::CriticalSection;
+use agb::interrupt::{add_interrupt_handler, InterruptHandler};
+use agb::timer::{Divider, Timer};
+
+use crate::utils::{GbaCell, CPU_HZ};
+
+use super::{Parity, Uart, UartSiocnt, SIODATA8};
+
+/// The in-progress background transfer.
+static BACKGROUND_TX: GbaCell<TxState> = GbaCell::new(TxState::empty());
+
+/// The buffer being sent by [Uart::write_dma], and how far into it we are.
+struct TxState {
+    data: *const u8,
+    len: usize,
+    sent: usize,
+}
+
+/// #SAFETY
+///
+/// The pointer is only ever dereferenced by the timer interrupt while the
+/// [TxHandle] that borrows the underlying data is alive.
+unsafe impl Send for TxState {}
+
+impl TxState {
+    const fn empty() -> Self {
+        Self {
+            data: ptr::null(),
+            len: 0,
+            sent: 0,
+        }
+    }
+    const fn remaining(&self) -> usize {
+        self.len - self.sent
+    }
+}
+
+impl Default for TxState {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// A handle to a transfer started with [Uart::write_dma].
+///
+/// Dropping the handle stops the transfer, even if not all bytes have been
+/// sent.
+pub struct TxHandle<'t> {
+    _data: PhantomData<&'t [u8]>,
+    _uart: PhantomData<&'t mut Uart<'t>>,
+    timer: &'t mut Timer,
+    _interrupt: InterruptHandler,
+}
+
+impl TxHandle<'_> {
+    /// How many bytes have yet to be handed off to the hardware.
+    pub fn remaining(&self) -> usize {
+        BACKGROUND_TX.lock(|state| state.remaining())
+    }
+    /// Whether or not every byte has been handed off to the hardware.
+    ///
+    /// Note that the last few bytes may still be sitting in the send FIFO.
+    pub fn is_done(&self) -> bool {
+        self.remaining() == 0
+    }
+    /// Blocks until every byte has been handed off to the hardware.
+    pub fn wait(self) {
+        while !self.is_done() {}
+    }
+}
+
+impl Drop for TxHandle<'_> {
+    fn drop(&mut self) {
+        self.timer.set_interrupt(false).set_enabled(false);
+        BACKGROUND_TX.swap(TxState::empty());
+    }
+}
+
+impl<'a> Uart<'a> {
+    /// Starts sending `data` in the background, returning a handle that can be
+    /// polled for completion.
+    ///
+    /// Despite the name no DMA channel is used, since the serial port can't
+    /// trigger DMA transfers; instead `timer` is used to fire an interrupt
+    /// that refills the send FIFO every couple of bytes.
+    ///
+    /// # Safety
+    /// The returned [TxHandle] **must not** be leaked (eg via
+    /// [core::mem::forget]), since the interrupt would then continue reading
+    /// `data` after it is no longer borrowed.
+    pub unsafe fn write_dma<'t>(
+        &'t mut self,
+        data: &'t [u8],
+        timer: &'t mut Timer,
+    ) -> TxHandle<'t> {
+        BACKGROUND_TX.swap(TxState {
+            data: data.as_ptr(),
+            len: data.len(),
+            sent: 0,
+        });
+
+        // Fire roughly once every 2 bytes, assuming the longest possible frame
+        // of a start bit, 8 data bits, a parity bit, and a stop bit. That way
+        // the 4-byte FIFO never runs dry but we don't waste time servicing
+        // interrupts while it's full.
+        let bits_per_byte = if self.parity() == Parity::None {
+            10
+        } else {
+            11
+        };
+        let cycles_per_byte = CPU_HZ * bits_per_byte / self.baud_rate().baud();
+        let period = (2 * cycles_per_byte).min(u16::MAX as u32) as u16;
+
+        let interrupt = add_interrupt_handler(timer.interrupt(), dma_tx_interrupt_callback);
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_divider(Divider::Divider1)
+            .set_overflow_amount(period)
+            .set_interrupt(true)
+            .set_enabled(true);
+
+        // Don't wait for the first interrupt to get started.
+        agb::external::critical_section::with(dma_tx_interrupt_callback);
+
+        TxHandle {
+            _data: PhantomData,
+            _uart: PhantomData,
+            timer,
+            _interrupt: interrupt,
+        }
+    }
+}
+
+/// The timer interrupt callback that moves the next few bytes of the
+/// background transfer into the send FIFO.
+fn dma_tx_interrupt_callback(cs: CriticalSection<'_>) {
+    let siocnt = UartSiocnt::get();
+    BACKGROUND_TX.lock_mut_in(cs, |state| {
+        while state.remaining() > 0 && !siocnt.send_full() {
+            // #SAFETY
+            //
+            // `state.sent < state.len`, and the data is kept alive by the
+            // `TxHandle` that owns this interrupt.
+            let byte = unsafe { state.data.add(state.sent).read() };
+            SIODATA8.write(byte);
+            state.sent += 1;
+        }
+    });
+}
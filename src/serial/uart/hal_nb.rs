@@ -0,0 +1,83 @@
+//! [embedded_hal_nb] serial trait implementations, for drivers that expect
+//! `nb`-style non-blocking serial ports.
+
+use embedded_hal_nb::nb;
+use embedded_hal_nb::serial::{ErrorKind, ErrorType, Read, Write};
+
+use super::{buffered::BufferedUart, Uart, UartError};
+
+impl embedded_hal_nb::serial::Error for UartError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            UartError::Parity => ErrorKind::Parity,
+            UartError::Framing => ErrorKind::FrameFormat,
+            UartError::Overrun => ErrorKind::Overrun,
+        }
+    }
+}
+
+impl ErrorType for Uart<'_> {
+    type Error = UartError;
+}
+
+impl Read for Uart<'_> {
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let has_data = self.has_data();
+        if let Some(err) = self.error() {
+            return Err(nb::Error::Other(err));
+        }
+        if !has_data {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.recv_byte().map_err(nb::Error::Other)
+    }
+}
+
+impl Write for Uart<'_> {
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        if !self.can_send() {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.send_byte(word);
+        Ok(())
+    }
+    /// The hardware doesn't report when the send FIFO has fully drained, so
+    /// this only guarantees that every byte has been handed off to it.
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ErrorType for BufferedUart<'_> {
+    type Error = UartError;
+}
+
+impl Read for BufferedUart<'_> {
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut byte = [0];
+        match BufferedUart::read(self, &mut byte) {
+            Ok(0) => Err(nb::Error::WouldBlock),
+            Ok(_) => Ok(byte[0]),
+            Err(err) => Err(nb::Error::Other(err)),
+        }
+    }
+}
+
+impl Write for BufferedUart<'_> {
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        match BufferedUart::write(self, &[word]) {
+            0 => Err(nb::Error::WouldBlock),
+            _ => Ok(()),
+        }
+    }
+    /// Waits for the send buffers to be handed off to the hardware; see
+    /// [Uart]'s implementation for caveats.
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        // Nothing else refills the send FIFO as it drains.
+        self.fill_send_fifo();
+        if self.pending() > 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
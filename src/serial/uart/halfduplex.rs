@@ -0,0 +1,186 @@
+//! Turn-taking for GBA-to-GBA links where both sides share a single UART
+//! session and only one should be transmitting at a time.
+//!
+//! Whoever holds the "token" is allowed to send; once it is done it passes the
+//! token to the other side by sending a [TOKEN] byte. Any [TOKEN] or [ESCAPE]
+//! bytes in the data itself are escaped so they can't be mistaken for a
+//! handoff.
+//!
+//! # Basic Usage
+//!
+//! 1. Create a [BufferedUart] on both sides and wrap it with
+//!    [HalfDuplexUart::new], with exactly one side starting with the token.
+//! 2. On the side with the token, [HalfDuplexUart::send] data and then call
+//!    [HalfDuplexUart::pass_token].
+//! 3. On the other side, call [HalfDuplexUart::read] until
+//!    [HalfDuplexUart::has_token] returns `true`, then repeat from step 2.
+
+use super::buffered::BufferedUart;
+use super::UartError;
+
+/// The byte that hands the turn to the other side; ASCII "End of
+/// Transmission".
+pub const TOKEN: u8 = 0x04;
+/// The byte that marks the next byte as escaped; ASCII "Data Link Escape".
+pub const ESCAPE: u8 = 0x10;
+/// Escaped bytes are XORed with this value, so that they are neither [TOKEN]
+/// nor [ESCAPE] on the wire.
+const ESCAPE_XOR: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HalfDuplexError {
+    /// Tried to send without holding the token.
+    NotOurTurn,
+    Uart(UartError),
+}
+
+impl From<UartError> for HalfDuplexError {
+    fn from(value: UartError) -> Self {
+        HalfDuplexError::Uart(value)
+    }
+}
+
+/// A single decoded unit of the incoming stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoded {
+    Data(u8),
+    Token,
+}
+
+/// Wraps a [BufferedUart] so that the two sides take turns transmitting.
+pub struct HalfDuplexUart<'a> {
+    uart: BufferedUart<'a>,
+    has_token: bool,
+    /// Whether the last byte received was an [ESCAPE].
+    escaped: bool,
+}
+
+impl<'a> HalfDuplexUart<'a> {
+    /// Wraps `uart`, with `has_token` deciding whether we get the first turn.
+    ///
+    /// Exactly one side of the link should start with the token.
+    pub fn new(uart: BufferedUart<'a>, has_token: bool) -> Self {
+        Self {
+            uart,
+            has_token,
+            escaped: false,
+        }
+    }
+    /// Whether or not it is currently our turn to send.
+    pub fn has_token(&self) -> bool {
+        self.has_token
+    }
+
+    /// Queues `data` to be sent, blocking until all of it fits into the send
+    /// buffer.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), HalfDuplexError> {
+        if !self.has_token {
+            return Err(HalfDuplexError::NotOurTurn);
+        }
+        for &byte in data {
+            match escape(byte) {
+                Some(escaped) => self.send_raw(&[ESCAPE, escaped]),
+                None => self.send_raw(&[byte]),
+            }
+        }
+        Ok(())
+    }
+    /// Hands the turn over to the other side.
+    pub fn pass_token(&mut self) -> Result<(), HalfDuplexError> {
+        if !self.has_token {
+            return Err(HalfDuplexError::NotOurTurn);
+        }
+        self.send_raw(&[TOKEN]);
+        self.has_token = false;
+        Ok(())
+    }
+    /// Takes the token back without waiting for the other side to pass it.
+    ///
+    /// Only meant for recovering from a lost token, such as after the peer
+    /// reset or a [TOKEN] byte was corrupted; calling this while the peer
+    /// still thinks it's their turn will lead to collisions.
+    pub fn reclaim_token(&mut self) {
+        self.has_token = true;
+        self.escaped = false;
+    }
+
+    /// Reads as much received data as is available into `buffer`, stopping
+    /// early if the peer passes us the token. Returns the number of bytes
+    /// read.
+    ///
+    /// Does NOT block.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, UartError> {
+        let mut read = 0;
+        while read < buffer.len() && !self.has_token {
+            let mut raw = [0];
+            if self.uart.read(&mut raw)? == 0 {
+                break;
+            }
+            match self.decode(raw[0]) {
+                Some(Decoded::Data(byte)) => {
+                    buffer[read] = byte;
+                    read += 1;
+                }
+                Some(Decoded::Token) => self.has_token = true,
+                None => {}
+            }
+        }
+        Ok(read)
+    }
+
+    /// Returns the underlying [BufferedUart].
+    pub fn into_inner(self) -> BufferedUart<'a> {
+        self.uart
+    }
+
+    fn send_raw(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let written = self.uart.write(data);
+            data = &data[written..];
+        }
+    }
+
+    fn decode(&mut self, byte: u8) -> Option<Decoded> {
+        if self.escaped {
+            self.escaped = false;
+            return Some(Decoded::Data(byte ^ ESCAPE_XOR));
+        }
+        match byte {
+            ESCAPE => {
+                self.escaped = true;
+                None
+            }
+            TOKEN => Some(Decoded::Token),
+            other => Some(Decoded::Data(other)),
+        }
+    }
+}
+
+/// Returns the escaped form of `byte` if it needs to be escaped.
+const fn escape(byte: u8) -> Option<u8> {
+    match byte {
+        TOKEN | ESCAPE => Some(byte ^ ESCAPE_XOR),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_escape_roundtrip(_gba: &mut Gba) {
+        for byte in 0..=u8::MAX {
+            match escape(byte) {
+                Some(escaped) => {
+                    assert!(byte == TOKEN || byte == ESCAPE);
+                    assert_ne!(escaped, TOKEN);
+                    assert_ne!(escaped, ESCAPE);
+                    assert_eq!(escaped ^ ESCAPE_XOR, byte);
+                }
+                None => assert!(byte != TOKEN && byte != ESCAPE),
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! [embedded_io] trait implementations, allowing existing `no_std` libraries
+//! to run directly over the link cable.
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+
+use super::{buffered::BufferedUart, Uart, UartError};
+use crate::utils::VBlankTimeout;
+
+impl embedded_io::Error for UartError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            UartError::Parity | UartError::Framing => ErrorKind::InvalidData,
+            UartError::Overrun => ErrorKind::Other,
+        }
+    }
+}
+
+impl ErrorType for Uart<'_> {
+    type Error = UartError;
+}
+
+impl Read for Uart<'_> {
+    /// Blocks until at least 1 byte is received, then reads as many more as
+    /// are already waiting in the receive FIFO.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let Some((first, rest)) = buf.split_first_mut() else {
+            return Ok(0);
+        };
+        *first = self.recv_byte()?;
+        let mut retvl = 1;
+        for slot in rest {
+            if !self.has_data() {
+                break;
+            }
+            match self.recv_byte() {
+                Ok(byte) => *slot = byte,
+                // Report what we got so far; the error is sticky so the next
+                // read will see it.
+                Err(_) => break,
+            }
+            retvl += 1;
+        }
+        Ok(retvl)
+    }
+}
+
+impl ReadReady for Uart<'_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.has_data())
+    }
+}
+
+impl Write for Uart<'_> {
+    /// Blocks until at least 1 byte is sent, then sends as many more as will
+    /// fit in the send FIFO.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let Some((first, rest)) = buf.split_first() else {
+            return Ok(0);
+        };
+        self.send_byte(*first);
+        let mut retvl = 1;
+        for byte in rest {
+            if !self.can_send() {
+                break;
+            }
+            self.send_byte(*byte);
+            retvl += 1;
+        }
+        Ok(retvl)
+    }
+    /// The hardware doesn't report when the final byte has left the FIFO, so
+    /// this is a no-op.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl WriteReady for Uart<'_> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.can_send())
+    }
+}
+
+impl ErrorType for BufferedUart<'_> {
+    type Error = UartError;
+}
+
+impl Read for BufferedUart<'_> {
+    /// Blocks until at least 1 byte is available in the receive buffer.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let read = BufferedUart::read(self, buf)?;
+            if read > 0 {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+impl ReadReady for BufferedUart<'_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.available() > 0)
+    }
+}
+
+impl Write for BufferedUart<'_> {
+    /// Blocks until at least 1 byte fits in the send buffer.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let written = BufferedUart::write(self, buf);
+            if written > 0 {
+                return Ok(written);
+            }
+        }
+    }
+    /// Blocks until every queued byte has been sent.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        while self.pending() > 0 {
+            self.fill_send_fifo();
+        }
+        // The hardware can't report when its send FIFO is empty, but even at
+        // the slowest baud rate the 4 bytes it holds are gone within a frame;
+        // waiting for the 2nd VBlank to start guarantees a whole frame passes.
+        let mut timeout = VBlankTimeout::new(Some(2));
+        while !timeout.expired() {}
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! Detection of line breaks & prolonged idle periods, so that higher-level
+//! protocols can tell when the peer has rebooted or the cable was unplugged
+//! mid-stream.
+//!
+//! The hardware has no dedicated break detection, so a break is inferred from
+//! its side effect: holding the line low for longer than a full frame produces
+//! a `0x00` byte with a bad stop bit. Idle detection is simply a count of
+//! frames without any received data.
+
+use super::UartError;
+
+/// The default number of frames without data before the line is considered
+/// idle; roughly 1 second.
+pub const DEFAULT_IDLE_FRAMES: u32 = 60;
+
+/// A change in the state of the receive line.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LineEvent {
+    /// The peer held the line low for longer than a full frame, which usually
+    /// means it reset or the cable was pulled mid-byte.
+    Break,
+    /// No data has arrived for the configured number of frames.
+    Idle,
+    /// Data started arriving again after a [LineEvent::Break] or
+    /// [LineEvent::Idle].
+    Active,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum LineState {
+    Active,
+    Idle,
+    Break,
+}
+
+/// Turns per-byte & per-frame observations into [LineEvent]s.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct LineMonitor {
+    idle_after: u32,
+    idle_frames: u32,
+    state: LineState,
+    saw_data: bool,
+    saw_break: bool,
+}
+
+impl Default for LineMonitor {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDLE_FRAMES)
+    }
+}
+
+impl LineMonitor {
+    /// Creates a new monitor that reports the line as idle after `idle_after`
+    /// frames without data.
+    pub const fn new(idle_after: u32) -> Self {
+        Self {
+            idle_after,
+            idle_frames: 0,
+            // Treat the line as idle until we hear something, so that the
+            // first data is reported as `Active`.
+            state: LineState::Idle,
+            saw_data: false,
+            saw_break: false,
+        }
+    }
+    /// The number of frames without data before the line is considered idle.
+    pub const fn idle_after(&self) -> u32 {
+        self.idle_after
+    }
+    pub fn set_idle_after(&mut self, frames: u32) {
+        self.idle_after = frames;
+    }
+    /// Records a received byte along with the error the hardware flagged for
+    /// it, if any.
+    pub fn record(&mut self, byte: u8, error: Option<UartError>) {
+        match error {
+            Some(UartError::Framing | UartError::Parity) if byte == 0 => self.saw_break = true,
+            _ => self.saw_data = true,
+        }
+    }
+    /// Advances the monitor by a single frame, returning the event that
+    /// happened during it, if any.
+    ///
+    /// Should be called exactly once per frame.
+    pub fn tick(&mut self) -> Option<LineEvent> {
+        let saw_data = core::mem::take(&mut self.saw_data);
+        let saw_break = core::mem::take(&mut self.saw_break);
+        if saw_break {
+            self.idle_frames = 0;
+            self.state = LineState::Break;
+            return Some(LineEvent::Break);
+        }
+        if saw_data {
+            self.idle_frames = 0;
+            if self.state != LineState::Active {
+                self.state = LineState::Active;
+                return Some(LineEvent::Active);
+            }
+            return None;
+        }
+        self.idle_frames = self.idle_frames.saturating_add(1);
+        if self.state == LineState::Active && self.idle_frames >= self.idle_after {
+            self.state = LineState::Idle;
+            return Some(LineEvent::Idle);
+        }
+        None
+    }
+    /// Whether or not data is currently arriving.
+    pub const fn is_active(&self) -> bool {
+        matches!(self.state, LineState::Active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_line_monitor(_gba: &mut Gba) {
+        let mut monitor = LineMonitor::new(3);
+        assert_eq!(monitor.tick(), None);
+        monitor.record(b'a', None);
+        assert_eq!(monitor.tick(), Some(LineEvent::Active));
+        monitor.record(b'b', None);
+        assert_eq!(monitor.tick(), None);
+        assert!(monitor.is_active());
+
+        assert_eq!(monitor.tick(), None);
+        assert_eq!(monitor.tick(), None);
+        assert_eq!(monitor.tick(), Some(LineEvent::Idle));
+        assert_eq!(monitor.tick(), None);
+
+        monitor.record(b'c', None);
+        assert_eq!(monitor.tick(), Some(LineEvent::Active));
+        monitor.record(0x00, Some(UartError::Framing));
+        assert_eq!(monitor.tick(), Some(LineEvent::Break));
+        assert!(!monitor.is_active());
+        // A garbled non-zero byte is just noise, not a break.
+        monitor.record(0x7F, Some(UartError::Framing));
+        assert_eq!(monitor.tick(), Some(LineEvent::Active));
+    }
+}
@@ -0,0 +1,123 @@
+//! A self-test for validating cables, flashcarts, and adapters using a
+//! loopback plug.
+//!
+//! The GBA has no way to internally connect its UART's output to its input, so
+//! this test requires a plug (or a bit of wire) connecting the SO pin back to
+//! the SI pin. If [FlowControl::Cts](super::FlowControl::Cts) is enabled, SD
+//! also needs to be connected back to SC.
+
+use super::{DataBits, Uart, UartError};
+
+/// The bytes sent during [Uart::loopback_test]; chosen to exercise every bit
+/// both on its own and alongside its neighbours.
+const TEST_PATTERN: [u8; 14] = [
+    0x55, 0xAA, 0x00, 0xFF, 0x0F, 0xF0, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80,
+];
+
+/// How many times to poll the hardware for each byte before giving up on it,
+/// both while waiting for room to send it & for it to come back; a generous
+/// upper bound on how long a byte takes to arrive at 9600 baud.
+const POLL_LIMIT: u32 = 0x4_0000;
+
+/// The results of a [Uart::loopback_test].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct LoopbackReport {
+    /// How many bytes were sent.
+    pub sent: usize,
+    /// How many bytes came back, whether or not they were correct.
+    pub received: usize,
+    /// How many bytes came back with a different value than was sent.
+    pub mismatches: usize,
+    /// How many bytes never came back at all, or could never be sent because
+    /// the send FIFO stayed full.
+    pub timeouts: usize,
+    /// How many bytes were flagged with an error by the hardware.
+    pub errors: usize,
+    /// The first error flagged by the hardware, if any.
+    pub first_error: Option<UartError>,
+}
+
+impl LoopbackReport {
+    /// Whether or not every byte made it back intact.
+    pub const fn passed(&self) -> bool {
+        self.sent == self.received && self.mismatches == 0 && self.timeouts == 0 && self.errors == 0
+    }
+}
+
+impl Uart<'_> {
+    /// Sends a known byte pattern and verifies that it comes back unchanged.
+    ///
+    /// Requires a loopback plug; see the [module-level docs](self) for wiring.
+    /// Any data sitting in the receive FIFO beforehand is discarded, and any
+    /// errors are cleared both before and after the test.
+    pub fn loopback_test(&mut self) -> LoopbackReport {
+        let mask = match self.data_bits() {
+            DataBits::Seven => 0x7F,
+            DataBits::Eight => 0xFF,
+        };
+        self.discard_received();
+
+        let mut report = LoopbackReport::default();
+        let mut timed_out = false;
+        for byte in TEST_PATTERN.map(|b| b & mask) {
+            if timed_out {
+                // A byte that arrives late would otherwise be compared
+                // against this one.
+                self.discard_received();
+                timed_out = false;
+            }
+
+            let mut polls = 0;
+            while !self.can_send() && polls < POLL_LIMIT {
+                polls += 1;
+            }
+            if !self.can_send() {
+                // Nothing is draining the send FIFO, such as when CTS is
+                // enabled but SD isn't looped back to SC.
+                report.timeouts += 1;
+                continue;
+            }
+            self.send_byte(byte);
+            report.sent += 1;
+
+            let mut polls = 0;
+            while !self.has_data() && polls < POLL_LIMIT {
+                polls += 1;
+            }
+            if !self.has_data() {
+                report.timeouts += 1;
+                timed_out = true;
+                continue;
+            }
+            match self.recv_byte() {
+                Ok(received) => {
+                    report.received += 1;
+                    if received != byte {
+                        report.mismatches += 1;
+                    }
+                }
+                Err(err) => {
+                    report.received += 1;
+                    report.errors += 1;
+                    report.first_error.get_or_insert(err);
+                    // Skip the bad byte so it isn't compared against the next
+                    // one we send.
+                    self.clear_errors();
+                    let _ = self.recv_byte();
+                    self.clear_errors();
+                }
+            }
+        }
+        report
+    }
+
+    /// Throws away anything sitting in the receive FIFO, along with any
+    /// errors flagged by the hardware.
+    fn discard_received(&mut self) {
+        while self.has_data() {
+            self.clear_errors();
+            let _ = self.recv_byte();
+        }
+        self.clear_errors();
+    }
+}
@@ -0,0 +1,713 @@
+//! Allows the serial port to be used as a standard UART, with SI acting as the
+//! receive line (RXD), SO as the transmit line (TXD), and SC/SD as the
+//! CTS/RTS flow control lines.
+//!
+//! This is the mode to use when talking to a PC via a USB-UART adapter wired
+//! to the link port, or to other GBAs in a simple point-to-point session.
+
+use super::*;
+
+use super::multiplayer::BaudRate;
+use crate::utils::{GbaCell, TimerTimeout};
+use agb::{
+    external::critical_section::CriticalSection,
+    interrupt::{add_interrupt_handler, Interrupt, InterruptHandler},
+    timer::Timer,
+};
+use buffered::{BufferedInitError, BufferedUart};
+use writer::UartWriter;
+
+use core::marker::PhantomData;
+
+pub mod buffered;
+pub mod console;
+pub mod diagnostics;
+pub mod dma;
+pub mod halfduplex;
+#[cfg(feature = "embedded-hal-nb")]
+mod hal_nb;
+pub mod line;
+#[cfg(feature = "embedded-io")]
+mod io;
+pub mod loopback;
+pub mod slip;
+pub mod writer;
+
+/// The most recent error reported by the hardware that has not yet been
+/// cleared by [Uart::clear_errors].
+///
+/// This is needed because the hardware clears its error flag every time the
+/// SIOCNT register is read, which happens constantly while polling the FIFO
+/// status bits.
+static LATCHED_ERROR: GbaCell<Option<UartError>> = GbaCell::new(None);
+
+/// An error reported by the UART hardware.
+///
+/// Note that the hardware only has a single error flag, so the specific
+/// variant is a best guess based on the rest of the register's state at the
+/// time the flag was seen.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum UartError {
+    /// A byte was received with the wrong parity bit.
+    Parity,
+    /// A byte was received with an invalid stop bit, which generally means the
+    /// two sides disagree on the baud rate or frame format.
+    Framing,
+    /// A byte was received while there was no room left to store it.
+    Overrun,
+}
+
+impl UartError {
+    /// Guesses the cause of an error from a SIOCNT value with the error flag
+    /// set.
+    const fn classify(siocnt: u16) -> Self {
+        if !read_bit(siocnt, 5) {
+            // Data was still waiting to be read, so the most likely cause is
+            // that we didn't read it fast enough.
+            UartError::Overrun
+        } else if read_bit(siocnt, 9) {
+            UartError::Parity
+        } else {
+            UartError::Framing
+        }
+    }
+}
+
+/// An error that can happen while waiting for data with a timeout.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ReadTimeoutError {
+    /// No data arrived before the timeout expired.
+    Timeout,
+    /// The hardware flagged an error.
+    Uart(UartError),
+}
+
+impl From<UartError> for ReadTimeoutError {
+    fn from(value: UartError) -> Self {
+        ReadTimeoutError::Uart(value)
+    }
+}
+
+/// How many data bits are in each UART frame.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum DataBits {
+    Seven,
+    #[default]
+    Eight,
+}
+
+/// The parity bit appended to each UART frame, if any.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+/// Whether or not the hardware should wait for the peer to signal it is ready
+/// before sending each byte.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum FlowControl {
+    /// Send data blindly, regardless of whether or not the peer is ready.
+    #[default]
+    None,
+    /// Only send data while the peer holds our SC (CTS) line LOW.
+    ///
+    /// The peer's RTS line should be wired to our SC pin; since the GBA
+    /// drives its own RTS on SD, two GBAs need SC and SD crossed over for this
+    /// to work.
+    Cts,
+}
+
+/// The top-level handle for using the serial port in UART mode.
+pub struct Uart<'a> {
+    _handle: PhantomData<&'a mut Serial>,
+    buffer_interrupt: Option<InterruptHandler>,
+}
+
+/// Clears both the hardware error flag and the latched copy of it.
+fn clear_errors() {
+    // Reading the register is what clears the hardware flag.
+    let _ = UartSiocnt::get().inner.read();
+    LATCHED_ERROR.swap(None);
+}
+
+/// Helper to enter UART mode with the given configuration, which is assumed to
+/// already be validated.
+fn enter_uart(cfg: UartConfig) {
+    let rcnt = RcntWrapper::get();
+    let siocnt = UartSiocnt::get();
+
+    rcnt.set_mode(SerialMode::Uart);
+    siocnt.set_uart_mode();
+    // The FIFOs are only reset when toggling the enable bit, so make sure
+    // we start with them empty.
+    siocnt.enable_fifo(false);
+    siocnt.write_config(cfg);
+    clear_errors();
+}
+
+impl<'a> Uart<'a> {
+    /// Enters UART mode with 8 data bits, no parity, and the FIFOs enabled.
+    pub fn new(_handle: &'a mut Serial, rate: BaudRate) -> Self {
+        enter_uart(UartConfig::new().with_baud_rate(rate));
+        Self {
+            _handle: PhantomData,
+            buffer_interrupt: None,
+        }
+    }
+    /// Enters UART mode using the provided configuration.
+    pub fn with_config(_handle: &'a mut Serial, cfg: UartConfig) -> Result<Self, UartConfigError> {
+        cfg.validate()?;
+        enter_uart(cfg);
+        Ok(Self {
+            _handle: PhantomData,
+            buffer_interrupt: None,
+        })
+    }
+
+    /// Reads the currently active configuration.
+    pub fn config(&self) -> UartConfig {
+        UartSiocnt::get().config()
+    }
+    /// Validates `cfg` and then applies the entire configuration in a single
+    /// write.
+    pub fn set_config(&mut self, cfg: UartConfig) -> Result<(), UartConfigError> {
+        cfg.validate()?;
+        UartSiocnt::get().write_config(cfg);
+        Ok(())
+    }
+
+    /// Switches to buffered mode, allocating send & receive buffers that can
+    /// each hold `buffer_cap` bytes.
+    pub fn enable_buffered_mode(
+        self,
+        buffer_cap: usize,
+    ) -> Result<BufferedUart<'a>, BufferedInitError> {
+        BufferedUart::new(self, buffer_cap)
+    }
+
+    pub fn baud_rate(&self) -> BaudRate {
+        UartSiocnt::get().baud_rate()
+    }
+    pub fn set_baud_rate(&mut self, rate: BaudRate) {
+        UartSiocnt::get().set_baud_rate(rate)
+    }
+    pub fn data_bits(&self) -> DataBits {
+        UartSiocnt::get().data_bits()
+    }
+    pub fn set_data_bits(&mut self, bits: DataBits) {
+        UartSiocnt::get().set_data_bits(bits)
+    }
+    pub fn parity(&self) -> Parity {
+        UartSiocnt::get().parity()
+    }
+    pub fn set_parity(&mut self, parity: Parity) {
+        UartSiocnt::get().set_parity(parity)
+    }
+    pub fn flow_control(&self) -> FlowControl {
+        if UartSiocnt::get().cts_enabled() {
+            FlowControl::Cts
+        } else {
+            FlowControl::None
+        }
+    }
+    pub fn set_flow_control(&mut self, flow: FlowControl) {
+        UartSiocnt::get().enable_cts(flow == FlowControl::Cts)
+    }
+    /// Checks whether the peer is currently allowing us to send data, IE
+    /// whether our SC (CTS) line is LOW.
+    ///
+    /// This reflects the line's state regardless of the current
+    /// [FlowControl] setting.
+    pub fn clear_to_send(&self) -> bool {
+        !RcntWrapper::get().sc_data()
+    }
+    /// Tells the peer whether or not we are ready to receive more data.
+    ///
+    /// This is done by toggling the receive enable bit, since the hardware
+    /// only drives our SD (RTS) line LOW while receiving is enabled. Note that
+    /// any data sent to us while not ready will be lost.
+    pub fn set_ready_to_receive(&mut self, ready: bool) {
+        UartSiocnt::get().enable_receive(ready)
+    }
+    /// Whether or not we are currently telling the peer we are ready to
+    /// receive data.
+    pub fn ready_to_receive(&self) -> bool {
+        UartSiocnt::get().receive_enabled()
+    }
+
+    /// Whether or not the 4-byte send & receive FIFOs are enabled.
+    ///
+    /// If disabled, only a single byte can be waiting in each direction.
+    pub fn fifo_enabled(&self) -> bool {
+        UartSiocnt::get().fifo_enabled()
+    }
+    /// Enables or disables the send & receive FIFOs.
+    ///
+    /// Note that disabling the FIFOs will discard any data currently in them.
+    pub fn enable_fifo(&mut self, enable: bool) {
+        UartSiocnt::get().enable_fifo(enable)
+    }
+
+    /// Checks whether there is room to queue another byte for sending.
+    pub fn can_send(&self) -> bool {
+        !UartSiocnt::get().send_full()
+    }
+    /// Checks whether there is a received byte waiting to be read.
+    pub fn has_data(&self) -> bool {
+        !UartSiocnt::get().receive_empty()
+    }
+
+    /// Sends a single byte, blocking until there is room for it in the send
+    /// FIFO.
+    pub fn send_byte(&mut self, byte: u8) {
+        while !self.can_send() {}
+        SIODATA8.write(byte);
+    }
+    /// Receives a single byte, blocking until one arrives.
+    ///
+    /// Returns an error without reading anything if the hardware has flagged an
+    /// error that hasn't been cleared with [Self::clear_errors].
+    pub fn recv_byte(&mut self) -> Result<u8, UartError> {
+        loop {
+            let has_data = self.has_data();
+            if let Some(err) = self.error() {
+                return Err(err);
+            }
+            if has_data {
+                return Ok(SIODATA8.read());
+            }
+        }
+    }
+
+    /// Reads as many bytes as are available into `buffer`, waiting up to
+    /// `frames` frames for at least 1 byte to arrive. Returns the number of
+    /// bytes read.
+    ///
+    /// `timer` is used to measure the timeout, and will be disabled again
+    /// before this returns.
+    pub fn read_timeout(
+        &mut self,
+        buffer: &mut [u8],
+        frames: u32,
+        timer: &mut Timer,
+    ) -> Result<usize, ReadTimeoutError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let mut timeout = TimerTimeout::new(timer, frames);
+        while !self.has_data() {
+            if let Some(err) = self.error() {
+                return Err(err.into());
+            }
+            if timeout.expired() {
+                return Err(ReadTimeoutError::Timeout);
+            }
+        }
+        let mut read = 0;
+        for slot in buffer.iter_mut() {
+            if !self.has_data() {
+                break;
+            }
+            match self.recv_byte() {
+                Ok(byte) => *slot = byte,
+                Err(err) if read == 0 => return Err(err.into()),
+                // Report what we got so far; the error is sticky so the next
+                // read will see it.
+                Err(_) => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    /// The error currently flagged by the hardware, if any.
+    ///
+    /// Errors are sticky and will continue to be reported by every read until
+    /// cleared with [Self::clear_errors].
+    pub fn error(&self) -> Option<UartError> {
+        // Make sure we've seen the latest value of the hardware flag.
+        let _ = UartSiocnt::get().read();
+        LATCHED_ERROR.get_copy()
+    }
+    /// Clears any errors reported by the hardware.
+    pub fn clear_errors(&mut self) {
+        clear_errors()
+    }
+
+    /// Creates a [UartWriter] for sending formatted text with `write!` and
+    /// `writeln!`.
+    pub fn writer(&mut self) -> UartWriter<'_, 'a> {
+        UartWriter::new(self)
+    }
+
+    /// Enables the SERIAL interrupt, which will trigger whenever the send
+    /// FIFO becomes full, the receive FIFO becomes empty, or an error occurs.
+    pub fn enable_interrupt(&self, should_enable: bool) {
+        UartSiocnt::get().enable_irq(should_enable)
+    }
+    /// Whether or not the SERIAL interrupt is currently enabled.
+    pub fn interrupt_enabled(&self) -> bool {
+        UartSiocnt::get().irq_enabled()
+    }
+    /// Adds an interrupt handler that will be triggered on UART events,
+    /// assuming you also call [Self::enable_interrupt].
+    ///
+    /// # Safety
+    /// The callback `cb` **must not** allocate on the heap.
+    pub unsafe fn add_interrupt<F>(&mut self, cb: F)
+    where
+        F: Fn(CriticalSection) + Send + Sync + 'static,
+    {
+        self.buffer_interrupt = Some(add_interrupt_handler(Interrupt::Serial, cb));
+    }
+}
+
+/// The full set of user-configurable UART settings, which can be applied at
+/// once with [Uart::with_config] or [Uart::set_config].
+///
+/// Defaults to 9600 baud, 8 data bits, no parity, no flow control, and both the
+/// FIFOs & the send & receive lines enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct UartConfig {
+    value: u16,
+}
+
+impl Default for UartConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UartConfig {
+    /// The SIOCNT bits that are covered by the config; everything else is
+    /// either status, mode, or interrupt bits.
+    const MASK: u16 = 0b1111_1000_1111;
+
+    pub const fn new() -> Self {
+        Self { value: 0 }
+            .with_data_bits(DataBits::Eight)
+            .with_fifo(true)
+            .with_send(true)
+            .with_receive(true)
+    }
+    pub const fn baud_rate(&self) -> BaudRate {
+        let bits = (self.value & 3) as u8;
+        unsafe { core::mem::transmute(bits) }
+    }
+    pub const fn with_baud_rate(self, rate: BaudRate) -> Self {
+        let value = (self.value & !3) | rate as u16;
+        Self { value }
+    }
+    pub const fn flow_control(&self) -> FlowControl {
+        if read_bit(self.value, 2) {
+            FlowControl::Cts
+        } else {
+            FlowControl::None
+        }
+    }
+    pub const fn with_flow_control(self, flow: FlowControl) -> Self {
+        let value = write_bit(self.value, 2, matches!(flow, FlowControl::Cts));
+        Self { value }
+    }
+    pub const fn parity(&self) -> Parity {
+        match (read_bit(self.value, 9), read_bit(self.value, 3)) {
+            (false, _) => Parity::None,
+            (true, false) => Parity::Even,
+            (true, true) => Parity::Odd,
+        }
+    }
+    pub const fn with_parity(self, parity: Parity) -> Self {
+        let (enabled, odd) = match parity {
+            Parity::None => (false, false),
+            Parity::Even => (true, false),
+            Parity::Odd => (true, true),
+        };
+        let value = write_bit(write_bit(self.value, 3, odd), 9, enabled);
+        Self { value }
+    }
+    pub const fn data_bits(&self) -> DataBits {
+        if read_bit(self.value, 7) {
+            DataBits::Eight
+        } else {
+            DataBits::Seven
+        }
+    }
+    pub const fn with_data_bits(self, bits: DataBits) -> Self {
+        let value = write_bit(self.value, 7, matches!(bits, DataBits::Eight));
+        Self { value }
+    }
+    pub const fn fifo_enabled(&self) -> bool {
+        read_bit(self.value, 8)
+    }
+    pub const fn with_fifo(self, enable: bool) -> Self {
+        let value = write_bit(self.value, 8, enable);
+        Self { value }
+    }
+    pub const fn send_enabled(&self) -> bool {
+        read_bit(self.value, 10)
+    }
+    pub const fn with_send(self, enable: bool) -> Self {
+        let value = write_bit(self.value, 10, enable);
+        Self { value }
+    }
+    pub const fn receive_enabled(&self) -> bool {
+        read_bit(self.value, 11)
+    }
+    pub const fn with_receive(self, enable: bool) -> Self {
+        let value = write_bit(self.value, 11, enable);
+        Self { value }
+    }
+
+    /// Checks that this configuration describes a usable UART link.
+    pub const fn validate(&self) -> Result<(), UartConfigError> {
+        if !self.send_enabled() && !self.receive_enabled() {
+            return Err(UartConfigError::NothingEnabled);
+        }
+        if !self.send_enabled() && matches!(self.flow_control(), FlowControl::Cts) {
+            return Err(UartConfigError::FlowControlWithoutSend);
+        }
+        Ok(())
+    }
+
+    const fn from_siocnt(value: u16) -> Self {
+        Self {
+            value: value & Self::MASK,
+        }
+    }
+    const fn into_siocnt(self) -> u16 {
+        self.value
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum UartConfigError {
+    /// Neither sending nor receiving was enabled.
+    NothingEnabled,
+    /// CTS flow control was requested without enabling sending, which is the
+    /// only thing it affects.
+    FlowControlWithoutSend,
+}
+
+/// Newtype extention wrapper around the Serial I/O Control register with extra
+/// methods for UART mode.
+///
+/// # GBATEK Table of Bits
+/// | Bit |  Explanation        | Notes |
+/// | :-- | :--                 | :--   |
+/// | 0-1 | Baud Rate           | (0-3: 9600,38400,57600,115200 bps)
+/// | 2   | CTS Flag            | (0=Send always/blindly, 1=Send only when SC=LOW)
+/// | 3   | Parity Control      | (0=Even, 1=Odd)
+/// | 4   | Send Data Flag      | (0=Not Full,  1=Full)    (Read Only)
+/// | 5   | Receive Data Flag   | (0=Not Empty, 1=Empty)   (Read Only)
+/// | 6   | Error Flag          | (0=No Error,  1=Error)   (Read Only)
+/// | 7   | Data Length         | (0=7bits,   1=8bits)
+/// | 8   | FIFO Enable Flag    | (0=Disable, 1=Enable)
+/// | 9   | Parity Enable Flag  | (0=Disable, 1=Enable)
+/// | 10  | Send Enable Flag    | (0=Disable, 1=Enable)
+/// | 11  | Receive Enable Flag | (0=Disable, 1=Enable)
+/// | 12  | Must be "1" for UART mode |
+/// | 13  | Must be "1" for UART mode |
+/// | 14  | IRQ Enable          | (0=Disable, 1=IRQ when any Bit 4/5/6 become set)
+/// | 15  | Not used            | (Read only, always 0)
+///
+/// Unlike the other SIOCNT wrappers this doesn't deref to [SiocntWrapper], so
+/// that every read goes through [UartSiocnt::read] & latches the error flag.
+struct UartSiocnt {
+    inner: SiocntWrapper,
+}
+
+impl UartSiocnt {
+    const fn new() -> Self {
+        Self {
+            inner: SiocntWrapper::new(),
+        }
+    }
+    pub const fn get() -> Self {
+        Self::new()
+    }
+
+    /// Reads the register, latching the error flag into [LATCHED_ERROR] since
+    /// the hardware clears it on every read.
+    ///
+    /// This shadows [RegisterWrapper::read] so that every other UART helper
+    /// method goes through it.
+    pub fn read(&self) -> u16 {
+        let value = self.inner.read();
+        if read_bit(value, 6) {
+            LATCHED_ERROR.swap(Some(UartError::classify(value)));
+        }
+        value
+    }
+    pub fn read_bit(&self, n: u8) -> bool {
+        read_bit(self.read(), n)
+    }
+    pub fn write(&self, value: u16) {
+        self.inner.write(value)
+    }
+    pub fn write_bit(&self, n: u8, value: bool) {
+        self.write(write_bit(self.read(), n, value));
+    }
+
+    /// Sets the SIOCNT bits for UART mode, leaving every other bit untouched.
+    pub fn set_uart_mode(&self) {
+        self.write(write_bit(write_bit(self.read(), 12, true), 13, true));
+    }
+    pub fn irq_enabled(&self) -> bool {
+        self.read_bit(14)
+    }
+    pub fn enable_irq(&self, v: bool) {
+        self.write_bit(14, v)
+    }
+
+    pub fn config(&self) -> UartConfig {
+        UartConfig::from_siocnt(self.read())
+    }
+    /// Writes every config bit at once, leaving the mode & interrupt bits
+    /// untouched.
+    pub fn write_config(&self, cfg: UartConfig) {
+        let old = self.read();
+        let new = (old & !UartConfig::MASK) | cfg.into_siocnt();
+        self.write(new)
+    }
+
+    pub fn baud_rate(&self) -> BaudRate {
+        let v = self.read();
+        let bits = (v & 3) as u8;
+        unsafe { core::mem::transmute(bits) }
+    }
+    pub fn set_baud_rate(&self, rate: BaudRate) {
+        let old = self.read();
+        let new = (old & !3) | rate as u16;
+        self.write(new)
+    }
+
+    pub fn cts_enabled(&self) -> bool {
+        self.read_bit(2)
+    }
+    pub fn enable_cts(&self, enable: bool) {
+        self.write_bit(2, enable)
+    }
+
+    pub fn parity(&self) -> Parity {
+        match (self.read_bit(9), self.read_bit(3)) {
+            (false, _) => Parity::None,
+            (true, false) => Parity::Even,
+            (true, true) => Parity::Odd,
+        }
+    }
+    pub fn set_parity(&self, parity: Parity) {
+        let (enabled, odd) = match parity {
+            Parity::None => (false, false),
+            Parity::Even => (true, false),
+            Parity::Odd => (true, true),
+        };
+        let old = self.read();
+        let new = write_bit(write_bit(old, 3, odd), 9, enabled);
+        self.write(new)
+    }
+
+    /// Whether or not the send FIFO (or the single send register, if the FIFO
+    /// is disabled) is full.
+    pub fn send_full(&self) -> bool {
+        self.read_bit(4)
+    }
+    /// Whether or not the receive FIFO (or the single receive register, if the
+    /// FIFO is disabled) is empty.
+    pub fn receive_empty(&self) -> bool {
+        self.read_bit(5)
+    }
+
+    pub fn data_bits(&self) -> DataBits {
+        if self.read_bit(7) {
+            DataBits::Eight
+        } else {
+            DataBits::Seven
+        }
+    }
+    pub fn set_data_bits(&self, bits: DataBits) {
+        self.write_bit(7, bits == DataBits::Eight)
+    }
+
+    pub fn fifo_enabled(&self) -> bool {
+        self.read_bit(8)
+    }
+    pub fn enable_fifo(&self, enable: bool) {
+        self.write_bit(8, enable)
+    }
+    #[allow(unused)]
+    pub fn enable_send(&self, enable: bool) {
+        self.write_bit(10, enable)
+    }
+    pub fn receive_enabled(&self) -> bool {
+        self.read_bit(11)
+    }
+    pub fn enable_receive(&self, enable: bool) {
+        self.write_bit(11, enable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_config_roundtrip(_gba: &mut Gba) {
+        let cfg = UartConfig::new();
+        assert_eq!(cfg.baud_rate(), BaudRate::B9600);
+        assert_eq!(cfg.data_bits(), DataBits::Eight);
+        assert_eq!(cfg.parity(), Parity::None);
+        assert_eq!(cfg.flow_control(), FlowControl::None);
+        assert!(cfg.fifo_enabled() && cfg.send_enabled() && cfg.receive_enabled());
+        assert_eq!(cfg.validate(), Ok(()));
+
+        let cfg = cfg
+            .with_baud_rate(BaudRate::B115200)
+            .with_data_bits(DataBits::Seven)
+            .with_parity(Parity::Odd)
+            .with_flow_control(FlowControl::Cts)
+            .with_fifo(false);
+        assert_eq!(cfg.baud_rate(), BaudRate::B115200);
+        assert_eq!(cfg.data_bits(), DataBits::Seven);
+        assert_eq!(cfg.parity(), Parity::Odd);
+        assert_eq!(cfg.flow_control(), FlowControl::Cts);
+        assert!(!cfg.fifo_enabled());
+        assert_eq!(cfg.into_siocnt() & !UartConfig::MASK, 0);
+        assert_eq!(UartConfig::from_siocnt(cfg.into_siocnt() | 0xF070), cfg);
+    }
+
+    #[test_case]
+    fn test_error_classification(_gba: &mut Gba) {
+        let error_bit = 1 << 6;
+        let rx_empty = 1 << 5;
+        let parity_enabled = 1 << 9;
+        assert_eq!(UartError::classify(error_bit), UartError::Overrun);
+        assert_eq!(
+            UartError::classify(error_bit | parity_enabled),
+            UartError::Overrun
+        );
+        assert_eq!(
+            UartError::classify(error_bit | rx_empty | parity_enabled),
+            UartError::Parity
+        );
+        assert_eq!(
+            UartError::classify(error_bit | rx_empty),
+            UartError::Framing
+        );
+    }
+
+    #[test_case]
+    fn test_config_validation(_gba: &mut Gba) {
+        let cfg = UartConfig::new().with_send(false).with_receive(false);
+        assert_eq!(cfg.validate(), Err(UartConfigError::NothingEnabled));
+        let cfg = UartConfig::new()
+            .with_send(false)
+            .with_flow_control(FlowControl::Cts);
+        assert_eq!(cfg.validate(), Err(UartConfigError::FlowControlWithoutSend));
+        let cfg = UartConfig::new().with_send(false);
+        assert_eq!(cfg.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,235 @@
+//! [SLIP](https://datatracker.ietf.org/doc/html/rfc1055) framing, for
+//! exchanging packets with a PC over a USB-UART adapter.
+//!
+//! Each frame is wrapped in [END] bytes, with any [END] or [ESC] bytes inside
+//! the frame replaced by two-byte escape sequences. This is supported by most
+//! PC-side serial libraries (eg Python's `sliplib`).
+
+use super::{buffered::BufferedUart, UartError};
+
+/// Marks the boundary between two frames.
+pub const END: u8 = 0xC0;
+/// Marks the start of an escape sequence.
+pub const ESC: u8 = 0xDB;
+/// Follows an [ESC] to represent a literal [END] byte.
+pub const ESC_END: u8 = 0xDC;
+/// Follows an [ESC] to represent a literal [ESC] byte.
+pub const ESC_ESC: u8 = 0xDD;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SlipError {
+    /// The output buffer was too small to hold the frame.
+    BufferTooSmall,
+    /// An [ESC] byte was followed by something other than [ESC_END] or
+    /// [ESC_ESC].
+    InvalidEscape,
+    /// The UART hardware flagged an error while receiving the frame.
+    Uart(UartError),
+}
+
+/// Calculates how many bytes [encode] will write for `data`, including both
+/// [END] markers.
+pub fn encoded_len(data: &[u8]) -> usize {
+    let escapes = data.iter().filter(|b| matches!(**b, END | ESC)).count();
+    data.len() + escapes + 2
+}
+
+/// Encodes `data` as a single SLIP frame into `out`, returning the number of
+/// bytes written.
+///
+/// The frame is both preceded and followed by an [END] byte, which flushes
+/// any line noise out of the receiver's decoder before the frame starts.
+pub fn encode(data: &[u8], out: &mut [u8]) -> Result<usize, SlipError> {
+    if out.len() < encoded_len(data) {
+        return Err(SlipError::BufferTooSmall);
+    }
+    let mut written = 0;
+    encode_with(data, |byte| {
+        out[written] = byte;
+        written += 1;
+    });
+    Ok(written)
+}
+
+/// Encodes `data` as a single SLIP frame, passing each byte to `sink` in
+/// order.
+fn encode_with(data: &[u8], mut sink: impl FnMut(u8)) {
+    sink(END);
+    for byte in data {
+        match *byte {
+            END => {
+                sink(ESC);
+                sink(ESC_END);
+            }
+            ESC => {
+                sink(ESC);
+                sink(ESC_ESC);
+            }
+            other => sink(other),
+        }
+    }
+    sink(END);
+}
+
+/// Incrementally decodes a stream of bytes into SLIP frames.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SlipDecoder {
+    /// How many bytes of the current frame have been decoded so far.
+    len: usize,
+    /// Whether or not the previous byte was an [ESC].
+    escaped: bool,
+    /// Set if the current frame has hit an error, in which case we drop
+    /// everything until the next [END] and then report it.
+    error: Option<SlipError>,
+}
+
+impl SlipDecoder {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            escaped: false,
+            error: None,
+        }
+    }
+
+    /// Feeds a single byte into the decoder, storing decoded data in `frame`.
+    ///
+    /// Returns `Some(Ok(len))` once a complete frame has been decoded into
+    /// `frame[..len]`, or `Some(Err(_))` once a frame ends if it could not be
+    /// decoded. Empty frames are skipped. The same `frame` buffer must be
+    /// passed in until a frame is returned.
+    pub fn push(&mut self, byte: u8, frame: &mut [u8]) -> Option<Result<usize, SlipError>> {
+        if byte == END {
+            let retvl = match (self.error, self.escaped, self.len) {
+                (Some(err), _, _) => Some(Err(err)),
+                (None, true, _) => Some(Err(SlipError::InvalidEscape)),
+                (None, false, 0) => None,
+                (None, false, len) => Some(Ok(len)),
+            };
+            self.reset();
+            return retvl;
+        }
+        if self.error.is_some() {
+            return None;
+        }
+        let decoded = match (self.escaped, byte) {
+            (false, ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (false, other) => other,
+            (true, ESC_END) => END,
+            (true, ESC_ESC) => ESC,
+            (true, _) => {
+                self.error = Some(SlipError::InvalidEscape);
+                return None;
+            }
+        };
+        self.escaped = false;
+        let Some(slot) = frame.get_mut(self.len) else {
+            self.error = Some(SlipError::BufferTooSmall);
+            return None;
+        };
+        *slot = decoded;
+        self.len += 1;
+        None
+    }
+
+    /// Discards any partially-decoded frame.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl BufferedUart<'_> {
+    /// Queues `data` to be sent as a single SLIP frame, blocking until the
+    /// entire frame fits in the send buffer.
+    pub fn send_slip(&mut self, data: &[u8]) {
+        encode_with(data, |byte| while self.write(&[byte]) == 0 {});
+    }
+
+    /// Feeds any received bytes into `decoder`, stopping once a full frame has
+    /// been decoded into `frame`.
+    ///
+    /// Returns `None` if no complete frame has arrived yet; in that case the
+    /// same `decoder` and `frame` should be passed in again later. If the
+    /// hardware flags an error the partial frame is discarded and the error
+    /// is cleared before being returned.
+    ///
+    /// Does NOT block.
+    pub fn recv_slip(
+        &mut self,
+        decoder: &mut SlipDecoder,
+        frame: &mut [u8],
+    ) -> Option<Result<usize, SlipError>> {
+        let mut byte = [0];
+        loop {
+            match self.read(&mut byte) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => {
+                    decoder.reset();
+                    self.clear_errors();
+                    return Some(Err(SlipError::Uart(err)));
+                }
+            }
+            if let Some(res) = decoder.push(byte[0], frame) {
+                return Some(res);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::vec::Vec;
+
+    fn decode_all(encoded: &[u8], frame: &mut [u8]) -> Vec<Result<Vec<u8>, SlipError>> {
+        let mut decoder = SlipDecoder::new();
+        let mut retvl = Vec::new();
+        for byte in encoded {
+            if let Some(res) = decoder.push(*byte, frame) {
+                retvl.push(res.map(|len| frame[..len].to_vec()));
+            }
+        }
+        retvl
+    }
+
+    #[test_case]
+    fn test_encode(_gba: &mut Gba) {
+        let data = [0x01, END, 0x02, ESC, 0x03];
+        let mut out = [0; 16];
+        assert_eq!(encoded_len(&data), 9);
+        assert_eq!(encode(&data, &mut out), Ok(9));
+        assert_eq!(
+            &out[..9],
+            &[END, 0x01, ESC, ESC_END, 0x02, ESC, ESC_ESC, 0x03, END]
+        );
+        assert_eq!(encode(&data, &mut out[..8]), Err(SlipError::BufferTooSmall));
+    }
+
+    #[test_case]
+    fn test_roundtrip(_gba: &mut Gba) {
+        let data = [END, ESC, 0x00, 0xFF, ESC_END, ESC_ESC, END];
+        let mut encoded = [0; 32];
+        let len = encode(&data, &mut encoded).unwrap();
+        let mut frame = [0; 16];
+        let frames = decode_all(&encoded[..len], &mut frame);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].as_deref(), Ok(&data[..]));
+    }
+
+    #[test_case]
+    fn test_decode_errors(_gba: &mut Gba) {
+        let mut frame = [0; 2];
+        // Too long, then a bad escape, then a good frame.
+        let stream = [END, 1, 2, 3, END, 4, ESC, 5, 6, END, 7, 8, END];
+        let frames = decode_all(&stream, &mut frame);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], Err(SlipError::BufferTooSmall));
+        assert_eq!(frames[1], Err(SlipError::InvalidEscape));
+        assert_eq!(frames[2].as_deref(), Ok(&[7, 8][..]));
+    }
+}
@@ -0,0 +1,44 @@
+//! A [core::fmt::Write] sink for streaming formatted text out over UART, such
+//! as to a terminal on a PC.
+
+use core::fmt;
+
+use super::Uart;
+
+/// Streams formatted text out over a [Uart] using blocking sends.
+///
+/// By default every `\n` is sent as `\r\n`, since that is what most serial
+/// terminals expect; use [UartWriter::with_crlf] to send text as-is instead.
+///
+/// # Examples
+/// ```
+/// use core::fmt::Write;
+/// let mut writer = uart.writer();
+/// writeln!(writer, "Player position: {:?}", pos).ok();
+/// ```
+pub struct UartWriter<'u, 'a> {
+    uart: &'u mut Uart<'a>,
+    crlf: bool,
+}
+
+impl<'u, 'a> UartWriter<'u, 'a> {
+    pub fn new(uart: &'u mut Uart<'a>) -> Self {
+        Self { uart, crlf: true }
+    }
+    /// Sets whether or not `\n` should be translated into `\r\n`.
+    pub fn with_crlf(self, crlf: bool) -> Self {
+        Self { crlf, ..self }
+    }
+}
+
+impl fmt::Write for UartWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if self.crlf && byte == b'\n' {
+                self.uart.send_byte(b'\r');
+            }
+            self.uart.send_byte(byte);
+        }
+        Ok(())
+    }
+}
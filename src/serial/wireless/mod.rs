@@ -0,0 +1,390 @@
+//! A driver for the GBA Wireless Adapter (AKA the RFU), talking to it over the
+//! link port in 32-bit Normal mode.
+//!
+//! # Protocol
+//!
+//! The adapter is reset by pulsing SD high in GPIO mode, after which the GBA
+//! logs in by exchanging the string "NINTENDO" with it 1 halfword at a time;
+//! see [LOGIN_PARTS]. Each login word holds the GBA's next halfword in the low
+//! half and the complement of the adapter's last halfword in the high half,
+//! and the adapter answers the same way.
+//!
+//! After that, everything is a command sent with the GBA as the master:
+//!
+//! | Word                          | Adapter answers
+//! | :--                           | :--
+//! | `0x9966_LLCC`                 | [DATA_REQUEST]; LL is the number of parameter words, CC the [Command]
+//! | LL parameter words            | [DATA_REQUEST]
+//! | [DATA_REQUEST]                | `0x9966_RRAA`; RR is the number of response words, AA is CC + 0x80
+//! | RR x [DATA_REQUEST]           | The response words
+//!
+//! If the command failed AA is instead [ERROR_ACK], with a single response
+//! word holding the error code.
+//!
+//! Before every transfer the GBA waits for the adapter to pull SI low, showing
+//! it's ready.
+
+use agb::interrupt::VBlank;
+
+use super::multiplayer::PlayerId;
+use super::normal::{ClockConfig, NormalSerial};
+use super::{RcntWrapper, Serial, SerialMode};
+
+pub mod quality;
+pub mod room;
+pub mod session;
+
+/// The halfwords of "NINTENDO" exchanged during login, followed by the final
+/// magic halfword.
+pub const LOGIN_PARTS: [u16; 9] = [
+    0x494E, 0x494E, 0x544E, 0x544E, 0x4E45, 0x4E45, 0x4F44, 0x4F44, 0x8001,
+];
+
+/// The upper halfword of every command & response header.
+pub const COMMAND_MAGIC: u16 = 0x9966;
+/// Sent by the GBA to read the next word from the adapter, and answered by the
+/// adapter while it's receiving a command.
+pub const DATA_REQUEST: u32 = 0x8000_0000;
+/// The acknowledgement byte of a response to a failed command.
+pub const ERROR_ACK: u8 = 0xEE;
+/// The most parameter or response words a single command can have.
+pub const MAX_COMMAND_LEN: usize = u8::MAX as usize;
+
+/// How many times SIOCNT is polled waiting for the adapter to be ready for a
+/// transfer before giving up; a few frames.
+const READY_POLLS: u32 = 0x1_0000;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Command {
+    /// Checks that the adapter is still there; sent right after logging in.
+    Hello = 0x10,
+    /// The signal strength of each connection.
+    SignalLevel = 0x11,
+    /// The adapter's firmware version.
+    VersionStatus = 0x12,
+    /// The adapter's current state & our player ID.
+    SystemStatus = 0x13,
+    /// The players connected to the room we're hosting.
+    SlotStatus = 0x14,
+    /// Sets the data other adapters see while scanning for rooms.
+    Broadcast = 0x16,
+    /// Configures the session.
+    Setup = 0x17,
+    /// Starts hosting a room.
+    StartHost = 0x19,
+    /// Lists the players that have joined the room we're hosting.
+    PollHost = 0x1A,
+    /// Stops letting new players join the room we're hosting.
+    EndHost = 0x1B,
+    /// Starts scanning for rooms.
+    BroadcastReadStart = 0x1C,
+    /// Lists the rooms found so far.
+    BroadcastReadPoll = 0x1D,
+    /// Stops scanning for rooms, listing the rooms found.
+    BroadcastReadEnd = 0x1E,
+    /// Starts joining a room.
+    Connect = 0x1F,
+    /// Checks whether joining a room has finished.
+    IsConnectionComplete = 0x20,
+    /// Finishes joining a room.
+    FinishConnection = 0x21,
+    /// Queues data to be sent to the other players.
+    SendData = 0x24,
+    /// Reads the data received from the other players.
+    ReceiveData = 0x26,
+    /// Leaves the session.
+    Bye = 0x3D,
+}
+
+/// The ID number of a GBA unit in a wireless session.
+///
+/// This is like [PlayerId], but a wireless session can have up to 5 units
+/// instead of 4.
+#[repr(u8)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
+pub enum WirelessPlayerId {
+    /// Player 0, AKA the host of the room.
+    #[default]
+    P0 = 0,
+    /// Player 1
+    P1 = 1,
+    /// Player 2
+    P2 = 2,
+    /// Player 3
+    P3 = 3,
+    /// Player 4, which only exists in wireless sessions.
+    P4 = 4,
+}
+
+impl WirelessPlayerId {
+    /// An array of all available player IDs for easy iteration.
+    pub const ALL: [WirelessPlayerId; 5] = [
+        WirelessPlayerId::P0,
+        WirelessPlayerId::P1,
+        WirelessPlayerId::P2,
+        WirelessPlayerId::P3,
+        WirelessPlayerId::P4,
+    ];
+    /// The ID of the client the adapter put in the 0-based client `slot`.
+    pub const fn from_client_slot(slot: u8) -> Option<Self> {
+        match slot {
+            0..=3 => Some(Self::ALL[slot as usize + 1]),
+            _ => None,
+        }
+    }
+    /// The adapter's 0-based client slot for this player, or `None` for the
+    /// host.
+    pub const fn client_slot(self) -> Option<u8> {
+        match self {
+            Self::P0 => None,
+            other => Some(other as u8 - 1),
+        }
+    }
+    pub const fn is_host(self) -> bool {
+        matches!(self, Self::P0)
+    }
+    /// The matching link cable [PlayerId], if there is one.
+    pub const fn to_cable(self) -> Option<PlayerId> {
+        match self {
+            Self::P0 => Some(PlayerId::P0),
+            Self::P1 => Some(PlayerId::P1),
+            Self::P2 => Some(PlayerId::P2),
+            Self::P3 => Some(PlayerId::P3),
+            Self::P4 => None,
+        }
+    }
+}
+
+impl From<PlayerId> for WirelessPlayerId {
+    fn from(value: PlayerId) -> Self {
+        Self::ALL[value as usize]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum WirelessError {
+    /// The adapter didn't answer the login, so it probably isn't connected.
+    NotConnected,
+    /// The adapter didn't become ready for the next transfer in time.
+    Timeout,
+    /// The adapter answered with something that doesn't follow the protocol.
+    InvalidResponse(u32),
+    /// The adapter reported that the command failed, with this error code.
+    CommandFailed { command: Command, code: u32 },
+    /// The command had too many parameters, or the response didn't fit into
+    /// the buffer given for it.
+    TooLong,
+}
+
+/// Builds the header word of a command with `len` parameter words.
+pub const fn command_header(command: Command, len: u8) -> u32 {
+    ((COMMAND_MAGIC as u32) << 16) | ((len as u32) << 8) | command as u32
+}
+
+/// Parses the header word of the adapter's response to `command`, returning
+/// the number of response words that follow.
+///
+/// A response to a failed command also counts as valid, since its error code
+/// follows it just like a normal response.
+fn parse_response_header(command: Command, word: u32) -> Result<usize, WirelessError> {
+    let [magic_hi, magic_lo, len, ack] = word.to_be_bytes();
+    let valid_ack = ack == (command as u8).wrapping_add(0x80) || ack == ERROR_ACK;
+    if u16::from_be_bytes([magic_hi, magic_lo]) != COMMAND_MAGIC || !valid_ack {
+        return Err(WirelessError::InvalidResponse(word));
+    }
+    Ok(len as usize)
+}
+
+/// The halfwords each side sent last during login.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Login {
+    gba: u16,
+    adapter: u16,
+}
+
+impl Login {
+    const fn new() -> Self {
+        Self {
+            gba: 0xFFFF,
+            adapter: 0xFFFF,
+        }
+    }
+    /// The word to send for the next login halfword.
+    const fn packet(&self, data: u16) -> u32 {
+        ((!self.adapter as u32) << 16) | data as u32
+    }
+    /// Checks the adapter's answer to [Self::packet], which should hold
+    /// `expected` & our last halfword's complement.
+    fn check(&mut self, data: u16, expected: u16, response: u32) -> bool {
+        let valid = (response >> 16) as u16 == expected && response as u16 == !self.gba;
+        if valid {
+            self.gba = data;
+            self.adapter = expected;
+        }
+        valid
+    }
+}
+
+/// A handle to a logged-in Wireless Adapter.
+pub struct WirelessAdapter<'a> {
+    serial: NormalSerial<'a>,
+}
+
+impl<'a> WirelessAdapter<'a> {
+    /// Resets the adapter & logs in, checking that it's connected.
+    pub fn new(handle: &'a mut Serial) -> Result<Self, WirelessError> {
+        reset_adapter();
+        let serial = NormalSerial::with_clock(handle, ClockConfig::Internal256KHz);
+        let mut retvl = Self { serial };
+        retvl.login()?;
+        retvl.command(Command::Hello, &[], &mut [])?;
+        Ok(retvl)
+    }
+
+    /// Resets the adapter & logs in again, such as after it lost power or
+    /// stopped answering.
+    ///
+    /// This leaves any session, so rooms need to be hosted or joined again.
+    pub fn relogin(&mut self) -> Result<(), WirelessError> {
+        reset_adapter();
+        RcntWrapper::get().set_mode(SerialMode::Normal);
+        self.login()?;
+        self.command(Command::Hello, &[], &mut [])?;
+        Ok(())
+    }
+
+    fn login(&mut self) -> Result<(), WirelessError> {
+        let mut login = Login::new();
+        let steps =
+            core::iter::once((LOGIN_PARTS[0], 0)).chain(LOGIN_PARTS.map(|part| (part, part)));
+        for (data, expected) in steps {
+            let response = self.transfer(login.packet(data))?;
+            if !login.check(data, expected, response) {
+                return Err(WirelessError::NotConnected);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `command` with the given parameters, writing the adapter's
+    /// response into `response` and returning its length.
+    pub fn command(
+        &mut self,
+        command: Command,
+        params: &[u32],
+        response: &mut [u32],
+    ) -> Result<usize, WirelessError> {
+        if params.len() > MAX_COMMAND_LEN {
+            return Err(WirelessError::TooLong);
+        }
+        let header = command_header(command, params.len() as u8);
+        for &word in core::iter::once(&header).chain(params) {
+            let answer = self.transfer(word)?;
+            if answer != DATA_REQUEST {
+                return Err(WirelessError::InvalidResponse(answer));
+            }
+        }
+        let answer = self.transfer(DATA_REQUEST)?;
+        let len = parse_response_header(command, answer)?;
+        let failed = answer as u8 == ERROR_ACK;
+        let mut code = 0;
+        for idx in 0..len {
+            let word = self.transfer(DATA_REQUEST)?;
+            if failed {
+                code = word;
+            } else if let Some(slot) = response.get_mut(idx) {
+                *slot = word;
+            }
+        }
+        if failed {
+            return Err(WirelessError::CommandFailed { command, code });
+        }
+        if len > response.len() {
+            return Err(WirelessError::TooLong);
+        }
+        Ok(len)
+    }
+
+    /// Returns the underlying [NormalSerial] handle.
+    pub fn into_inner(self) -> NormalSerial<'a> {
+        self.serial
+    }
+
+    /// Exchanges a single word once the adapter is ready.
+    fn transfer(&mut self, word: u32) -> Result<u32, WirelessError> {
+        let mut polls = 0;
+        while !self.serial.peer_ready() {
+            polls += 1;
+            if polls >= READY_POLLS {
+                return Err(WirelessError::Timeout);
+            }
+        }
+        Ok(self.serial.exchange_u32(word))
+    }
+}
+
+/// Resets the adapter by pulsing SD high for a frame.
+fn reset_adapter() {
+    let rcnt = RcntWrapper::get();
+    rcnt.set_mode(SerialMode::Gpio);
+    rcnt.set_sd_direction(true);
+    rcnt.write_sd_data(true);
+    VBlank::get().wait_for_vblank();
+    rcnt.write_sd_data(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_command_framing(_gba: &mut Gba) {
+        assert_eq!(command_header(Command::Setup, 1), 0x9966_0117);
+        assert_eq!(command_header(Command::Hello, 0), 0x9966_0010);
+
+        assert_eq!(parse_response_header(Command::Hello, 0x9966_0090), Ok(0));
+        assert_eq!(
+            parse_response_header(Command::SlotStatus, 0x9966_0294),
+            Ok(2)
+        );
+        assert_eq!(parse_response_header(Command::Connect, 0x9966_01EE), Ok(1));
+        assert_eq!(
+            parse_response_header(Command::Hello, 0x9966_0091),
+            Err(WirelessError::InvalidResponse(0x9966_0091))
+        );
+        assert_eq!(
+            parse_response_header(Command::Hello, DATA_REQUEST),
+            Err(WirelessError::InvalidResponse(DATA_REQUEST))
+        );
+    }
+
+    #[test_case]
+    fn test_player_ids(_gba: &mut Gba) {
+        assert_eq!(
+            WirelessPlayerId::from_client_slot(0),
+            Some(WirelessPlayerId::P1)
+        );
+        assert_eq!(
+            WirelessPlayerId::from_client_slot(3),
+            Some(WirelessPlayerId::P4)
+        );
+        assert_eq!(WirelessPlayerId::from_client_slot(4), None);
+        assert_eq!(WirelessPlayerId::P0.client_slot(), None);
+        assert_eq!(WirelessPlayerId::P4.client_slot(), Some(3));
+        assert_eq!(WirelessPlayerId::P4.to_cable(), None);
+        assert_eq!(WirelessPlayerId::from(PlayerId::P3), WirelessPlayerId::P3);
+    }
+
+    #[test_case]
+    fn test_login(_gba: &mut Gba) {
+        let mut login = Login::new();
+        assert_eq!(login.packet(0x494E), 0x0000_494E);
+        assert!(!login.check(0x494E, 0, 0x1234_0000));
+        assert!(login.check(0x494E, 0, 0x0000_0000));
+        assert_eq!(login.packet(0x494E), 0xFFFF_494E);
+        assert!(login.check(0x494E, 0x494E, 0x494E_B6B1));
+        assert_eq!(login.packet(0x544E), 0xB6B1_544E);
+    }
+}
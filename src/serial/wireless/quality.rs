@@ -0,0 +1,166 @@
+//! Checking how good the wireless connection to each player is.
+//!
+//! [WirelessAdapter::signal_levels] reads the signal strength of each
+//! connection, so games can show it per player and warn before a connection
+//! drops, while [WirelessAdapter::slot_status] lists who is still connected.
+
+use alloc::vec::Vec;
+
+use super::room::RoomClient;
+use super::{Command, WirelessAdapter, WirelessError, WirelessPlayerId};
+
+/// The number of client slots whose signal is reported.
+pub const SIGNAL_SLOTS: usize = 4;
+
+/// The lowest level still counted as [SignalQuality::Fair].
+const FAIR_LEVEL: u8 = 0x40;
+/// The lowest level counted as [SignalQuality::Good].
+const GOOD_LEVEL: u8 = 0xA0;
+
+/// A rough grading of a connection's signal level.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum SignalQuality {
+    /// There is no signal at all, so the player has dropped or is about to.
+    Lost,
+    /// The signal is weak enough that the connection may drop soon.
+    Weak,
+    Fair,
+    Good,
+}
+
+impl SignalQuality {
+    /// Grades a raw signal level, where 0 is no signal & 0xFF is the best.
+    pub const fn from_level(level: u8) -> Self {
+        match level {
+            0 => Self::Lost,
+            level if level < FAIR_LEVEL => Self::Weak,
+            level if level < GOOD_LEVEL => Self::Fair,
+            _ => Self::Good,
+        }
+    }
+    /// Whether the player should be warned that their connection may drop.
+    pub const fn is_at_risk(self) -> bool {
+        matches!(self, Self::Lost | Self::Weak)
+    }
+}
+
+/// The signal level of each client's connection to the host.
+///
+/// The host sees the levels of every client, while a client only sees its
+/// own.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SignalLevels {
+    levels: [u8; SIGNAL_SLOTS],
+}
+
+impl SignalLevels {
+    /// Parses the answer to [Command::SignalLevel], which has 1 byte per
+    /// client slot.
+    pub const fn from_word(word: u32) -> Self {
+        Self {
+            levels: word.to_le_bytes(),
+        }
+    }
+    /// The raw signal level of the connection to `player`, or `None` for the
+    /// host.
+    pub const fn level(&self, player: WirelessPlayerId) -> Option<u8> {
+        match player.client_slot() {
+            Some(slot) => Some(self.levels[slot as usize]),
+            None => None,
+        }
+    }
+    /// The graded signal of the connection to `player`; see [Self::level].
+    pub const fn quality(&self, player: WirelessPlayerId) -> Option<SignalQuality> {
+        match self.level(player) {
+            Some(level) => Some(SignalQuality::from_level(level)),
+            None => None,
+        }
+    }
+    /// The player with the weakest signal that's still connected, along with
+    /// its level.
+    pub fn weakest(&self) -> Option<(WirelessPlayerId, u8)> {
+        WirelessPlayerId::ALL
+            .into_iter()
+            .filter_map(|player| Some((player, self.level(player)?)))
+            .filter(|&(_, level)| level != 0)
+            .min_by_key(|&(_, level)| level)
+    }
+}
+
+/// Who is connected to the session.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SlotStatus {
+    /// The slot the next player to join will get, or 0xFF if the room is full.
+    pub next_slot: u8,
+    /// The clients still connected.
+    pub clients: Vec<RoomClient>,
+}
+
+impl SlotStatus {
+    /// Parses the answer to [Command::SlotStatus].
+    fn from_words(words: &[u32]) -> Result<Self, WirelessError> {
+        let Some((&first, clients)) = words.split_first() else {
+            return Err(WirelessError::InvalidResponse(0));
+        };
+        Ok(Self {
+            next_slot: first as u8,
+            clients: clients
+                .iter()
+                .map(|&word| RoomClient::from_word(word))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl WirelessAdapter<'_> {
+    /// Reads the signal level of each connection in the session.
+    pub fn signal_levels(&mut self) -> Result<SignalLevels, WirelessError> {
+        let mut response = [0; 1];
+        let len = self.command(Command::SignalLevel, &[], &mut response)?;
+        if len == 0 {
+            return Err(WirelessError::InvalidResponse(0));
+        }
+        Ok(SignalLevels::from_word(response[0]))
+    }
+    /// Lists the clients still connected to the session.
+    pub fn slot_status(&mut self) -> Result<SlotStatus, WirelessError> {
+        let mut response = [0; 1 + SIGNAL_SLOTS];
+        let len = self.command(Command::SlotStatus, &[], &mut response)?;
+        SlotStatus::from_words(&response[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_signal_parsing(_gba: &mut Gba) {
+        let levels = SignalLevels::from_word(0x00FF_1050);
+        assert_eq!(levels.level(WirelessPlayerId::P0), None);
+        assert_eq!(levels.level(WirelessPlayerId::P1), Some(0x50));
+        let quality = WirelessPlayerId::ALL.map(|player| levels.quality(player));
+        assert_eq!(
+            quality,
+            [
+                None,
+                Some(SignalQuality::Fair),
+                Some(SignalQuality::Weak),
+                Some(SignalQuality::Good),
+                Some(SignalQuality::Lost)
+            ]
+        );
+        assert_eq!(levels.weakest(), Some((WirelessPlayerId::P2, 0x10)));
+        assert!(SignalQuality::Weak.is_at_risk());
+        assert!(!SignalQuality::Fair.is_at_risk());
+
+        let status = SlotStatus::from_words(&[0x02, 0x0000_1234, 0x0001_5678]).unwrap();
+        assert_eq!(status.next_slot, 2);
+        assert_eq!(status.clients[1].player, WirelessPlayerId::P2);
+        assert_eq!(
+            SlotStatus::from_words(&[]),
+            Err(WirelessError::InvalidResponse(0))
+        );
+    }
+}
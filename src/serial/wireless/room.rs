@@ -0,0 +1,356 @@
+//! Hosting, finding & joining rooms over the Wireless Adapter.
+//!
+//! A room is advertised by its host with a [RoomInfo] broadcast, which every
+//! other adapter scanning nearby can see. A client then joins the room by its
+//! ID, after which the host sees it in [WirelessAdapter::poll_host].
+//!
+//! The broadcast keeps the standard layout that other games & tools read: the
+//! game ID, then a 14 byte game name, then 8 bytes of user data. The user
+//! data area is free-form, so [RoomInfo] splits it between a short
+//! [RoomInfo::user_name], the [RoomInfo::players] count & [RoomInfo::custom]
+//! bytes.
+//!
+//! The broadcast can be changed while hosting with
+//! [WirelessAdapter::update_broadcast], so that scanning players see things
+//! like the current player count live.
+
+use alloc::vec::Vec;
+
+use super::{Command, WirelessAdapter, WirelessError, WirelessPlayerId};
+
+/// The most bytes in a [RoomInfo::game_name].
+pub const GAME_NAME_LEN: usize = 14;
+/// The number of bytes of user data at the end of a broadcast, holding
+/// [RoomInfo::user_name], [RoomInfo::players] & [RoomInfo::custom].
+pub const USER_DATA_LEN: usize = 8;
+/// The most bytes in a [RoomInfo::user_name].
+pub const USER_NAME_LEN: usize = 4;
+/// The number of bytes in [RoomInfo::custom].
+pub const CUSTOM_LEN: usize = 3;
+/// The number of words in a broadcast.
+pub const BROADCAST_WORDS: usize = 6;
+/// The most players in a session, including the host.
+pub const MAX_PLAYERS: u8 = WirelessPlayerId::ALL.len() as u8;
+/// The most rooms a single scan can find.
+pub const MAX_ROOMS: usize = 4;
+
+/// The base value of the [Command::Setup] parameter.
+const SETUP_MAGIC: u32 = 0x003C_0420;
+/// The answer to [Command::IsConnectionComplete] while still joining.
+const STILL_CONNECTING: u32 = 0x0100_0000;
+/// Where each field of a [RoomInfo] starts in its broadcast.
+const GAME_NAME_OFFSET: usize = 2;
+const USER_NAME_OFFSET: usize = GAME_NAME_OFFSET + GAME_NAME_LEN;
+const PLAYERS_OFFSET: usize = USER_NAME_OFFSET + USER_NAME_LEN;
+const CUSTOM_OFFSET: usize = PLAYERS_OFFSET + 1;
+/// The number of words describing each room found by a scan.
+const ROOM_WORDS: usize = BROADCAST_WORDS + 1;
+/// The "next slot" value of a room that can't be joined.
+const ROOM_FULL: u8 = 0xFF;
+
+/// The data a host broadcasts to describe its room.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct RoomInfo {
+    /// Identifies the game, so that scanning games can ignore rooms for
+    /// other games; only the low 15 bits are used.
+    pub game_id: u16,
+    /// The game's name, padded with 0s.
+    pub game_name: [u8; GAME_NAME_LEN],
+    /// The hosting player's name, padded with 0s.
+    pub user_name: [u8; USER_NAME_LEN],
+    /// The number of players in the room, including the host.
+    pub players: u8,
+    /// Anything else the game wants scanning players to see, such as the
+    /// selected mode or map.
+    pub custom: [u8; CUSTOM_LEN],
+}
+
+impl RoomInfo {
+    /// Builds a broadcast for a room with just the host in it from strings,
+    /// cutting off any bytes that don't fit.
+    pub fn new(game_id: u16, game_name: &str, user_name: &str) -> Self {
+        let mut retvl = Self {
+            game_id: game_id & 0x7FFF,
+            players: 1,
+            ..Self::default()
+        };
+        copy_truncated(&mut retvl.game_name, game_name.as_bytes());
+        copy_truncated(&mut retvl.user_name, user_name.as_bytes());
+        retvl
+    }
+    /// Packs the broadcast into the words sent with [Command::Broadcast].
+    pub fn to_words(&self) -> [u32; BROADCAST_WORDS] {
+        let mut bytes = [0; BROADCAST_WORDS * 4];
+        bytes[..GAME_NAME_OFFSET].copy_from_slice(&self.game_id.to_le_bytes());
+        bytes[GAME_NAME_OFFSET..USER_NAME_OFFSET].copy_from_slice(&self.game_name);
+        bytes[USER_NAME_OFFSET..PLAYERS_OFFSET].copy_from_slice(&self.user_name);
+        bytes[PLAYERS_OFFSET] = self.players;
+        bytes[CUSTOM_OFFSET..].copy_from_slice(&self.custom);
+        let mut retvl = [0; BROADCAST_WORDS];
+        for (word, chunk) in retvl.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        retvl
+    }
+    /// Unpacks a broadcast packed with [Self::to_words].
+    pub fn from_words(words: &[u32; BROADCAST_WORDS]) -> Self {
+        let mut bytes = [0; BROADCAST_WORDS * 4];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        let mut retvl = Self {
+            game_id: u16::from_le_bytes([bytes[0], bytes[1]]) & 0x7FFF,
+            players: bytes[PLAYERS_OFFSET],
+            ..Self::default()
+        };
+        retvl
+            .game_name
+            .copy_from_slice(&bytes[GAME_NAME_OFFSET..USER_NAME_OFFSET]);
+        retvl
+            .user_name
+            .copy_from_slice(&bytes[USER_NAME_OFFSET..PLAYERS_OFFSET]);
+        retvl.custom.copy_from_slice(&bytes[CUSTOM_OFFSET..]);
+        retvl
+    }
+}
+
+/// Copies as much of `src` as fits into `dst`.
+fn copy_truncated(dst: &mut [u8], src: &[u8]) {
+    let len = dst.len().min(src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
+
+/// A room found while scanning.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Room {
+    /// The ID used to join the room.
+    pub id: u16,
+    /// The slot the next player to join will get, or 0xFF if the room is full.
+    pub next_slot: u8,
+    pub info: RoomInfo,
+}
+
+impl Room {
+    /// Parses the words describing a single room in a scan's response.
+    fn from_words(words: &[u32; ROOM_WORDS]) -> Self {
+        let mut broadcast = [0; BROADCAST_WORDS];
+        broadcast.copy_from_slice(&words[1..]);
+        Self {
+            id: words[0] as u16,
+            next_slot: (words[0] >> 16) as u8,
+            info: RoomInfo::from_words(&broadcast),
+        }
+    }
+    pub const fn is_full(&self) -> bool {
+        self.next_slot == ROOM_FULL
+    }
+}
+
+/// Parses every room in a scan's response.
+fn parse_rooms(words: &[u32]) -> Vec<Room> {
+    words
+        .chunks_exact(ROOM_WORDS)
+        .map(|chunk| {
+            let mut room = [0; ROOM_WORDS];
+            room.copy_from_slice(chunk);
+            Room::from_words(&room)
+        })
+        .collect()
+}
+
+/// A client that has joined the room we're hosting.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RoomClient {
+    /// The adapter's ID for the client.
+    pub id: u16,
+    /// The client's player ID; the host is always [WirelessPlayerId::P0].
+    pub player: WirelessPlayerId,
+}
+
+impl RoomClient {
+    /// Parses a client's word in the answer to [Command::PollHost] or
+    /// [Command::SlotStatus].
+    pub fn from_word(word: u32) -> Result<Self, WirelessError> {
+        Ok(Self {
+            id: word as u16,
+            player: parse_client_slot(word)?,
+        })
+    }
+}
+
+/// Parses the client slot in the upper half of `word`.
+fn parse_client_slot(word: u32) -> Result<WirelessPlayerId, WirelessError> {
+    WirelessPlayerId::from_client_slot((word >> 16) as u8)
+        .ok_or(WirelessError::InvalidResponse(word))
+}
+
+/// The progress of joining a room.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JoinStatus {
+    /// The host hasn't accepted us yet.
+    Connecting,
+    /// We've joined the room with this player ID.
+    Joined(WirelessPlayerId),
+}
+
+/// Parses the answer to [Command::IsConnectionComplete].
+fn parse_join_status(word: u32) -> Result<JoinStatus, WirelessError> {
+    if word == STILL_CONNECTING {
+        return Ok(JoinStatus::Connecting);
+    }
+    Ok(JoinStatus::Joined(parse_client_slot(word)?))
+}
+
+impl WirelessAdapter<'_> {
+    /// Configures the adapter for a session of up to `max_players` players,
+    /// including the host.
+    pub fn setup(&mut self, max_players: u8) -> Result<(), WirelessError> {
+        let max_players = max_players.clamp(2, MAX_PLAYERS);
+        let param = SETUP_MAGIC | (((MAX_PLAYERS - max_players) as u32 & 0b11) << 16);
+        self.command(Command::Setup, &[param], &mut [])?;
+        Ok(())
+    }
+
+    /// Starts hosting a room for up to `max_players` players, advertised with
+    /// `info`. Does NOT block.
+    pub fn host_room(&mut self, info: &RoomInfo, max_players: u8) -> Result<(), WirelessError> {
+        self.setup(max_players)?;
+        self.update_broadcast(info)?;
+        self.command(Command::StartHost, &[], &mut [])?;
+        Ok(())
+    }
+    /// Changes what scanning players see about the room we're hosting.
+    pub fn update_broadcast(&mut self, info: &RoomInfo) -> Result<(), WirelessError> {
+        self.command(Command::Broadcast, &info.to_words(), &mut [])?;
+        Ok(())
+    }
+    /// Like [Self::poll_host], but also updates [RoomInfo::players] in the
+    /// broadcast whenever the number of clients changes.
+    pub fn poll_host_advertising(
+        &mut self,
+        info: &mut RoomInfo,
+    ) -> Result<Vec<RoomClient>, WirelessError> {
+        let clients = self.poll_host()?;
+        let players = clients.len() as u8 + 1;
+        if info.players != players {
+            info.players = players;
+            self.update_broadcast(info)?;
+        }
+        Ok(clients)
+    }
+    /// Lists the clients that have joined the room we're hosting so far.
+    pub fn poll_host(&mut self) -> Result<Vec<RoomClient>, WirelessError> {
+        let mut response = [0; MAX_PLAYERS as usize];
+        let len = self.command(Command::PollHost, &[], &mut response)?;
+        response[..len]
+            .iter()
+            .map(|&word| RoomClient::from_word(word))
+            .collect()
+    }
+    /// Stops letting new clients join the room we're hosting, returning the
+    /// clients that did.
+    pub fn close_room(&mut self) -> Result<Vec<RoomClient>, WirelessError> {
+        let clients = self.poll_host()?;
+        self.command(Command::EndHost, &[], &mut [])?;
+        Ok(clients)
+    }
+
+    /// Starts scanning for rooms. Does NOT block.
+    ///
+    /// Rooms take around a second to show up; use [Self::poll_scan] to list
+    /// the ones found so far.
+    pub fn start_scan(&mut self) -> Result<(), WirelessError> {
+        self.command(Command::BroadcastReadStart, &[], &mut [])?;
+        Ok(())
+    }
+    /// Lists the rooms found since [Self::start_scan].
+    pub fn poll_scan(&mut self) -> Result<Vec<Room>, WirelessError> {
+        self.scan_command(Command::BroadcastReadPoll)
+    }
+    /// Stops scanning, listing the rooms found.
+    pub fn end_scan(&mut self) -> Result<Vec<Room>, WirelessError> {
+        self.scan_command(Command::BroadcastReadEnd)
+    }
+    fn scan_command(&mut self, command: Command) -> Result<Vec<Room>, WirelessError> {
+        let mut response = [0; MAX_ROOMS * ROOM_WORDS];
+        let len = self.command(command, &[], &mut response)?;
+        Ok(parse_rooms(&response[..len]))
+    }
+
+    /// Starts joining the room with the given ID. Does NOT block.
+    pub fn join_room(&mut self, room_id: u16) -> Result<(), WirelessError> {
+        self.command(Command::Connect, &[room_id as u32], &mut [])?;
+        Ok(())
+    }
+    /// Checks whether joining the room has finished, finishing the connection
+    /// once it has.
+    pub fn poll_join(&mut self) -> Result<JoinStatus, WirelessError> {
+        let mut response = [0; 1];
+        let len = self.command(Command::IsConnectionComplete, &[], &mut response)?;
+        if len == 0 {
+            return Err(WirelessError::InvalidResponse(0));
+        }
+        let status = parse_join_status(response[0])?;
+        if let JoinStatus::Joined(_) = status {
+            self.command(Command::FinishConnection, &[], &mut [])?;
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+
+    #[test_case]
+    fn test_room_parsing(_gba: &mut Gba) {
+        let info = RoomInfo::new(0x8123, "Serial Experiments GBA", "Lain");
+        assert_eq!(info.game_id, 0x0123);
+        assert_eq!(info.players, 1);
+        assert_eq!(&info.game_name, b"Serial Experim");
+        assert_eq!(&info.user_name, b"Lain");
+        let info = RoomInfo {
+            players: 3,
+            custom: [1, 2, 3],
+            ..info
+        };
+        let words = info.to_words();
+        assert_eq!(words[0], 0x6553_0123);
+        assert_eq!(words[4], 0x6E69_614C);
+        assert_eq!(words[5], 0x0302_0103);
+        assert_eq!(RoomInfo::from_words(&words), info);
+
+        let mut response = [0; ROOM_WORDS * 2];
+        response[0] = 0x0002_1234;
+        response[1..ROOM_WORDS].copy_from_slice(&words);
+        response[ROOM_WORDS] = 0x00FF_5678;
+        let rooms = parse_rooms(&response);
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[0].id, 0x1234);
+        assert_eq!(rooms[0].next_slot, 2);
+        assert!(!rooms[0].is_full());
+        assert_eq!(rooms[0].info, info);
+        assert!(rooms[1].is_full());
+
+        assert_eq!(
+            parse_join_status(STILL_CONNECTING),
+            Ok(JoinStatus::Connecting)
+        );
+        assert_eq!(
+            parse_join_status(0x0002_4321),
+            Ok(JoinStatus::Joined(WirelessPlayerId::P3))
+        );
+        assert_eq!(
+            parse_join_status(0x0004_4321),
+            Err(WirelessError::InvalidResponse(0x0004_4321))
+        );
+        assert_eq!(
+            RoomClient::from_word(0x0001_4321),
+            Ok(RoomClient {
+                id: 0x4321,
+                player: WirelessPlayerId::P2
+            })
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Exchanging data with the other players in a wireless session, using the
+//! same interface as [BulkMultiplayer](crate::serial::multiplayer::bulk::BulkMultiplayer).
+//!
+//! Each [tick](WirelessMultiplayer::tick) sends the next batch of queued words
+//! with [Command::SendData] and then reads whatever arrived with
+//! [Command::ReceiveData]. Both commands start with a header word holding byte
+//! counts:
+//!
+//! * The host's header is just the number of bytes it's sending, up to
+//!   [HOST_MAX_BYTES].
+//! * A client's header holds the number of bytes it's sending, up to
+//!   [CLIENT_MAX_BYTES], in the 5 bits starting at bit `3 + 5 * player`.
+//!
+//! The host receives each client's data in player order, and clients only
+//! receive data from the host. Since a session can have up to 5 players, data
+//! is buffered per [WirelessPlayerId] rather than per
+//! [PlayerId](crate::serial::multiplayer::PlayerId).
+//!
+//! # Reconnecting
+//!
+//! By default any error from the adapter is returned by
+//! [tick](WirelessMultiplayer::tick). Once [WirelessMultiplayer::set_rejoin]
+//! has been called, errors instead start a reconnection in the background:
+//! later ticks log in to the adapter again and re-host or re-join the room,
+//! with the progress reported by [WirelessMultiplayer::poll_event]. Each
+//! failed attempt doubles the number of ticks until the next one, up to
+//! [MAX_BACKOFF_TICKS], so an unplugged adapter isn't hammered every frame.
+
+use alloc::collections::VecDeque;
+
+use super::room::{JoinStatus, RoomInfo};
+use super::{Command, WirelessAdapter, WirelessError, WirelessPlayerId};
+use crate::serial::transport::{MultiplayerTransport, MAX_PLAYERS};
+
+/// The most bytes the host can send in a single tick.
+pub const HOST_MAX_BYTES: usize = 80;
+/// The most bytes a client can send in a single tick.
+pub const CLIENT_MAX_BYTES: usize = 16;
+/// The most players whose data is buffered, including the host.
+pub const SESSION_PLAYERS: usize = WirelessPlayerId::ALL.len();
+
+/// The mask of a single client's byte count in a header.
+const CLIENT_COUNT_MASK: u32 = 0x1F;
+/// The mask of the host's byte count in a header.
+const HOST_COUNT_MASK: u32 = 0x7F;
+
+/// The header sent with [Command::SendData] by `player`.
+const fn send_header(player: WirelessPlayerId, bytes: usize) -> u32 {
+    match player {
+        WirelessPlayerId::P0 => bytes as u32,
+        player => (bytes as u32) << (3 + 5 * player as u32),
+    }
+}
+
+/// How many bytes `player` sent, according to the header received by
+/// `receiver`.
+const fn received_bytes(
+    receiver: WirelessPlayerId,
+    header: u32,
+    player: WirelessPlayerId,
+) -> usize {
+    match (receiver.is_host(), player.is_host()) {
+        (true, false) => ((header >> (3 + 5 * player as u32)) & CLIENT_COUNT_MASK) as usize,
+        (false, true) => (header & HOST_COUNT_MASK) as usize,
+        _ => 0,
+    }
+}
+
+/// Splits the data words of a [Command::ReceiveData] response by player,
+/// calling `sink` with each player's halfwords in order.
+fn split_received(
+    receiver: WirelessPlayerId,
+    response: &[u32],
+    mut sink: impl FnMut(WirelessPlayerId, u16),
+) {
+    let Some((&header, mut words)) = response.split_first() else {
+        return;
+    };
+    for player in WirelessPlayerId::ALL {
+        let bytes = received_bytes(receiver, header, player);
+        let len = bytes.div_ceil(4).min(words.len());
+        let (data, rest) = words.split_at(len);
+        words = rest;
+        let halfwords = data
+            .iter()
+            .flat_map(|word| [*word as u16, (*word >> 16) as u16]);
+        for half in halfwords.take(bytes / 2) {
+            sink(player, half);
+        }
+    }
+}
+
+/// Packs up to `max_bytes` bytes of halfwords from `outbox` into `words`,
+/// returning the number of bytes & words used.
+fn pack_outgoing(
+    outbox: &mut VecDeque<u16>,
+    max_bytes: usize,
+    words: &mut [u32],
+) -> (usize, usize) {
+    let halves = outbox.len().min(max_bytes / 2);
+    let mut idx = 0;
+    while idx < halves {
+        let low = outbox.pop_front().unwrap_or_default() as u32;
+        let high = if idx + 1 < halves {
+            outbox.pop_front().unwrap_or_default() as u32
+        } else {
+            0
+        };
+        words[idx / 2] = low | (high << 16);
+        idx += 2;
+    }
+    (halves * 2, halves.div_ceil(2))
+}
+
+/// How to get back into the session after the connection drops.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Rejoin {
+    /// Host the room again with the same broadcast & player limit.
+    Host { info: RoomInfo, max_players: u8 },
+    /// Join the room with this ID again.
+    Client { room_id: u16 },
+}
+
+/// Something that happened to the connection, reported instead of an error
+/// while reconnecting is enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SessionEvent {
+    /// The connection dropped because of this error; reconnecting has started.
+    Disconnected(WirelessError),
+    /// We're back in the session with this player ID, which may differ from
+    /// the one we had before.
+    Reconnected(WirelessPlayerId),
+}
+
+/// Where a session is in reconnecting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LinkState {
+    Connected,
+    /// Waiting to log in to the adapter again.
+    LoggingIn,
+    /// Waiting for the host to accept us back.
+    Joining,
+}
+
+/// The most events kept for [WirelessMultiplayer::poll_event]; older events
+/// are dropped first.
+const MAX_EVENTS: usize = 8;
+/// The most ticks between reconnection attempts.
+pub const MAX_BACKOFF_TICKS: u32 = 64;
+
+/// Spaces out reconnection attempts, doubling the wait after each failure.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Backoff {
+    delay: u32,
+    remaining: u32,
+}
+
+impl Backoff {
+    /// Makes the first attempt straight away.
+    const fn new() -> Self {
+        Self {
+            delay: 0,
+            remaining: 0,
+        }
+    }
+    /// Counts down a tick, returning whether an attempt is due.
+    fn tick(&mut self) -> bool {
+        if self.remaining == 0 {
+            return true;
+        }
+        self.remaining -= 1;
+        false
+    }
+    /// Records a failed attempt, waiting longer before the next one.
+    fn failed(&mut self) {
+        self.delay = (self.delay * 2).clamp(1, MAX_BACKOFF_TICKS);
+        self.remaining = self.delay;
+    }
+}
+
+/// A wireless multiplayer session, with buffers for data in both directions.
+pub struct WirelessMultiplayer<'a> {
+    adapter: WirelessAdapter<'a>,
+    id: WirelessPlayerId,
+    cap: usize,
+    outbox: VecDeque<u16>,
+    inboxes: [VecDeque<u16>; SESSION_PLAYERS],
+    rejoin: Option<Rejoin>,
+    state: LinkState,
+    backoff: Backoff,
+    events: VecDeque<SessionEvent>,
+}
+
+impl<'a> WirelessMultiplayer<'a> {
+    /// Starts exchanging data in a session we've already joined (or are
+    /// hosting) as player `id`, buffering up to `cap` words in each direction
+    /// per player.
+    pub fn new(adapter: WirelessAdapter<'a>, id: WirelessPlayerId, cap: usize) -> Self {
+        Self {
+            adapter,
+            id,
+            cap,
+            outbox: VecDeque::with_capacity(cap),
+            inboxes: core::array::from_fn(|_| VecDeque::with_capacity(cap)),
+            rejoin: None,
+            state: LinkState::Connected,
+            backoff: Backoff::new(),
+            events: VecDeque::new(),
+        }
+    }
+    /// Enables reconnecting after the connection drops, using `rejoin` to get
+    /// back into the session, or disables it when `None`.
+    ///
+    /// Disabling it while reconnecting gives up on the reconnection: later
+    /// ticks return [WirelessError::NotConnected] without touching the
+    /// adapter, until reconnecting is enabled again.
+    pub fn set_rejoin(&mut self, rejoin: Option<Rejoin>) {
+        self.rejoin = rejoin;
+    }
+    /// Whether we're currently in the session, rather than reconnecting.
+    pub fn is_connected(&self) -> bool {
+        self.state == LinkState::Connected
+    }
+    /// Takes the oldest connection event that hasn't been read yet.
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+    /// Our player ID; the host is always [WirelessPlayerId::P0].
+    pub fn id(&self) -> WirelessPlayerId {
+        self.id
+    }
+    pub fn is_host(&self) -> bool {
+        self.id.is_host()
+    }
+    /// Queues as much of `buffer` as fits to be sent to every other player,
+    /// returning how many words were queued.
+    pub fn queue_send(&mut self, buffer: &[u16]) -> usize {
+        let len = buffer.len().min(self.cap - self.outbox.len());
+        self.outbox.extend(&buffer[..len]);
+        len
+    }
+    /// Pulls received data into `buffers`, 1 per player in player order,
+    /// returning how many words were read for each player.
+    ///
+    /// Unlike with the link cable, each player's data arrives independently,
+    /// so different amounts can be read for each player.
+    pub fn read_bulk(
+        &mut self,
+        buffers: &mut [&mut [u16]; SESSION_PLAYERS],
+    ) -> [usize; SESSION_PLAYERS] {
+        let mut retvl = [0; SESSION_PLAYERS];
+        for ((inbox, buffer), read) in self.inboxes.iter_mut().zip(buffers).zip(&mut retvl) {
+            let len = inbox.len().min(buffer.len());
+            for (slot, word) in buffer.iter_mut().zip(inbox.drain(..len)) {
+                *slot = word;
+            }
+            *read = len;
+        }
+        retvl
+    }
+    /// Sends the next batch of queued data and reads whatever has arrived.
+    ///
+    /// While reconnecting this instead takes the next reconnection step, and
+    /// keeps any queued data until we're back in the session.
+    pub fn tick(&mut self) -> Result<(), WirelessError> {
+        let result = match self.state {
+            LinkState::Connected => self.exchange(),
+            _ if self.rejoin.is_none() => return Err(WirelessError::NotConnected),
+            _ if !self.backoff.tick() => return Ok(()),
+            LinkState::LoggingIn => self.log_in_again(),
+            LinkState::Joining => self.poll_rejoin(),
+        };
+        match (result, self.rejoin) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), None) => Err(e),
+            (Err(e), Some(_)) => {
+                if self.state == LinkState::Connected {
+                    self.push_event(SessionEvent::Disconnected(e));
+                    self.backoff = Backoff::new();
+                } else {
+                    self.backoff.failed();
+                }
+                self.state = LinkState::LoggingIn;
+                Ok(())
+            }
+        }
+    }
+    fn log_in_again(&mut self) -> Result<(), WirelessError> {
+        self.adapter.relogin()?;
+        match self.rejoin {
+            Some(Rejoin::Host { info, max_players }) => {
+                self.adapter.host_room(&info, max_players)?;
+                self.reconnected(WirelessPlayerId::P0);
+            }
+            Some(Rejoin::Client { room_id }) => {
+                self.adapter.join_room(room_id)?;
+                self.state = LinkState::Joining;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+    fn poll_rejoin(&mut self) -> Result<(), WirelessError> {
+        if let JoinStatus::Joined(id) = self.adapter.poll_join()? {
+            self.reconnected(id);
+        }
+        Ok(())
+    }
+    fn reconnected(&mut self, id: WirelessPlayerId) {
+        self.id = id;
+        self.state = LinkState::Connected;
+        self.push_event(SessionEvent::Reconnected(id));
+    }
+    fn push_event(&mut self, event: SessionEvent) {
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+    fn exchange(&mut self) -> Result<(), WirelessError> {
+        let max_bytes = if self.is_host() {
+            HOST_MAX_BYTES
+        } else {
+            CLIENT_MAX_BYTES
+        };
+        let mut params = [0; 1 + HOST_MAX_BYTES / 4];
+        if !self.outbox.is_empty() {
+            let (bytes, words) = pack_outgoing(&mut self.outbox, max_bytes, &mut params[1..]);
+            params[0] = send_header(self.id, bytes);
+            self.adapter
+                .command(Command::SendData, &params[..1 + words], &mut [])?;
+        }
+
+        let mut response = [0; 1 + HOST_MAX_BYTES / 4];
+        let len = self
+            .adapter
+            .command(Command::ReceiveData, &[], &mut response)?;
+        let cap = self.cap;
+        let inboxes = &mut self.inboxes;
+        split_received(self.id, &response[..len], |player, half| {
+            let inbox = &mut inboxes[player as usize];
+            if inbox.len() < cap {
+                inbox.push_back(half);
+            }
+        });
+        Ok(())
+    }
+    pub fn adapter(&mut self) -> &mut WirelessAdapter<'a> {
+        &mut self.adapter
+    }
+    /// Stops exchanging data, dropping anything still buffered.
+    pub fn leave(self) -> WirelessAdapter<'a> {
+        self.adapter
+    }
+}
+
+impl MultiplayerTransport for WirelessMultiplayer<'_> {
+    type Error = WirelessError;
+    fn queue_send(&mut self, buffer: &[u16]) -> Result<usize, Self::Error> {
+        Ok(WirelessMultiplayer::queue_send(self, buffer))
+    }
+    const PLAYERS: usize = SESSION_PLAYERS;
+    fn read_bulk(
+        &mut self,
+        buffers: &mut [&mut [u16]; MAX_PLAYERS],
+    ) -> Result<[usize; MAX_PLAYERS], Self::Error> {
+        Ok(WirelessMultiplayer::read_bulk(self, buffers))
+    }
+    fn tick(&mut self) -> Result<(), Self::Error> {
+        WirelessMultiplayer::tick(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agb::Gba;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_data_framing(_gba: &mut Gba) {
+        assert_eq!(send_header(WirelessPlayerId::P0, 80), 80);
+        assert_eq!(send_header(WirelessPlayerId::P1, 16), 16 << 8);
+        assert_eq!(send_header(WirelessPlayerId::P3, 6), 6 << 18);
+        assert_eq!(send_header(WirelessPlayerId::P4, 2), 2 << 23);
+
+        let mut outbox: VecDeque<u16> = [1, 2, 3].into_iter().collect();
+        let mut words = [0; 4];
+        assert_eq!(
+            pack_outgoing(&mut outbox, CLIENT_MAX_BYTES, &mut words),
+            (6, 2)
+        );
+        assert_eq!(words[..2], [0x0002_0001, 0x0000_0003]);
+        assert!(outbox.is_empty());
+
+        // The host receiving 4 bytes from P1 & 2 from P4.
+        let header = send_header(WirelessPlayerId::P1, 4) | send_header(WirelessPlayerId::P4, 2);
+        let mut received = Vec::new();
+        let response = [header, 0x0002_0001, 0x0000_0003];
+        split_received(WirelessPlayerId::P0, &response, |player, half| {
+            received.push((player as u8, half))
+        });
+        assert_eq!(received, [(1, 1), (1, 2), (4, 3)]);
+
+        // A client receiving 6 bytes from the host.
+        received.clear();
+        let response = [6, 0x0002_0001, 0x0000_0003];
+        split_received(WirelessPlayerId::P2, &response, |player, half| {
+            received.push((player as u8, half))
+        });
+        assert_eq!(received, [(0, 1), (0, 2), (0, 3)]);
+    }
+
+    #[test_case]
+    fn test_backoff(_gba: &mut Gba) {
+        let mut backoff = Backoff::new();
+        assert!(backoff.tick());
+        for delay in [1, 2, 4] {
+            backoff.failed();
+            for _ in 0..delay {
+                assert!(!backoff.tick());
+            }
+            assert!(backoff.tick());
+        }
+        for _ in 0..10 {
+            backoff.failed();
+        }
+        assert_eq!(backoff.remaining, MAX_BACKOFF_TICKS);
+    }
+}
@@ -1,7 +1,10 @@
 //! Misc utility structs and functions.
 
 use agb::external::critical_section::{self, CriticalSection, Mutex};
+use agb::interrupt::VBlank;
+use agb::timer::{Divider, Timer};
 use core::cell::Cell;
+use voladdress::{Safe, VolAddress};
 
 /// Reads the `n`th bit from a `u16` as a bool.
 ///
@@ -127,6 +130,156 @@ impl<T: Default> GbaCell<T> {
     }
 }
 
+/// Counts down a timeout measured in frames, using the VBlank interrupt to
+/// sleep between checks.
+///
+/// # Examples
+/// ```
+/// let mut timeout = FrameTimeout::new(Some(60));
+/// loop {
+///     if try_the_thing() {
+///         break;
+///     }
+///     if !timeout.wait() {
+///         return Err(Timeout);
+///     }
+/// }
+/// ```
+pub struct FrameTimeout {
+    vblank: VBlank,
+    remaining: Option<u32>,
+}
+
+impl FrameTimeout {
+    /// Creates a timeout that will expire after `frames` frames, or never if
+    /// `frames` is `None`.
+    pub fn new(frames: Option<u32>) -> Self {
+        Self {
+            vblank: VBlank::get(),
+            remaining: frames,
+        }
+    }
+    /// Sleeps until the next frame, unless the timeout has already expired.
+    ///
+    /// Returns `false` (without sleeping) if the timeout has expired.
+    pub fn wait(&mut self) -> bool {
+        match self.remaining.as_mut() {
+            Some(0) => return false,
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        self.vblank.wait_for_vblank();
+        true
+    }
+    /// Whether or not the timeout has expired.
+    pub fn expired(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+/// The current scanline; lines from [VBLANK_LINE] onwards are in VBlank.
+const VCOUNT: VolAddress<u16, Safe, ()> = unsafe { VolAddress::new(0x4000006) };
+const VBLANK_LINE: u16 = 160;
+
+/// Busy-waits until the next scanline starts, which is at most 1232 cycles
+/// (about 73µs) away.
+pub fn wait_next_scanline() {
+    let line = VCOUNT.read();
+    while VCOUNT.read() == line {}
+}
+
+/// Counts down a timeout measured in frames by watching the scanline counter
+/// for the start of each VBlank.
+///
+/// Unlike [FrameTimeout] this never sleeps, so nothing is missed between
+/// checks, and unlike [TimerTimeout] it doesn't need a hardware timer.
+/// [VBlankTimeout::expired] must be called at least once per VBlank (about
+/// 4.5ms) for the timeout to be accurate.
+pub struct VBlankTimeout {
+    remaining: Option<u32>,
+    in_vblank: bool,
+}
+
+impl VBlankTimeout {
+    /// Creates a timeout that will expire after `frames` VBlanks start, or
+    /// never if `frames` is `None`.
+    pub fn new(frames: Option<u32>) -> Self {
+        Self {
+            remaining: frames,
+            in_vblank: VCOUNT.read() >= VBLANK_LINE,
+        }
+    }
+    /// Whether or not the timeout has expired. Does NOT block.
+    pub fn expired(&mut self) -> bool {
+        let in_vblank = VCOUNT.read() >= VBLANK_LINE;
+        let vblank_started = in_vblank && !self.in_vblank;
+        self.in_vblank = in_vblank;
+        match self.remaining.as_mut() {
+            Some(n) if vblank_started => *n = n.saturating_sub(1),
+            _ => {}
+        }
+        self.remaining == Some(0)
+    }
+}
+
+/// The CPU clock speed, in cycles per second.
+pub(crate) const CPU_HZ: u32 = 16_777_216;
+
+/// The number of CPU cycles in a single frame.
+pub(crate) const CYCLES_PER_FRAME: u32 = 280_896;
+
+/// Measures a timeout in frames using one of the GBA's hardware timers.
+///
+/// Unlike [FrameTimeout] this never sleeps, so it can be used in tight polling
+/// loops where waiting for the next VBlank would take too long (eg while
+/// waiting on a hardware FIFO that could overflow within a single frame).
+///
+/// The timer is reconfigured on creation and disabled again when this is
+/// dropped. [TimerTimeout::expired] must be called at least once every 4
+/// seconds for the timeout to be accurate.
+pub struct TimerTimeout<'t> {
+    timer: &'t mut Timer,
+    last: u16,
+    remaining_ticks: u64,
+}
+
+impl<'t> TimerTimeout<'t> {
+    /// The divider used for the timer; each tick is 1024 CPU cycles.
+    const DIVIDER: Divider = Divider::Divider1024;
+    const CYCLES_PER_TICK: u64 = 1024;
+
+    /// Starts a timeout that will expire after `frames` frames.
+    pub fn new(timer: &'t mut Timer, frames: u32) -> Self {
+        timer
+            .set_enabled(false)
+            .set_cascade(false)
+            .set_interrupt(false)
+            .set_divider(Self::DIVIDER)
+            .set_overflow_amount(0)
+            .set_enabled(true);
+        let last = timer.value();
+        Self {
+            timer,
+            last,
+            remaining_ticks: (frames as u64 * CYCLES_PER_FRAME as u64) / Self::CYCLES_PER_TICK,
+        }
+    }
+    /// Whether or not the timeout has expired.
+    pub fn expired(&mut self) -> bool {
+        let now = self.timer.value();
+        let elapsed = now.wrapping_sub(self.last);
+        self.last = now;
+        self.remaining_ticks = self.remaining_ticks.saturating_sub(elapsed as u64);
+        self.remaining_ticks == 0
+    }
+}
+
+impl Drop for TimerTimeout<'_> {
+    fn drop(&mut self) {
+        self.timer.set_enabled(false);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;